@@ -66,6 +66,9 @@ fn main() {
 
     println!("cargo:rustc-link-search={}", out.display());
 
+    write_config(out, "wifi_config.rs", &WIFI_CONFIG);
+    write_config(out, "scheduler_config.rs", &SCHEDULER_CONFIG);
+
     // Only re-run the build script when memory.x is changed,
     // instead of when any part of the source code changes.
     println!("cargo:rerun-if-changed=memory.x");
@@ -130,11 +133,51 @@ fn main() {
 
     println!("cargo:rustc-link-search={}", out.display());
 
+    write_config(out, "wifi_config.rs", &WIFI_CONFIG);
+    write_config(out, "scheduler_config.rs", &SCHEDULER_CONFIG);
+
     // Only re-run the build script when memory.x is changed,
     // instead of when any part of the source code changes.
     println!("cargo:rerun-if-changed=memory.x");
 }
 
+// Defaults for `WifiConfig` - name, type, default, min, max
+const WIFI_CONFIG: [(&str, &str, u32, u32, u32); 5] = [
+    ("STATIC_RX_BUF_NUM", "u8", 10, 2, 25),
+    ("DYNAMIC_RX_BUF_NUM", "u16", 32, 0, 1024),
+    ("DYNAMIC_TX_BUF_NUM", "u16", 32, 1, 128),
+    ("RX_BA_WIN", "u8", 6, 2, 32),
+    ("MGMT_SBUF_NUM", "u8", 32, 6, 32),
+];
+
+// Static pool for the task stacks - name, type, default, min, max
+const SCHEDULER_CONFIG: [(&str, &str, u32, u32, u32); 2] = [
+    ("STACK_POOL_NUM", "usize", 2, 0, 8),
+    ("STACK_POOL_SIZE", "usize", 16384, 4096, 65536),
+];
+
+fn write_config(out: &PathBuf, file_name: &str, config: &[(&str, &str, u32, u32, u32)]) {
+    let mut file = File::create(out.join(file_name)).unwrap();
+
+    for &(name, typ, default, min, max) in config {
+        let var = format!("ESP_WIFI_{}", name);
+        println!("cargo:rerun-if-env-changed={}", var);
+
+        let value = match env::var(&var) {
+            Ok(value) => value
+                .parse::<u32>()
+                .unwrap_or_else(|_| panic!("{} needs to be a number", var)),
+            Err(_) => default,
+        };
+
+        if value < min || value > max {
+            panic!("{} needs to be between {} and {}", var, min, max);
+        }
+
+        writeln!(file, "const {}: {} = {};", name, typ, value).unwrap();
+    }
+}
+
 fn copy(path: &PathBuf, data: &[u8], name: &str) {
     File::create(path.join(name))
         .unwrap()
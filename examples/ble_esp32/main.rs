@@ -3,26 +3,67 @@
 #![feature(c_variadic)]
 #![feature(const_mut_refs)]
 
-use ble_hci::ad_structure::{
-    create_advertising_data, AdStructure, BR_EDR_NOT_SUPPORTED, LE_GENERAL_DISCOVERABLE,
+use esp32_hal::{
+    clock::{ClockControl, CpuClock},
+    pac::Peripherals,
+    prelude::*,
+    RtcCntl,
 };
-use ble_hci::att::Uuid;
-use ble_hci::attribute_server::{
-    AttributeServer, Service, WorkResult, ATT_READABLE, ATT_WRITEABLE,
-};
-use ble_hci::{Ble, Data, HciConnector};
-use esp32_hal::{pac::Peripherals, RtcCntl};
 use esp_println::println;
-use esp_wifi::ble::ble_init;
 use esp_wifi::ble::controller::BleConnector;
-use esp_wifi::wifi::initialize_ble;
+use esp_wifi::ble::host::advertising::{
+    AdStructure, AdvertisingData, AdvertisingParameters, Uuids16, BR_EDR_NOT_SUPPORTED,
+    LE_GENERAL_DISCOVERABLE,
+};
+use esp_wifi::ble::host::att::{AttErrorCode, Uuid};
+use esp_wifi::ble::host::gatt::{
+    Characteristic, GattServer, Service, WorkResult, PROP_READ, PROP_WRITE,
+};
+use esp_wifi::ble::host::Host;
+use esp_wifi::{EspWifiInit, EspWifiInitFor};
 use xtensa_lx_rt::entry;
 
 use esp_backtrace as _;
 
+static SERVICES: &[Service] = &[Service::new(
+    Uuid::Uuid16(0x1809),
+    &[
+        Characteristic::new(
+            Uuid::Uuid128([
+                0xC9, 0x15, 0x15, 0x96, 0x54, 0x56, 0x64, 0xB3, 0x38, 0x45, 0x26, 0x5D, 0xF1, 0x62,
+                0x6A, 0xA8,
+            ]),
+            PROP_READ | PROP_WRITE,
+        )
+        .on_read(read_hello)
+        .on_write(print_received),
+        Characteristic::new(
+            Uuid::Uuid128([
+                0xC8, 0x15, 0x15, 0x96, 0x54, 0x56, 0x64, 0xB3, 0x38, 0x45, 0x26, 0x5D, 0xF1, 0x62,
+                0x6A, 0xA8,
+            ]),
+            PROP_WRITE,
+        ),
+    ],
+)];
+
+fn read_hello(data: &mut [u8]) -> usize {
+    let hello = b"Hello Bare-Metal BLE";
+    let len = data.len().min(hello.len());
+    data[..len].copy_from_slice(&hello[..len]);
+    len
+}
+
+fn print_received(data: &[u8]) -> Result<(), AttErrorCode> {
+    println!("RECEIVED: {:x?}", data);
+    Ok(())
+}
+
 #[entry]
 fn main() -> ! {
     let peripherals = Peripherals::take().unwrap();
+    let system = peripherals.DPORT.split();
+    let clocks = ClockControl::configure(system.clock_control, CpuClock::Clock240MHz).freeze();
 
     let mut rtc_cntl = RtcCntl::new(peripherals.RTC_CNTL);
 
@@ -31,71 +72,45 @@ fn main() -> ! {
 
     init_logger();
 
-    initialize_ble(peripherals.TIMG1, peripherals.RNG).unwrap();
+    let init = EspWifiInit::new(
+        EspWifiInitFor::Ble,
+        peripherals.TIMG1,
+        peripherals.RNG,
+        &clocks,
+    )
+    .init()
+    .unwrap();
 
-    println!("before ble init");
-    ble_init();
-    println!("after ble init");
+    let mut host = Host::new(BleConnector::new(&init));
+    println!("{:?}", host.init());
 
-    loop {
-        let connector = BleConnector {};
-        let hci = HciConnector::new(connector, esp_wifi::current_millis);
-        let mut ble = Ble::new(&hci);
+    let advertising_data = AdvertisingData::from_structures(&[
+        AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
+        AdStructure::ServiceUuids16(Uuids16::new(&[0x1809])),
+        AdStructure::CompleteLocalName("ESP32 BLE"),
+    ])
+    .unwrap();
+
+    let mut server = GattServer::new(SERVICES);
 
-        println!("{:?}", ble.init());
-        println!("{:?}", ble.cmd_set_le_advertising_parameters());
+    loop {
         println!(
             "{:?}",
-            ble.cmd_set_le_advertising_data(create_advertising_data(&[
-                AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
-                AdStructure::ServiceUuids16(&[Uuid::Uuid16(0x1809)]),
-                AdStructure::CompleteLocalName("ESP32 BLE"),
-            ]))
+            host.start_advertising(
+                &AdvertisingParameters::default().with_interval_ms(100),
+                &advertising_data,
+                None,
+            )
         );
-        println!("{:?}", ble.cmd_set_le_advertise_enable(true));
 
         println!("started advertising");
 
-        let mut rf = || Data::new(b"Hello Bare-Metal BLE");
-        let mut wf = |data: Data| {
-            println!("RECEIVED: {:x?}", data.to_slice());
-        };
-
-        let srv1 = Service::new(
-            Uuid::Uuid128([
-                0xC9, 0x15, 0x15, 0x96, 0x54, 0x56, 0x64, 0xB3, 0x38, 0x45, 0x26, 0x5D, 0xF1, 0x62,
-                0x6A, 0xA8,
-            ]),
-            ATT_READABLE | ATT_WRITEABLE,
-            &mut rf,
-            &mut wf,
-        );
-
-        let mut rf2 = || Data::default();
-        let mut wf2 = |_data| {};
-
-        let srv2 = Service::new(
-            Uuid::Uuid128([
-                0xC8, 0x15, 0x15, 0x96, 0x54, 0x56, 0x64, 0xB3, 0x38, 0x45, 0x26, 0x5D, 0xF1, 0x62,
-                0x6A, 0xA8,
-            ]),
-            ATT_WRITEABLE,
-            &mut rf2,
-            &mut wf2,
-        );
-
-        let services = &mut [srv1, srv2];
-        let mut srv = AttributeServer::new(&mut ble, services);
-
         loop {
-            match srv.do_work() {
-                Ok(res) => {
-                    if let WorkResult::GotDisconnected = res {
-                        break;
-                    }
-                }
+            match server.do_work(&mut host) {
+                Ok(WorkResult::Disconnected { .. }) => break,
+                Ok(_) => (),
                 Err(err) => {
-                    println!("{:x?}", err);
+                    println!("{:?}", err);
                 }
             }
         }
@@ -5,12 +5,17 @@ use embedded_svc::wifi::{
     ClientConfiguration, ClientConnectionStatus, ClientIpStatus, ClientStatus, Configuration,
     Status, Wifi,
 };
-use esp32_hal::{pac::Peripherals, RtcCntl};
+use esp32_hal::{
+    clock::{ClockControl, CpuClock},
+    pac::Peripherals,
+    prelude::*,
+    RtcCntl,
+};
 use esp_println::{print, println};
-use esp_wifi::wifi::initialize;
 use esp_wifi::wifi::utils::create_network_interface;
 use esp_wifi::wifi_interface::timestamp;
 use esp_wifi::{create_network_stack_storage, network_stack_storage};
+use esp_wifi::{EspWifiInit, EspWifiInitFor};
 use smoltcp::iface::SocketHandle;
 use smoltcp::socket::{Socket, TcpSocket};
 use xtensa_lx_rt::entry;
@@ -25,19 +30,28 @@ const PASSWORD: &str = env!("PASSWORD");
 #[entry]
 fn main() -> ! {
     let peripherals = Peripherals::take().unwrap();
+    let system = peripherals.DPORT.split();
+    let clocks = ClockControl::configure(system.clock_control, CpuClock::Clock240MHz).freeze();
 
     let mut rtc_cntl = RtcCntl::new(peripherals.RTC_CNTL);
 
     // Disable MWDT and RWDT (Watchdog) flash boot protection
     rtc_cntl.set_wdt_global_enable(false);
 
-    let mut storage = create_network_stack_storage!(3, 8, 1);
-    let ethernet = create_network_interface(network_stack_storage!(storage));
-    let mut wifi_interface = esp_wifi::wifi_interface::Wifi::new(ethernet);
-
     init_logger();
 
-    initialize(peripherals.TIMG1, peripherals.RNG).unwrap();
+    let init = EspWifiInit::new(
+        EspWifiInitFor::Wifi,
+        peripherals.TIMG1,
+        peripherals.RNG,
+        &clocks,
+    )
+    .init()
+    .unwrap();
+
+    let mut storage = create_network_stack_storage!(3, 8, 1);
+    let ethernet = create_network_interface(&init, network_stack_storage!(storage));
+    let mut wifi_interface = esp_wifi::wifi_interface::Wifi::new(ethernet);
 
     println!("{:?}", wifi_interface.get_status());
 
@@ -7,12 +7,17 @@ use embedded_svc::wifi::{
     ClientConfiguration, ClientConnectionStatus, ClientIpStatus, ClientStatus, Configuration,
     Status, Wifi,
 };
-use esp32c3_hal::{pac::Peripherals, RtcCntl};
+use esp32c3_hal::{
+    clock::{ClockControl, CpuClock},
+    pac::Peripherals,
+    prelude::*,
+    RtcCntl,
+};
 use esp_println::{print, println};
-use esp_wifi::wifi::initialize;
 use esp_wifi::wifi::utils::create_network_interface;
 use esp_wifi::wifi_interface::timestamp;
 use esp_wifi::{create_network_stack_storage, network_stack_storage};
+use esp_wifi::{EspWifiInit, EspWifiInitFor};
 use riscv_rt::entry;
 use smoltcp::iface::SocketHandle;
 use smoltcp::socket::{Socket, TcpSocket};
@@ -26,7 +31,9 @@ const PASSWORD: &str = env!("PASSWORD");
 
 #[entry]
 fn main() -> ! {
-    let mut peripherals = Peripherals::take().unwrap();
+    let peripherals = Peripherals::take().unwrap();
+    let system = peripherals.SYSTEM.split();
+    let clocks = ClockControl::configure(system.clock_control, CpuClock::Clock160MHz).freeze();
 
     let mut rtc_cntl = RtcCntl::new(peripherals.RTC_CNTL);
 
@@ -34,19 +41,21 @@ fn main() -> ! {
     rtc_cntl.set_super_wdt_enable(false);
     rtc_cntl.set_wdt_enable(false);
 
-    let mut storage = create_network_stack_storage!(3, 8, 1);
-    let ethernet = create_network_interface(network_stack_storage!(storage));
-    let mut wifi_interface = esp_wifi::wifi_interface::Wifi::new(ethernet);
-
     init_logger();
 
-    initialize(
-        &mut peripherals.SYSTIMER,
-        &mut peripherals.INTERRUPT_CORE0,
+    let init = EspWifiInit::new(
+        EspWifiInitFor::Wifi,
+        peripherals.SYSTIMER,
         peripherals.RNG,
+        &clocks,
     )
+    .init()
     .unwrap();
 
+    let mut storage = create_network_stack_storage!(3, 8, 1);
+    let ethernet = create_network_interface(&init, network_stack_storage!(storage));
+    let mut wifi_interface = esp_wifi::wifi_interface::Wifi::new(ethernet);
+
     println!("{:?}", wifi_interface.get_status());
 
     println!("Start Wifi Scan");
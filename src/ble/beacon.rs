@@ -0,0 +1,560 @@
+//! Non-connectable beacons: iBeacon and Eddystone UID, URL and TLM frames.
+//!
+//! [BeaconAdvertiser] advertises one or more beacons, rotating the payload on a timer.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::binary::c_types::c_void;
+use crate::binary::include::ets_timer;
+use crate::compat::timer_compat::{compat_timer_arm, compat_timer_disarm, compat_timer_setfn};
+use crate::timer::{get_systimer_count, TICKS_PER_SECOND};
+
+use super::host::advertising::{
+    AdStructure, AdvertisingData, AdvertisingError, AdvertisingMode, AdvertisingParameters,
+    Uuids16, BR_EDR_NOT_SUPPORTED, LE_GENERAL_DISCOVERABLE,
+};
+use super::host::{Host, HostError};
+
+const APPLE_COMPANY_IDENTIFIER: u16 = 0x004c;
+const IBEACON_TYPE: u8 = 0x02;
+const IBEACON_LEN: u8 = 0x15;
+
+const EDDYSTONE_UUID: u16 = 0xfeaa;
+const EDDYSTONE_UID: u8 = 0x00;
+const EDDYSTONE_URL: u8 = 0x10;
+const EDDYSTONE_TLM: u8 = 0x20;
+
+/// Maximum length of an encoded Eddystone URL without the scheme prefix
+const MAX_URL_LEN: usize = 17;
+
+const URL_SCHEMES: [&str; 4] = ["http://www.", "https://www.", "http://", "https://"];
+
+/// Expansion codes 0x00 to 0x0d, the ones ending with a slash come first so they are
+/// preferred
+const URL_EXPANSIONS: [&str; 14] = [
+    ".com/", ".org/", ".edu/", ".net/", ".info/", ".biz/", ".gov/", ".com", ".org", ".edu", ".net",
+    ".info", ".biz", ".gov",
+];
+
+/// Temperature value of TLM frames if it isn't supported
+pub const TLM_TEMPERATURE_UNSUPPORTED: i16 = -0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BeaconError {
+    /// The URL doesn't start with a scheme Eddystone can encode or contains characters
+    /// which can't be encoded
+    InvalidUrl,
+    /// The encoded URL is longer than 17 bytes
+    UrlTooLong,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IBeacon {
+    /// Proximity UUID, most significant byte first as it is usually written
+    pub uuid: [u8; 16],
+    pub major: u16,
+    pub minor: u16,
+    /// Measured RSSI at 1m in dBm
+    pub tx_power: i8,
+}
+
+impl IBeacon {
+    pub fn advertising_data(&self) -> AdvertisingData {
+        let mut data = [0u8; 23];
+        data[0] = IBEACON_TYPE;
+        data[1] = IBEACON_LEN;
+        data[2..18].copy_from_slice(&self.uuid);
+        data[18..20].copy_from_slice(&self.major.to_be_bytes());
+        data[20..22].copy_from_slice(&self.minor.to_be_bytes());
+        data[22] = self.tx_power as u8;
+
+        // 30 bytes, always fits
+        AdvertisingData::from_structures(&[
+            AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
+            AdStructure::ManufacturerSpecificData {
+                company_identifier: APPLE_COMPANY_IDENTIFIER,
+                data: &data,
+            },
+        ])
+        .unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EddystoneUid {
+    /// Calibrated TX power at 0m in dBm
+    pub tx_power: i8,
+    pub namespace: [u8; 10],
+    pub instance: [u8; 6],
+}
+
+impl EddystoneUid {
+    pub fn advertising_data(&self) -> AdvertisingData {
+        let mut frame = [0u8; 20];
+        frame[0] = EDDYSTONE_UID;
+        frame[1] = self.tx_power as u8;
+        frame[2..12].copy_from_slice(&self.namespace);
+        frame[12..18].copy_from_slice(&self.instance);
+        // the last two bytes are reserved
+
+        eddystone_advertising_data(&frame)
+    }
+}
+
+/// An Eddystone URL, encoded when it's created
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EddystoneUrl {
+    tx_power: i8,
+    scheme: u8,
+    url: [u8; MAX_URL_LEN],
+    len: usize,
+}
+
+impl EddystoneUrl {
+    /// Encodes the URL, `tx_power` is the calibrated TX power at 0m in dBm
+    pub fn new(tx_power: i8, url: &str) -> Result<EddystoneUrl, BeaconError> {
+        // the longer schemes come first so "http://www." isn't taken for "http://"
+        let (scheme, mut rest) = URL_SCHEMES
+            .iter()
+            .enumerate()
+            .find_map(|(i, scheme)| Some((i as u8, url.strip_prefix(scheme)?)))
+            .ok_or(BeaconError::InvalidUrl)?;
+
+        let mut encoded = [0u8; MAX_URL_LEN];
+        let mut len = 0;
+        while !rest.is_empty() {
+            if len == MAX_URL_LEN {
+                return Err(BeaconError::UrlTooLong);
+            }
+
+            let expansion = URL_EXPANSIONS
+                .iter()
+                .position(|expansion| rest.starts_with(expansion));
+
+            match expansion {
+                Some(code) => {
+                    encoded[len] = code as u8;
+                    rest = &rest[URL_EXPANSIONS[code].len()..];
+                }
+                None => {
+                    let c = rest.as_bytes()[0];
+                    // everything else is reserved for expansion codes
+                    if !(0x21..0x7f).contains(&c) {
+                        return Err(BeaconError::InvalidUrl);
+                    }
+
+                    encoded[len] = c;
+                    rest = &rest[1..];
+                }
+            }
+            len += 1;
+        }
+
+        Ok(EddystoneUrl {
+            tx_power,
+            scheme,
+            url: encoded,
+            len,
+        })
+    }
+
+    pub fn advertising_data(&self) -> AdvertisingData {
+        let mut frame = [0u8; 3 + MAX_URL_LEN];
+        frame[0] = EDDYSTONE_URL;
+        frame[1] = self.tx_power as u8;
+        frame[2] = self.scheme;
+        frame[3..][..self.len].copy_from_slice(&self.url[..self.len]);
+
+        eddystone_advertising_data(&frame[..3 + self.len])
+    }
+}
+
+/// Unencrypted Eddystone telemetry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EddystoneTlm {
+    /// Battery voltage in mV, 0 if it isn't supported
+    pub battery_voltage: u16,
+    /// Temperature in units of 1/256 °C, [TLM_TEMPERATURE_UNSUPPORTED] if it isn't supported
+    pub temperature: i16,
+    /// Number of advertising events since power-up
+    pub advertising_count: u32,
+    /// Time since power-up in units of 0.1s
+    pub uptime: u32,
+}
+
+impl EddystoneTlm {
+    pub fn advertising_data(&self) -> AdvertisingData {
+        let mut frame = [0u8; 14];
+        frame[0] = EDDYSTONE_TLM;
+        // version 0 is the unencrypted frame
+        frame[1] = 0x00;
+        frame[2..4].copy_from_slice(&self.battery_voltage.to_be_bytes());
+        frame[4..6].copy_from_slice(&self.temperature.to_be_bytes());
+        frame[6..10].copy_from_slice(&self.advertising_count.to_be_bytes());
+        frame[10..14].copy_from_slice(&self.uptime.to_be_bytes());
+
+        eddystone_advertising_data(&frame)
+    }
+}
+
+fn eddystone_advertising_data(frame: &[u8]) -> AdvertisingData {
+    // at most 31 bytes for an UID frame
+    AdvertisingData::from_structures(&[
+        AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
+        AdStructure::ServiceUuids16(Uuids16::new(&[EDDYSTONE_UUID])),
+        AdStructure::ServiceData16 {
+            uuid: EDDYSTONE_UUID,
+            data: frame,
+        },
+    ])
+    .unwrap()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Beacon {
+    IBeacon(IBeacon),
+    EddystoneUid(EddystoneUid),
+    EddystoneUrl(EddystoneUrl),
+    /// [BeaconAdvertiser] fills in the uptime and the advertising events since it was started
+    EddystoneTlm(EddystoneTlm),
+}
+
+impl Beacon {
+    pub fn advertising_data(&self) -> AdvertisingData {
+        match self {
+            Beacon::IBeacon(beacon) => beacon.advertising_data(),
+            Beacon::EddystoneUid(beacon) => beacon.advertising_data(),
+            Beacon::EddystoneUrl(beacon) => beacon.advertising_data(),
+            Beacon::EddystoneTlm(beacon) => beacon.advertising_data(),
+        }
+    }
+}
+
+static mut ROTATION_TIMER: ets_timer = ets_timer {
+    next: core::ptr::null_mut(),
+    expire: 0,
+    period: 0,
+    func: None,
+    priv_: core::ptr::null_mut(),
+};
+
+static ROTATION_DUE: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn rotation_timer_callback(_arg: *mut c_void) {
+    ROTATION_DUE.store(true, Ordering::Relaxed);
+}
+
+/// Advertises beacons non-connectable, switching to the next one every rotation interval.
+///
+/// The switch happens in [BeaconAdvertiser::poll] which has to be called regularly. There is
+/// only one advertising set, don't use more than one advertiser at a time.
+pub struct BeaconAdvertiser<'a> {
+    beacons: &'a [Beacon],
+    rotation_interval_ms: u32,
+    params: AdvertisingParameters,
+    current: usize,
+    started: u64,
+}
+
+impl<'a> BeaconAdvertiser<'a> {
+    /// The advertising interval defaults to 100ms
+    pub fn new(beacons: &'a [Beacon], rotation_interval_ms: u32) -> BeaconAdvertiser<'a> {
+        BeaconAdvertiser {
+            beacons,
+            rotation_interval_ms,
+            params: AdvertisingParameters::default()
+                .with_interval_ms(100)
+                .with_mode(AdvertisingMode::NonConnectable),
+            current: 0,
+            started: 0,
+        }
+    }
+
+    /// Replaces the advertising parameters, the mode is always non-connectable
+    pub fn with_parameters(self, params: AdvertisingParameters) -> BeaconAdvertiser<'a> {
+        BeaconAdvertiser {
+            params: params.with_mode(AdvertisingMode::NonConnectable),
+            ..self
+        }
+    }
+
+    /// Starts advertising the first beacon, fails with [AdvertisingError::InvalidInterval] if
+    /// the rotation interval is zero
+    pub fn start(&mut self, host: &mut Host) -> Result<(), HostError> {
+        if self.rotation_interval_ms == 0 {
+            return Err(HostError::Advertising(AdvertisingError::InvalidInterval));
+        }
+
+        if self.beacons.is_empty() {
+            return Ok(());
+        }
+
+        self.current = 0;
+        self.started = get_systimer_count();
+        host.start_advertising(&self.params, &self.current_data(), None)?;
+
+        // a single TLM frame is still updated to refresh its counters
+        if self.beacons.len() > 1 || matches!(self.beacons[0], Beacon::EddystoneTlm(_)) {
+            ROTATION_DUE.store(false, Ordering::Relaxed);
+            unsafe {
+                let timer = &mut ROTATION_TIMER as *mut ets_timer as *mut c_void;
+                compat_timer_setfn(
+                    timer,
+                    rotation_timer_callback as *const () as *mut c_void,
+                    core::ptr::null_mut(),
+                );
+                compat_timer_arm(timer, self.rotation_interval_ms, true);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switches to the next beacon if the rotation interval passed, returns true if it did.
+    pub fn poll(&mut self, host: &mut Host) -> Result<bool, HostError> {
+        if !ROTATION_DUE.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        ROTATION_DUE.store(false, Ordering::Relaxed);
+
+        self.current = (self.current + 1) % self.beacons.len();
+        host.set_advertising_data(&self.current_data())?;
+
+        Ok(true)
+    }
+
+    pub fn stop(&mut self, host: &mut Host) -> Result<(), HostError> {
+        unsafe {
+            compat_timer_disarm(&mut ROTATION_TIMER as *mut ets_timer as *mut c_void);
+        }
+        ROTATION_DUE.store(false, Ordering::Relaxed);
+
+        host.set_advertise_enable(false)
+    }
+
+    fn current_data(&self) -> AdvertisingData {
+        match self.beacons[self.current] {
+            Beacon::EddystoneTlm(tlm) => {
+                let now = get_systimer_count();
+                let interval_us = self.params.interval_min as u64 * 625;
+                let advertising_us = (now - self.started) / (TICKS_PER_SECOND / 1_000_000);
+
+                EddystoneTlm {
+                    advertising_count: (advertising_us / interval_us) as u32,
+                    uptime: (now / (TICKS_PER_SECOND / 10)) as u32,
+                    ..tlm
+                }
+                .advertising_data()
+            }
+            beacon => beacon.advertising_data(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAGS: [u8; 3] = [0x02, 0x01, 0x06];
+    const EDDYSTONE_UUIDS: [u8; 4] = [0x03, 0x03, 0xaa, 0xfe];
+
+    /// Flags, the Eddystone service UUID and the service data header of a frame of `len` bytes
+    fn eddystone_header(len: u8) -> [u8; 11] {
+        [
+            0x02,
+            0x01,
+            0x06,
+            0x03,
+            0x03,
+            0xaa,
+            0xfe,
+            3 + len,
+            0x16,
+            0xaa,
+            0xfe,
+        ]
+    }
+
+    fn url(url: &str) -> Result<([u8; 31], usize), BeaconError> {
+        let data = EddystoneUrl::new(-20, url)?.advertising_data();
+        let mut bytes = [0u8; 31];
+        bytes[..data.as_slice().len()].copy_from_slice(data.as_slice());
+        Ok((bytes, data.as_slice().len()))
+    }
+
+    /// The encoded URL after the TX power and the scheme
+    fn encoded_url(url: &str) -> Result<([u8; MAX_URL_LEN], usize), BeaconError> {
+        let (data, len) = self::url(url)?;
+        let frame = &data[11..len];
+        assert_eq!(frame[0], EDDYSTONE_URL);
+        assert_eq!(frame[1], -20i8 as u8);
+
+        let mut encoded = [0u8; MAX_URL_LEN];
+        encoded[..frame.len() - 3].copy_from_slice(&frame[3..]);
+        Ok((encoded, frame.len() - 3))
+    }
+
+    #[test]
+    fn ibeacon_apple_example() {
+        // the example of Apple's Proximity Beacon Specification
+        let beacon = IBeacon {
+            uuid: [
+                0xe2, 0xc5, 0x6d, 0xb5, 0xdf, 0xfb, 0x48, 0xd2, 0xb0, 0x60, 0xd0, 0xf5, 0xa7, 0x10,
+                0x96, 0xe0,
+            ],
+            major: 0,
+            minor: 0,
+            tx_power: -59,
+        };
+
+        assert_eq!(
+            beacon.advertising_data().as_slice(),
+            &[
+                0x02, 0x01, 0x06, 0x1a, 0xff, 0x4c, 0x00, 0x02, 0x15, 0xe2, 0xc5, 0x6d, 0xb5, 0xdf,
+                0xfb, 0x48, 0xd2, 0xb0, 0x60, 0xd0, 0xf5, 0xa7, 0x10, 0x96, 0xe0, 0x00, 0x00, 0x00,
+                0x00, 0xc5,
+            ]
+        );
+    }
+
+    #[test]
+    fn ibeacon_major_minor_big_endian() {
+        let beacon = IBeacon {
+            uuid: [0x11; 16],
+            major: 0x1234,
+            minor: 0xabcd,
+            tx_power: -1,
+        };
+
+        let data = beacon.advertising_data();
+        assert_eq!(&data.as_slice()[25..], &[0x12, 0x34, 0xab, 0xcd, 0xff]);
+    }
+
+    #[test]
+    fn eddystone_uid() {
+        let beacon = EddystoneUid {
+            tx_power: -18,
+            namespace: [0x8b, 0x0c, 0xa7, 0x50, 0xe7, 0xa7, 0x4e, 0x14, 0xbd, 0x99],
+            instance: [0x01, 0x02, 0x03, 0x04, 0x05, 0x06],
+        };
+
+        let data = beacon.advertising_data();
+        let data = data.as_slice();
+        assert_eq!(data.len(), 31);
+        assert_eq!(&data[..11], &eddystone_header(20));
+        assert_eq!(
+            &data[11..],
+            &[
+                0x00, 0xee, 0x8b, 0x0c, 0xa7, 0x50, 0xe7, 0xa7, 0x4e, 0x14, 0xbd, 0x99, 0x01, 0x02,
+                0x03, 0x04, 0x05, 0x06, 0x00, 0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn eddystone_url_frame() {
+        let (data, len) = url("https://www.google.com/").unwrap();
+        assert_eq!(&data[..3], &FLAGS);
+        assert_eq!(&data[3..7], &EDDYSTONE_UUIDS);
+        assert_eq!(&data[..11], &eddystone_header(10));
+        assert_eq!(
+            &data[11..len],
+            &[0x10, 0xec, 0x01, b'g', b'o', b'o', b'g', b'l', b'e', 0x00]
+        );
+    }
+
+    #[test]
+    fn eddystone_url_schemes() {
+        let scheme = |url: &str| self::url(url).unwrap().0[13];
+
+        assert_eq!(scheme("http://www.a"), 0x00);
+        assert_eq!(scheme("https://www.a"), 0x01);
+        assert_eq!(scheme("http://a"), 0x02);
+        assert_eq!(scheme("https://a"), 0x03);
+    }
+
+    #[test]
+    fn eddystone_url_expansions() {
+        let (encoded, len) = encoded_url("http://a.com/b.org/c.edu/d.net/").unwrap();
+        assert_eq!(
+            &encoded[..len],
+            &[b'a', 0x00, b'b', 0x01, b'c', 0x02, b'd', 0x03]
+        );
+
+        let (encoded, len) = encoded_url("http://a.info/b.biz/c.gov/").unwrap();
+        assert_eq!(&encoded[..len], &[b'a', 0x04, b'b', 0x05, b'c', 0x06]);
+
+        let (encoded, len) = encoded_url("http://a.com.org.edu.net").unwrap();
+        assert_eq!(&encoded[..len], &[b'a', 0x07, 0x08, 0x09, 0x0a]);
+
+        let (encoded, len) = encoded_url("http://a.info.biz.gov").unwrap();
+        assert_eq!(&encoded[..len], &[b'a', 0x0b, 0x0c, 0x0d]);
+
+        // no expansion for other top level domains
+        let (encoded, len) = encoded_url("https://a.de/x").unwrap();
+        assert_eq!(&encoded[..len], b"a.de/x");
+    }
+
+    #[test]
+    fn eddystone_url_length() {
+        // 17 encoded bytes fit
+        let (_, len) = encoded_url("https://www.abcdefghijklmnop.com").unwrap();
+        assert_eq!(len, MAX_URL_LEN);
+        let (data, len) = url("https://www.abcdefghijklmnop.com").unwrap();
+        assert_eq!(len, 31);
+        assert_eq!(&data[..11], &eddystone_header(20));
+
+        assert_eq!(
+            EddystoneUrl::new(0, "https://www.abcdefghijklmnopq.com"),
+            Err(BeaconError::UrlTooLong)
+        );
+        assert_eq!(
+            EddystoneUrl::new(0, "https://abcdefghijklmnopqrstuvwxyz"),
+            Err(BeaconError::UrlTooLong)
+        );
+    }
+
+    #[test]
+    fn eddystone_url_invalid() {
+        assert_eq!(
+            EddystoneUrl::new(0, "ftp://example.com"),
+            Err(BeaconError::InvalidUrl)
+        );
+        assert_eq!(
+            EddystoneUrl::new(0, "example.com"),
+            Err(BeaconError::InvalidUrl)
+        );
+        assert_eq!(
+            EddystoneUrl::new(0, "https://a b"),
+            Err(BeaconError::InvalidUrl)
+        );
+        assert_eq!(
+            EddystoneUrl::new(0, "https://ä"),
+            Err(BeaconError::InvalidUrl)
+        );
+    }
+
+    #[test]
+    fn eddystone_tlm() {
+        let tlm = EddystoneTlm {
+            battery_voltage: 3000,
+            temperature: 0x1880,
+            advertising_count: 0x01020304,
+            uptime: 0x0a0b0c0d,
+        };
+
+        let data = tlm.advertising_data();
+        let data = data.as_slice();
+        assert_eq!(&data[..11], &eddystone_header(14));
+        assert_eq!(
+            &data[11..],
+            &[0x20, 0x00, 0x0b, 0xb8, 0x18, 0x80, 0x01, 0x02, 0x03, 0x04, 0x0a, 0x0b, 0x0c, 0x0d,]
+        );
+
+        let tlm = EddystoneTlm {
+            temperature: TLM_TEMPERATURE_UNSUPPORTED,
+            ..Default::default()
+        };
+        assert_eq!(&tlm.advertising_data().as_slice()[15..17], &[0x80, 0x00]);
+    }
+}
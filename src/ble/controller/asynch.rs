@@ -0,0 +1,130 @@
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use embedded_io::asynch::{Read, Write};
+
+use super::{BleConnector, BleConnectorError};
+use crate::ble::{have_hci_read_data, hci_send_available, read_hci, send_hci};
+
+static mut HCI_READ_WAKER: Option<Waker> = None;
+static mut HCI_SEND_WAKER: Option<Waker> = None;
+
+fn register(slot: *mut Option<Waker>, waker: &Waker) {
+    critical_section::with(|_| unsafe {
+        match &*slot {
+            Some(registered) if registered.will_wake(waker) => (),
+            _ => *slot = Some(waker.clone()),
+        }
+    });
+}
+
+fn wake(slot: *mut Option<Waker>) {
+    let waker = critical_section::with(|_| unsafe { (*slot).take() });
+
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+/// Called when the controller queued a packet for the host
+pub(crate) fn hci_read_data_available() {
+    wake(unsafe { &mut HCI_READ_WAKER });
+}
+
+/// Called when the controller is able to take another packet from the host
+pub(crate) fn hci_send_space_available() {
+    wake(unsafe { &mut HCI_SEND_WAKER });
+}
+
+impl<'d> Read for BleConnector<'d> {
+    type ReadFuture<'a>
+        = HciReadFuture<'a>
+    where
+        Self: 'a;
+
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Self::ReadFuture<'a> {
+        HciReadFuture { buf }
+    }
+}
+
+impl<'d> Write for BleConnector<'d> {
+    type WriteFuture<'a>
+        = HciWriteFuture<'a>
+    where
+        Self: 'a;
+
+    fn write<'a>(&'a mut self, buf: &'a [u8]) -> Self::WriteFuture<'a> {
+        HciWriteFuture { buf }
+    }
+
+    type FlushFuture<'a>
+        = core::future::Ready<Result<(), BleConnectorError>>
+    where
+        Self: 'a;
+
+    fn flush<'a>(&'a mut self) -> Self::FlushFuture<'a> {
+        // nothing to do
+        core::future::ready(Ok(()))
+    }
+}
+
+/// Resolves as soon as at least one byte received from the controller was copied into the buffer.
+pub struct HciReadFuture<'a> {
+    buf: &'a mut [u8],
+}
+
+impl<'a> Future for HciReadFuture<'a> {
+    type Output = Result<usize, BleConnectorError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        // register before checking so a packet arriving in between isn't missed
+        register(unsafe { &mut HCI_READ_WAKER }, cx.waker());
+
+        if !have_hci_read_data() {
+            return Poll::Pending;
+        }
+
+        let mut total = 0;
+        for b in self.buf.iter_mut() {
+            let mut buffer = [0u8];
+            if read_hci(&mut buffer) == 0 {
+                break;
+            }
+
+            *b = buffer[0];
+            total += 1;
+        }
+
+        Poll::Ready(Ok(total))
+    }
+}
+
+/// Resolves once the controller accepted the data.
+pub struct HciWriteFuture<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Future for HciWriteFuture<'a> {
+    type Output = Result<usize, BleConnectorError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        register(unsafe { &mut HCI_SEND_WAKER }, cx.waker());
+
+        if !hci_send_available() {
+            return Poll::Pending;
+        }
+
+        Poll::Ready(
+            send_hci(self.buf)
+                .map(|_| self.buf.len())
+                .map_err(BleConnectorError::from),
+        )
+    }
+}
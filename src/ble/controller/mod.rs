@@ -3,13 +3,59 @@ use embedded_io::{
     Error, Io,
 };
 
-use super::{read_hci, send_hci};
+use core::marker::PhantomData;
 
-pub struct BleConnector {}
+use super::hci::{HciError, HciPacket};
+use super::{read_hci, receive_packet, send_hci, send_packet};
+use crate::EspWifiInitialization;
+
+#[cfg(feature = "async")]
+pub mod asynch;
+
+/// The HCI connection to the controller.
+///
+/// It borrows the initialization, Bluetooth LE can't be deinitialized while it's in use.
+pub struct BleConnector<'d> {
+    _init: PhantomData<&'d EspWifiInitialization>,
+}
 
 #[derive(Debug)]
 pub enum BleConnectorError {
     Unknown,
+    Hci(HciError),
+}
+
+impl From<HciError> for BleConnectorError {
+    fn from(err: HciError) -> Self {
+        BleConnectorError::Hci(err)
+    }
+}
+
+impl<'d> BleConnector<'d> {
+    /// Creates the connector, Bluetooth LE has to be enabled in the initialization.
+    pub fn new(init: &'d EspWifiInitialization) -> BleConnector<'d> {
+        assert!(init.is_ble(), "Bluetooth LE is not initialized");
+
+        BleConnector { _init: PhantomData }
+    }
+
+    /// Sends a complete packet to the controller.
+    pub fn send_packet(&mut self, packet: HciPacket) -> Result<(), BleConnectorError> {
+        Ok(send_packet(packet)?)
+    }
+
+    /// Receives a complete packet from the controller.
+    ///
+    /// The packet is copied into `buf` which should be at least
+    /// [HCI_MAX_PACKET_LEN](super::hci::HCI_MAX_PACKET_LEN) bytes. Returns `Ok(None)` if no
+    /// packet is available. This shouldn't be mixed with reading via [Read] since a packet
+    /// partially read that way is skipped.
+    pub fn receive_packet<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+    ) -> Result<Option<HciPacket<'a>>, BleConnectorError> {
+        Ok(receive_packet(buf)?)
+    }
 }
 
 impl Error for BleConnectorError {
@@ -18,11 +64,11 @@ impl Error for BleConnectorError {
     }
 }
 
-impl Io for BleConnector {
+impl<'d> Io for BleConnector<'d> {
     type Error = BleConnectorError;
 }
 
-impl Read for BleConnector {
+impl<'d> Read for BleConnector<'d> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
         let mut total = 0;
         for b in buf {
@@ -41,11 +87,9 @@ impl Read for BleConnector {
     }
 }
 
-impl Write for BleConnector {
+impl<'d> Write for BleConnector<'d> {
     fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
-        for b in buf {
-            send_hci(&[*b]);
-        }
+        send_hci(buf)?;
         Ok(buf.len())
     }
 
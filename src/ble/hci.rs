@@ -0,0 +1,388 @@
+//! HCI packets as exchanged with the controller over the VHCI interface.
+//!
+//! Every packet on the wire starts with a one byte H4 packet indicator followed by the
+//! packet header and payload. The variants of [HciPacket] hold everything after the
+//! indicator.
+
+/// Size of the largest H4 framed packet (indicator + header + payload) we handle.
+///
+/// This is enough for commands (3 + 255) and events (2 + 255) as well as the ACL buffer
+/// size reported by the controllers.
+pub const HCI_MAX_PACKET_LEN: usize = 260;
+
+const H4_COMMAND: u8 = 0x01;
+const H4_ACL: u8 = 0x02;
+const H4_SCO: u8 = 0x03;
+const H4_EVENT: u8 = 0x04;
+const H4_ISO: u8 = 0x05;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HciError {
+    /// The H4 packet indicator isn't known
+    UnknownPacketType(u8),
+    /// The packet doesn't fit into [HCI_MAX_PACKET_LEN] bytes
+    PacketTooLarge,
+    /// The length in the packet header doesn't match the data
+    LengthMismatch,
+    /// The buffer passed in is too small to hold the packet
+    BufferTooSmall,
+}
+
+/// A HCI packet without its H4 packet indicator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HciPacket<'a> {
+    Command(&'a [u8]),
+    Acl(&'a [u8]),
+    Sco(&'a [u8]),
+    Event(&'a [u8]),
+    Iso(&'a [u8]),
+}
+
+impl<'a> HciPacket<'a> {
+    /// Parses a H4 framed packet. The length given in the header has to match the data.
+    pub fn from_h4(data: &'a [u8]) -> Result<HciPacket<'a>, HciError> {
+        if data.is_empty() {
+            return Err(HciError::LengthMismatch);
+        }
+
+        if data.len() > HCI_MAX_PACKET_LEN {
+            return Err(HciError::PacketTooLarge);
+        }
+
+        let indicator = data[0];
+        let body = &data[1..];
+        match h4_packet_len(indicator, body)? {
+            Some(len) if len == data.len() => (),
+            _ => return Err(HciError::LengthMismatch),
+        }
+
+        Ok(match indicator {
+            H4_COMMAND => HciPacket::Command(body),
+            H4_ACL => HciPacket::Acl(body),
+            H4_SCO => HciPacket::Sco(body),
+            H4_EVENT => HciPacket::Event(body),
+            _ => HciPacket::Iso(body),
+        })
+    }
+
+    pub fn indicator(&self) -> u8 {
+        match self {
+            HciPacket::Command(_) => H4_COMMAND,
+            HciPacket::Acl(_) => H4_ACL,
+            HciPacket::Sco(_) => H4_SCO,
+            HciPacket::Event(_) => H4_EVENT,
+            HciPacket::Iso(_) => H4_ISO,
+        }
+    }
+
+    /// The packet without its indicator
+    pub fn body(&self) -> &'a [u8] {
+        match self {
+            HciPacket::Command(body)
+            | HciPacket::Acl(body)
+            | HciPacket::Sco(body)
+            | HciPacket::Event(body)
+            | HciPacket::Iso(body) => body,
+        }
+    }
+
+    /// Length of the packet including the indicator
+    pub fn h4_len(&self) -> usize {
+        self.body().len() + 1
+    }
+
+    /// Writes the H4 framed packet into `buf` and returns the number of bytes written.
+    pub fn write_h4(&self, buf: &mut [u8]) -> Result<usize, HciError> {
+        let len = self.h4_len();
+
+        if len > HCI_MAX_PACKET_LEN {
+            return Err(HciError::PacketTooLarge);
+        }
+
+        if h4_packet_len(self.indicator(), self.body())? != Some(len) {
+            return Err(HciError::LengthMismatch);
+        }
+
+        if buf.len() < len {
+            return Err(HciError::BufferTooSmall);
+        }
+
+        buf[0] = self.indicator();
+        buf[1..len].copy_from_slice(self.body());
+
+        Ok(len)
+    }
+}
+
+/// Returns the full length (including the indicator) of the packet if enough of the
+/// header is available in `body` to know it.
+fn h4_packet_len(indicator: u8, body: &[u8]) -> Result<Option<usize>, HciError> {
+    let (header_len, payload_len) = match indicator {
+        H4_COMMAND if body.len() >= 3 => (3, body[2] as usize),
+        H4_ACL if body.len() >= 4 => (4, u16::from_le_bytes([body[2], body[3]]) as usize),
+        H4_SCO if body.len() >= 3 => (3, body[2] as usize),
+        H4_EVENT if body.len() >= 2 => (2, body[1] as usize),
+        // the upper two bits are reserved
+        H4_ISO if body.len() >= 4 => (
+            4,
+            (u16::from_le_bytes([body[2], body[3]]) & 0x3fff) as usize,
+        ),
+        H4_COMMAND | H4_ACL | H4_SCO | H4_EVENT | H4_ISO => return Ok(None),
+        _ => return Err(HciError::UnknownPacketType(indicator)),
+    };
+
+    Ok(Some(1 + header_len + payload_len))
+}
+
+/// Reassembles H4 framed packets written by the host in arbitrary chunks.
+pub(crate) struct HciOutCollector {
+    data: [u8; HCI_MAX_PACKET_LEN],
+    index: usize,
+    expected: Option<usize>,
+}
+
+impl HciOutCollector {
+    pub(crate) const fn new() -> HciOutCollector {
+        HciOutCollector {
+            data: [0u8; HCI_MAX_PACKET_LEN],
+            index: 0,
+            expected: None,
+        }
+    }
+
+    pub(crate) fn is_ready(&self) -> bool {
+        self.expected == Some(self.index)
+    }
+
+    /// Appends `data` to the current packet and returns the number of bytes consumed.
+    ///
+    /// Bytes are consumed up to the end of the current packet, the caller is expected to
+    /// take the packet once it's ready and to push the remaining bytes afterwards.
+    pub(crate) fn push(&mut self, data: &[u8]) -> Result<usize, HciError> {
+        let mut consumed = 0;
+
+        for b in data {
+            if self.is_ready() {
+                break;
+            }
+
+            if self.index >= HCI_MAX_PACKET_LEN {
+                self.reset();
+                return Err(HciError::PacketTooLarge);
+            }
+
+            self.data[self.index] = *b;
+            self.index += 1;
+            consumed += 1;
+
+            if self.expected.is_none() {
+                match h4_packet_len(self.data[0], &self.data[1..self.index]) {
+                    Ok(Some(len)) if len > HCI_MAX_PACKET_LEN => {
+                        self.reset();
+                        return Err(HciError::PacketTooLarge);
+                    }
+                    Ok(expected) => self.expected = expected,
+                    Err(err) => {
+                        self.reset();
+                        return Err(err);
+                    }
+                }
+            }
+        }
+
+        Ok(consumed)
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.index = 0;
+        self.expected = None;
+    }
+
+    pub(crate) fn packet(&self) -> &[u8] {
+        &self.data[0..self.index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMAND: &[u8] = &[0x01, 0x03, 0x0c, 0x00];
+    const COMMAND_WITH_PARAMS: &[u8] = &[0x01, 0x01, 0x0c, 0x03, 0xaa, 0xbb, 0xcc];
+    const ACL: &[u8] = &[0x02, 0x40, 0x20, 0x05, 0x00, 0x01, 0x00, 0x04, 0x00, 0x0a];
+    const SCO: &[u8] = &[0x03, 0x01, 0x00, 0x02, 0x11, 0x22];
+    const EVENT: &[u8] = &[0x04, 0x0e, 0x04, 0x01, 0x03, 0x0c, 0x00];
+    const ISO: &[u8] = &[0x05, 0x01, 0x00, 0x03, 0xc0, 0x01, 0x02, 0x03];
+
+    const PACKETS: &[&[u8]] = &[COMMAND, COMMAND_WITH_PARAMS, ACL, SCO, EVENT, ISO];
+
+    #[test]
+    fn packet_length_from_header() {
+        for packet in PACKETS {
+            assert_eq!(
+                h4_packet_len(packet[0], &packet[1..]),
+                Ok(Some(packet.len()))
+            );
+        }
+    }
+
+    #[test]
+    fn packet_length_needs_the_header() {
+        assert_eq!(h4_packet_len(H4_COMMAND, &[0x03, 0x0c]), Ok(None));
+        assert_eq!(h4_packet_len(H4_ACL, &[0x40, 0x20, 0x05]), Ok(None));
+        assert_eq!(h4_packet_len(H4_SCO, &[0x01, 0x00]), Ok(None));
+        assert_eq!(h4_packet_len(H4_EVENT, &[0x0e]), Ok(None));
+        assert_eq!(h4_packet_len(H4_ISO, &[]), Ok(None));
+    }
+
+    #[test]
+    fn reassemble_in_one_chunk() {
+        for packet in PACKETS {
+            let mut collector = HciOutCollector::new();
+            assert_eq!(collector.push(packet), Ok(packet.len()));
+            assert!(collector.is_ready());
+            assert_eq!(collector.packet(), *packet);
+        }
+    }
+
+    #[test]
+    fn reassemble_split_at_every_offset() {
+        for packet in PACKETS {
+            for split in 1..packet.len() {
+                let mut collector = HciOutCollector::new();
+
+                assert_eq!(collector.push(&packet[..split]), Ok(split));
+                assert!(!collector.is_ready());
+                assert_eq!(collector.push(&packet[split..]), Ok(packet.len() - split));
+
+                assert!(collector.is_ready());
+                assert_eq!(collector.packet(), *packet);
+            }
+        }
+    }
+
+    #[test]
+    fn reassemble_byte_by_byte() {
+        for packet in PACKETS {
+            let mut collector = HciOutCollector::new();
+
+            for (i, b) in packet.iter().enumerate() {
+                assert!(!collector.is_ready());
+                assert_eq!(collector.push(&[*b]), Ok(1), "byte {}", i);
+            }
+
+            assert!(collector.is_ready());
+            assert_eq!(collector.packet(), *packet);
+        }
+    }
+
+    #[test]
+    fn stops_at_the_end_of_a_packet() {
+        let mut data = [0u8; 64];
+        data[..EVENT.len()].copy_from_slice(EVENT);
+        data[EVENT.len()..][..ACL.len()].copy_from_slice(ACL);
+        let data = &data[..EVENT.len() + ACL.len()];
+
+        for split in 1..data.len() {
+            let mut collector = HciOutCollector::new();
+            let mut packets = 0;
+            let mut chunks = [&data[..split], &data[split..]];
+
+            for chunk in chunks.iter_mut() {
+                while !chunk.is_empty() {
+                    let consumed = collector.push(chunk).unwrap();
+                    *chunk = &chunk[consumed..];
+
+                    if collector.is_ready() {
+                        let expected = if packets == 0 { EVENT } else { ACL };
+                        assert_eq!(collector.packet(), expected);
+                        packets += 1;
+                        collector.reset();
+                    }
+                }
+            }
+
+            assert_eq!(packets, 2);
+        }
+    }
+
+    #[test]
+    fn push_after_ready_consumes_nothing() {
+        let mut collector = HciOutCollector::new();
+        assert_eq!(collector.push(EVENT), Ok(EVENT.len()));
+
+        assert_eq!(collector.push(COMMAND), Ok(0));
+        assert_eq!(collector.packet(), EVENT);
+    }
+
+    #[test]
+    fn unknown_indicator() {
+        let mut collector = HciOutCollector::new();
+        assert_eq!(
+            collector.push(&[0x07, 0x01, 0x02]),
+            Err(HciError::UnknownPacketType(0x07))
+        );
+
+        // the collector starts over
+        assert_eq!(collector.push(EVENT), Ok(EVENT.len()));
+        assert_eq!(collector.packet(), EVENT);
+
+        assert_eq!(
+            HciPacket::from_h4(&[0x00, 0x01]),
+            Err(HciError::UnknownPacketType(0x00))
+        );
+    }
+
+    #[test]
+    fn oversize_packet() {
+        // ACL header announcing 0x0200 bytes of payload
+        let header = [0x02, 0x01, 0x00, 0x00, 0x02];
+
+        for split in 1..header.len() {
+            let mut collector = HciOutCollector::new();
+            assert_eq!(collector.push(&header[..split]), Ok(split));
+            assert_eq!(
+                collector.push(&header[split..]),
+                Err(HciError::PacketTooLarge)
+            );
+
+            // the collector starts over
+            assert_eq!(collector.push(COMMAND), Ok(COMMAND.len()));
+            assert_eq!(collector.packet(), COMMAND);
+        }
+
+        let data = [0u8; HCI_MAX_PACKET_LEN + 1];
+        assert_eq!(HciPacket::from_h4(&data), Err(HciError::PacketTooLarge));
+    }
+
+    #[test]
+    fn from_h4_and_write_h4() {
+        for packet in PACKETS {
+            let parsed = HciPacket::from_h4(packet).unwrap();
+            assert_eq!(parsed.indicator(), packet[0]);
+            assert_eq!(parsed.body(), &packet[1..]);
+
+            let mut buf = [0u8; HCI_MAX_PACKET_LEN];
+            assert_eq!(parsed.write_h4(&mut buf), Ok(packet.len()));
+            assert_eq!(&buf[..packet.len()], *packet);
+
+            let mut small = [0u8; 4];
+            if packet.len() > small.len() {
+                assert_eq!(parsed.write_h4(&mut small), Err(HciError::BufferTooSmall));
+            }
+        }
+    }
+
+    #[test]
+    fn from_h4_length_mismatch() {
+        assert_eq!(HciPacket::from_h4(&[]), Err(HciError::LengthMismatch));
+        assert_eq!(
+            HciPacket::from_h4(&EVENT[..EVENT.len() - 1]),
+            Err(HciError::LengthMismatch)
+        );
+        assert_eq!(
+            HciPacket::Event(&[0x0e, 0x04, 0x01]).write_h4(&mut [0u8; 16]),
+            Err(HciError::LengthMismatch)
+        );
+    }
+}
@@ -0,0 +1,504 @@
+//! Legacy advertising: AD structures and advertising parameters.
+
+use super::event::{Address, AddressKind};
+
+/// Maximum length of legacy advertising and scan response data
+pub const MAX_ADVERTISING_DATA_LEN: usize = 31;
+
+pub const LE_LIMITED_DISCOVERABLE: u8 = 0x01;
+pub const LE_GENERAL_DISCOVERABLE: u8 = 0x02;
+pub const BR_EDR_NOT_SUPPORTED: u8 = 0x04;
+
+const AD_FLAGS: u8 = 0x01;
+const AD_INCOMPLETE_SERVICE_UUIDS_16: u8 = 0x02;
+const AD_COMPLETE_SERVICE_UUIDS_16: u8 = 0x03;
+const AD_INCOMPLETE_SERVICE_UUIDS_128: u8 = 0x06;
+const AD_COMPLETE_SERVICE_UUIDS_128: u8 = 0x07;
+const AD_SHORTENED_LOCAL_NAME: u8 = 0x08;
+const AD_COMPLETE_LOCAL_NAME: u8 = 0x09;
+const AD_TX_POWER_LEVEL: u8 = 0x0a;
+const AD_SERVICE_DATA_16: u8 = 0x16;
+const AD_SERVICE_DATA_128: u8 = 0x21;
+const AD_MANUFACTURER_SPECIFIC_DATA: u8 = 0xff;
+
+/// Advertising intervals are given in units of 0.625ms
+const MIN_ADVERTISING_INTERVAL: u16 = 0x0020;
+const MAX_ADVERTISING_INTERVAL: u16 = 0x4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdvertisingError {
+    /// The data exceeds [MAX_ADVERTISING_DATA_LEN] bytes
+    TooLong,
+    /// The advertising interval, or the rotation interval of a beacon advertiser, is out of
+    /// range
+    InvalidInterval,
+    /// No advertising channel is enabled
+    InvalidChannelMap,
+}
+
+/// An AD structure. 128 bit UUIDs are given least significant byte first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdStructure<'a> {
+    /// Any combination of [LE_LIMITED_DISCOVERABLE], [LE_GENERAL_DISCOVERABLE] and
+    /// [BR_EDR_NOT_SUPPORTED]
+    Flags(u8),
+    IncompleteServiceUuids16(Uuids16<'a>),
+    ServiceUuids16(Uuids16<'a>),
+    IncompleteServiceUuids128(&'a [[u8; 16]]),
+    ServiceUuids128(&'a [[u8; 16]]),
+    ShortenedLocalName(&'a str),
+    CompleteLocalName(&'a str),
+    /// TX power in dBm
+    TxPowerLevel(i8),
+    ServiceData16 {
+        uuid: u16,
+        data: &'a [u8],
+    },
+    ServiceData128 {
+        uuid: [u8; 16],
+        data: &'a [u8],
+    },
+    ManufacturerSpecificData {
+        company_identifier: u16,
+        data: &'a [u8],
+    },
+    /// Any other AD type
+    Unknown {
+        ad_type: u8,
+        data: &'a [u8],
+    },
+}
+
+impl<'a> AdStructure<'a> {
+    /// Length including the length and AD type bytes
+    pub fn encoded_len(&self) -> usize {
+        2 + match self {
+            AdStructure::Flags(_) => 1,
+            AdStructure::IncompleteServiceUuids16(uuids) | AdStructure::ServiceUuids16(uuids) => {
+                uuids.len() * 2
+            }
+            AdStructure::IncompleteServiceUuids128(uuids) | AdStructure::ServiceUuids128(uuids) => {
+                uuids.len() * 16
+            }
+            AdStructure::ShortenedLocalName(name) | AdStructure::CompleteLocalName(name) => {
+                name.len()
+            }
+            AdStructure::TxPowerLevel(_) => 1,
+            AdStructure::ServiceData16 { data, .. } => 2 + data.len(),
+            AdStructure::ServiceData128 { data, .. } => 16 + data.len(),
+            AdStructure::ManufacturerSpecificData { data, .. } => 2 + data.len(),
+            AdStructure::Unknown { data, .. } => data.len(),
+        }
+    }
+
+    fn ad_type(&self) -> u8 {
+        match self {
+            AdStructure::Flags(_) => AD_FLAGS,
+            AdStructure::IncompleteServiceUuids16(_) => AD_INCOMPLETE_SERVICE_UUIDS_16,
+            AdStructure::ServiceUuids16(_) => AD_COMPLETE_SERVICE_UUIDS_16,
+            AdStructure::IncompleteServiceUuids128(_) => AD_INCOMPLETE_SERVICE_UUIDS_128,
+            AdStructure::ServiceUuids128(_) => AD_COMPLETE_SERVICE_UUIDS_128,
+            AdStructure::ShortenedLocalName(_) => AD_SHORTENED_LOCAL_NAME,
+            AdStructure::CompleteLocalName(_) => AD_COMPLETE_LOCAL_NAME,
+            AdStructure::TxPowerLevel(_) => AD_TX_POWER_LEVEL,
+            AdStructure::ServiceData16 { .. } => AD_SERVICE_DATA_16,
+            AdStructure::ServiceData128 { .. } => AD_SERVICE_DATA_128,
+            AdStructure::ManufacturerSpecificData { .. } => AD_MANUFACTURER_SPECIFIC_DATA,
+            AdStructure::Unknown { ad_type, .. } => *ad_type,
+        }
+    }
+
+    /// Encodes the structure into `buf` which has to be at least [AdStructure::encoded_len]
+    /// bytes.
+    fn write(&self, buf: &mut [u8]) {
+        buf[0] = (self.encoded_len() - 1) as u8;
+        buf[1] = self.ad_type();
+        let data = &mut buf[2..];
+
+        match self {
+            AdStructure::Flags(flags) => data[0] = *flags,
+            AdStructure::IncompleteServiceUuids16(uuids) | AdStructure::ServiceUuids16(uuids) => {
+                for (i, uuid) in (*uuids).enumerate() {
+                    data[i * 2..][..2].copy_from_slice(&uuid.to_le_bytes());
+                }
+            }
+            AdStructure::IncompleteServiceUuids128(uuids) | AdStructure::ServiceUuids128(uuids) => {
+                for (i, uuid) in uuids.iter().enumerate() {
+                    data[i * 16..][..16].copy_from_slice(uuid);
+                }
+            }
+            AdStructure::ShortenedLocalName(name) | AdStructure::CompleteLocalName(name) => {
+                data[..name.len()].copy_from_slice(name.as_bytes());
+            }
+            AdStructure::TxPowerLevel(power) => data[0] = *power as u8,
+            AdStructure::ServiceData16 { uuid, data: value } => {
+                data[..2].copy_from_slice(&uuid.to_le_bytes());
+                data[2..][..value.len()].copy_from_slice(value);
+            }
+            AdStructure::ServiceData128 { uuid, data: value } => {
+                data[..16].copy_from_slice(uuid);
+                data[16..][..value.len()].copy_from_slice(value);
+            }
+            AdStructure::ManufacturerSpecificData {
+                company_identifier,
+                data: value,
+            } => {
+                data[..2].copy_from_slice(&company_identifier.to_le_bytes());
+                data[2..][..value.len()].copy_from_slice(value);
+            }
+            AdStructure::Unknown { data: value, .. } => {
+                data[..value.len()].copy_from_slice(value);
+            }
+        }
+    }
+}
+
+impl<'a> AdStructure<'a> {
+    /// Decodes a single structure given its AD type and data
+    fn decode(ad_type: u8, data: &'a [u8]) -> AdStructure<'a> {
+        let unknown = AdStructure::Unknown { ad_type, data };
+
+        match ad_type {
+            AD_FLAGS if data.len() == 1 => AdStructure::Flags(data[0]),
+            AD_INCOMPLETE_SERVICE_UUIDS_16 | AD_COMPLETE_SERVICE_UUIDS_16
+                if data.len() % 2 == 0 =>
+            {
+                let uuids = Uuids16::encoded(data);
+
+                if ad_type == AD_COMPLETE_SERVICE_UUIDS_16 {
+                    AdStructure::ServiceUuids16(uuids)
+                } else {
+                    AdStructure::IncompleteServiceUuids16(uuids)
+                }
+            }
+            AD_INCOMPLETE_SERVICE_UUIDS_128 | AD_COMPLETE_SERVICE_UUIDS_128 => {
+                let uuids = match as_arrays::<16>(data) {
+                    Some(uuids) => uuids,
+                    None => return unknown,
+                };
+
+                if ad_type == AD_COMPLETE_SERVICE_UUIDS_128 {
+                    AdStructure::ServiceUuids128(uuids)
+                } else {
+                    AdStructure::IncompleteServiceUuids128(uuids)
+                }
+            }
+            AD_SHORTENED_LOCAL_NAME | AD_COMPLETE_LOCAL_NAME => {
+                let name = match core::str::from_utf8(data) {
+                    Ok(name) => name,
+                    Err(_) => return unknown,
+                };
+
+                if ad_type == AD_COMPLETE_LOCAL_NAME {
+                    AdStructure::CompleteLocalName(name)
+                } else {
+                    AdStructure::ShortenedLocalName(name)
+                }
+            }
+            AD_TX_POWER_LEVEL if data.len() == 1 => AdStructure::TxPowerLevel(data[0] as i8),
+            AD_SERVICE_DATA_16 if data.len() >= 2 => AdStructure::ServiceData16 {
+                uuid: u16::from_le_bytes([data[0], data[1]]),
+                data: &data[2..],
+            },
+            AD_SERVICE_DATA_128 if data.len() >= 16 => {
+                let mut uuid = [0u8; 16];
+                uuid.copy_from_slice(&data[..16]);
+                AdStructure::ServiceData128 {
+                    uuid,
+                    data: &data[16..],
+                }
+            }
+            AD_MANUFACTURER_SPECIFIC_DATA if data.len() >= 2 => {
+                AdStructure::ManufacturerSpecificData {
+                    company_identifier: u16::from_le_bytes([data[0], data[1]]),
+                    data: &data[2..],
+                }
+            }
+            _ => unknown,
+        }
+    }
+}
+
+/// Reinterprets `data` as a slice of arrays if the length fits
+fn as_arrays<const N: usize>(data: &[u8]) -> Option<&[[u8; N]]> {
+    if data.len() % N != 0 {
+        return None;
+    }
+
+    // arrays of u8 have no alignment requirements
+    Some(unsafe { core::slice::from_raw_parts(data.as_ptr() as *const [u8; N], data.len() / N) })
+}
+
+/// A list of 16 bit UUIDs, iterated in order.
+///
+/// Advertised UUIDs are given as numbers via [Uuids16::new], decoded ones refer to the
+/// received data.
+#[derive(Debug, Clone, Copy)]
+pub struct Uuids16<'a> {
+    uuids: Uuids16Repr<'a>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Uuids16Repr<'a> {
+    Values(&'a [u16]),
+    /// Least significant byte first, an even number of bytes
+    Encoded(&'a [u8]),
+}
+
+impl<'a> Uuids16<'a> {
+    pub const fn new(uuids: &'a [u16]) -> Uuids16<'a> {
+        Uuids16 {
+            uuids: Uuids16Repr::Values(uuids),
+        }
+    }
+
+    fn encoded(data: &'a [u8]) -> Uuids16<'a> {
+        Uuids16 {
+            uuids: Uuids16Repr::Encoded(data),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self.uuids {
+            Uuids16Repr::Values(uuids) => uuids.len(),
+            Uuids16Repr::Encoded(data) => data.len() / 2,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, uuid: u16) -> bool {
+        let mut uuids = *self;
+        uuids.any(|u| u == uuid)
+    }
+}
+
+impl<'a> From<&'a [u16]> for Uuids16<'a> {
+    fn from(uuids: &'a [u16]) -> Self {
+        Uuids16::new(uuids)
+    }
+}
+
+impl<'a> PartialEq for Uuids16<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.zip(*other).all(|(a, b)| a == b)
+    }
+}
+
+impl<'a> Eq for Uuids16<'a> {}
+
+impl<'a> Iterator for Uuids16<'a> {
+    type Item = u16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.uuids {
+            Uuids16Repr::Values(uuids) => {
+                let (uuid, rest) = uuids.split_first()?;
+                *uuids = rest;
+                Some(*uuid)
+            }
+            Uuids16Repr::Encoded(data) => {
+                if data.len() < 2 {
+                    return None;
+                }
+
+                let uuid = u16::from_le_bytes([data[0], data[1]]);
+                *data = &data[2..];
+                Some(uuid)
+            }
+        }
+    }
+}
+
+/// Iterates over the AD structures in advertising or scan response data.
+///
+/// Iteration stops at the first zero length structure (the remaining data is padding) or
+/// at a structure exceeding the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdStructures<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> AdStructures<'a> {
+    pub fn new(data: &'a [u8]) -> AdStructures<'a> {
+        AdStructures { data }
+    }
+}
+
+impl<'a> Iterator for AdStructures<'a> {
+    type Item = AdStructure<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = *self.data.first()? as usize;
+        if len == 0 || len + 1 > self.data.len() {
+            self.data = &[];
+            return None;
+        }
+
+        let structure = AdStructure::decode(self.data[1], &self.data[2..][..len - 1]);
+        self.data = &self.data[len + 1..];
+
+        Some(structure)
+    }
+}
+
+/// Legacy advertising or scan response data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdvertisingData {
+    data: [u8; MAX_ADVERTISING_DATA_LEN],
+    len: usize,
+}
+
+impl AdvertisingData {
+    pub const fn new() -> AdvertisingData {
+        AdvertisingData {
+            data: [0u8; MAX_ADVERTISING_DATA_LEN],
+            len: 0,
+        }
+    }
+
+    /// Encodes all given structures
+    pub fn from_structures(
+        structures: &[AdStructure],
+    ) -> Result<AdvertisingData, AdvertisingError> {
+        let mut data = AdvertisingData::new();
+        for structure in structures {
+            data.push(structure)?;
+        }
+
+        Ok(data)
+    }
+
+    /// Appends a structure, fails if it doesn't fit anymore
+    pub fn push(&mut self, structure: &AdStructure) -> Result<(), AdvertisingError> {
+        let len = structure.encoded_len();
+        if self.len + len > MAX_ADVERTISING_DATA_LEN {
+            return Err(AdvertisingError::TooLong);
+        }
+
+        structure.write(&mut self.data[self.len..]);
+        self.len += len;
+
+        Ok(())
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    pub fn structures(&self) -> AdStructures<'_> {
+        AdStructures::new(self.as_slice())
+    }
+}
+
+impl Default for AdvertisingData {
+    fn default() -> Self {
+        AdvertisingData::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdvertisingMode {
+    /// Connectable and scannable (ADV_IND)
+    Connectable,
+    /// Connectable by the given peer only (ADV_DIRECT_IND, low duty cycle)
+    DirectedConnectable(Address),
+    /// Scannable but not connectable (ADV_SCAN_IND)
+    Scannable,
+    /// Neither connectable nor scannable (ADV_NONCONN_IND)
+    NonConnectable,
+}
+
+impl AdvertisingMode {
+    fn advertising_type(&self) -> u8 {
+        match self {
+            AdvertisingMode::Connectable => 0x00,
+            AdvertisingMode::DirectedConnectable(_) => 0x04,
+            AdvertisingMode::Scannable => 0x02,
+            AdvertisingMode::NonConnectable => 0x03,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdvertisingParameters {
+    /// Minimum interval in units of 0.625ms
+    pub interval_min: u16,
+    /// Maximum interval in units of 0.625ms
+    pub interval_max: u16,
+    pub mode: AdvertisingMode,
+    pub own_address_kind: AddressKind,
+    /// Bit 0 to 2 enable channel 37, 38 and 39
+    pub channel_map: u8,
+    /// Allow scan and connection requests from devices in the filter accept list only
+    pub filter_policy: u8,
+}
+
+impl Default for AdvertisingParameters {
+    fn default() -> Self {
+        AdvertisingParameters {
+            // 1.28s which is the controller's default
+            interval_min: 0x0800,
+            interval_max: 0x0800,
+            mode: AdvertisingMode::Connectable,
+            own_address_kind: AddressKind::Public,
+            channel_map: 0b111,
+            filter_policy: 0,
+        }
+    }
+}
+
+impl AdvertisingParameters {
+    /// Sets the minimum and maximum interval to the same value given in milliseconds, it's
+    /// clamped to the range allowed by the spec (20ms to 10.24s)
+    pub fn with_interval_ms(self, interval: u32) -> AdvertisingParameters {
+        let interval = (interval as u64 * 1000 / 625).clamp(
+            MIN_ADVERTISING_INTERVAL as u64,
+            MAX_ADVERTISING_INTERVAL as u64,
+        ) as u16;
+
+        AdvertisingParameters {
+            interval_min: interval,
+            interval_max: interval,
+            ..self
+        }
+    }
+
+    pub fn with_mode(self, mode: AdvertisingMode) -> AdvertisingParameters {
+        AdvertisingParameters { mode, ..self }
+    }
+
+    /// Encodes the parameters of the LE Set Advertising Parameters command
+    pub(crate) fn encode(&self) -> Result<[u8; 15], AdvertisingError> {
+        // directed advertising with a low duty cycle has the same interval limits
+        if self.interval_min < MIN_ADVERTISING_INTERVAL
+            || self.interval_max > MAX_ADVERTISING_INTERVAL
+            || self.interval_min > self.interval_max
+        {
+            return Err(AdvertisingError::InvalidInterval);
+        }
+
+        if self.channel_map & 0b111 == 0 {
+            return Err(AdvertisingError::InvalidChannelMap);
+        }
+
+        let peer = match self.mode {
+            AdvertisingMode::DirectedConnectable(peer) => peer,
+            _ => Address::default(),
+        };
+
+        let mut params = [0u8; 15];
+        params[0..2].copy_from_slice(&self.interval_min.to_le_bytes());
+        params[2..4].copy_from_slice(&self.interval_max.to_le_bytes());
+        params[4] = self.mode.advertising_type();
+        params[5] = self.own_address_kind.to_u8();
+        params[6] = peer.kind.to_u8();
+        params[7..13].copy_from_slice(&peer.addr);
+        params[13] = self.channel_map & 0b111;
+        params[14] = self.filter_policy;
+
+        Ok(params)
+    }
+}
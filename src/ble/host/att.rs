@@ -0,0 +1,274 @@
+//! Attribute protocol PDUs.
+
+use super::event::read_u16;
+
+pub const ATT_ERROR_RSP: u8 = 0x01;
+pub const ATT_EXCHANGE_MTU_REQ: u8 = 0x02;
+pub const ATT_EXCHANGE_MTU_RSP: u8 = 0x03;
+pub const ATT_FIND_INFORMATION_REQ: u8 = 0x04;
+pub const ATT_FIND_INFORMATION_RSP: u8 = 0x05;
+pub const ATT_FIND_BY_TYPE_VALUE_REQ: u8 = 0x06;
+pub const ATT_FIND_BY_TYPE_VALUE_RSP: u8 = 0x07;
+pub const ATT_READ_BY_TYPE_REQ: u8 = 0x08;
+pub const ATT_READ_BY_TYPE_RSP: u8 = 0x09;
+pub const ATT_READ_REQ: u8 = 0x0a;
+pub const ATT_READ_RSP: u8 = 0x0b;
+pub const ATT_READ_BLOB_REQ: u8 = 0x0c;
+pub const ATT_READ_BLOB_RSP: u8 = 0x0d;
+pub const ATT_READ_BY_GROUP_TYPE_REQ: u8 = 0x10;
+pub const ATT_READ_BY_GROUP_TYPE_RSP: u8 = 0x11;
+pub const ATT_WRITE_REQ: u8 = 0x12;
+pub const ATT_WRITE_RSP: u8 = 0x13;
+pub const ATT_HANDLE_VALUE_NTF: u8 = 0x1b;
+pub const ATT_HANDLE_VALUE_IND: u8 = 0x1d;
+pub const ATT_HANDLE_VALUE_CFM: u8 = 0x1e;
+pub const ATT_WRITE_CMD: u8 = 0x52;
+
+/// The MTU every connection starts with
+pub const ATT_DEFAULT_MTU: u16 = 23;
+
+/// Largest MTU we agree to, this is limited by the L2CAP buffers
+pub const ATT_MAX_MTU: u16 = super::l2cap::MAX_L2CAP_PAYLOAD as u16;
+
+/// Maximum length of an attribute value
+pub const ATT_MAX_VALUE_LEN: usize = 512;
+
+pub const PRIMARY_SERVICE: Uuid = Uuid::Uuid16(0x2800);
+pub const SECONDARY_SERVICE: Uuid = Uuid::Uuid16(0x2801);
+pub const CHARACTERISTIC: Uuid = Uuid::Uuid16(0x2803);
+pub const CLIENT_CHARACTERISTIC_CONFIGURATION: Uuid = Uuid::Uuid16(0x2902);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AttErrorCode {
+    InvalidHandle = 0x01,
+    ReadNotPermitted = 0x02,
+    WriteNotPermitted = 0x03,
+    InvalidPdu = 0x04,
+    InsufficientAuthentication = 0x05,
+    RequestNotSupported = 0x06,
+    InvalidOffset = 0x07,
+    InsufficientAuthorization = 0x08,
+    AttributeNotFound = 0x0a,
+    AttributeNotLong = 0x0b,
+    InvalidAttributeValueLength = 0x0d,
+    UnlikelyError = 0x0e,
+    InsufficientEncryption = 0x0f,
+    UnsupportedGroupType = 0x10,
+    InsufficientResources = 0x11,
+}
+
+/// Bluetooth base UUID 0000xxxx-0000-1000-8000-00805F9B34FB, least significant byte first
+const BASE_UUID: [u8; 16] = [
+    0xfb, 0x34, 0x9b, 0x5f, 0x80, 0x00, 0x00, 0x80, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// A UUID, 128 bit UUIDs are stored least significant byte first as sent over the air
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Uuid {
+    Uuid16(u16),
+    Uuid128([u8; 16]),
+}
+
+impl Uuid {
+    pub fn from_slice(data: &[u8]) -> Option<Uuid> {
+        match data.len() {
+            2 => Some(Uuid::Uuid16(read_u16(data, 0))),
+            16 => {
+                let mut uuid = [0u8; 16];
+                uuid.copy_from_slice(data);
+                Some(Uuid::Uuid128(uuid))
+            }
+            _ => None,
+        }
+    }
+
+    /// Length of the UUID when sent over the air
+    pub fn len(&self) -> usize {
+        match self {
+            Uuid::Uuid16(_) => 2,
+            Uuid::Uuid128(_) => 16,
+        }
+    }
+
+    /// Never true, there are no empty UUIDs
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Writes the UUID into `buf` and returns the number of bytes written.
+    pub fn write(&self, buf: &mut [u8]) -> usize {
+        match self {
+            Uuid::Uuid16(uuid) => buf[0..2].copy_from_slice(&uuid.to_le_bytes()),
+            Uuid::Uuid128(uuid) => buf[0..16].copy_from_slice(uuid),
+        }
+
+        self.len()
+    }
+
+    /// Returns the 128 bit form of the UUID
+    pub fn as_uuid128(&self) -> [u8; 16] {
+        match self {
+            Uuid::Uuid16(uuid) => {
+                let mut full = BASE_UUID;
+                full[12..14].copy_from_slice(&uuid.to_le_bytes());
+                full
+            }
+            Uuid::Uuid128(uuid) => *uuid,
+        }
+    }
+
+    /// Compares UUIDs regardless of their representation
+    pub fn matches(&self, other: &Uuid) -> bool {
+        self.as_uuid128() == other.as_uuid128()
+    }
+}
+
+/// Requests, commands and confirmations sent by a client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttRequest<'a> {
+    ExchangeMtu {
+        mtu: u16,
+    },
+    FindInformation {
+        start: u16,
+        end: u16,
+    },
+    FindByTypeValue {
+        start: u16,
+        end: u16,
+        att_type: u16,
+        value: &'a [u8],
+    },
+    ReadByType {
+        start: u16,
+        end: u16,
+        att_type: Uuid,
+    },
+    Read {
+        handle: u16,
+    },
+    ReadBlob {
+        handle: u16,
+        offset: u16,
+    },
+    ReadByGroupType {
+        start: u16,
+        end: u16,
+        group_type: Uuid,
+    },
+    Write {
+        handle: u16,
+        value: &'a [u8],
+    },
+    WriteCommand {
+        handle: u16,
+        value: &'a [u8],
+    },
+    Confirmation,
+    /// Anything else, the opcode is kept to be able to reject requests
+    Unsupported(u8),
+}
+
+impl<'a> AttRequest<'a> {
+    pub fn parse(pdu: &'a [u8]) -> Result<AttRequest<'a>, AttErrorCode> {
+        if pdu.is_empty() {
+            return Err(AttErrorCode::InvalidPdu);
+        }
+
+        let opcode = pdu[0];
+        let data = &pdu[1..];
+
+        let check = |len: usize| {
+            if data.len() < len {
+                Err(AttErrorCode::InvalidPdu)
+            } else {
+                Ok(())
+            }
+        };
+
+        Ok(match opcode {
+            ATT_EXCHANGE_MTU_REQ => {
+                check(2)?;
+                AttRequest::ExchangeMtu {
+                    mtu: read_u16(data, 0),
+                }
+            }
+            ATT_FIND_INFORMATION_REQ => {
+                check(4)?;
+                AttRequest::FindInformation {
+                    start: read_u16(data, 0),
+                    end: read_u16(data, 2),
+                }
+            }
+            ATT_FIND_BY_TYPE_VALUE_REQ => {
+                check(6)?;
+                AttRequest::FindByTypeValue {
+                    start: read_u16(data, 0),
+                    end: read_u16(data, 2),
+                    att_type: read_u16(data, 4),
+                    value: &data[6..],
+                }
+            }
+            ATT_READ_BY_TYPE_REQ => {
+                check(6)?;
+                AttRequest::ReadByType {
+                    start: read_u16(data, 0),
+                    end: read_u16(data, 2),
+                    att_type: Uuid::from_slice(&data[4..]).ok_or(AttErrorCode::InvalidPdu)?,
+                }
+            }
+            ATT_READ_REQ => {
+                check(2)?;
+                AttRequest::Read {
+                    handle: read_u16(data, 0),
+                }
+            }
+            ATT_READ_BLOB_REQ => {
+                check(4)?;
+                AttRequest::ReadBlob {
+                    handle: read_u16(data, 0),
+                    offset: read_u16(data, 2),
+                }
+            }
+            ATT_READ_BY_GROUP_TYPE_REQ => {
+                check(6)?;
+                AttRequest::ReadByGroupType {
+                    start: read_u16(data, 0),
+                    end: read_u16(data, 2),
+                    group_type: Uuid::from_slice(&data[4..]).ok_or(AttErrorCode::InvalidPdu)?,
+                }
+            }
+            ATT_WRITE_REQ => {
+                check(2)?;
+                AttRequest::Write {
+                    handle: read_u16(data, 0),
+                    value: &data[2..],
+                }
+            }
+            ATT_WRITE_CMD => {
+                check(2)?;
+                AttRequest::WriteCommand {
+                    handle: read_u16(data, 0),
+                    value: &data[2..],
+                }
+            }
+            ATT_HANDLE_VALUE_CFM => AttRequest::Confirmation,
+            _ => AttRequest::Unsupported(opcode),
+        })
+    }
+
+    /// Commands (and confirmations) never get a response, not even an error
+    pub fn is_command(opcode: u8) -> bool {
+        opcode & 0x40 != 0 || opcode == ATT_HANDLE_VALUE_CFM
+    }
+}
+
+/// Writes an Error Response into `buf` and returns its length.
+pub fn write_error(buf: &mut [u8], request: u8, handle: u16, code: AttErrorCode) -> usize {
+    buf[0] = ATT_ERROR_RSP;
+    buf[1] = request;
+    buf[2..4].copy_from_slice(&handle.to_le_bytes());
+    buf[4] = code as u8;
+    5
+}
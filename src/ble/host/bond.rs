@@ -0,0 +1,124 @@
+//! Keys exchanged during pairing and where they are kept.
+
+use super::crypto;
+use super::event::{Address, AddressKind};
+
+/// A long term key and the values identifying it in the LTK request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LtkInfo {
+    pub ltk: [u8; 16],
+    pub ediv: u16,
+    pub rand: [u8; 8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Bond {
+    /// Identity address of the peer, the address it connected with if it didn't distribute one
+    pub peer: Address,
+    /// The key the peer encrypts with when it is central
+    pub local_ltk: Option<LtkInfo>,
+    /// The key we encrypt with when we are central
+    pub peer_ltk: Option<LtkInfo>,
+    /// Identity resolving key of the peer
+    pub irk: Option<[u8; 16]>,
+    /// Pairing was protected against man-in-the-middle attacks
+    pub authenticated: bool,
+    /// The keys were created by LE Secure Connections pairing
+    pub secure_connections: bool,
+    /// Size of the keys in bytes
+    pub key_size: u8,
+}
+
+impl Bond {
+    /// Returns true if the address is the peer's, resolvable private addresses are resolved
+    /// with its IRK.
+    pub fn matches(&self, address: &Address) -> bool {
+        if self.peer == *address {
+            return true;
+        }
+
+        let irk = match &self.irk {
+            Some(irk) => irk,
+            None => return false,
+        };
+
+        // resolvable private addresses have 0b01 as the most significant bits
+        if address.kind != AddressKind::Random || address.addr[5] & 0xc0 != 0x40 {
+            return false;
+        }
+
+        let mut prand = [0u8; 3];
+        prand.copy_from_slice(&address.addr[3..]);
+        crypto::ah(irk, &prand) == address.addr[..3]
+    }
+}
+
+/// Keeps the bonds, implement it to persist them.
+pub trait BondStore {
+    /// Returns the bond of the peer using the given address
+    fn find(&self, address: &Address) -> Option<Bond>;
+
+    /// Stores a bond, replacing an existing one with the same peer
+    fn store(&mut self, bond: Bond);
+
+    /// Forgets the bond of the peer using the given address
+    fn remove(&mut self, address: &Address);
+}
+
+/// Keeps up to `N` bonds in RAM, the oldest one gets replaced when it's full.
+pub struct MemoryBondStore<const N: usize> {
+    bonds: [Option<Bond>; N],
+    next: usize,
+}
+
+impl<const N: usize> MemoryBondStore<N> {
+    pub const fn new() -> MemoryBondStore<N> {
+        MemoryBondStore {
+            bonds: [None; N],
+            next: 0,
+        }
+    }
+}
+
+impl<const N: usize> Default for MemoryBondStore<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> BondStore for MemoryBondStore<N> {
+    fn find(&self, address: &Address) -> Option<Bond> {
+        self.bonds
+            .iter()
+            .flatten()
+            .find(|bond| bond.matches(address))
+            .copied()
+    }
+
+    fn store(&mut self, bond: Bond) {
+        let index = self
+            .bonds
+            .iter()
+            .position(|b| matches!(b, Some(b) if b.peer == bond.peer))
+            .or_else(|| self.bonds.iter().position(|b| b.is_none()));
+
+        let index = match index {
+            Some(index) => index,
+            None => {
+                let index = self.next;
+                self.next = (self.next + 1) % N;
+                index
+            }
+        };
+
+        self.bonds[index] = Some(bond);
+    }
+
+    fn remove(&mut self, address: &Address) {
+        for bond in self.bonds.iter_mut() {
+            if matches!(bond, Some(b) if b.matches(address)) {
+                *bond = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! HCI command opcodes and encoding.
+
+pub const fn opcode(ogf: u16, ocf: u16) -> u16 {
+    (ogf << 10) | ocf
+}
+
+const OGF_LINK_CONTROL: u16 = 0x01;
+const OGF_CONTROLLER: u16 = 0x03;
+const OGF_INFORMATIONAL: u16 = 0x04;
+const OGF_LE: u16 = 0x08;
+
+pub const DISCONNECT: u16 = opcode(OGF_LINK_CONTROL, 0x0006);
+
+pub const SET_EVENT_MASK: u16 = opcode(OGF_CONTROLLER, 0x0001);
+pub const RESET: u16 = opcode(OGF_CONTROLLER, 0x0003);
+
+pub const READ_BD_ADDR: u16 = opcode(OGF_INFORMATIONAL, 0x0009);
+
+pub const LE_SET_EVENT_MASK: u16 = opcode(OGF_LE, 0x0001);
+pub const LE_READ_BUFFER_SIZE: u16 = opcode(OGF_LE, 0x0002);
+pub const LE_SET_ADVERTISING_PARAMETERS: u16 = opcode(OGF_LE, 0x0006);
+pub const LE_SET_ADVERTISING_DATA: u16 = opcode(OGF_LE, 0x0008);
+pub const LE_SET_SCAN_RESPONSE_DATA: u16 = opcode(OGF_LE, 0x0009);
+pub const LE_SET_ADVERTISE_ENABLE: u16 = opcode(OGF_LE, 0x000a);
+pub const LE_SET_SCAN_PARAMETERS: u16 = opcode(OGF_LE, 0x000b);
+pub const LE_SET_SCAN_ENABLE: u16 = opcode(OGF_LE, 0x000c);
+pub const LE_CREATE_CONNECTION: u16 = opcode(OGF_LE, 0x000d);
+pub const LE_CREATE_CONNECTION_CANCEL: u16 = opcode(OGF_LE, 0x000e);
+pub const LE_CONNECTION_UPDATE: u16 = opcode(OGF_LE, 0x0013);
+pub const LE_ENABLE_ENCRYPTION: u16 = opcode(OGF_LE, 0x0019);
+pub const LE_LONG_TERM_KEY_REQUEST_REPLY: u16 = opcode(OGF_LE, 0x001a);
+pub const LE_LONG_TERM_KEY_REQUEST_NEGATIVE_REPLY: u16 = opcode(OGF_LE, 0x001b);
+pub const LE_READ_LOCAL_P256_PUBLIC_KEY: u16 = opcode(OGF_LE, 0x0025);
+pub const LE_GENERATE_DHKEY: u16 = opcode(OGF_LE, 0x0026);
+
+/// Maximum length of the parameters of a command
+pub const MAX_PARAMETERS_LEN: usize = 255;
+
+/// Writes the command (without the H4 indicator) into `buf` and returns the length.
+pub(crate) fn write_command(buf: &mut [u8], opcode: u16, params: &[u8]) -> usize {
+    buf[0..2].copy_from_slice(&opcode.to_le_bytes());
+    buf[2] = params.len() as u8;
+    buf[3..][..params.len()].copy_from_slice(params);
+
+    params.len() + 3
+}
@@ -0,0 +1,149 @@
+//! Parameters for creating and updating connections in the central role.
+
+use super::event::{Address, AddressKind};
+
+/// Connection intervals are given in units of 1.25ms
+const MIN_CONNECTION_INTERVAL: u16 = 0x0006;
+const MAX_CONNECTION_INTERVAL: u16 = 0x0c80;
+const MAX_LATENCY: u16 = 0x01f3;
+/// Supervision timeouts are given in units of 10ms
+const MIN_SUPERVISION_TIMEOUT: u16 = 0x000a;
+const MAX_SUPERVISION_TIMEOUT: u16 = 0x0c80;
+/// Scan intervals and windows are given in units of 0.625ms
+const MIN_SCAN_INTERVAL: u16 = 0x0004;
+const MAX_SCAN_INTERVAL: u16 = 0x4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionError {
+    InvalidInterval,
+    InvalidLatency,
+    /// The supervision timeout is out of range or too short for the interval and latency
+    InvalidSupervisionTimeout,
+    InvalidScanTiming,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionParameters {
+    /// Minimum connection interval in units of 1.25ms
+    pub interval_min: u16,
+    /// Maximum connection interval in units of 1.25ms
+    pub interval_max: u16,
+    /// Number of connection events the peripheral may skip
+    pub latency: u16,
+    /// Supervision timeout in units of 10ms
+    pub supervision_timeout: u16,
+    /// Expected connection event length in units of 0.625ms
+    pub min_ce_length: u16,
+    pub max_ce_length: u16,
+}
+
+impl Default for ConnectionParameters {
+    fn default() -> Self {
+        ConnectionParameters {
+            // 30ms to 50ms
+            interval_min: 0x0018,
+            interval_max: 0x0028,
+            latency: 0,
+            // 4s
+            supervision_timeout: 0x0190,
+            min_ce_length: 0,
+            max_ce_length: 0,
+        }
+    }
+}
+
+impl ConnectionParameters {
+    fn validate(&self) -> Result<(), ConnectionError> {
+        if self.interval_min < MIN_CONNECTION_INTERVAL
+            || self.interval_max > MAX_CONNECTION_INTERVAL
+            || self.interval_min > self.interval_max
+        {
+            return Err(ConnectionError::InvalidInterval);
+        }
+
+        if self.latency > MAX_LATENCY {
+            return Err(ConnectionError::InvalidLatency);
+        }
+
+        // the timeout has to be longer than (1 + latency) * interval_max * 2
+        if self.supervision_timeout < MIN_SUPERVISION_TIMEOUT
+            || self.supervision_timeout > MAX_SUPERVISION_TIMEOUT
+            || self.supervision_timeout as u32 * 4
+                <= (1 + self.latency as u32) * self.interval_max as u32
+        {
+            return Err(ConnectionError::InvalidSupervisionTimeout);
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, buf: &mut [u8]) {
+        buf[0..2].copy_from_slice(&self.interval_min.to_le_bytes());
+        buf[2..4].copy_from_slice(&self.interval_max.to_le_bytes());
+        buf[4..6].copy_from_slice(&self.latency.to_le_bytes());
+        buf[6..8].copy_from_slice(&self.supervision_timeout.to_le_bytes());
+        buf[8..10].copy_from_slice(&self.min_ce_length.to_le_bytes());
+        buf[10..12].copy_from_slice(&self.max_ce_length.to_le_bytes());
+    }
+
+    /// Encodes the parameters of the LE Connection Update command
+    pub(crate) fn encode_update(&self, handle: u16) -> Result<[u8; 14], ConnectionError> {
+        self.validate()?;
+
+        let mut params = [0u8; 14];
+        params[0..2].copy_from_slice(&handle.to_le_bytes());
+        self.write(&mut params[2..]);
+
+        Ok(params)
+    }
+}
+
+/// How to look for the peer when connecting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InitiatorParameters {
+    /// Scan interval in units of 0.625ms
+    pub scan_interval: u16,
+    /// Scan window in units of 0.625ms
+    pub scan_window: u16,
+    pub own_address_kind: AddressKind,
+    pub connection: ConnectionParameters,
+}
+
+impl Default for InitiatorParameters {
+    fn default() -> Self {
+        InitiatorParameters {
+            // 60ms, 30ms
+            scan_interval: 0x0060,
+            scan_window: 0x0030,
+            own_address_kind: AddressKind::Public,
+            connection: ConnectionParameters::default(),
+        }
+    }
+}
+
+impl InitiatorParameters {
+    /// Encodes the parameters of the LE Create Connection command
+    pub(crate) fn encode(&self, peer: &Address) -> Result<[u8; 25], ConnectionError> {
+        if self.scan_interval < MIN_SCAN_INTERVAL
+            || self.scan_interval > MAX_SCAN_INTERVAL
+            || self.scan_window < MIN_SCAN_INTERVAL
+            || self.scan_window > self.scan_interval
+        {
+            return Err(ConnectionError::InvalidScanTiming);
+        }
+
+        self.connection.validate()?;
+
+        let mut params = [0u8; 25];
+        params[0..2].copy_from_slice(&self.scan_interval.to_le_bytes());
+        params[2..4].copy_from_slice(&self.scan_window.to_le_bytes());
+        // connect to the given peer, not the filter accept list
+        params[4] = 0;
+        params[5] = peer.kind.to_u8();
+        params[6..12].copy_from_slice(&peer.addr);
+        params[12] = self.own_address_kind.to_u8();
+        self.connection.write(&mut params[13..]);
+
+        Ok(params)
+    }
+}
@@ -0,0 +1,485 @@
+//! Cryptographic toolbox of the security manager.
+//!
+//! AES-128 and AES-CMAC work on big endian values as in their specifications. The SMP
+//! functions take and return values least significant byte first as they are sent over the
+//! air and used by HCI.
+
+const SBOX: [u8; 256] = [
+    0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+    0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+    0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+    0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+    0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+    0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+    0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+    0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+    0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+    0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+    0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+    0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+    0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+    0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+    0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+    0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+];
+
+const RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+/// f5 salt, big endian
+const F5_SALT: [u8; 16] = [
+    0x6c, 0x88, 0x83, 0x91, 0xaa, 0xf5, 0xa5, 0x38, 0x60, 0x37, 0x0b, 0xdb, 0x5a, 0x60, 0x83, 0xbe,
+];
+
+/// "btle"
+const F5_KEY_ID: [u8; 4] = [0x62, 0x74, 0x6c, 0x65];
+
+fn xtime(b: u8) -> u8 {
+    (b << 1) ^ if b & 0x80 != 0 { 0x1b } else { 0 }
+}
+
+/// Encrypts a single block with AES-128
+pub fn aes128(key: &[u8; 16], block: &[u8; 16]) -> [u8; 16] {
+    let mut round_keys = [[0u8; 16]; 11];
+    round_keys[0] = *key;
+    for round in 1..11 {
+        let prev = round_keys[round - 1];
+        let mut temp = [
+            SBOX[prev[13] as usize] ^ RCON[round - 1],
+            SBOX[prev[14] as usize],
+            SBOX[prev[15] as usize],
+            SBOX[prev[12] as usize],
+        ];
+
+        for word in 0..4 {
+            for i in 0..4 {
+                temp[i] ^= prev[word * 4 + i];
+                round_keys[round][word * 4 + i] = temp[i];
+            }
+        }
+    }
+
+    let mut state = *block;
+    for (s, k) in state.iter_mut().zip(round_keys[0].iter()) {
+        *s ^= k;
+    }
+
+    for round in 1..11 {
+        for s in state.iter_mut() {
+            *s = SBOX[*s as usize];
+        }
+
+        // shift rows, the state is stored column by column
+        let mut shifted = [0u8; 16];
+        for column in 0..4 {
+            for row in 0..4 {
+                shifted[column * 4 + row] = state[((column + row) % 4) * 4 + row];
+            }
+        }
+        state = shifted;
+
+        if round != 10 {
+            for column in 0..4 {
+                let c = &mut state[column * 4..][..4];
+                let all = c[0] ^ c[1] ^ c[2] ^ c[3];
+                let first = c[0];
+                c[0] ^= all ^ xtime(c[0] ^ c[1]);
+                c[1] ^= all ^ xtime(c[1] ^ c[2]);
+                c[2] ^= all ^ xtime(c[2] ^ c[3]);
+                c[3] ^= all ^ xtime(c[3] ^ first);
+            }
+        }
+
+        for (s, k) in state.iter_mut().zip(round_keys[round].iter()) {
+            *s ^= k;
+        }
+    }
+
+    state
+}
+
+fn shift_left(block: &[u8; 16]) -> [u8; 16] {
+    let mut shifted = [0u8; 16];
+    for i in 0..16 {
+        shifted[i] = block[i] << 1;
+        if i < 15 {
+            shifted[i] |= block[i + 1] >> 7;
+        }
+    }
+    shifted
+}
+
+fn subkey(block: &[u8; 16]) -> [u8; 16] {
+    let mut key = shift_left(block);
+    if block[0] & 0x80 != 0 {
+        key[15] ^= 0x87;
+    }
+    key
+}
+
+/// AES-CMAC as specified in RFC 4493
+pub fn aes_cmac(key: &[u8; 16], message: &[u8]) -> [u8; 16] {
+    let k1 = subkey(&aes128(key, &[0u8; 16]));
+    let k2 = subkey(&k1);
+
+    let blocks = if message.is_empty() {
+        1
+    } else {
+        (message.len() + 15) / 16
+    };
+
+    let mut x = [0u8; 16];
+    for index in 0..blocks {
+        let chunk = &message[(index * 16).min(message.len())..message.len().min(index * 16 + 16)];
+
+        let mut block = [0u8; 16];
+        block[..chunk.len()].copy_from_slice(chunk);
+
+        if index == blocks - 1 {
+            let subkey = if chunk.len() == 16 {
+                k1
+            } else {
+                block[chunk.len()] = 0x80;
+                k2
+            };
+
+            for (b, k) in block.iter_mut().zip(subkey.iter()) {
+                *b ^= k;
+            }
+        }
+
+        for (x, b) in x.iter_mut().zip(block.iter()) {
+            *x ^= b;
+        }
+        x = aes128(key, &x);
+    }
+
+    x
+}
+
+/// Copies `src` reversed into `dst`
+fn swap_into(dst: &mut [u8], src: &[u8]) {
+    for (d, s) in dst.iter_mut().zip(src.iter().rev()) {
+        *d = *s;
+    }
+}
+
+fn swapped16(value: &[u8; 16]) -> [u8; 16] {
+    let mut swapped = [0u8; 16];
+    swap_into(&mut swapped, value);
+    swapped
+}
+
+/// Security function e with values least significant byte first
+pub fn e(key: &[u8; 16], plaintext: &[u8; 16]) -> [u8; 16] {
+    swapped16(&aes128(&swapped16(key), &swapped16(plaintext)))
+}
+
+/// Confirm value generation function f4
+pub fn f4(u: &[u8; 32], v: &[u8; 32], x: &[u8; 16], z: u8) -> [u8; 16] {
+    let mut message = [0u8; 65];
+    swap_into(&mut message[0..32], u);
+    swap_into(&mut message[32..64], v);
+    message[64] = z;
+
+    swapped16(&aes_cmac(&swapped16(x), &message))
+}
+
+/// Key generation function f5, returns the MacKey and the LTK.
+///
+/// Addresses are given as address type followed by the address.
+pub fn f5(
+    w: &[u8; 32],
+    n1: &[u8; 16],
+    n2: &[u8; 16],
+    a1: &[u8; 7],
+    a2: &[u8; 7],
+) -> ([u8; 16], [u8; 16]) {
+    let mut swapped_w = [0u8; 32];
+    swap_into(&mut swapped_w, w);
+    let t = aes_cmac(&F5_SALT, &swapped_w);
+
+    let mut message = [0u8; 53];
+    message[1..5].copy_from_slice(&F5_KEY_ID);
+    swap_into(&mut message[5..21], n1);
+    swap_into(&mut message[21..37], n2);
+    write_address(&mut message[37..44], a1);
+    write_address(&mut message[44..51], a2);
+    // length of the result in bits
+    message[51..53].copy_from_slice(&256u16.to_be_bytes());
+
+    let mac_key = aes_cmac(&t, &message);
+    message[0] = 1;
+    let ltk = aes_cmac(&t, &message);
+
+    (swapped16(&mac_key), swapped16(&ltk))
+}
+
+/// Check value generation function f6
+///
+/// `io_cap` is IO capability, OOB data flag and authentication requirements as in the
+/// pairing request and response.
+pub fn f6(
+    w: &[u8; 16],
+    n1: &[u8; 16],
+    n2: &[u8; 16],
+    r: &[u8; 16],
+    io_cap: &[u8; 3],
+    a1: &[u8; 7],
+    a2: &[u8; 7],
+) -> [u8; 16] {
+    let mut message = [0u8; 65];
+    swap_into(&mut message[0..16], n1);
+    swap_into(&mut message[16..32], n2);
+    swap_into(&mut message[32..48], r);
+    swap_into(&mut message[48..51], io_cap);
+    write_address(&mut message[51..58], a1);
+    write_address(&mut message[58..65], a2);
+
+    swapped16(&aes_cmac(&swapped16(w), &message))
+}
+
+/// Numeric comparison value generation function g2, returns the six digit value to display
+pub fn g2(u: &[u8; 32], v: &[u8; 32], x: &[u8; 16], y: &[u8; 16]) -> u32 {
+    let mut message = [0u8; 80];
+    swap_into(&mut message[0..32], u);
+    swap_into(&mut message[32..64], v);
+    swap_into(&mut message[64..80], y);
+
+    let result = aes_cmac(&swapped16(x), &message);
+    u32::from_be_bytes([result[12], result[13], result[14], result[15]]) % 1_000_000
+}
+
+/// Random address hash function ah, returns the 24 bit hash least significant byte first
+pub fn ah(irk: &[u8; 16], prand: &[u8; 3]) -> [u8; 3] {
+    let mut plaintext = [0u8; 16];
+    plaintext[..3].copy_from_slice(prand);
+
+    let hash = e(irk, &plaintext);
+    [hash[0], hash[1], hash[2]]
+}
+
+/// Legacy confirm value generation function c1
+#[cfg(feature = "ble_legacy_pairing")]
+pub fn c1(
+    k: &[u8; 16],
+    r: &[u8; 16],
+    preq: &[u8; 7],
+    pres: &[u8; 7],
+    initiator: &[u8; 7],
+    responder: &[u8; 7],
+) -> [u8; 16] {
+    let mut p1 = [0u8; 16];
+    p1[0] = initiator[0];
+    p1[1] = responder[0];
+    p1[2..9].copy_from_slice(preq);
+    p1[9..16].copy_from_slice(pres);
+
+    let mut p2 = [0u8; 16];
+    p2[0..6].copy_from_slice(&responder[1..]);
+    p2[6..12].copy_from_slice(&initiator[1..]);
+
+    let mut value = *r;
+    for (v, p) in value.iter_mut().zip(p1.iter()) {
+        *v ^= p;
+    }
+    let mut value = e(k, &value);
+    for (v, p) in value.iter_mut().zip(p2.iter()) {
+        *v ^= p;
+    }
+    e(k, &value)
+}
+
+/// Legacy key generation function s1
+#[cfg(feature = "ble_legacy_pairing")]
+pub fn s1(k: &[u8; 16], r1: &[u8; 16], r2: &[u8; 16]) -> [u8; 16] {
+    let mut r = [0u8; 16];
+    r[..8].copy_from_slice(&r2[..8]);
+    r[8..].copy_from_slice(&r1[..8]);
+    e(k, &r)
+}
+
+/// Writes an address given as type followed by the address least significant byte first
+/// the way the SMP functions expect it
+fn write_address(dst: &mut [u8], address: &[u8; 7]) {
+    dst[0] = address[0];
+    swap_into(&mut dst[1..7], &address[1..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses a hex string, whitespace is ignored
+    fn be<const N: usize>(hex: &str) -> [u8; N] {
+        let mut digits = hex
+            .bytes()
+            .filter(|c| !c.is_ascii_whitespace())
+            .map(|c| (c as char).to_digit(16).unwrap() as u8);
+
+        let mut value = [0u8; N];
+        for b in value.iter_mut() {
+            *b = (digits.next().unwrap() << 4) | digits.next().unwrap();
+        }
+        assert_eq!(digits.next(), None);
+        value
+    }
+
+    /// Parses a hex string written most significant byte first like in the Core Spec into a
+    /// value least significant byte first
+    fn le<const N: usize>(hex: &str) -> [u8; N] {
+        let mut value = be::<N>(hex);
+        value.reverse();
+        value
+    }
+
+    /// Address type followed by the address given most significant byte first
+    fn address(kind: u8, hex: &str) -> [u8; 7] {
+        let mut address = [0u8; 7];
+        address[0] = kind;
+        address[1..].copy_from_slice(&le::<6>(hex));
+        address
+    }
+
+    // Core Spec Vol 3 Part H Appendix D
+    const U: &str = "20b003d2 f297be2c 5e2c83a7 e9f9a5b9 eff49111 acf4fddb cc030148 0e359de6";
+    const V: &str = "55188b3d 32f6bb9a 900afcfb eed4e72a 59cb9ac2 f19d7cfb 6b4fdd49 f47fc5fd";
+    const N1: &str = "d5cb8454 d177733e ffffb2ec 712baeab";
+    const N2: &str = "a6e8e7cc 25a75f6e 216583f7 ff3dc4cf";
+    const DHKEY: &str = "ec0234a3 57c8ad05 341010a6 0a397d9b 99796b13 b4f866f1 868d34f3 73bfa698";
+    const MAC_KEY: &str = "2965f176 a1084a02 fd3f6a20 ce636e20";
+    const A1: &str = "56123737bfce";
+    const A2: &str = "a713702dcfc1";
+
+    const RFC4493_KEY: &str = "2b7e1516 28aed2a6 abf71588 09cf4f3c";
+    const RFC4493_MESSAGE: &str = "6bc1bee2 2e409f96 e93d7e11 7393172a \
+                                   ae2d8a57 1e03ac9c 9eb76fac 45af8e51 \
+                                   30c81c46 a35ce411 e5fbc119 1a0a52ef \
+                                   f69f2445 df4f9b17 ad2b417b e66c3710";
+
+    #[test]
+    fn aes128_fips197() {
+        // Appendix B
+        assert_eq!(
+            aes128(
+                &be("2b7e1516 28aed2a6 abf71588 09cf4f3c"),
+                &be("3243f6a8 885a308d 313198a2 e0370734")
+            ),
+            be("3925841d 02dc09fb dc118597 196a0b32")
+        );
+
+        // Appendix C.1
+        assert_eq!(
+            aes128(
+                &be("00010203 04050607 08090a0b 0c0d0e0f"),
+                &be("00112233 44556677 8899aabb ccddeeff")
+            ),
+            be("69c4e0d8 6a7b0430 d8cdb780 70b4c55a")
+        );
+    }
+
+    #[test]
+    fn aes_cmac_rfc4493() {
+        let key = be(RFC4493_KEY);
+        let message = be::<64>(RFC4493_MESSAGE);
+
+        assert_eq!(
+            aes_cmac(&key, &[]),
+            be("bb1d6929 e9593728 7fa37d12 9b756746")
+        );
+        assert_eq!(
+            aes_cmac(&key, &message[..16]),
+            be("070a16b4 6b4d4144 f79bdd9d d04a287c")
+        );
+        assert_eq!(
+            aes_cmac(&key, &message[..40]),
+            be("dfa66747 de9ae630 30ca3261 1497c827")
+        );
+        assert_eq!(
+            aes_cmac(&key, &message),
+            be("51f0bebf 7e3b9d92 fc497417 79363cfe")
+        );
+    }
+
+    #[test]
+    fn f4_core_spec() {
+        assert_eq!(
+            f4(&le(U), &le(V), &le(N1), 0),
+            le("f2c916f1 07a9bd1c f1eda1be a974872d")
+        );
+    }
+
+    #[test]
+    fn f5_core_spec() {
+        let (mac_key, ltk) = f5(
+            &le(DHKEY),
+            &le(N1),
+            &le(N2),
+            &address(0, A1),
+            &address(0, A2),
+        );
+
+        assert_eq!(mac_key, le(MAC_KEY));
+        assert_eq!(ltk, le("69867911 69d7cd23 980522b5 94750a38"));
+    }
+
+    #[test]
+    fn f6_core_spec() {
+        assert_eq!(
+            f6(
+                &le(MAC_KEY),
+                &le(N1),
+                &le(N2),
+                &le("12a3343b b453bb54 08da42d2 0c2d0fc8"),
+                &le("010102"),
+                &address(0, A1),
+                &address(0, A2),
+            ),
+            le("e3c47398 9cd0e8c5 d26c0b09 da958f61")
+        );
+    }
+
+    #[test]
+    fn g2_core_spec() {
+        // the spec gives 2f9ed5ba, the displayed value is that modulo 10^6
+        assert_eq!(g2(&le(U), &le(V), &le(N1), &le(N2)), 0x2f9ed5ba % 1_000_000);
+    }
+
+    #[test]
+    fn ah_core_spec() {
+        assert_eq!(
+            ah(&le("ec0234a3 57c8ad05 341010a6 0a397d9b"), &le("708194")),
+            le("0dfbaa")
+        );
+    }
+
+    #[cfg(feature = "ble_legacy_pairing")]
+    #[test]
+    fn c1_core_spec() {
+        // Vol 3 Part H 2.2.3
+        assert_eq!(
+            c1(
+                &[0u8; 16],
+                &le("5783d521 56ad6f0e 6388274e c6702ee0"),
+                &le("07071000 000101"),
+                &le("05000800 000302"),
+                &address(1, "a1a2a3a4a5a6"),
+                &address(0, "b1b2b3b4b5b6"),
+            ),
+            le("1e1e3fef 878988ea d2a74dc5 bef13b86")
+        );
+    }
+
+    #[cfg(feature = "ble_legacy_pairing")]
+    #[test]
+    fn s1_core_spec() {
+        // Vol 3 Part H 2.2.4
+        assert_eq!(
+            s1(
+                &[0u8; 16],
+                &le("000f0e0d 0c0b0a09 11223344 55667788"),
+                &le("01020304 05060708 99aabbcc ddeeff00")
+            ),
+            le("9a1fe1f0 e8b0f49b 5b4216ae 796da062")
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! Parsing of HCI events.
+
+use super::scan::AdvertisingReports;
+
+pub const DISCONNECTION_COMPLETE: u8 = 0x05;
+pub const ENCRYPTION_CHANGE: u8 = 0x08;
+pub const COMMAND_COMPLETE: u8 = 0x0e;
+pub const COMMAND_STATUS: u8 = 0x0f;
+pub const NUMBER_OF_COMPLETED_PACKETS: u8 = 0x13;
+pub const ENCRYPTION_KEY_REFRESH_COMPLETE: u8 = 0x30;
+pub const LE_META: u8 = 0x3e;
+
+pub const LE_CONNECTION_COMPLETE: u8 = 0x01;
+pub const LE_ADVERTISING_REPORT: u8 = 0x02;
+pub const LE_LONG_TERM_KEY_REQUEST: u8 = 0x05;
+pub const LE_READ_LOCAL_P256_PUBLIC_KEY_COMPLETE: u8 = 0x08;
+pub const LE_GENERATE_DHKEY_COMPLETE: u8 = 0x09;
+
+/// A LE device address, least significant byte first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Address {
+    pub kind: AddressKind,
+    pub addr: [u8; 6],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressKind {
+    #[default]
+    Public,
+    Random,
+}
+
+impl AddressKind {
+    pub fn from_u8(kind: u8) -> AddressKind {
+        match kind & 0x01 {
+            0 => AddressKind::Public,
+            _ => AddressKind::Random,
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            AddressKind::Public => 0,
+            AddressKind::Random => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Central,
+    Peripheral,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionComplete {
+    pub status: u8,
+    pub handle: u16,
+    pub role: Role,
+    pub peer: Address,
+    /// Connection interval in units of 1.25ms
+    pub interval: u16,
+    pub latency: u16,
+    /// Supervision timeout in units of 10ms
+    pub supervision_timeout: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event<'a> {
+    CommandComplete {
+        num_packets: u8,
+        opcode: u16,
+        status: u8,
+        return_params: &'a [u8],
+    },
+    CommandStatus {
+        status: u8,
+        num_packets: u8,
+        opcode: u16,
+    },
+    DisconnectionComplete {
+        status: u8,
+        handle: u16,
+        reason: u8,
+    },
+    EncryptionChange {
+        status: u8,
+        handle: u16,
+        enabled: bool,
+    },
+    EncryptionKeyRefreshComplete {
+        status: u8,
+        handle: u16,
+    },
+    /// Raw handle/count pairs
+    NumberOfCompletedPackets(&'a [u8]),
+    LeConnectionComplete(ConnectionComplete),
+    LeAdvertisingReport(AdvertisingReports<'a>),
+    LeLongTermKeyRequest {
+        handle: u16,
+        rand: [u8; 8],
+        ediv: u16,
+    },
+    /// X and Y coordinate, least significant byte first
+    LeReadLocalP256PublicKeyComplete {
+        status: u8,
+        key: &'a [u8],
+    },
+    /// Least significant byte first
+    LeGenerateDhKeyComplete {
+        status: u8,
+        dhkey: &'a [u8],
+    },
+    LeMeta {
+        subevent: u8,
+        data: &'a [u8],
+    },
+    Unknown {
+        code: u8,
+        data: &'a [u8],
+    },
+}
+
+impl<'a> Event<'a> {
+    /// Parses the body of an event packet (without the H4 indicator).
+    ///
+    /// Returns `None` if the event is too short for its kind.
+    pub fn parse(body: &'a [u8]) -> Option<Event<'a>> {
+        if body.len() < 2 || body.len() < 2 + body[1] as usize {
+            return None;
+        }
+
+        let code = body[0];
+        let data = &body[2..][..body[1] as usize];
+
+        Some(match code {
+            COMMAND_COMPLETE => {
+                // the status is the first return parameter for all commands we care about
+                if data.len() < 3 {
+                    return None;
+                }
+
+                let (status, return_params) = if data.len() > 3 {
+                    (data[3], &data[4..])
+                } else {
+                    (0, &data[3..])
+                };
+
+                Event::CommandComplete {
+                    num_packets: data[0],
+                    opcode: read_u16(data, 1),
+                    status,
+                    return_params,
+                }
+            }
+            COMMAND_STATUS if data.len() >= 4 => Event::CommandStatus {
+                status: data[0],
+                num_packets: data[1],
+                opcode: read_u16(data, 2),
+            },
+            DISCONNECTION_COMPLETE if data.len() >= 4 => Event::DisconnectionComplete {
+                status: data[0],
+                handle: read_u16(data, 1) & 0x0fff,
+                reason: data[3],
+            },
+            ENCRYPTION_CHANGE if data.len() >= 4 => Event::EncryptionChange {
+                status: data[0],
+                handle: read_u16(data, 1) & 0x0fff,
+                enabled: data[3] != 0,
+            },
+            ENCRYPTION_KEY_REFRESH_COMPLETE if data.len() >= 3 => {
+                Event::EncryptionKeyRefreshComplete {
+                    status: data[0],
+                    handle: read_u16(data, 1) & 0x0fff,
+                }
+            }
+            NUMBER_OF_COMPLETED_PACKETS => Event::NumberOfCompletedPackets(data),
+            LE_META if !data.is_empty() => match data[0] {
+                LE_CONNECTION_COMPLETE if data.len() >= 19 => {
+                    let mut addr = [0u8; 6];
+                    addr.copy_from_slice(&data[6..12]);
+
+                    Event::LeConnectionComplete(ConnectionComplete {
+                        status: data[1],
+                        handle: read_u16(data, 2) & 0x0fff,
+                        role: if data[4] == 0 {
+                            Role::Central
+                        } else {
+                            Role::Peripheral
+                        },
+                        peer: Address {
+                            kind: AddressKind::from_u8(data[5]),
+                            addr,
+                        },
+                        interval: read_u16(data, 12),
+                        latency: read_u16(data, 14),
+                        supervision_timeout: read_u16(data, 16),
+                    })
+                }
+                LE_ADVERTISING_REPORT => {
+                    Event::LeAdvertisingReport(AdvertisingReports::new(&data[1..]))
+                }
+                LE_LONG_TERM_KEY_REQUEST if data.len() >= 13 => {
+                    let mut rand = [0u8; 8];
+                    rand.copy_from_slice(&data[3..11]);
+
+                    Event::LeLongTermKeyRequest {
+                        handle: read_u16(data, 1) & 0x0fff,
+                        rand,
+                        ediv: read_u16(data, 11),
+                    }
+                }
+                LE_READ_LOCAL_P256_PUBLIC_KEY_COMPLETE if data.len() >= 66 => {
+                    Event::LeReadLocalP256PublicKeyComplete {
+                        status: data[1],
+                        key: &data[2..66],
+                    }
+                }
+                LE_GENERATE_DHKEY_COMPLETE if data.len() >= 34 => Event::LeGenerateDhKeyComplete {
+                    status: data[1],
+                    dhkey: &data[2..34],
+                },
+                subevent => Event::LeMeta {
+                    subevent,
+                    data: &data[1..],
+                },
+            },
+            COMMAND_STATUS
+            | DISCONNECTION_COMPLETE
+            | ENCRYPTION_CHANGE
+            | ENCRYPTION_KEY_REFRESH_COMPLETE
+            | LE_META => return None,
+            _ => Event::Unknown { code, data },
+        })
+    }
+}
+
+pub(crate) fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
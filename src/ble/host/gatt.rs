@@ -0,0 +1,744 @@
+//! A GATT server serving statically declared services.
+//!
+//! Attribute handles are assigned in declaration order starting at 1. Every service takes
+//! one handle, every characteristic two (declaration and value) plus one for the client
+//! characteristic configuration descriptor if it can notify or indicate.
+
+use super::att::*;
+use super::event::{ConnectionComplete, Role};
+use super::l2cap::{CID_ATT, MAX_L2CAP_PAYLOAD};
+use super::smp::SecurityEvent;
+use super::{Host, HostError, HostEvent};
+
+pub const PROP_BROADCAST: u8 = 0x01;
+pub const PROP_READ: u8 = 0x02;
+pub const PROP_WRITE_WITHOUT_RESPONSE: u8 = 0x04;
+pub const PROP_WRITE: u8 = 0x08;
+pub const PROP_NOTIFY: u8 = 0x10;
+pub const PROP_INDICATE: u8 = 0x20;
+
+/// Maximum number of characteristics which can notify or indicate
+pub const MAX_SUBSCRIBABLE: usize = 16;
+
+const CCCD_NOTIFY: u16 = 0x0001;
+const CCCD_INDICATE: u16 = 0x0002;
+
+/// What the client enabled in the client characteristic configuration descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Subscription {
+    pub notify: bool,
+    pub indicate: bool,
+}
+
+pub struct Characteristic {
+    pub uuid: Uuid,
+    /// Any combination of the `PROP_*` flags
+    pub properties: u8,
+    /// Fills the buffer with the current value and returns its length
+    pub read: Option<fn(&mut [u8]) -> usize>,
+    /// Called with the value written by the client
+    pub write: Option<fn(&[u8]) -> Result<(), AttErrorCode>>,
+    /// Called when the client changes its subscription
+    pub subscribe: Option<fn(Subscription)>,
+    /// The value and its subscription are only accessible on an encrypted link
+    pub encrypted: bool,
+}
+
+impl Characteristic {
+    pub const fn new(uuid: Uuid, properties: u8) -> Characteristic {
+        Characteristic {
+            uuid,
+            properties,
+            read: None,
+            write: None,
+            subscribe: None,
+            encrypted: false,
+        }
+    }
+
+    pub const fn on_read(self, read: fn(&mut [u8]) -> usize) -> Characteristic {
+        Characteristic {
+            read: Some(read),
+            ..self
+        }
+    }
+
+    pub const fn on_write(self, write: fn(&[u8]) -> Result<(), AttErrorCode>) -> Characteristic {
+        Characteristic {
+            write: Some(write),
+            ..self
+        }
+    }
+
+    pub const fn on_subscribe(self, subscribe: fn(Subscription)) -> Characteristic {
+        Characteristic {
+            subscribe: Some(subscribe),
+            ..self
+        }
+    }
+
+    /// Requires an encrypted link, see [Host::enable_security]
+    pub const fn encrypted(self) -> Characteristic {
+        Characteristic {
+            encrypted: true,
+            ..self
+        }
+    }
+
+    fn has_cccd(&self) -> bool {
+        self.properties & (PROP_NOTIFY | PROP_INDICATE) != 0
+    }
+
+    fn attribute_count(&self) -> u16 {
+        if self.has_cccd() {
+            3
+        } else {
+            2
+        }
+    }
+}
+
+pub struct Service<'a> {
+    pub uuid: Uuid,
+    pub characteristics: &'a [Characteristic],
+}
+
+impl<'a> Service<'a> {
+    pub const fn new(uuid: Uuid, characteristics: &'a [Characteristic]) -> Service<'a> {
+        Service {
+            uuid,
+            characteristics,
+        }
+    }
+
+    fn attribute_count(&self) -> u16 {
+        1 + self
+            .characteristics
+            .iter()
+            .map(|c| c.attribute_count())
+            .sum::<u16>()
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Attribute<'a> {
+    Service(&'a Service<'a>),
+    Declaration(&'a Characteristic, u16),
+    Value(&'a Characteristic),
+    /// Index into the CCCD states and the characteristic it belongs to
+    Cccd(usize, &'a Characteristic),
+}
+
+impl<'a> Attribute<'a> {
+    fn att_type(&self) -> Uuid {
+        match self {
+            Attribute::Service(_) => PRIMARY_SERVICE,
+            Attribute::Declaration(..) => CHARACTERISTIC,
+            Attribute::Value(characteristic) => characteristic.uuid,
+            Attribute::Cccd(..) => CLIENT_CHARACTERISTIC_CONFIGURATION,
+        }
+    }
+}
+
+/// Walks all attributes in handle order
+struct Attributes<'a> {
+    services: &'a [Service<'a>],
+    service: usize,
+    characteristic: Option<usize>,
+    step: u8,
+    handle: u16,
+    cccd: usize,
+}
+
+impl<'a> Attributes<'a> {
+    fn new(services: &'a [Service<'a>]) -> Attributes<'a> {
+        Attributes {
+            services,
+            service: 0,
+            characteristic: None,
+            step: 0,
+            handle: 0,
+            cccd: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for Attributes<'a> {
+    type Item = (u16, Attribute<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let service = self.services.get(self.service)?;
+
+            let attribute = match self.characteristic {
+                None => {
+                    self.characteristic = Some(0);
+                    self.step = 0;
+                    Some(Attribute::Service(service))
+                }
+                Some(index) => match service.characteristics.get(index) {
+                    None => {
+                        self.service += 1;
+                        self.characteristic = None;
+                        None
+                    }
+                    Some(characteristic) => {
+                        let step = self.step;
+                        self.step += 1;
+
+                        match step {
+                            0 => Some(Attribute::Declaration(characteristic, self.handle + 2)),
+                            1 => Some(Attribute::Value(characteristic)),
+                            2 if characteristic.has_cccd() => {
+                                self.cccd += 1;
+                                Some(Attribute::Cccd(self.cccd - 1, characteristic))
+                            }
+                            _ => {
+                                self.characteristic = Some(index + 1);
+                                self.step = 0;
+                                None
+                            }
+                        }
+                    }
+                },
+            };
+
+            if let Some(attribute) = attribute {
+                self.handle += 1;
+                return Some((self.handle, attribute));
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WorkResult {
+    /// Nothing was received
+    Idle,
+    DidWork,
+    Connected(ConnectionComplete),
+    Disconnected {
+        reason: u8,
+    },
+    Security(SecurityEvent),
+}
+
+pub struct GattServer<'a> {
+    services: &'a [Service<'a>],
+    connection: Option<u16>,
+    encrypted: bool,
+    mtu: u16,
+    cccd: [u16; MAX_SUBSCRIBABLE],
+    indication_pending: bool,
+    tx: [u8; MAX_L2CAP_PAYLOAD],
+}
+
+impl<'a> GattServer<'a> {
+    pub fn new(services: &'a [Service<'a>]) -> GattServer<'a> {
+        let subscribable = services
+            .iter()
+            .flat_map(|service| service.characteristics.iter())
+            .filter(|characteristic| characteristic.has_cccd())
+            .count();
+        if subscribable > MAX_SUBSCRIBABLE {
+            panic!(
+                "More than {} characteristics notify or indicate",
+                MAX_SUBSCRIBABLE
+            );
+        }
+
+        GattServer {
+            services,
+            connection: None,
+            encrypted: false,
+            mtu: ATT_DEFAULT_MTU,
+            cccd: [0u16; MAX_SUBSCRIBABLE],
+            indication_pending: false,
+            tx: [0u8; MAX_L2CAP_PAYLOAD],
+        }
+    }
+
+    /// The handle of the current connection
+    pub fn connection(&self) -> Option<u16> {
+        self.connection
+    }
+
+    /// Returns the value handle of the first characteristic with the given UUID
+    pub fn characteristic_handle(&self, uuid: &Uuid) -> Option<u16> {
+        Attributes::new(self.services).find_map(|(handle, attribute)| match attribute {
+            Attribute::Value(characteristic) if characteristic.uuid.matches(uuid) => Some(handle),
+            _ => None,
+        })
+    }
+
+    /// Polls the host and answers ATT requests.
+    pub fn do_work(&mut self, host: &mut Host) -> Result<WorkResult, HostError> {
+        let mut request = [0u8; MAX_L2CAP_PAYLOAD];
+
+        let (handle, len) = match host.poll()? {
+            None => return Ok(WorkResult::Idle),
+            Some(HostEvent::Connected(connection)) => {
+                if connection.role == Role::Peripheral {
+                    self.connected(connection.handle);
+                }
+                return Ok(WorkResult::Connected(connection));
+            }
+            Some(HostEvent::Disconnected { handle, reason }) => {
+                if self.connection != Some(handle) {
+                    return Ok(WorkResult::DidWork);
+                }
+
+                self.disconnected();
+                return Ok(WorkResult::Disconnected { reason });
+            }
+            Some(HostEvent::Att { handle, pdu }) => {
+                request[..pdu.len()].copy_from_slice(pdu);
+                (handle, pdu.len())
+            }
+            Some(HostEvent::Security(event)) => {
+                if let SecurityEvent::EncryptionChanged { enabled } = event {
+                    self.encrypted = enabled;
+                }
+                return Ok(WorkResult::Security(event));
+            }
+            Some(_) => return Ok(WorkResult::DidWork),
+        };
+
+        if self.connection != Some(handle) {
+            return Ok(WorkResult::DidWork);
+        }
+
+        if let Some(len) = self.process(&request[..len]) {
+            host.send_l2cap(handle, CID_ATT, &self.tx[..len])?;
+        }
+
+        Ok(WorkResult::DidWork)
+    }
+
+    /// Sends a notification if the client subscribed to it. Returns false if it wasn't sent.
+    ///
+    /// The value is truncated to fit into the MTU.
+    pub fn notify(
+        &mut self,
+        host: &mut Host,
+        value_handle: u16,
+        value: &[u8],
+    ) -> Result<bool, HostError> {
+        self.send_value(host, value_handle, value, ATT_HANDLE_VALUE_NTF)
+    }
+
+    /// Sends an indication if the client subscribed to it. Returns false if it wasn't sent,
+    /// which is also the case while the previous indication wasn't confirmed yet.
+    ///
+    /// The value is truncated to fit into the MTU.
+    pub fn indicate(
+        &mut self,
+        host: &mut Host,
+        value_handle: u16,
+        value: &[u8],
+    ) -> Result<bool, HostError> {
+        if self.indication_pending {
+            return Ok(false);
+        }
+
+        let sent = self.send_value(host, value_handle, value, ATT_HANDLE_VALUE_IND)?;
+        self.indication_pending = sent;
+        Ok(sent)
+    }
+
+    fn send_value(
+        &mut self,
+        host: &mut Host,
+        value_handle: u16,
+        value: &[u8],
+        opcode: u8,
+    ) -> Result<bool, HostError> {
+        let connection = match self.connection {
+            Some(connection) => connection,
+            None => return Ok(false),
+        };
+
+        let flag = if opcode == ATT_HANDLE_VALUE_IND {
+            CCCD_INDICATE
+        } else {
+            CCCD_NOTIFY
+        };
+
+        // the CCCD directly follows the value, there is none after handle 0xffff
+        let subscribed = match value_handle.checked_add(1).and_then(|h| self.attribute(h)) {
+            Some(Attribute::Cccd(index, _)) => self.cccd[index] & flag != 0,
+            _ => false,
+        };
+        if !subscribed {
+            return Ok(false);
+        }
+
+        let len = value.len().min(self.mtu as usize - 3);
+        self.tx[0] = opcode;
+        self.tx[1..3].copy_from_slice(&value_handle.to_le_bytes());
+        self.tx[3..][..len].copy_from_slice(&value[..len]);
+        host.send_l2cap(connection, CID_ATT, &self.tx[..len + 3])?;
+
+        Ok(true)
+    }
+
+    fn connected(&mut self, handle: u16) {
+        self.connection = Some(handle);
+        self.encrypted = false;
+        self.mtu = ATT_DEFAULT_MTU;
+        self.indication_pending = false;
+    }
+
+    fn disconnected(&mut self) {
+        self.connection = None;
+        self.encrypted = false;
+        self.indication_pending = false;
+
+        // we don't bond, so subscriptions don't survive the connection
+        for index in 0..self.cccd.len() {
+            if self.cccd[index] != 0 {
+                self.cccd[index] = 0;
+                self.notify_subscription(index);
+            }
+        }
+    }
+
+    fn notify_subscription(&self, index: usize) {
+        let subscribe = Attributes::new(self.services).find_map(|(_, attribute)| match attribute {
+            Attribute::Cccd(i, characteristic) if i == index => Some(characteristic.subscribe),
+            _ => None,
+        });
+
+        if let Some(Some(subscribe)) = subscribe {
+            subscribe(Subscription {
+                notify: self.cccd[index] & CCCD_NOTIFY != 0,
+                indicate: self.cccd[index] & CCCD_INDICATE != 0,
+            });
+        }
+    }
+
+    fn attribute(&self, handle: u16) -> Option<Attribute<'a>> {
+        Attributes::new(self.services)
+            .find(|(h, _)| *h == handle)
+            .map(|(_, attribute)| attribute)
+    }
+
+    fn in_range(&self, start: u16, end: u16) -> impl Iterator<Item = (u16, Attribute<'a>)> {
+        Attributes::new(self.services)
+            .skip_while(move |(handle, _)| *handle < start)
+            .take_while(move |(handle, _)| *handle <= end)
+    }
+
+    /// Handles a request and returns the length of the response written to `tx` if there is one
+    fn process(&mut self, pdu: &[u8]) -> Option<usize> {
+        let opcode = *pdu.first()?;
+
+        let result = AttRequest::parse(pdu)
+            .map_err(|code| (0, code))
+            .and_then(|request| self.handle_request(request));
+
+        match result {
+            Ok(len) => len,
+            Err(_) if AttRequest::is_command(opcode) => None,
+            Err((handle, code)) => Some(write_error(&mut self.tx, opcode, handle, code)),
+        }
+    }
+
+    fn handle_request(
+        &mut self,
+        request: AttRequest,
+    ) -> Result<Option<usize>, (u16, AttErrorCode)> {
+        let mtu = self.mtu as usize;
+
+        match request {
+            AttRequest::ExchangeMtu { mtu } => {
+                self.mtu = mtu.clamp(ATT_DEFAULT_MTU, ATT_MAX_MTU);
+                self.tx[0] = ATT_EXCHANGE_MTU_RSP;
+                self.tx[1..3].copy_from_slice(&ATT_MAX_MTU.to_le_bytes());
+                Ok(Some(3))
+            }
+            AttRequest::FindInformation { start, end } => {
+                check_range(start, end)?;
+
+                let mut len = 2;
+                let mut uuid_len = 0;
+                for (handle, attribute) in self.in_range(start, end) {
+                    let uuid = attribute.att_type();
+                    if uuid_len == 0 {
+                        uuid_len = uuid.len();
+                    }
+
+                    if uuid.len() != uuid_len || len + 2 + uuid_len > mtu {
+                        break;
+                    }
+
+                    self.tx[len..][..2].copy_from_slice(&handle.to_le_bytes());
+                    uuid.write(&mut self.tx[len + 2..]);
+                    len += 2 + uuid_len;
+                }
+
+                if len == 2 {
+                    return Err((start, AttErrorCode::AttributeNotFound));
+                }
+
+                self.tx[0] = ATT_FIND_INFORMATION_RSP;
+                self.tx[1] = if uuid_len == 2 { 0x01 } else { 0x02 };
+                Ok(Some(len))
+            }
+            AttRequest::FindByTypeValue {
+                start,
+                end,
+                att_type,
+                value,
+            } => {
+                check_range(start, end)?;
+
+                let mut len = 1;
+                if att_type == 0x2800 {
+                    for (handle, attribute) in self.in_range(start, end) {
+                        if let Attribute::Service(service) = attribute {
+                            if !Uuid::from_slice(value)
+                                .map(|uuid| uuid.matches(&service.uuid))
+                                .unwrap_or(false)
+                            {
+                                continue;
+                            }
+
+                            if len + 4 > mtu {
+                                break;
+                            }
+
+                            let group_end = handle + service.attribute_count() - 1;
+                            self.tx[len..][..2].copy_from_slice(&handle.to_le_bytes());
+                            self.tx[len + 2..][..2].copy_from_slice(&group_end.to_le_bytes());
+                            len += 4;
+                        }
+                    }
+                }
+
+                if len == 1 {
+                    return Err((start, AttErrorCode::AttributeNotFound));
+                }
+
+                self.tx[0] = ATT_FIND_BY_TYPE_VALUE_RSP;
+                Ok(Some(len))
+            }
+            AttRequest::ReadByType {
+                start,
+                end,
+                att_type,
+            } => {
+                check_range(start, end)?;
+
+                let mut value = [0u8; ATT_MAX_VALUE_LEN];
+                let mut len = 2;
+                let mut entry_len = 0;
+                for (handle, attribute) in self.in_range(start, end) {
+                    if !attribute.att_type().matches(&att_type) {
+                        continue;
+                    }
+
+                    let value_len = match self.read(attribute, &mut value) {
+                        Ok(value_len) => value_len,
+                        Err(code) if entry_len == 0 => return Err((handle, code)),
+                        Err(_) => break,
+                    };
+                    // the length of an entry has to fit into a byte
+                    let value_len = value_len.min(mtu - 4).min(253);
+
+                    if entry_len == 0 {
+                        entry_len = value_len + 2;
+                    }
+
+                    if value_len + 2 != entry_len || len + entry_len > mtu {
+                        break;
+                    }
+
+                    self.tx[len..][..2].copy_from_slice(&handle.to_le_bytes());
+                    self.tx[len + 2..][..value_len].copy_from_slice(&value[..value_len]);
+                    len += entry_len;
+                }
+
+                if len == 2 {
+                    return Err((start, AttErrorCode::AttributeNotFound));
+                }
+
+                self.tx[0] = ATT_READ_BY_TYPE_RSP;
+                self.tx[1] = entry_len as u8;
+                Ok(Some(len))
+            }
+            AttRequest::Read { handle } => self.read_response(handle, 0, ATT_READ_RSP),
+            AttRequest::ReadBlob { handle, offset } => {
+                self.read_response(handle, offset as usize, ATT_READ_BLOB_RSP)
+            }
+            AttRequest::ReadByGroupType {
+                start,
+                end,
+                group_type,
+            } => {
+                check_range(start, end)?;
+
+                if !group_type.matches(&PRIMARY_SERVICE) {
+                    return Err((start, AttErrorCode::UnsupportedGroupType));
+                }
+
+                let mut len = 2;
+                let mut uuid_len = 0;
+                for (handle, attribute) in self.in_range(start, end) {
+                    if let Attribute::Service(service) = attribute {
+                        if uuid_len == 0 {
+                            uuid_len = service.uuid.len();
+                        }
+
+                        if service.uuid.len() != uuid_len || len + 4 + uuid_len > mtu {
+                            break;
+                        }
+
+                        let group_end = handle + service.attribute_count() - 1;
+                        self.tx[len..][..2].copy_from_slice(&handle.to_le_bytes());
+                        self.tx[len + 2..][..2].copy_from_slice(&group_end.to_le_bytes());
+                        service.uuid.write(&mut self.tx[len + 4..]);
+                        len += 4 + uuid_len;
+                    }
+                }
+
+                if len == 2 {
+                    return Err((start, AttErrorCode::AttributeNotFound));
+                }
+
+                self.tx[0] = ATT_READ_BY_GROUP_TYPE_RSP;
+                self.tx[1] = (4 + uuid_len) as u8;
+                Ok(Some(len))
+            }
+            AttRequest::Write { handle, value } => {
+                self.write(handle, value)?;
+                self.tx[0] = ATT_WRITE_RSP;
+                Ok(Some(1))
+            }
+            AttRequest::WriteCommand { handle, value } => {
+                self.write(handle, value)?;
+                Ok(None)
+            }
+            AttRequest::Confirmation => {
+                self.indication_pending = false;
+                Ok(None)
+            }
+            AttRequest::Unsupported(_) => Err((0, AttErrorCode::RequestNotSupported)),
+        }
+    }
+
+    fn read_response(
+        &mut self,
+        handle: u16,
+        offset: usize,
+        opcode: u8,
+    ) -> Result<Option<usize>, (u16, AttErrorCode)> {
+        let attribute = self
+            .attribute(handle)
+            .ok_or((handle, AttErrorCode::InvalidHandle))?;
+
+        let mut value = [0u8; ATT_MAX_VALUE_LEN];
+        let value_len = self
+            .read(attribute, &mut value)
+            .map_err(|code| (handle, code))?;
+
+        if offset > value_len {
+            return Err((handle, AttErrorCode::InvalidOffset));
+        }
+
+        let len = (value_len - offset).min(self.mtu as usize - 1);
+        self.tx[0] = opcode;
+        self.tx[1..][..len].copy_from_slice(&value[offset..][..len]);
+        Ok(Some(len + 1))
+    }
+
+    /// Clients are expected to pair when they get this error
+    fn check_encryption(&self, characteristic: &Characteristic) -> Result<(), AttErrorCode> {
+        if characteristic.encrypted && !self.encrypted {
+            Err(AttErrorCode::InsufficientAuthentication)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read(&self, attribute: Attribute, value: &mut [u8]) -> Result<usize, AttErrorCode> {
+        Ok(match attribute {
+            Attribute::Service(service) => service.uuid.write(value),
+            Attribute::Declaration(characteristic, value_handle) => {
+                value[0] = characteristic.properties;
+                value[1..3].copy_from_slice(&value_handle.to_le_bytes());
+                3 + characteristic.uuid.write(&mut value[3..])
+            }
+            Attribute::Value(characteristic) => {
+                if characteristic.properties & PROP_READ == 0 {
+                    return Err(AttErrorCode::ReadNotPermitted);
+                }
+                self.check_encryption(characteristic)?;
+
+                match characteristic.read {
+                    Some(read) => read(value).min(value.len()),
+                    None => 0,
+                }
+            }
+            Attribute::Cccd(index, characteristic) => {
+                self.check_encryption(characteristic)?;
+                value[0..2].copy_from_slice(&self.cccd[index].to_le_bytes());
+                2
+            }
+        })
+    }
+
+    fn write(&mut self, handle: u16, value: &[u8]) -> Result<(), (u16, AttErrorCode)> {
+        let attribute = self
+            .attribute(handle)
+            .ok_or((handle, AttErrorCode::InvalidHandle))?;
+
+        match attribute {
+            Attribute::Value(characteristic) => {
+                if characteristic.properties & (PROP_WRITE | PROP_WRITE_WITHOUT_RESPONSE) == 0 {
+                    return Err((handle, AttErrorCode::WriteNotPermitted));
+                }
+                self.check_encryption(characteristic)
+                    .map_err(|code| (handle, code))?;
+
+                match characteristic.write {
+                    Some(write) => write(value).map_err(|code| (handle, code)),
+                    None => Ok(()),
+                }
+            }
+            Attribute::Cccd(index, characteristic) => {
+                self.check_encryption(characteristic)
+                    .map_err(|code| (handle, code))?;
+
+                if value.len() != 2 {
+                    return Err((handle, AttErrorCode::InvalidAttributeValueLength));
+                }
+
+                let mut allowed = 0;
+                if characteristic.properties & PROP_NOTIFY != 0 {
+                    allowed |= CCCD_NOTIFY;
+                }
+                if characteristic.properties & PROP_INDICATE != 0 {
+                    allowed |= CCCD_INDICATE;
+                }
+
+                let cccd = u16::from_le_bytes([value[0], value[1]]) & allowed;
+                if cccd != self.cccd[index] {
+                    self.cccd[index] = cccd;
+                    self.notify_subscription(index);
+                }
+
+                Ok(())
+            }
+            _ => Err((handle, AttErrorCode::WriteNotPermitted)),
+        }
+    }
+}
+
+fn check_range(start: u16, end: u16) -> Result<(), (u16, AttErrorCode)> {
+    if start == 0 || start > end {
+        Err((start, AttErrorCode::InvalidHandle))
+    } else {
+        Ok(())
+    }
+}
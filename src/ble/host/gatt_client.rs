@@ -0,0 +1,480 @@
+//! A GATT client for connections in the central role.
+//!
+//! All procedures block until the peer responded. Notifications and indications received
+//! meanwhile are handed to the notification callback, requests of the peer are rejected.
+
+use super::att::*;
+use super::event::read_u16;
+use super::gatt::Subscription;
+use super::l2cap::{CID_ATT, MAX_L2CAP_PAYLOAD};
+use super::{Host, HostError, HostEvent, PendingEvent};
+
+/// ATT transactions time out after 30s
+const TRANSACTION_TIMEOUT_MS: u64 = 30_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServiceInfo {
+    pub start: u16,
+    pub end: u16,
+    pub uuid: Uuid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CharacteristicInfo {
+    pub declaration_handle: u16,
+    /// Any combination of the `PROP_*` flags in [super::gatt]
+    pub properties: u8,
+    pub value_handle: u16,
+    pub uuid: Uuid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DescriptorInfo {
+    pub handle: u16,
+    pub uuid: Uuid,
+}
+
+pub struct GattClient {
+    connection: u16,
+    mtu: u16,
+    on_notification: Option<fn(u16, &[u8])>,
+    rx: [u8; MAX_L2CAP_PAYLOAD],
+}
+
+impl GattClient {
+    /// Creates a client for the given connection
+    pub fn new(connection: u16) -> GattClient {
+        GattClient {
+            connection,
+            mtu: ATT_DEFAULT_MTU,
+            on_notification: None,
+            rx: [0u8; MAX_L2CAP_PAYLOAD],
+        }
+    }
+
+    /// Sets the callback getting the value handle and value of received notifications and
+    /// indications
+    pub fn on_notification(&mut self, on_notification: fn(u16, &[u8])) {
+        self.on_notification = Some(on_notification);
+    }
+
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    /// Handles notifications and indications while no procedure is running. Other events are
+    /// kept for [Host::poll].
+    ///
+    /// Fails with [HostError::Disconnected] once the connection is gone.
+    pub fn poll(&mut self, host: &mut Host) -> Result<(), HostError> {
+        self.receive(host)?;
+        Ok(())
+    }
+
+    pub fn exchange_mtu(&mut self, host: &mut Host) -> Result<u16, HostError> {
+        let mtu = ATT_MAX_MTU.to_le_bytes();
+        self.request(
+            host,
+            &[ATT_EXCHANGE_MTU_REQ, mtu[0], mtu[1]],
+            ATT_EXCHANGE_MTU_RSP,
+            3,
+        )?;
+
+        let server_mtu = read_u16(&self.rx, 1);
+        self.mtu = server_mtu.clamp(ATT_DEFAULT_MTU, ATT_MAX_MTU);
+        Ok(self.mtu)
+    }
+
+    /// Discovers all primary services
+    pub fn discover_services(
+        &mut self,
+        host: &mut Host,
+        mut f: impl FnMut(ServiceInfo),
+    ) -> Result<(), HostError> {
+        let mut start = 0x0001u16;
+
+        loop {
+            let mut request = [0u8; 7];
+            request[0] = ATT_READ_BY_GROUP_TYPE_REQ;
+            request[1..3].copy_from_slice(&start.to_le_bytes());
+            request[3..5].copy_from_slice(&0xffffu16.to_le_bytes());
+            PRIMARY_SERVICE.write(&mut request[5..]);
+
+            let len = match self.request(host, &request, ATT_READ_BY_GROUP_TYPE_RSP, 2) {
+                Err(HostError::Att { code, .. })
+                    if code == AttErrorCode::AttributeNotFound as u8 =>
+                {
+                    return Ok(())
+                }
+                result => result?,
+            };
+
+            let entry_len = self.rx[1] as usize;
+            if entry_len < 6 {
+                return Err(HostError::Att {
+                    handle: start,
+                    code: AttErrorCode::InvalidPdu as u8,
+                });
+            }
+
+            let mut end = 0;
+            for entry in self.rx[2..len].chunks_exact(entry_len) {
+                end = read_u16(entry, 2);
+                if let Some(uuid) = Uuid::from_slice(&entry[4..]) {
+                    f(ServiceInfo {
+                        start: read_u16(entry, 0),
+                        end,
+                        uuid,
+                    });
+                }
+            }
+
+            if end == 0xffff || end < start {
+                return Ok(());
+            }
+            start = end + 1;
+        }
+    }
+
+    /// Finds the primary service with the given UUID
+    pub fn find_service(
+        &mut self,
+        host: &mut Host,
+        uuid: &Uuid,
+    ) -> Result<Option<ServiceInfo>, HostError> {
+        let mut request = [0u8; 23];
+        request[0] = ATT_FIND_BY_TYPE_VALUE_REQ;
+        request[1..3].copy_from_slice(&0x0001u16.to_le_bytes());
+        request[3..5].copy_from_slice(&0xffffu16.to_le_bytes());
+        request[5..7].copy_from_slice(&0x2800u16.to_le_bytes());
+        let len = 7 + uuid.write(&mut request[7..]);
+
+        match self.request(host, &request[..len], ATT_FIND_BY_TYPE_VALUE_RSP, 1) {
+            Err(HostError::Att { code, .. }) if code == AttErrorCode::AttributeNotFound as u8 => {
+                Ok(None)
+            }
+            Err(err) => Err(err),
+            Ok(len) if len >= 5 => Ok(Some(ServiceInfo {
+                start: read_u16(&self.rx, 1),
+                end: read_u16(&self.rx, 3),
+                uuid: *uuid,
+            })),
+            Ok(_) => Ok(None),
+        }
+    }
+
+    /// Discovers all characteristics of a service
+    pub fn discover_characteristics(
+        &mut self,
+        host: &mut Host,
+        service: &ServiceInfo,
+        mut f: impl FnMut(CharacteristicInfo),
+    ) -> Result<(), HostError> {
+        let mut start = service.start;
+
+        while start <= service.end {
+            let mut request = [0u8; 7];
+            request[0] = ATT_READ_BY_TYPE_REQ;
+            request[1..3].copy_from_slice(&start.to_le_bytes());
+            request[3..5].copy_from_slice(&service.end.to_le_bytes());
+            CHARACTERISTIC.write(&mut request[5..]);
+
+            let len = match self.request(host, &request, ATT_READ_BY_TYPE_RSP, 2) {
+                Err(HostError::Att { code, .. })
+                    if code == AttErrorCode::AttributeNotFound as u8 =>
+                {
+                    return Ok(())
+                }
+                result => result?,
+            };
+
+            // handle, properties, value handle, UUID
+            let entry_len = self.rx[1] as usize;
+            if entry_len < 7 {
+                return Err(HostError::Att {
+                    handle: start,
+                    code: AttErrorCode::InvalidPdu as u8,
+                });
+            }
+
+            let mut last = start;
+            for entry in self.rx[2..len].chunks_exact(entry_len) {
+                last = read_u16(entry, 0);
+                if let Some(uuid) = Uuid::from_slice(&entry[5..]) {
+                    f(CharacteristicInfo {
+                        declaration_handle: last,
+                        properties: entry[2],
+                        value_handle: read_u16(entry, 3),
+                        uuid,
+                    });
+                }
+            }
+
+            if last == 0xffff || last < start {
+                return Ok(());
+            }
+            start = last + 1;
+        }
+
+        Ok(())
+    }
+
+    /// Discovers the descriptors in the given handle range. For a characteristic this is the
+    /// range from the value handle + 1 to the handle before the next characteristic
+    /// declaration (or the end of the service).
+    pub fn discover_descriptors(
+        &mut self,
+        host: &mut Host,
+        start: u16,
+        end: u16,
+        mut f: impl FnMut(DescriptorInfo),
+    ) -> Result<(), HostError> {
+        let mut start = start;
+
+        while start <= end {
+            let mut request = [0u8; 5];
+            request[0] = ATT_FIND_INFORMATION_REQ;
+            request[1..3].copy_from_slice(&start.to_le_bytes());
+            request[3..5].copy_from_slice(&end.to_le_bytes());
+
+            let len = match self.request(host, &request, ATT_FIND_INFORMATION_RSP, 2) {
+                Err(HostError::Att { code, .. })
+                    if code == AttErrorCode::AttributeNotFound as u8 =>
+                {
+                    return Ok(())
+                }
+                result => result?,
+            };
+
+            // handle and a 16 or 128 bit UUID
+            let entry_len = match self.rx[1] {
+                0x01 => 4,
+                0x02 => 18,
+                _ => {
+                    return Err(HostError::Att {
+                        handle: start,
+                        code: AttErrorCode::InvalidPdu as u8,
+                    })
+                }
+            };
+
+            let mut last = start;
+            for entry in self.rx[2..len].chunks_exact(entry_len) {
+                last = read_u16(entry, 0);
+                if let Some(uuid) = Uuid::from_slice(&entry[2..]) {
+                    f(DescriptorInfo { handle: last, uuid });
+                }
+            }
+
+            if last == 0xffff || last < start {
+                return Ok(());
+            }
+            start = last + 1;
+        }
+
+        Ok(())
+    }
+
+    /// Reads a value into `buf` and returns its length. Long values are read using multiple
+    /// requests until `buf` is full.
+    pub fn read(
+        &mut self,
+        host: &mut Host,
+        handle: u16,
+        buf: &mut [u8],
+    ) -> Result<usize, HostError> {
+        let handle_bytes = handle.to_le_bytes();
+        let len = self.request(
+            host,
+            &[ATT_READ_REQ, handle_bytes[0], handle_bytes[1]],
+            ATT_READ_RSP,
+            1,
+        )?;
+
+        let mut offset = self.copy_value(buf, 0, len);
+
+        // a full response means there might be more
+        let mut response_len = len - 1;
+        while response_len == self.mtu as usize - 1 && offset < buf.len() {
+            let offset_bytes = u16::try_from(offset)
+                .map_err(|_| HostError::Att {
+                    handle,
+                    code: AttErrorCode::InvalidOffset as u8,
+                })?
+                .to_le_bytes();
+            let request = [
+                ATT_READ_BLOB_REQ,
+                handle_bytes[0],
+                handle_bytes[1],
+                offset_bytes[0],
+                offset_bytes[1],
+            ];
+
+            let len = match self.request(host, &request, ATT_READ_BLOB_RSP, 1) {
+                Err(HostError::Att { code, .. })
+                    if code == AttErrorCode::AttributeNotLong as u8
+                        || code == AttErrorCode::InvalidOffset as u8 =>
+                {
+                    break
+                }
+                result => result?,
+            };
+
+            response_len = len - 1;
+            offset = self.copy_value(buf, offset, len);
+        }
+
+        Ok(offset)
+    }
+
+    /// Writes a value and waits for the peer to acknowledge it
+    pub fn write(&mut self, host: &mut Host, handle: u16, value: &[u8]) -> Result<(), HostError> {
+        let mut request = [0u8; MAX_L2CAP_PAYLOAD];
+        let len = self.write_request(&mut request, ATT_WRITE_REQ, handle, value)?;
+        self.request(host, &request[..len], ATT_WRITE_RSP, 1)?;
+        Ok(())
+    }
+
+    pub fn write_without_response(
+        &mut self,
+        host: &mut Host,
+        handle: u16,
+        value: &[u8],
+    ) -> Result<(), HostError> {
+        let mut request = [0u8; MAX_L2CAP_PAYLOAD];
+        let len = self.write_request(&mut request, ATT_WRITE_CMD, handle, value)?;
+        host.send_l2cap(self.connection, CID_ATT, &request[..len])
+    }
+
+    /// Writes the client characteristic configuration descriptor at `cccd_handle`
+    pub fn subscribe(
+        &mut self,
+        host: &mut Host,
+        cccd_handle: u16,
+        subscription: Subscription,
+    ) -> Result<(), HostError> {
+        let value = (subscription.notify as u16) | ((subscription.indicate as u16) << 1);
+        self.write(host, cccd_handle, &value.to_le_bytes())
+    }
+
+    fn write_request(
+        &self,
+        request: &mut [u8],
+        opcode: u8,
+        handle: u16,
+        value: &[u8],
+    ) -> Result<usize, HostError> {
+        if value.len() > self.mtu as usize - 3 {
+            return Err(HostError::PayloadTooLarge);
+        }
+
+        request[0] = opcode;
+        request[1..3].copy_from_slice(&handle.to_le_bytes());
+        request[3..][..value.len()].copy_from_slice(value);
+        Ok(value.len() + 3)
+    }
+
+    /// Copies the value of a read response to `buf` at `offset` and returns the new offset
+    fn copy_value(&self, buf: &mut [u8], offset: usize, len: usize) -> usize {
+        let value = &self.rx[1..len];
+        let count = value.len().min(buf.len() - offset);
+        buf[offset..][..count].copy_from_slice(&value[..count]);
+        offset + count
+    }
+
+    /// Sends a request and waits for the response which is left in `rx`. Returns the length
+    /// of the response, a response shorter than `min_len` is an invalid PDU.
+    fn request(
+        &mut self,
+        host: &mut Host,
+        request: &[u8],
+        response: u8,
+        min_len: usize,
+    ) -> Result<usize, HostError> {
+        host.send_l2cap(self.connection, CID_ATT, request)?;
+
+        let started = crate::current_millis();
+        loop {
+            if let Some(len) = self.receive(host)? {
+                if self.rx[0] == response {
+                    if len < min_len {
+                        return Err(HostError::Att {
+                            handle: 0,
+                            code: AttErrorCode::InvalidPdu as u8,
+                        });
+                    }
+                    return Ok(len);
+                }
+
+                if self.rx[0] == ATT_ERROR_RSP && len >= 5 && self.rx[1] == request[0] {
+                    return Err(HostError::Att {
+                        handle: read_u16(&self.rx, 2),
+                        code: self.rx[4],
+                    });
+                }
+
+                log::warn!("dropping unexpected ATT PDU {:02x}", self.rx[0]);
+            }
+
+            if crate::current_millis() - started > TRANSACTION_TIMEOUT_MS {
+                return Err(HostError::Timeout);
+            }
+        }
+    }
+
+    /// Polls the host and handles notifications, indications and requests from the peer.
+    /// Events not about our connection's ATT bearer are handed back to the host.
+    ///
+    /// Returns the length of anything else received on our connection, which is left in `rx`.
+    fn receive(&mut self, host: &mut Host) -> Result<Option<usize>, HostError> {
+        let len = match host.poll_received()? {
+            Some(HostEvent::Att { handle, pdu }) if handle == self.connection => {
+                if pdu.is_empty() || pdu.len() > self.rx.len() {
+                    return Ok(None);
+                }
+
+                self.rx[..pdu.len()].copy_from_slice(pdu);
+                pdu.len()
+            }
+            Some(event @ HostEvent::Disconnected { handle, .. }) if handle == self.connection => {
+                // the application learns about it from the host, too
+                let pending = PendingEvent::new(&event);
+                host.push_back(pending);
+                return Err(HostError::Disconnected);
+            }
+            Some(event) => {
+                let pending = PendingEvent::new(&event);
+                host.push_back(pending);
+                return Ok(None);
+            }
+            None => return Ok(None),
+        };
+
+        let opcode = self.rx[0];
+        match opcode {
+            ATT_HANDLE_VALUE_NTF | ATT_HANDLE_VALUE_IND if len >= 3 => {
+                if let Some(on_notification) = self.on_notification {
+                    on_notification(read_u16(&self.rx, 1), &self.rx[3..len]);
+                }
+
+                if opcode == ATT_HANDLE_VALUE_IND {
+                    host.send_l2cap(self.connection, CID_ATT, &[ATT_HANDLE_VALUE_CFM])?;
+                }
+
+                Ok(None)
+            }
+            // requests have even opcodes, responses odd ones
+            _ if opcode & 0x01 == 0 => {
+                if !AttRequest::is_command(opcode) {
+                    let mut response = [0u8; 5];
+                    let len =
+                        write_error(&mut response, opcode, 0, AttErrorCode::RequestNotSupported);
+                    host.send_l2cap(self.connection, CID_ATT, &response[..len])?;
+                }
+
+                Ok(None)
+            }
+            _ => Ok(Some(len)),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! L2CAP fixed channels on top of ACL data packets.
+
+use super::event::read_u16;
+
+pub const CID_ATT: u16 = 0x0004;
+pub const CID_SIGNALING: u16 = 0x0005;
+pub const CID_SMP: u16 = 0x0006;
+
+/// Largest L2CAP payload we reassemble
+pub const MAX_L2CAP_PAYLOAD: usize = 251;
+
+const PB_FIRST_FLUSHABLE: u16 = 0b10;
+const PB_FIRST_NON_FLUSHABLE: u16 = 0b00;
+const PB_CONTINUATION: u16 = 0b01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum L2capError {
+    /// The ACL packet header is broken
+    InvalidPacket,
+    /// The PDU doesn't fit into [MAX_L2CAP_PAYLOAD] bytes
+    PduTooLarge,
+    /// A continuation fragment arrived without a start fragment
+    UnexpectedContinuation,
+}
+
+/// Reassembles L2CAP PDUs from ACL data packets.
+pub(crate) struct L2capReassembler {
+    data: [u8; MAX_L2CAP_PAYLOAD + 4],
+    index: usize,
+    expected: usize,
+    handle: u16,
+}
+
+impl L2capReassembler {
+    pub(crate) const fn new() -> L2capReassembler {
+        L2capReassembler {
+            data: [0u8; MAX_L2CAP_PAYLOAD + 4],
+            index: 0,
+            expected: 0,
+            handle: 0,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.index = 0;
+        self.expected = 0;
+    }
+
+    /// Takes the body of an ACL packet and returns the connection handle, channel and payload
+    /// once a PDU is complete.
+    pub(crate) fn push(&mut self, acl: &[u8]) -> Result<Option<(u16, u16, &[u8])>, L2capError> {
+        if acl.len() < 4 {
+            return Err(L2capError::InvalidPacket);
+        }
+
+        let handle_and_flags = read_u16(acl, 0);
+        let handle = handle_and_flags & 0x0fff;
+        let pb = (handle_and_flags >> 12) & 0b11;
+        let len = read_u16(acl, 2) as usize;
+        if acl.len() != len + 4 {
+            return Err(L2capError::InvalidPacket);
+        }
+        let fragment = &acl[4..];
+
+        match pb {
+            PB_FIRST_FLUSHABLE | PB_FIRST_NON_FLUSHABLE => {
+                if self.index != 0 {
+                    log::warn!("dropping incomplete L2CAP PDU");
+                }
+                self.reset();
+
+                if fragment.len() < 4 {
+                    return Err(L2capError::InvalidPacket);
+                }
+
+                let pdu_len = read_u16(fragment, 0) as usize;
+                if pdu_len > MAX_L2CAP_PAYLOAD {
+                    return Err(L2capError::PduTooLarge);
+                }
+
+                self.expected = pdu_len + 4;
+                self.handle = handle;
+            }
+            PB_CONTINUATION => {
+                if self.index == 0 || self.handle != handle {
+                    return Err(L2capError::UnexpectedContinuation);
+                }
+            }
+            _ => return Err(L2capError::InvalidPacket),
+        }
+
+        if self.index + fragment.len() > self.expected {
+            self.reset();
+            return Err(L2capError::PduTooLarge);
+        }
+
+        self.data[self.index..][..fragment.len()].copy_from_slice(fragment);
+        self.index += fragment.len();
+
+        if self.index < self.expected {
+            return Ok(None);
+        }
+
+        let len = self.index;
+        self.reset();
+
+        Ok(Some((
+            self.handle,
+            read_u16(&self.data, 2),
+            &self.data[4..len],
+        )))
+    }
+}
+
+/// Splits a L2CAP PDU into ACL packets of at most `acl_len` payload bytes and hands each
+/// ACL packet body (without the H4 indicator) to `f`.
+pub(crate) fn fragment<E>(
+    handle: u16,
+    cid: u16,
+    payload: &[u8],
+    acl_len: usize,
+    mut f: impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut pdu = [0u8; MAX_L2CAP_PAYLOAD + 4];
+    pdu[0..2].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+    pdu[2..4].copy_from_slice(&cid.to_le_bytes());
+    pdu[4..][..payload.len()].copy_from_slice(payload);
+    let pdu = &pdu[..payload.len() + 4];
+
+    let mut packet = [0u8; MAX_L2CAP_PAYLOAD + 8];
+    for (i, chunk) in pdu.chunks(acl_len).enumerate() {
+        let pb = if i == 0 {
+            PB_FIRST_NON_FLUSHABLE
+        } else {
+            PB_CONTINUATION
+        };
+
+        packet[0..2].copy_from_slice(&(handle | (pb << 12)).to_le_bytes());
+        packet[2..4].copy_from_slice(&(chunk.len() as u16).to_le_bytes());
+        packet[4..][..chunk.len()].copy_from_slice(chunk);
+        f(&packet[..chunk.len() + 4])?;
+    }
+
+    Ok(())
+}
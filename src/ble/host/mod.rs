@@ -0,0 +1,761 @@
+//! A minimal BLE host on top of [BleConnector].
+//!
+//! It covers what's needed to run a GATT server: HCI command and event handling, the
+//! L2CAP fixed channels, ATT and the security manager. Only a single connection is
+//! supported at a time.
+
+use log::{debug, trace, warn};
+
+use super::controller::{BleConnector, BleConnectorError};
+use super::hci::{HciPacket, HCI_MAX_PACKET_LEN};
+use super::ReceivedPacket;
+use crate::compat::queue::SimpleQueue;
+
+pub mod advertising;
+pub mod att;
+pub mod bond;
+pub mod command;
+pub mod connection;
+mod crypto;
+pub mod event;
+pub mod gatt;
+pub mod gatt_client;
+pub mod l2cap;
+pub mod scan;
+pub mod smp;
+
+use advertising::{AdvertisingData, AdvertisingError, AdvertisingParameters};
+use bond::BondStore;
+use connection::{ConnectionError, ConnectionParameters, InitiatorParameters};
+use event::{Address, AddressKind, ConnectionComplete, Event};
+use l2cap::{L2capError, L2capReassembler, CID_ATT, CID_SIGNALING, CID_SMP, MAX_L2CAP_PAYLOAD};
+use scan::{AdvertisingReports, ScanError, ScanParameters};
+use smp::{SecurityConfig, SecurityEvent, SecurityManager};
+
+/// How long to wait for a command to complete
+const COMMAND_TIMEOUT_MS: u64 = 1000;
+
+/// Packets received while waiting for a command are kept for [Host::poll], one less than this
+const DEFERRED_PACKETS: usize = 9;
+
+/// Events handed back by [Host::push_back] are kept for [Host::poll], one less than this
+const PENDING_EVENTS: usize = 5;
+
+/// ACL payload size to use if the controller doesn't report one
+const DEFAULT_ACL_LEN: usize = 27;
+
+/// Disconnection complete, encryption change, encryption key refresh and LE meta events
+const EVENT_MASK: u64 = (1 << 4) | (1 << 7) | (1 << 47) | (1 << 61);
+
+/// Connection complete, advertising report, connection update, remote features,
+/// LTK request, P-256 public key and DHKey complete
+const LE_EVENT_MASK: u64 = 0x19f;
+
+const SIGNALING_COMMAND_REJECT: u8 = 0x01;
+const SIGNALING_FLOW_CONTROL_CREDIT: u8 = 0x16;
+
+#[derive(Debug)]
+pub enum HostError {
+    Connector(BleConnectorError),
+    L2cap(L2capError),
+    Advertising(AdvertisingError),
+    Scan(ScanError),
+    Connection(ConnectionError),
+    /// The controller didn't answer a command in time
+    Timeout,
+    /// The controller answered a command with a non-zero status
+    CommandFailed {
+        opcode: u16,
+        status: u8,
+    },
+    /// The controller sent an event we couldn't parse
+    InvalidEvent,
+    /// The payload doesn't fit into a L2CAP PDU
+    PayloadTooLarge,
+    /// The connection is gone
+    Disconnected,
+    /// The peer answered an ATT request with an error
+    Att {
+        handle: u16,
+        code: u8,
+    },
+    /// [Host::enable_security] wasn't called
+    SecurityDisabled,
+}
+
+impl From<BleConnectorError> for HostError {
+    fn from(err: BleConnectorError) -> Self {
+        HostError::Connector(err)
+    }
+}
+
+impl From<AdvertisingError> for HostError {
+    fn from(err: AdvertisingError) -> Self {
+        HostError::Advertising(err)
+    }
+}
+
+impl From<ScanError> for HostError {
+    fn from(err: ScanError) -> Self {
+        HostError::Scan(err)
+    }
+}
+
+impl From<ConnectionError> for HostError {
+    fn from(err: ConnectionError) -> Self {
+        HostError::Connection(err)
+    }
+}
+
+impl From<L2capError> for HostError {
+    fn from(err: L2capError) -> Self {
+        HostError::L2cap(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum HostEvent<'a> {
+    Connected(ConnectionComplete),
+    Disconnected {
+        handle: u16,
+        reason: u8,
+    },
+    /// An ATT PDU received on the given connection
+    Att {
+        handle: u16,
+        pdu: &'a [u8],
+    },
+    AdvertisingReports(AdvertisingReports<'a>),
+    /// Pairing and encryption, only if security is enabled
+    Security(SecurityEvent),
+    /// Any other event
+    Event(Event<'a>),
+}
+
+/// A copy of an event returned by [Host::poll] which the receiver didn't handle, see
+/// [Host::push_back]
+pub(crate) enum PendingEvent {
+    Connected(ConnectionComplete),
+    Disconnected {
+        handle: u16,
+        reason: u8,
+    },
+    Security(SecurityEvent),
+    Att {
+        handle: u16,
+        len: u16,
+        pdu: [u8; MAX_L2CAP_PAYLOAD],
+    },
+    /// The HCI event packet, it's copied from the host's buffer by [Host::push_back]
+    HciEvent(ReceivedPacket),
+}
+
+impl PendingEvent {
+    pub(crate) fn new(event: &HostEvent) -> PendingEvent {
+        match *event {
+            HostEvent::Connected(connection) => PendingEvent::Connected(connection),
+            HostEvent::Disconnected { handle, reason } => {
+                PendingEvent::Disconnected { handle, reason }
+            }
+            HostEvent::Security(event) => PendingEvent::Security(event),
+            HostEvent::Att { handle, pdu } => {
+                let mut copy = [0u8; MAX_L2CAP_PAYLOAD];
+                copy[..pdu.len()].copy_from_slice(pdu);
+                PendingEvent::Att {
+                    handle,
+                    len: pdu.len() as u16,
+                    pdu: copy,
+                }
+            }
+            HostEvent::AdvertisingReports(_) | HostEvent::Event(_) => {
+                PendingEvent::HciEvent(ReceivedPacket {
+                    len: 0,
+                    data: [0u8; HCI_MAX_PACKET_LEN],
+                })
+            }
+        }
+    }
+}
+
+pub struct Host<'d> {
+    connector: BleConnector<'d>,
+    buf: [u8; HCI_MAX_PACKET_LEN],
+    /// Length of the HCI event packet in `buf` last returned by [Host::poll]
+    event_len: usize,
+    l2cap: L2capReassembler,
+    acl_len: usize,
+    security: Option<SecurityManager>,
+    deferred: SimpleQueue<ReceivedPacket, DEFERRED_PACKETS>,
+    pending: SimpleQueue<PendingEvent, PENDING_EVENTS>,
+}
+
+impl<'d> Host<'d> {
+    pub fn new(connector: BleConnector<'d>) -> Host<'d> {
+        Host {
+            connector,
+            buf: [0u8; HCI_MAX_PACKET_LEN],
+            event_len: 0,
+            l2cap: L2capReassembler::new(),
+            acl_len: DEFAULT_ACL_LEN,
+            security: None,
+            deferred: SimpleQueue::new(),
+            pending: SimpleQueue::new(),
+        }
+    }
+
+    /// Resets the controller and sets up the event masks.
+    ///
+    /// This disables security as the controller forgets its P-256 key.
+    pub fn init(&mut self) -> Result<(), HostError> {
+        self.security = None;
+        while self.deferred.dequeue().is_some() {}
+        while self.pending.dequeue().is_some() {}
+        self.command(command::RESET, &[])?;
+        self.command(command::SET_EVENT_MASK, &EVENT_MASK.to_le_bytes())?;
+        self.command(command::LE_SET_EVENT_MASK, &LE_EVENT_MASK.to_le_bytes())?;
+
+        let params = self.command(command::LE_READ_BUFFER_SIZE, &[])?;
+        if params.len() >= 2 {
+            let len = event::read_u16(params, 0) as usize;
+            if len != 0 {
+                self.acl_len = len;
+            }
+        }
+        debug!("using ACL packets of {} bytes", self.acl_len);
+
+        self.l2cap.reset();
+
+        Ok(())
+    }
+
+    /// Sends a command and waits for it to complete. Returns the return parameters following
+    /// the status.
+    ///
+    /// Everything else received while waiting is returned by [Host::poll] later on.
+    pub fn command(&mut self, opcode: u16, params: &[u8]) -> Result<&[u8], HostError> {
+        let mut buf = [0u8; command::MAX_PARAMETERS_LEN + 3];
+        let len = command::write_command(&mut buf, opcode, params);
+        self.connector
+            .send_packet(HciPacket::Command(&buf[..len]))?;
+
+        let started = crate::current_millis();
+        loop {
+            if let Some(packet) = self.connector.receive_packet(&mut self.buf)? {
+                match packet {
+                    HciPacket::Event(body) => match Event::parse(body) {
+                        Some(Event::CommandComplete { opcode: op, .. })
+                        | Some(Event::CommandStatus { opcode: op, .. })
+                            if op == opcode =>
+                        {
+                            break;
+                        }
+                        _ => defer(&mut self.deferred, &packet),
+                    },
+                    _ => defer(&mut self.deferred, &packet),
+                }
+            }
+
+            if crate::current_millis() - started > COMMAND_TIMEOUT_MS {
+                return Err(HostError::Timeout);
+            }
+        }
+
+        match Event::parse(&self.buf[1..]) {
+            Some(Event::CommandComplete {
+                status,
+                return_params,
+                ..
+            }) => {
+                if status != 0 {
+                    Err(HostError::CommandFailed { opcode, status })
+                } else {
+                    Ok(return_params)
+                }
+            }
+            Some(Event::CommandStatus { status, .. }) => {
+                if status != 0 {
+                    Err(HostError::CommandFailed { opcode, status })
+                } else {
+                    Ok(&[])
+                }
+            }
+            _ => Err(HostError::InvalidEvent),
+        }
+    }
+
+    /// Returns the public device address
+    pub fn read_bd_addr(&mut self) -> Result<[u8; 6], HostError> {
+        let params = self.command(command::READ_BD_ADDR, &[])?;
+        if params.len() < 6 {
+            return Err(HostError::InvalidEvent);
+        }
+
+        let mut addr = [0u8; 6];
+        addr.copy_from_slice(&params[..6]);
+        Ok(addr)
+    }
+
+    pub fn set_advertising_parameters(
+        &mut self,
+        params: &AdvertisingParameters,
+    ) -> Result<(), HostError> {
+        self.command(command::LE_SET_ADVERTISING_PARAMETERS, &params.encode()?)?;
+        Ok(())
+    }
+
+    pub fn set_advertising_data(&mut self, data: &AdvertisingData) -> Result<(), HostError> {
+        self.command(command::LE_SET_ADVERTISING_DATA, &significant_data(data))?;
+        Ok(())
+    }
+
+    pub fn set_scan_response_data(&mut self, data: &AdvertisingData) -> Result<(), HostError> {
+        self.command(command::LE_SET_SCAN_RESPONSE_DATA, &significant_data(data))?;
+        Ok(())
+    }
+
+    /// Configures advertising and enables it.
+    ///
+    /// The scan response data is only used in connectable and scannable mode.
+    pub fn start_advertising(
+        &mut self,
+        params: &AdvertisingParameters,
+        data: &AdvertisingData,
+        scan_response: Option<&AdvertisingData>,
+    ) -> Result<(), HostError> {
+        self.set_advertising_parameters(params)?;
+        self.set_advertising_data(data)?;
+        if let Some(scan_response) = scan_response {
+            self.set_scan_response_data(scan_response)?;
+        }
+        self.set_advertise_enable(true)
+    }
+
+    pub fn set_advertise_enable(&mut self, enable: bool) -> Result<(), HostError> {
+        self.command(command::LE_SET_ADVERTISE_ENABLE, &[enable as u8])?;
+        Ok(())
+    }
+
+    pub fn set_scan_parameters(&mut self, params: &ScanParameters) -> Result<(), HostError> {
+        self.command(command::LE_SET_SCAN_PARAMETERS, &params.encode()?)?;
+        Ok(())
+    }
+
+    /// Starts or stops scanning. Reports are returned by [Host::poll].
+    pub fn set_scan_enable(
+        &mut self,
+        enable: bool,
+        filter_duplicates: bool,
+    ) -> Result<(), HostError> {
+        self.command(
+            command::LE_SET_SCAN_ENABLE,
+            &[enable as u8, filter_duplicates as u8],
+        )?;
+        Ok(())
+    }
+
+    /// Starts connecting to a peer. The connection is reported by [Host::poll].
+    pub fn create_connection(
+        &mut self,
+        peer: &Address,
+        params: &InitiatorParameters,
+    ) -> Result<(), HostError> {
+        self.command(command::LE_CREATE_CONNECTION, &params.encode(peer)?)?;
+        Ok(())
+    }
+
+    pub fn cancel_create_connection(&mut self) -> Result<(), HostError> {
+        self.command(command::LE_CREATE_CONNECTION_CANCEL, &[])?;
+        Ok(())
+    }
+
+    /// Connects to a peer and waits for the connection to be established.
+    ///
+    /// Everything else received while waiting is dropped.
+    pub fn connect(
+        &mut self,
+        peer: &Address,
+        params: &InitiatorParameters,
+        timeout_ms: u64,
+    ) -> Result<ConnectionComplete, HostError> {
+        self.create_connection(peer, params)?;
+
+        let started = crate::current_millis();
+        loop {
+            match self.poll()? {
+                Some(HostEvent::Connected(connection)) => return Ok(connection),
+                Some(HostEvent::Event(Event::LeConnectionComplete(connection))) => {
+                    return Err(HostError::CommandFailed {
+                        opcode: command::LE_CREATE_CONNECTION,
+                        status: connection.status,
+                    })
+                }
+                _ => (),
+            }
+
+            if crate::current_millis() - started > timeout_ms {
+                self.cancel_create_connection()?;
+                return Err(HostError::Timeout);
+            }
+        }
+    }
+
+    /// Requests new parameters for a connection we are central of
+    pub fn update_connection(
+        &mut self,
+        handle: u16,
+        params: &ConnectionParameters,
+    ) -> Result<(), HostError> {
+        self.command(
+            command::LE_CONNECTION_UPDATE,
+            &params.encode_update(handle)?,
+        )?;
+        Ok(())
+    }
+
+    /// Enables pairing and encryption, call it after [Host::init] while not connected.
+    ///
+    /// Bonds are kept in `bonds` if given. We always use the public address.
+    pub fn enable_security(
+        &mut self,
+        config: SecurityConfig,
+        bonds: Option<&'static mut dyn BondStore>,
+    ) -> Result<(), HostError> {
+        let own_address = Address {
+            kind: AddressKind::Public,
+            addr: self.read_bd_addr()?,
+        };
+
+        self.command(command::LE_READ_LOCAL_P256_PUBLIC_KEY, &[])?;
+
+        let started = crate::current_millis();
+        let public_key = loop {
+            if let Some(packet) = self.connector.receive_packet(&mut self.buf)? {
+                match packet {
+                    HciPacket::Event(body) => match Event::parse(body) {
+                        Some(Event::LeReadLocalP256PublicKeyComplete { status, key }) => {
+                            if status != 0 {
+                                return Err(HostError::CommandFailed {
+                                    opcode: command::LE_READ_LOCAL_P256_PUBLIC_KEY,
+                                    status,
+                                });
+                            }
+
+                            let mut public_key = [0u8; 64];
+                            public_key.copy_from_slice(key);
+                            break public_key;
+                        }
+                        _ => defer(&mut self.deferred, &packet),
+                    },
+                    _ => defer(&mut self.deferred, &packet),
+                }
+            }
+
+            if crate::current_millis() - started > COMMAND_TIMEOUT_MS {
+                return Err(HostError::Timeout);
+            }
+        };
+
+        self.security = Some(SecurityManager::new(config, bonds, own_address, public_key));
+
+        Ok(())
+    }
+
+    /// Encrypts the connection. As central this uses the bond or pairs, as peripheral the
+    /// central is asked to do so. The outcome is reported by [Host::poll].
+    pub fn start_security(&mut self, handle: u16) -> Result<(), HostError> {
+        let (security, mut transport) = self.security()?;
+        security.start_security(&mut transport, handle)
+    }
+
+    /// Answers [SecurityEvent::PasskeyRequest], `None` cancels pairing
+    pub fn passkey_reply(&mut self, passkey: Option<u32>) -> Result<(), HostError> {
+        let (security, mut transport) = self.security()?;
+        security.passkey_reply(&mut transport, passkey)
+    }
+
+    /// Answers [SecurityEvent::NumericComparison]
+    pub fn numeric_comparison_reply(&mut self, accept: bool) -> Result<(), HostError> {
+        let (security, mut transport) = self.security()?;
+        security.numeric_comparison_reply(&mut transport, accept)
+    }
+
+    pub fn is_encrypted(&self, handle: u16) -> bool {
+        self.security
+            .as_ref()
+            .map(|security| security.is_encrypted(handle))
+            .unwrap_or(false)
+    }
+
+    /// The bond store given to [Host::enable_security]
+    pub fn bonds(&mut self) -> Option<&mut (dyn BondStore + 'static)> {
+        self.security.as_mut().and_then(|security| security.bonds())
+    }
+
+    fn security(&mut self) -> Result<(&mut SecurityManager, Transport<'_, 'd>), HostError> {
+        let security = self.security.as_mut().ok_or(HostError::SecurityDisabled)?;
+        Ok((
+            security,
+            Transport {
+                connector: &mut self.connector,
+                acl_len: self.acl_len,
+            },
+        ))
+    }
+
+    /// Terminates a connection. The disconnection is reported by [Host::poll].
+    pub fn disconnect(&mut self, handle: u16, reason: u8) -> Result<(), HostError> {
+        let handle = handle.to_le_bytes();
+        self.command(command::DISCONNECT, &[handle[0], handle[1], reason])?;
+        Ok(())
+    }
+
+    /// Handles the next packet received from the controller if there is one. Events a GATT
+    /// client procedure didn't handle and packets received while waiting for a command to
+    /// complete come first.
+    ///
+    /// L2CAP signaling requests are rejected here, SMP is handled by the security manager
+    /// or rejected if security isn't enabled. Everything else is returned.
+    pub fn poll(&mut self) -> Result<Option<HostEvent<'_>>, HostError> {
+        match self.pending.dequeue() {
+            Some(pending) => self.pending_event(pending).map(Some),
+            None => self.poll_received(),
+        }
+    }
+
+    /// Like [Host::poll] but skips the events handed back by [Host::push_back], procedures
+    /// waiting for an event use this
+    pub(crate) fn poll_received(&mut self) -> Result<Option<HostEvent<'_>>, HostError> {
+        if let Some(security) = self.security.as_mut() {
+            security.check_timeout();
+            if let Some(event) = security.dequeue_event() {
+                return Ok(Some(HostEvent::Security(event)));
+            }
+        }
+
+        let packet = match self.deferred.dequeue() {
+            Some(deferred) => {
+                let len = deferred.len as usize;
+                self.buf[..len].copy_from_slice(&deferred.data[..len]);
+                HciPacket::from_h4(&self.buf[..len]).map_err(BleConnectorError::from)?
+            }
+            None => match self.connector.receive_packet(&mut self.buf)? {
+                Some(packet) => packet,
+                None => return Ok(None),
+            },
+        };
+
+        let mut transport = Transport {
+            connector: &mut self.connector,
+            acl_len: self.acl_len,
+        };
+
+        match packet {
+            HciPacket::Event(body) => {
+                self.event_len = packet.h4_len();
+                let event = Event::parse(body).ok_or(HostError::InvalidEvent)?;
+                trace!("received {:?}", event);
+
+                match self.security.as_mut() {
+                    Some(security) => {
+                        security.handle_event(&mut transport, &event)?;
+
+                        // these are taken care of by the security manager
+                        if matches!(
+                            event,
+                            Event::EncryptionChange { .. }
+                                | Event::EncryptionKeyRefreshComplete { .. }
+                                | Event::LeLongTermKeyRequest { .. }
+                                | Event::LeGenerateDhKeyComplete { .. }
+                        ) {
+                            return Ok(security.dequeue_event().map(HostEvent::Security));
+                        }
+                    }
+                    None => {
+                        if let Event::LeLongTermKeyRequest { handle, .. } = event {
+                            smp::negative_ltk_reply(&mut transport, handle)?;
+                        }
+                    }
+                }
+
+                Ok(Some(match event {
+                    Event::LeConnectionComplete(connection) if connection.status == 0 => {
+                        self.l2cap.reset();
+                        HostEvent::Connected(connection)
+                    }
+                    Event::DisconnectionComplete {
+                        status: 0,
+                        handle,
+                        reason,
+                    } => HostEvent::Disconnected { handle, reason },
+                    Event::LeAdvertisingReport(reports) => HostEvent::AdvertisingReports(reports),
+                    event => HostEvent::Event(event),
+                }))
+            }
+            HciPacket::Acl(body) => {
+                let (handle, cid, payload) = match self.l2cap.push(body)? {
+                    Some(pdu) => pdu,
+                    None => return Ok(None),
+                };
+
+                match cid {
+                    CID_ATT => {
+                        return Ok(Some(HostEvent::Att {
+                            handle,
+                            pdu: payload,
+                        }))
+                    }
+                    CID_SIGNALING if payload.len() >= 2 => {
+                        // requests have even codes, responses odd ones
+                        if payload[0] & 0x01 == 0 && payload[0] != SIGNALING_FLOW_CONTROL_CREDIT {
+                            let reject = [SIGNALING_COMMAND_REJECT, payload[1], 2, 0, 0, 0];
+                            transport.send_l2cap(handle, CID_SIGNALING, &reject)?;
+                        }
+                    }
+                    CID_SMP => match self.security.as_mut() {
+                        Some(security) => {
+                            security.handle_smp(&mut transport, handle, payload)?;
+                            return Ok(security.dequeue_event().map(HostEvent::Security));
+                        }
+                        None => {
+                            if payload.first() == Some(&smp::PAIRING_REQUEST)
+                                || payload.first() == Some(&smp::SECURITY_REQUEST)
+                            {
+                                let failed =
+                                    [smp::PAIRING_FAILED, smp::REASON_PAIRING_NOT_SUPPORTED];
+                                transport.send_l2cap(handle, CID_SMP, &failed)?;
+                            }
+                        }
+                    },
+                    _ => trace!("dropping L2CAP PDU for channel {}", cid),
+                }
+
+                Ok(None)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Keeps an event the last [Host::poll_received] returned for the next [Host::poll], used
+    /// by procedures which wait for specific events
+    pub(crate) fn push_back(&mut self, mut event: PendingEvent) {
+        if self.pending.is_full() {
+            warn!("dropping event, too many received during a procedure");
+            return;
+        }
+
+        if let PendingEvent::HciEvent(packet) = &mut event {
+            packet.len = self.event_len as u16;
+            packet.data[..self.event_len].copy_from_slice(&self.buf[..self.event_len]);
+        }
+        self.pending.enqueue(event);
+    }
+
+    fn pending_event(&mut self, pending: PendingEvent) -> Result<HostEvent<'_>, HostError> {
+        Ok(match pending {
+            PendingEvent::Connected(connection) => HostEvent::Connected(connection),
+            PendingEvent::Disconnected { handle, reason } => {
+                HostEvent::Disconnected { handle, reason }
+            }
+            PendingEvent::Security(event) => HostEvent::Security(event),
+            PendingEvent::Att { handle, len, pdu } => {
+                let len = len as usize;
+                self.buf[..len].copy_from_slice(&pdu[..len]);
+                HostEvent::Att {
+                    handle,
+                    pdu: &self.buf[..len],
+                }
+            }
+            PendingEvent::HciEvent(packet) => {
+                let len = packet.len as usize;
+                self.buf[..len].copy_from_slice(&packet.data[..len]);
+                self.event_len = len;
+
+                // it was handled by the security manager already
+                match HciPacket::from_h4(&self.buf[..len]).map_err(BleConnectorError::from)? {
+                    HciPacket::Event(body) => {
+                        match Event::parse(body).ok_or(HostError::InvalidEvent)? {
+                            Event::LeAdvertisingReport(reports) => {
+                                HostEvent::AdvertisingReports(reports)
+                            }
+                            event => HostEvent::Event(event),
+                        }
+                    }
+                    _ => return Err(HostError::InvalidEvent),
+                }
+            }
+        })
+    }
+
+    /// Sends a L2CAP PDU on a fixed channel.
+    pub fn send_l2cap(&mut self, handle: u16, cid: u16, payload: &[u8]) -> Result<(), HostError> {
+        Transport {
+            connector: &mut self.connector,
+            acl_len: self.acl_len,
+        }
+        .send_l2cap(handle, cid, payload)
+    }
+}
+
+/// Sends to the controller and the peer while a received packet still borrows the host's
+/// buffers
+pub(crate) struct Transport<'a, 'd> {
+    connector: &'a mut BleConnector<'d>,
+    acl_len: usize,
+}
+
+impl<'a, 'd> Transport<'a, 'd> {
+    pub(crate) fn send_l2cap(
+        &mut self,
+        handle: u16,
+        cid: u16,
+        payload: &[u8],
+    ) -> Result<(), HostError> {
+        if payload.len() > MAX_L2CAP_PAYLOAD {
+            return Err(HostError::PayloadTooLarge);
+        }
+
+        let connector = &mut *self.connector;
+        l2cap::fragment(handle, cid, payload, self.acl_len, |acl| {
+            connector
+                .send_packet(HciPacket::Acl(acl))
+                .map_err(HostError::from)
+        })
+    }
+
+    /// Sends a command without waiting for it to complete. Its completion is returned by
+    /// [Host::poll].
+    pub(crate) fn send_command(&mut self, opcode: u16, params: &[u8]) -> Result<(), HostError> {
+        let mut buf = [0u8; command::MAX_PARAMETERS_LEN + 3];
+        let len = command::write_command(&mut buf, opcode, params);
+        self.connector
+            .send_packet(HciPacket::Command(&buf[..len]))?;
+        Ok(())
+    }
+}
+
+/// Keeps a packet received while waiting for a command for [Host::poll]
+fn defer(deferred: &mut SimpleQueue<ReceivedPacket, DEFERRED_PACKETS>, packet: &HciPacket) {
+    if deferred.is_full() {
+        warn!("dropping packet, too many received while waiting for a command");
+        return;
+    }
+
+    let mut data = [0u8; HCI_MAX_PACKET_LEN];
+    let len = packet.h4_len();
+    data[0] = packet.indicator();
+    data[1..len].copy_from_slice(packet.body());
+    deferred.enqueue(ReceivedPacket {
+        len: len as u16,
+        data,
+    });
+}
+
+/// Advertising and scan response data is always sent as 31 bytes prefixed by the length
+fn significant_data(data: &AdvertisingData) -> [u8; 32] {
+    let data = data.as_slice();
+
+    let mut params = [0u8; 32];
+    params[0] = data.len() as u8;
+    params[1..][..data.len()].copy_from_slice(data);
+    params
+}
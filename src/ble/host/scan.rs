@@ -0,0 +1,391 @@
+//! Scanning for advertisers and parsing of LE Advertising Reports.
+
+use super::advertising::AdStructures;
+use super::event::{Address, AddressKind};
+
+/// Scan intervals and windows are given in units of 0.625ms
+const MIN_SCAN_INTERVAL: u16 = 0x0004;
+const MAX_SCAN_INTERVAL: u16 = 0x4000;
+
+/// RSSI value meaning the controller couldn't measure it
+const RSSI_NOT_AVAILABLE: i8 = 127;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanError {
+    /// The scan interval is out of range
+    InvalidInterval,
+    /// The scan window is out of range or longer than the interval
+    InvalidWindow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanParameters {
+    /// Send scan requests to get the scan response data too
+    pub active: bool,
+    /// Interval in units of 0.625ms
+    pub interval: u16,
+    /// Window in units of 0.625ms
+    pub window: u16,
+    pub own_address_kind: AddressKind,
+    /// Only report devices in the filter accept list
+    pub filter_policy: u8,
+}
+
+impl Default for ScanParameters {
+    fn default() -> Self {
+        ScanParameters {
+            active: false,
+            // 10ms which is the controller's default
+            interval: 0x0010,
+            window: 0x0010,
+            own_address_kind: AddressKind::Public,
+            filter_policy: 0,
+        }
+    }
+}
+
+impl ScanParameters {
+    pub fn with_active(self, active: bool) -> ScanParameters {
+        ScanParameters { active, ..self }
+    }
+
+    /// Sets interval and window given in milliseconds, they are clamped to the range allowed
+    /// by the spec (2.5ms to 10.24s)
+    pub fn with_timing_ms(self, interval: u32, window: u32) -> ScanParameters {
+        let to_units = |ms: u32| {
+            (ms as u64 * 1000 / 625).clamp(MIN_SCAN_INTERVAL as u64, MAX_SCAN_INTERVAL as u64)
+                as u16
+        };
+
+        ScanParameters {
+            interval: to_units(interval),
+            window: to_units(window),
+            ..self
+        }
+    }
+
+    /// Encodes the parameters of the LE Set Scan Parameters command
+    pub(crate) fn encode(&self) -> Result<[u8; 7], ScanError> {
+        if !(MIN_SCAN_INTERVAL..=MAX_SCAN_INTERVAL).contains(&self.interval) {
+            return Err(ScanError::InvalidInterval);
+        }
+
+        if self.window < MIN_SCAN_INTERVAL || self.window > self.interval {
+            return Err(ScanError::InvalidWindow);
+        }
+
+        let mut params = [0u8; 7];
+        params[0] = self.active as u8;
+        params[1..3].copy_from_slice(&self.interval.to_le_bytes());
+        params[3..5].copy_from_slice(&self.window.to_le_bytes());
+        params[5] = self.own_address_kind.to_u8();
+        params[6] = self.filter_policy;
+
+        Ok(params)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportKind {
+    /// ADV_IND
+    Connectable,
+    /// ADV_DIRECT_IND
+    DirectedConnectable,
+    /// ADV_SCAN_IND
+    Scannable,
+    /// ADV_NONCONN_IND
+    NonConnectable,
+    /// SCAN_RSP
+    ScanResponse,
+    Unknown(u8),
+}
+
+impl ReportKind {
+    fn from_u8(kind: u8) -> ReportKind {
+        match kind {
+            0x00 => ReportKind::Connectable,
+            0x01 => ReportKind::DirectedConnectable,
+            0x02 => ReportKind::Scannable,
+            0x03 => ReportKind::NonConnectable,
+            0x04 => ReportKind::ScanResponse,
+            kind => ReportKind::Unknown(kind),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdvertisingReport<'a> {
+    pub kind: ReportKind,
+    pub address: Address,
+    /// Signal strength in dBm, `None` if not available
+    pub rssi: Option<i8>,
+    /// The raw advertising or scan response data
+    pub data: &'a [u8],
+}
+
+impl<'a> AdvertisingReport<'a> {
+    pub fn ad_structures(&self) -> AdStructures<'a> {
+        AdStructures::new(self.data)
+    }
+}
+
+/// The reports contained in a LE Advertising Report event.
+///
+/// Iteration stops early if a report is truncated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdvertisingReports<'a> {
+    remaining: u8,
+    data: &'a [u8],
+}
+
+impl<'a> AdvertisingReports<'a> {
+    /// Takes the parameters of the subevent following the subevent code
+    pub fn new(data: &'a [u8]) -> AdvertisingReports<'a> {
+        match data.split_first() {
+            Some((count, data)) => AdvertisingReports {
+                remaining: *count,
+                data,
+            },
+            None => AdvertisingReports { remaining: 0, data },
+        }
+    }
+}
+
+impl<'a> Iterator for AdvertisingReports<'a> {
+    type Item = AdvertisingReport<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        // event type, address type, address, data length, data, RSSI
+        let data = self.data;
+        if data.len() < 9 || data.len() < 10 + data[8] as usize {
+            self.remaining = 0;
+            return None;
+        }
+
+        let len = data[8] as usize;
+        let mut addr = [0u8; 6];
+        addr.copy_from_slice(&data[2..8]);
+        let rssi = data[9 + len] as i8;
+
+        self.data = &data[10 + len..];
+
+        Some(AdvertisingReport {
+            kind: ReportKind::from_u8(data[0]),
+            address: Address {
+                kind: AddressKind::from_u8(data[1]),
+                addr,
+            },
+            rssi: if rssi == RSSI_NOT_AVAILABLE {
+                None
+            } else {
+                Some(rssi)
+            },
+            data: &data[9..][..len],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble::host::advertising::{AdStructure, AdvertisingData, Uuids16};
+
+    /// Two reports: an ADV_IND with flags and a complete local name, a SCAN_RSP without RSSI
+    const TWO_REPORTS: &[u8] = &[
+        0x02, // number of reports
+        0x00, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, // ADV_IND, public address
+        0x07, // data length
+        0x02, 0x01, 0x06, 0x03, 0x09, b'h', b'i', // flags, complete local name
+        0xc4, // RSSI -60 dBm
+        0x04, 0x01, 0x11, 0x12, 0x13, 0x14, 0x15, 0xd6, // SCAN_RSP, random address
+        0x04, // data length
+        0x03, 0x03, 0x0d, 0x18, // complete list of 16 bit UUIDs: 0x180d
+        0x7f, // RSSI not available
+    ];
+
+    #[test]
+    fn multiple_reports() {
+        let mut reports = AdvertisingReports::new(TWO_REPORTS);
+
+        let first = reports.next().unwrap();
+        assert_eq!(first.kind, ReportKind::Connectable);
+        assert_eq!(
+            first.address,
+            Address {
+                kind: AddressKind::Public,
+                addr: [0x01, 0x02, 0x03, 0x04, 0x05, 0x06],
+            }
+        );
+        assert_eq!(first.rssi, Some(-60));
+        assert_eq!(first.data, &TWO_REPORTS[10..17]);
+
+        let mut structures = first.ad_structures();
+        assert_eq!(structures.next(), Some(AdStructure::Flags(0x06)));
+        assert_eq!(
+            structures.next(),
+            Some(AdStructure::CompleteLocalName("hi"))
+        );
+        assert_eq!(structures.next(), None);
+
+        let second = reports.next().unwrap();
+        assert_eq!(second.kind, ReportKind::ScanResponse);
+        assert_eq!(second.address.kind, AddressKind::Random);
+        assert_eq!(second.address.addr, [0x11, 0x12, 0x13, 0x14, 0x15, 0xd6]);
+        assert_eq!(second.rssi, None);
+
+        match second.ad_structures().next() {
+            Some(AdStructure::ServiceUuids16(uuids)) => {
+                assert_eq!(uuids.len(), 1);
+                assert!(uuids.contains(0x180d));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        assert_eq!(reports.next(), None);
+    }
+
+    #[test]
+    fn empty_event() {
+        assert_eq!(AdvertisingReports::new(&[]).next(), None);
+        assert_eq!(AdvertisingReports::new(&[0x00]).next(), None);
+    }
+
+    #[test]
+    fn truncated_reports() {
+        // the second report is cut off at every possible offset
+        for len in 18..TWO_REPORTS.len() {
+            let mut reports = AdvertisingReports::new(&TWO_REPORTS[..len]);
+            assert_eq!(reports.next().map(|report| report.rssi), Some(Some(-60)));
+            assert_eq!(reports.next(), None);
+            assert_eq!(reports.next(), None);
+        }
+
+        // the first one too
+        for len in 1..18 {
+            let mut reports = AdvertisingReports::new(&TWO_REPORTS[..len]);
+            assert_eq!(reports.next(), None);
+            assert_eq!(reports.next(), None);
+        }
+    }
+
+    #[test]
+    fn more_reports_announced_than_present() {
+        let mut data = [0u8; 64];
+        data[..TWO_REPORTS.len()].copy_from_slice(TWO_REPORTS);
+        data[0] = 3;
+
+        let reports = AdvertisingReports::new(&data[..TWO_REPORTS.len()]);
+        assert_eq!(reports.count(), 2);
+    }
+
+    #[test]
+    fn rssi() {
+        let rssi = |value: u8| {
+            let mut data = [0u8; 64];
+            data[..TWO_REPORTS.len()].copy_from_slice(TWO_REPORTS);
+            data[17] = value;
+            AdvertisingReports::new(&data[..TWO_REPORTS.len()])
+                .next()
+                .unwrap()
+                .rssi
+        };
+
+        assert_eq!(rssi(0x7f), None);
+        assert_eq!(rssi(0x7e), Some(126));
+        assert_eq!(rssi(0x80), Some(-128));
+        assert_eq!(rssi(0x00), Some(0));
+    }
+
+    #[test]
+    fn zero_length_ad_structure_ends_the_data() {
+        let data = [0x02, 0x01, 0x06, 0x00, 0x03, 0x09, b'h', b'i'];
+        let mut structures = AdStructures::new(&data);
+        assert_eq!(structures.next(), Some(AdStructure::Flags(0x06)));
+        assert_eq!(structures.next(), None);
+        assert_eq!(structures.next(), None);
+    }
+
+    #[test]
+    fn ad_structure_exceeding_the_data() {
+        let data = [0x02, 0x01, 0x06, 0x05, 0x09, b'h', b'i'];
+        let mut structures = AdStructures::new(&data);
+        assert_eq!(structures.next(), Some(AdStructure::Flags(0x06)));
+        assert_eq!(structures.next(), None);
+    }
+
+    #[test]
+    fn uuid_lists_of_the_wrong_length() {
+        let data = [0x04, 0x02, 0x0d, 0x18, 0x0f];
+        assert_eq!(
+            AdStructures::new(&data).next(),
+            Some(AdStructure::Unknown {
+                ad_type: 0x02,
+                data: &[0x0d, 0x18, 0x0f],
+            })
+        );
+
+        let mut data = [0u8; 17];
+        data[0] = 16;
+        data[1] = 0x07;
+        assert_eq!(
+            AdStructures::new(&data).next(),
+            Some(AdStructure::Unknown {
+                ad_type: 0x07,
+                data: &[0u8; 15],
+            })
+        );
+    }
+
+    #[test]
+    fn uuid_lists() {
+        let mut data = [0u8; 24];
+        data[..6].copy_from_slice(&[0x05, 0x02, 0x0d, 0x18, 0x0f, 0x18]);
+        data[6] = 17;
+        data[7] = 0x07;
+        for (i, b) in data[8..24].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let mut structures = AdStructures::new(&data);
+        assert_eq!(
+            structures.next(),
+            Some(AdStructure::IncompleteServiceUuids16(Uuids16::new(&[
+                0x180d, 0x180f
+            ])))
+        );
+
+        let mut expected = [0u8; 16];
+        expected.copy_from_slice(&data[8..24]);
+        assert_eq!(
+            structures.next(),
+            Some(AdStructure::ServiceUuids128(&[expected]))
+        );
+        assert_eq!(structures.next(), None);
+    }
+
+    #[test]
+    fn uuid_list_round_trip() {
+        let sent = AdStructure::ServiceUuids16(Uuids16::new(&[0x1809, 0xfeaa]));
+        let data = AdvertisingData::from_structures(&[sent]).unwrap();
+
+        let mut structures = data.structures();
+        assert_eq!(structures.next(), Some(sent));
+        assert_eq!(structures.next(), None);
+    }
+
+    #[test]
+    fn timing() {
+        let params = ScanParameters::default().with_timing_ms(100, 50);
+        assert_eq!(params.interval, 160);
+        assert_eq!(params.window, 80);
+
+        let params = ScanParameters::default().with_timing_ms(u32::MAX, 0);
+        assert_eq!(params.interval, MAX_SCAN_INTERVAL);
+        assert_eq!(params.window, MIN_SCAN_INTERVAL);
+        assert!(params.encode().is_ok());
+    }
+}
@@ -0,0 +1,1431 @@
+//! The security manager: pairing, key distribution and encryption of the link.
+//!
+//! LE Secure Connections pairing supports Just Works, passkey entry and numeric comparison.
+//! Legacy pairing is only available with the `ble_legacy_pairing` feature. Out of band
+//! data isn't supported.
+//!
+//! The controller generates the P-256 key pair and computes the Diffie-Hellman key, the
+//! rest of the cryptographic toolbox is done in software.
+
+use log::{debug, warn};
+
+use crate::compat::queue::SimpleQueue;
+
+use super::bond::{Bond, BondStore, LtkInfo};
+use super::command;
+use super::crypto;
+use super::event::{read_u16, Address, AddressKind, ConnectionComplete, Event, Role};
+use super::l2cap::CID_SMP;
+use super::{HostError, Transport};
+
+pub(crate) const PAIRING_REQUEST: u8 = 0x01;
+const PAIRING_RESPONSE: u8 = 0x02;
+const PAIRING_CONFIRM: u8 = 0x03;
+const PAIRING_RANDOM: u8 = 0x04;
+pub(crate) const PAIRING_FAILED: u8 = 0x05;
+const ENCRYPTION_INFORMATION: u8 = 0x06;
+const CENTRAL_IDENTIFICATION: u8 = 0x07;
+const IDENTITY_INFORMATION: u8 = 0x08;
+const IDENTITY_ADDRESS_INFORMATION: u8 = 0x09;
+const SIGNING_INFORMATION: u8 = 0x0a;
+pub(crate) const SECURITY_REQUEST: u8 = 0x0b;
+const PAIRING_PUBLIC_KEY: u8 = 0x0c;
+const PAIRING_DHKEY_CHECK: u8 = 0x0d;
+const KEYPRESS_NOTIFICATION: u8 = 0x0e;
+
+pub const REASON_PASSKEY_ENTRY_FAILED: u8 = 0x01;
+pub const REASON_AUTHENTICATION_REQUIREMENTS: u8 = 0x03;
+pub const REASON_CONFIRM_VALUE_FAILED: u8 = 0x04;
+pub const REASON_PAIRING_NOT_SUPPORTED: u8 = 0x05;
+pub const REASON_ENCRYPTION_KEY_SIZE: u8 = 0x06;
+pub const REASON_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+pub const REASON_UNSPECIFIED: u8 = 0x08;
+pub const REASON_INVALID_PARAMETERS: u8 = 0x0a;
+pub const REASON_DHKEY_CHECK_FAILED: u8 = 0x0b;
+pub const REASON_NUMERIC_COMPARISON_FAILED: u8 = 0x0c;
+
+const AUTH_BONDING: u8 = 0x01;
+const AUTH_MITM: u8 = 0x04;
+const AUTH_SECURE_CONNECTIONS: u8 = 0x08;
+
+const KEY_ENC: u8 = 0x01;
+const KEY_ID: u8 = 0x02;
+const KEY_SIGN: u8 = 0x04;
+
+const MIN_KEY_SIZE: u8 = 7;
+const MAX_KEY_SIZE: u8 = 16;
+
+/// Passkeys have six digits
+const MAX_PASSKEY: u32 = 999_999;
+
+/// Passkey entry confirms the passkey bit by bit
+const PASSKEY_ROUNDS: u8 = 20;
+
+/// Pairing fails if the peer doesn't continue it within 30s
+const SMP_TIMEOUT_MS: u64 = 30_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoCapabilities {
+    DisplayOnly = 0,
+    DisplayYesNo = 1,
+    KeyboardOnly = 2,
+    NoInputNoOutput = 3,
+    KeyboardDisplay = 4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecurityConfig {
+    pub io_capabilities: IoCapabilities,
+    /// Distribute keys and keep them in the bond store
+    pub bonding: bool,
+    /// Require protection against man-in-the-middle attacks, which rules out Just Works
+    pub mitm: bool,
+    /// Refuse legacy pairing even if the feature is enabled
+    pub secure_connections_only: bool,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        SecurityConfig {
+            io_capabilities: IoCapabilities::NoInputNoOutput,
+            bonding: true,
+            mitm: false,
+            secure_connections_only: false,
+        }
+    }
+}
+
+impl SecurityConfig {
+    pub fn with_io_capabilities(self, io_capabilities: IoCapabilities) -> SecurityConfig {
+        SecurityConfig {
+            io_capabilities,
+            ..self
+        }
+    }
+
+    pub fn with_bonding(self, bonding: bool) -> SecurityConfig {
+        SecurityConfig { bonding, ..self }
+    }
+
+    pub fn with_mitm(self, mitm: bool) -> SecurityConfig {
+        SecurityConfig { mitm, ..self }
+    }
+
+    pub fn with_secure_connections_only(self, secure_connections_only: bool) -> SecurityConfig {
+        SecurityConfig {
+            secure_connections_only,
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityEvent {
+    /// Show the passkey to the user who enters it on the peer
+    DisplayPasskey(u32),
+    /// The user has to enter the passkey shown by the peer, answer with
+    /// [super::Host::passkey_reply]
+    PasskeyRequest,
+    /// The user has to confirm both devices show the same value, answer with
+    /// [super::Host::numeric_comparison_reply]
+    NumericComparison(u32),
+    PairingComplete {
+        bonded: bool,
+        /// Pairing was protected against man-in-the-middle attacks
+        authenticated: bool,
+    },
+    /// Pairing failed for the given `REASON_*`, either on our side or the peer's
+    PairingFailed(u8),
+    EncryptionChanged {
+        enabled: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Method {
+    JustWorks,
+    NumericComparison,
+    /// The initiator enters the passkey displayed by the responder
+    InitiatorInputs,
+    /// The responder enters the passkey displayed by the initiator
+    ResponderInputs,
+    /// The user enters the same passkey on both devices
+    BothInput,
+}
+
+impl Method {
+    /// Selects the method from the IO capabilities if one of the devices asked for MITM
+    /// protection
+    fn select(initiator: u8, responder: u8, secure_connections: bool) -> Method {
+        const DISPLAY_ONLY: u8 = IoCapabilities::DisplayOnly as u8;
+        const DISPLAY_YES_NO: u8 = IoCapabilities::DisplayYesNo as u8;
+        const KEYBOARD_ONLY: u8 = IoCapabilities::KeyboardOnly as u8;
+        const KEYBOARD_DISPLAY: u8 = IoCapabilities::KeyboardDisplay as u8;
+
+        // numeric comparison is replaced by passkey entry or Just Works in legacy pairing
+        let comparison = |legacy| {
+            if secure_connections {
+                Method::NumericComparison
+            } else {
+                legacy
+            }
+        };
+
+        match (initiator, responder) {
+            (DISPLAY_ONLY, KEYBOARD_ONLY)
+            | (DISPLAY_ONLY, KEYBOARD_DISPLAY)
+            | (DISPLAY_YES_NO, KEYBOARD_ONLY)
+            | (KEYBOARD_DISPLAY, KEYBOARD_ONLY) => Method::ResponderInputs,
+            (KEYBOARD_ONLY, DISPLAY_ONLY)
+            | (KEYBOARD_ONLY, DISPLAY_YES_NO)
+            | (KEYBOARD_ONLY, KEYBOARD_DISPLAY)
+            | (KEYBOARD_DISPLAY, DISPLAY_ONLY) => Method::InitiatorInputs,
+            (KEYBOARD_ONLY, KEYBOARD_ONLY) => Method::BothInput,
+            (DISPLAY_YES_NO, DISPLAY_YES_NO) => comparison(Method::JustWorks),
+            (DISPLAY_YES_NO, KEYBOARD_DISPLAY) => comparison(Method::ResponderInputs),
+            (KEYBOARD_DISPLAY, DISPLAY_YES_NO) | (KEYBOARD_DISPLAY, KEYBOARD_DISPLAY) => {
+                comparison(Method::InitiatorInputs)
+            }
+            _ => Method::JustWorks,
+        }
+    }
+
+    fn is_passkey(self) -> bool {
+        matches!(
+            self,
+            Method::InitiatorInputs | Method::ResponderInputs | Method::BothInput
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Pairing request and response are exchanged
+    Features,
+    WaitPublicKey,
+    /// Confirm and random values are exchanged, once per passkey bit in passkey entry
+    Confirm,
+    Random,
+    /// Waiting for the user, the DHKey and the peer's DHKey check
+    Check,
+    WaitEncryption,
+    KeyDistribution,
+}
+
+enum PairingError {
+    Host(HostError),
+    /// Pairing has to fail with the given reason
+    Failed(u8),
+}
+
+impl From<HostError> for PairingError {
+    fn from(err: HostError) -> Self {
+        PairingError::Host(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    handle: u16,
+    role: Role,
+    peer: Address,
+    encrypted: bool,
+    /// Encrypting with the bond failed, pair again instead
+    bond_failed: bool,
+}
+
+struct Pairing {
+    initiator: bool,
+    /// Pairing request and response PDUs
+    preq: [u8; 7],
+    pres: [u8; 7],
+    /// Address type followed by the address
+    initiator_address: [u8; 7],
+    responder_address: [u8; 7],
+    phase: Phase,
+    last_activity: u64,
+    secure_connections: bool,
+    method: Method,
+    bonding: bool,
+    key_size: u8,
+    passkey: Option<u32>,
+    round: u8,
+    /// The user accepted the numeric comparison or there's nothing to accept
+    confirmed: bool,
+    confirm_sent: bool,
+    local_nonce: [u8; 16],
+    peer_nonce: [u8; 16],
+    peer_confirm: Option<[u8; 16]>,
+    peer_public_key: [u8; 64],
+    dhkey: Option<[u8; 32]>,
+    check_sent: bool,
+    peer_check: Option<[u8; 16]>,
+    /// The LTK or STK the link gets encrypted with
+    key: [u8; 16],
+    /// Keys still to be sent and received
+    local_keys: u8,
+    peer_keys: u8,
+    bond: Bond,
+}
+
+impl Pairing {
+    fn new(initiator: bool, own: &Address, peer: &Address) -> Pairing {
+        let (initiator_address, responder_address) = if initiator {
+            (encode_address(own), encode_address(peer))
+        } else {
+            (encode_address(peer), encode_address(own))
+        };
+
+        Pairing {
+            initiator,
+            preq: [0u8; 7],
+            pres: [0u8; 7],
+            initiator_address,
+            responder_address,
+            phase: Phase::Features,
+            last_activity: crate::current_millis(),
+            secure_connections: false,
+            method: Method::JustWorks,
+            bonding: false,
+            key_size: MAX_KEY_SIZE,
+            passkey: None,
+            round: 0,
+            confirmed: false,
+            confirm_sent: false,
+            local_nonce: [0u8; 16],
+            peer_nonce: [0u8; 16],
+            peer_confirm: None,
+            peer_public_key: [0u8; 64],
+            dhkey: None,
+            check_sent: false,
+            peer_check: None,
+            key: [0u8; 16],
+            local_keys: 0,
+            peer_keys: 0,
+            bond: Bond {
+                peer: *peer,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// The user has to enter the passkey on our side
+    fn inputs_passkey(&self) -> bool {
+        match self.method {
+            Method::BothInput => true,
+            Method::InitiatorInputs => self.initiator,
+            Method::ResponderInputs => !self.initiator,
+            _ => false,
+        }
+    }
+
+    fn passkey_ready(&self) -> bool {
+        !self.method.is_passkey() || self.passkey.is_some()
+    }
+
+    /// In LE Secure Connections Just Works and numeric comparison only the responder sends
+    /// a confirm value
+    fn sends_confirm(&self) -> bool {
+        !(self.initiator && self.secure_connections && !self.method.is_passkey())
+    }
+
+    /// The passkey as 128 bit value, zero if there is none
+    fn passkey_value(&self) -> [u8; 16] {
+        let mut value = [0u8; 16];
+        value[..4].copy_from_slice(&self.passkey.unwrap_or(0).to_le_bytes());
+        value
+    }
+
+    /// Returns the nonces of initiator and responder
+    fn nonces(&self) -> ([u8; 16], [u8; 16]) {
+        if self.initiator {
+            (self.local_nonce, self.peer_nonce)
+        } else {
+            (self.peer_nonce, self.local_nonce)
+        }
+    }
+
+    /// Computes our confirm value or the one expected from the peer
+    fn confirm_value(&self, public_key: &[u8; 64], local: bool) -> [u8; 16] {
+        let (nonce, u, v) = if local {
+            (&self.local_nonce, public_key, &self.peer_public_key)
+        } else {
+            (&self.peer_nonce, &self.peer_public_key, public_key)
+        };
+
+        if !self.secure_connections {
+            return self.legacy_confirm(nonce);
+        }
+
+        let z = if self.method.is_passkey() {
+            0x80 | ((self.passkey.unwrap_or(0) >> self.round) & 0x01) as u8
+        } else {
+            0
+        };
+
+        crypto::f4(x_coordinate(u), x_coordinate(v), nonce, z)
+    }
+
+    #[cfg(feature = "ble_legacy_pairing")]
+    fn legacy_confirm(&self, nonce: &[u8; 16]) -> [u8; 16] {
+        crypto::c1(
+            &self.passkey_value(),
+            nonce,
+            &self.preq,
+            &self.pres,
+            &self.initiator_address,
+            &self.responder_address,
+        )
+    }
+
+    #[cfg(feature = "ble_legacy_pairing")]
+    fn legacy_stk(&self) -> [u8; 16] {
+        let (initiator_nonce, responder_nonce) = self.nonces();
+        crypto::s1(&self.passkey_value(), &responder_nonce, &initiator_nonce)
+    }
+
+    #[cfg(not(feature = "ble_legacy_pairing"))]
+    fn legacy_confirm(&self, _nonce: &[u8; 16]) -> [u8; 16] {
+        unreachable!("legacy pairing is rejected during the feature exchange")
+    }
+
+    #[cfg(not(feature = "ble_legacy_pairing"))]
+    fn legacy_stk(&self) -> [u8; 16] {
+        unreachable!("legacy pairing is rejected during the feature exchange")
+    }
+}
+
+pub(crate) struct SecurityManager {
+    config: SecurityConfig,
+    bonds: Option<&'static mut dyn BondStore>,
+    own_address: Address,
+    /// Our P-256 public key, X and Y coordinate least significant byte first
+    public_key: [u8; 64],
+    link: Option<Link>,
+    pairing: Option<Pairing>,
+    events: SimpleQueue<SecurityEvent, 8>,
+}
+
+impl SecurityManager {
+    pub(crate) fn new(
+        config: SecurityConfig,
+        bonds: Option<&'static mut dyn BondStore>,
+        own_address: Address,
+        public_key: [u8; 64],
+    ) -> SecurityManager {
+        SecurityManager {
+            config,
+            bonds,
+            own_address,
+            public_key,
+            link: None,
+            pairing: None,
+            events: SimpleQueue::new(),
+        }
+    }
+
+    pub(crate) fn bonds(&mut self) -> Option<&mut (dyn BondStore + 'static)> {
+        self.bonds.as_deref_mut()
+    }
+
+    pub(crate) fn dequeue_event(&mut self) -> Option<SecurityEvent> {
+        self.events.dequeue()
+    }
+
+    pub(crate) fn is_encrypted(&self, handle: u16) -> bool {
+        matches!(self.link, Some(link) if link.handle == handle && link.encrypted)
+    }
+
+    /// Fails pairing if the peer stopped answering
+    pub(crate) fn check_timeout(&mut self) {
+        if let Some(pairing) = &self.pairing {
+            if crate::current_millis() - pairing.last_activity > SMP_TIMEOUT_MS {
+                warn!("pairing timed out");
+                self.pairing = None;
+                self.emit(SecurityEvent::PairingFailed(REASON_UNSPECIFIED));
+            }
+        }
+    }
+
+    /// Encrypts the link with the bond or starts pairing if we are central. Asks the
+    /// central to do so if we are peripheral.
+    pub(crate) fn start_security(
+        &mut self,
+        t: &mut Transport,
+        handle: u16,
+    ) -> Result<(), HostError> {
+        let result = self.start(t, handle);
+        self.finish(t, result)
+    }
+
+    /// Continues passkey entry with the passkey entered by the user, `None` if the user
+    /// cancelled it
+    pub(crate) fn passkey_reply(
+        &mut self,
+        t: &mut Transport,
+        passkey: Option<u32>,
+    ) -> Result<(), HostError> {
+        let result = match self.pairing.as_mut() {
+            Some(p) if p.inputs_passkey() && p.passkey.is_none() => match passkey {
+                Some(passkey) if passkey <= MAX_PASSKEY => {
+                    p.passkey = Some(passkey);
+                    self.try_send_confirm(t)
+                }
+                _ => Err(PairingError::Failed(REASON_PASSKEY_ENTRY_FAILED)),
+            },
+            _ => Ok(()),
+        };
+        self.finish(t, result)
+    }
+
+    pub(crate) fn numeric_comparison_reply(
+        &mut self,
+        t: &mut Transport,
+        accept: bool,
+    ) -> Result<(), HostError> {
+        let result = match self.pairing.as_mut() {
+            Some(p)
+                if p.method == Method::NumericComparison
+                    && p.phase == Phase::Check
+                    && !p.confirmed =>
+            {
+                if accept {
+                    p.confirmed = true;
+                    self.try_check(t)
+                } else {
+                    Err(PairingError::Failed(REASON_NUMERIC_COMPARISON_FAILED))
+                }
+            }
+            _ => Ok(()),
+        };
+        self.finish(t, result)
+    }
+
+    /// Handles connection, encryption and key related events
+    pub(crate) fn handle_event(
+        &mut self,
+        t: &mut Transport,
+        event: &Event,
+    ) -> Result<(), HostError> {
+        let result = match *event {
+            Event::LeConnectionComplete(connection) if connection.status == 0 => {
+                self.connected(&connection);
+                Ok(())
+            }
+            Event::DisconnectionComplete {
+                status: 0, handle, ..
+            } => {
+                self.disconnected(handle);
+                Ok(())
+            }
+            Event::EncryptionChange {
+                status,
+                handle,
+                enabled,
+            } => self.encryption_changed(t, handle, status == 0 && enabled),
+            Event::EncryptionKeyRefreshComplete { status, handle } => {
+                self.encryption_changed(t, handle, status == 0)
+            }
+            Event::LeLongTermKeyRequest { handle, rand, ediv } => self
+                .long_term_key_request(t, handle, &rand, ediv)
+                .map_err(PairingError::from),
+            Event::LeGenerateDhKeyComplete { status, dhkey } => {
+                self.dhkey_complete(t, status, dhkey)
+            }
+            Event::CommandStatus { status, opcode, .. } if status != 0 => {
+                self.command_failed(opcode)
+            }
+            _ => Ok(()),
+        };
+        self.finish(t, result)
+    }
+
+    /// Handles a PDU received on the SMP channel
+    pub(crate) fn handle_smp(
+        &mut self,
+        t: &mut Transport,
+        handle: u16,
+        pdu: &[u8],
+    ) -> Result<(), HostError> {
+        let link = match self.link {
+            Some(link) if link.handle == handle => link,
+            _ => return Ok(()),
+        };
+
+        let (code, data) = match pdu.split_first() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+
+        if let Some(pairing) = self.pairing.as_mut() {
+            pairing.last_activity = crate::current_millis();
+        }
+
+        let result = match *code {
+            PAIRING_REQUEST => self.on_pairing_request(t, &link, pdu),
+            PAIRING_RESPONSE => self.on_pairing_response(t, pdu),
+            PAIRING_PUBLIC_KEY => self.on_public_key(t, data),
+            PAIRING_CONFIRM => self.on_confirm(t, data),
+            PAIRING_RANDOM => self.on_random(t, data),
+            PAIRING_DHKEY_CHECK => self.on_dhkey_check(t, data),
+            ENCRYPTION_INFORMATION
+            | CENTRAL_IDENTIFICATION
+            | IDENTITY_INFORMATION
+            | IDENTITY_ADDRESS_INFORMATION
+            | SIGNING_INFORMATION => self.on_key(t, *code, data),
+            SECURITY_REQUEST if link.role == Role::Central => {
+                if self.pairing.is_none() && !link.encrypted {
+                    self.start(t, handle)
+                } else {
+                    Ok(())
+                }
+            }
+            PAIRING_FAILED => {
+                let reason = data.first().copied().unwrap_or(REASON_UNSPECIFIED);
+                warn!("peer failed pairing: {}", reason);
+                if self.pairing.take().is_some() {
+                    self.emit(SecurityEvent::PairingFailed(reason));
+                }
+                Ok(())
+            }
+            KEYPRESS_NOTIFICATION => Ok(()),
+            _ => Err(PairingError::Failed(REASON_COMMAND_NOT_SUPPORTED)),
+        };
+        self.finish(t, result)
+    }
+
+    fn emit(&mut self, event: SecurityEvent) {
+        debug!("{:?}", event);
+        if !self.events.enqueue(event) {
+            warn!("security event queue overflow");
+        }
+    }
+
+    fn finish(
+        &mut self,
+        t: &mut Transport,
+        result: Result<(), PairingError>,
+    ) -> Result<(), HostError> {
+        match result {
+            Ok(()) => Ok(()),
+            Err(PairingError::Host(err)) => Err(err),
+            Err(PairingError::Failed(reason)) => {
+                warn!("pairing failed: {}", reason);
+                if self.pairing.take().is_some() {
+                    self.emit(SecurityEvent::PairingFailed(reason));
+                }
+
+                match self.link {
+                    Some(link) => send(t, link.handle, &[PAIRING_FAILED, reason]),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    fn connected(&mut self, connection: &ConnectionComplete) {
+        self.link = Some(Link {
+            handle: connection.handle,
+            role: connection.role,
+            peer: connection.peer,
+            encrypted: false,
+            bond_failed: false,
+        });
+        self.pairing = None;
+    }
+
+    fn disconnected(&mut self, handle: u16) {
+        if !matches!(self.link, Some(link) if link.handle == handle) {
+            return;
+        }
+
+        self.link = None;
+        if self.pairing.take().is_some() {
+            self.emit(SecurityEvent::PairingFailed(REASON_UNSPECIFIED));
+        }
+    }
+
+    fn find_bond(&self, address: &Address) -> Option<Bond> {
+        self.bonds.as_ref().and_then(|bonds| bonds.find(address))
+    }
+
+    fn auth_req(&self) -> u8 {
+        let mut auth_req = AUTH_SECURE_CONNECTIONS;
+        if self.config.bonding {
+            auth_req |= AUTH_BONDING;
+        }
+        if self.config.mitm {
+            auth_req |= AUTH_MITM;
+        }
+        auth_req
+    }
+
+    fn start(&mut self, t: &mut Transport, handle: u16) -> Result<(), PairingError> {
+        let link = match self.link {
+            Some(link) if link.handle == handle => link,
+            _ => return Err(PairingError::Host(HostError::Disconnected)),
+        };
+
+        if self.pairing.is_some() || link.encrypted {
+            return Ok(());
+        }
+
+        if link.role == Role::Peripheral {
+            return Ok(send(t, handle, &[SECURITY_REQUEST, self.auth_req()])?);
+        }
+
+        if !link.bond_failed {
+            if let Some(ltk) = self.find_bond(&link.peer).and_then(|bond| bond.peer_ltk) {
+                return Ok(enable_encryption(t, handle, &ltk)?);
+            }
+        }
+
+        let (initiator_keys, responder_keys) = if self.config.bonding {
+            (KEY_ENC, KEY_ENC | KEY_ID)
+        } else {
+            (0, 0)
+        };
+
+        let mut pairing = Pairing::new(true, &self.own_address, &link.peer);
+        pairing.preq = [
+            PAIRING_REQUEST,
+            self.config.io_capabilities as u8,
+            0,
+            self.auth_req(),
+            MAX_KEY_SIZE,
+            initiator_keys,
+            responder_keys,
+        ];
+        send(t, handle, &pairing.preq)?;
+        self.pairing = Some(pairing);
+
+        Ok(())
+    }
+
+    fn on_pairing_request(
+        &mut self,
+        t: &mut Transport,
+        link: &Link,
+        pdu: &[u8],
+    ) -> Result<(), PairingError> {
+        if link.role != Role::Peripheral || self.pairing.is_some() {
+            return Err(PairingError::Failed(REASON_COMMAND_NOT_SUPPORTED));
+        }
+
+        if pdu.len() != 7 {
+            return Err(PairingError::Failed(REASON_INVALID_PARAMETERS));
+        }
+
+        let mut pairing = Pairing::new(false, &self.own_address, &link.peer);
+        pairing.preq.copy_from_slice(pdu);
+
+        // we only have a LTK to give and only take the peer's LTK and IRK
+        let (initiator_keys, responder_keys) =
+            if self.config.bonding && pairing.preq[3] & AUTH_BONDING != 0 {
+                (
+                    pairing.preq[5] & (KEY_ENC | KEY_ID),
+                    pairing.preq[6] & KEY_ENC,
+                )
+            } else {
+                (0, 0)
+            };
+
+        pairing.pres = [
+            PAIRING_RESPONSE,
+            self.config.io_capabilities as u8,
+            0,
+            self.auth_req(),
+            MAX_KEY_SIZE,
+            initiator_keys,
+            responder_keys,
+        ];
+        let pres = pairing.pres;
+        self.pairing = Some(pairing);
+
+        self.negotiate()?;
+        send(t, link.handle, &pres)?;
+        self.begin(t)
+    }
+
+    fn on_pairing_response(&mut self, t: &mut Transport, pdu: &[u8]) -> Result<(), PairingError> {
+        let p = expect(&mut self.pairing, Phase::Features)?;
+        if !p.initiator || pdu.len() != 7 {
+            return Err(PairingError::Failed(REASON_INVALID_PARAMETERS));
+        }
+        p.pres.copy_from_slice(pdu);
+
+        // the responder may only reduce the keys to distribute
+        if p.pres[5] & !p.preq[5] != 0 || p.pres[6] & !p.preq[6] != 0 {
+            return Err(PairingError::Failed(REASON_INVALID_PARAMETERS));
+        }
+
+        self.negotiate()?;
+        self.begin(t)
+    }
+
+    /// Settles the pairing features once request and response are known
+    fn negotiate(&mut self) -> Result<(), PairingError> {
+        let config = self.config;
+        let p = expect(&mut self.pairing, Phase::Features)?;
+        let (preq, pres) = (p.preq, p.pres);
+
+        if preq[1] > IoCapabilities::KeyboardDisplay as u8
+            || pres[1] > IoCapabilities::KeyboardDisplay as u8
+            || preq[4] > MAX_KEY_SIZE
+            || pres[4] > MAX_KEY_SIZE
+        {
+            return Err(PairingError::Failed(REASON_INVALID_PARAMETERS));
+        }
+
+        p.key_size = preq[4].min(pres[4]);
+        if p.key_size < MIN_KEY_SIZE {
+            return Err(PairingError::Failed(REASON_ENCRYPTION_KEY_SIZE));
+        }
+
+        p.secure_connections = preq[3] & pres[3] & AUTH_SECURE_CONNECTIONS != 0;
+        if !p.secure_connections {
+            if !cfg!(feature = "ble_legacy_pairing") {
+                return Err(PairingError::Failed(REASON_PAIRING_NOT_SUPPORTED));
+            }
+
+            if config.secure_connections_only {
+                return Err(PairingError::Failed(REASON_AUTHENTICATION_REQUIREMENTS));
+            }
+        }
+
+        p.method = if (preq[3] | pres[3]) & AUTH_MITM == 0 {
+            Method::JustWorks
+        } else {
+            Method::select(preq[1], pres[1], p.secure_connections)
+        };
+
+        if config.mitm && p.method == Method::JustWorks {
+            return Err(PairingError::Failed(REASON_AUTHENTICATION_REQUIREMENTS));
+        }
+
+        p.bonding = preq[3] & pres[3] & AUTH_BONDING != 0;
+
+        let (mut local_keys, mut peer_keys) = if p.initiator {
+            (pres[5], pres[6])
+        } else {
+            (pres[6], pres[5])
+        };
+
+        // the LTK is derived from the DHKey instead
+        if p.secure_connections {
+            local_keys &= !KEY_ENC;
+            peer_keys &= !KEY_ENC;
+        }
+
+        p.local_keys = local_keys;
+        p.peer_keys = peer_keys;
+
+        debug!(
+            "pairing with {:?}, secure connections {}, key size {}",
+            p.method, p.secure_connections, p.key_size
+        );
+
+        Ok(())
+    }
+
+    /// Continues after the feature exchange
+    fn begin(&mut self, t: &mut Transport) -> Result<(), PairingError> {
+        let handle = self.handle()?;
+        let p = expect(&mut self.pairing, Phase::Features)?;
+
+        if p.secure_connections {
+            p.phase = Phase::WaitPublicKey;
+            if p.initiator {
+                send_public_key(t, handle, &self.public_key)?;
+            }
+
+            return Ok(());
+        }
+
+        p.phase = Phase::Confirm;
+        self.prepare_passkey();
+        self.try_send_confirm(t)
+    }
+
+    fn on_public_key(&mut self, t: &mut Transport, data: &[u8]) -> Result<(), PairingError> {
+        let handle = self.handle()?;
+        let p = expect(&mut self.pairing, Phase::WaitPublicKey)?;
+        if data.len() != 64 {
+            return Err(PairingError::Failed(REASON_INVALID_PARAMETERS));
+        }
+        p.peer_public_key.copy_from_slice(data);
+
+        // a reflected key would make the peer's part of the DHKey known
+        if p.peer_public_key == self.public_key {
+            return Err(PairingError::Failed(REASON_INVALID_PARAMETERS));
+        }
+
+        if !p.initiator {
+            send_public_key(t, handle, &self.public_key)?;
+        }
+
+        t.send_command(command::LE_GENERATE_DHKEY, &p.peer_public_key)?;
+
+        p.phase = Phase::Confirm;
+        self.prepare_passkey();
+        self.try_send_confirm(t)
+    }
+
+    /// Shows a passkey or asks for one if the method needs it
+    fn prepare_passkey(&mut self) {
+        let p = match self.pairing.as_mut() {
+            Some(p) if p.method.is_passkey() => p,
+            _ => return,
+        };
+
+        if p.inputs_passkey() {
+            self.emit(SecurityEvent::PasskeyRequest);
+        } else {
+            let passkey = random_u32() % (MAX_PASSKEY + 1);
+            p.passkey = Some(passkey);
+            self.emit(SecurityEvent::DisplayPasskey(passkey));
+        }
+    }
+
+    /// Sends our confirm value once we have everything needed for it
+    fn try_send_confirm(&mut self, t: &mut Transport) -> Result<(), PairingError> {
+        let handle = self.handle()?;
+        let p = match self.pairing.as_mut() {
+            Some(p) if p.phase == Phase::Confirm => p,
+            _ => return Ok(()),
+        };
+
+        if p.confirm_sent || !p.sends_confirm() || !p.passkey_ready() {
+            return Ok(());
+        }
+
+        // the responder answers the initiator's confirm value unless only it sends one
+        let responder_waits = !p.secure_connections || p.method.is_passkey();
+        if !p.initiator && responder_waits && p.peer_confirm.is_none() {
+            return Ok(());
+        }
+
+        random_bytes(&mut p.local_nonce);
+        let confirm = p.confirm_value(&self.public_key, true);
+        send_value(t, handle, PAIRING_CONFIRM, &confirm)?;
+        p.confirm_sent = true;
+
+        if !p.initiator {
+            p.phase = Phase::Random;
+        }
+
+        Ok(())
+    }
+
+    fn on_confirm(&mut self, t: &mut Transport, data: &[u8]) -> Result<(), PairingError> {
+        let handle = self.handle()?;
+        let p = expect(&mut self.pairing, Phase::Confirm)?;
+        if data.len() != 16 {
+            return Err(PairingError::Failed(REASON_INVALID_PARAMETERS));
+        }
+
+        if p.peer_confirm.is_some() || (p.initiator && p.sends_confirm() && !p.confirm_sent) {
+            return Err(PairingError::Failed(REASON_UNSPECIFIED));
+        }
+
+        let mut confirm = [0u8; 16];
+        confirm.copy_from_slice(data);
+        p.peer_confirm = Some(confirm);
+
+        if !p.initiator {
+            return self.try_send_confirm(t);
+        }
+
+        if !p.sends_confirm() {
+            random_bytes(&mut p.local_nonce);
+        }
+        send_value(t, handle, PAIRING_RANDOM, &p.local_nonce)?;
+        p.phase = Phase::Random;
+
+        Ok(())
+    }
+
+    fn on_random(&mut self, t: &mut Transport, data: &[u8]) -> Result<(), PairingError> {
+        let handle = self.handle()?;
+        let p = expect(&mut self.pairing, Phase::Random)?;
+        if data.len() != 16 {
+            return Err(PairingError::Failed(REASON_INVALID_PARAMETERS));
+        }
+        p.peer_nonce.copy_from_slice(data);
+
+        // the responder doesn't get a confirm value in Just Works and numeric comparison
+        if p.peer_confirm.is_some()
+            && Some(p.confirm_value(&self.public_key, false)) != p.peer_confirm
+        {
+            return Err(PairingError::Failed(REASON_CONFIRM_VALUE_FAILED));
+        }
+
+        if !p.initiator {
+            send_value(t, handle, PAIRING_RANDOM, &p.local_nonce)?;
+        }
+
+        if !p.secure_connections {
+            p.key = mask_key(p.legacy_stk(), p.key_size);
+            p.phase = Phase::WaitEncryption;
+
+            if p.initiator {
+                let stk = LtkInfo {
+                    ltk: p.key,
+                    ..Default::default()
+                };
+                enable_encryption(t, handle, &stk)?;
+            }
+
+            return Ok(());
+        }
+
+        if p.method.is_passkey() {
+            p.round += 1;
+            if p.round < PASSKEY_ROUNDS {
+                p.phase = Phase::Confirm;
+                p.confirm_sent = false;
+                p.peer_confirm = None;
+                return self.try_send_confirm(t);
+            }
+        }
+
+        p.phase = Phase::Check;
+        if p.method == Method::NumericComparison {
+            let (initiator_nonce, responder_nonce) = p.nonces();
+            let (initiator_key, responder_key) = if p.initiator {
+                (&self.public_key, &p.peer_public_key)
+            } else {
+                (&p.peer_public_key, &self.public_key)
+            };
+
+            let value = crypto::g2(
+                x_coordinate(initiator_key),
+                x_coordinate(responder_key),
+                &initiator_nonce,
+                &responder_nonce,
+            );
+            self.emit(SecurityEvent::NumericComparison(value));
+        } else {
+            p.confirmed = true;
+        }
+
+        self.try_check(t)
+    }
+
+    fn dhkey_complete(
+        &mut self,
+        t: &mut Transport,
+        status: u8,
+        dhkey: &[u8],
+    ) -> Result<(), PairingError> {
+        let p = match self.pairing.as_mut() {
+            Some(p) if p.secure_connections && p.dhkey.is_none() => p,
+            _ => return Ok(()),
+        };
+
+        // the controller refuses public keys which aren't on the curve
+        if status != 0 || dhkey.len() != 32 {
+            return Err(PairingError::Failed(REASON_DHKEY_CHECK_FAILED));
+        }
+
+        let mut key = [0u8; 32];
+        key.copy_from_slice(dhkey);
+        p.dhkey = Some(key);
+
+        self.try_check(t)
+    }
+
+    fn on_dhkey_check(&mut self, t: &mut Transport, data: &[u8]) -> Result<(), PairingError> {
+        let p = expect(&mut self.pairing, Phase::Check)?;
+        if data.len() != 16 {
+            return Err(PairingError::Failed(REASON_INVALID_PARAMETERS));
+        }
+
+        if p.peer_check.is_some() || (p.initiator && !p.check_sent) {
+            return Err(PairingError::Failed(REASON_UNSPECIFIED));
+        }
+
+        let mut check = [0u8; 16];
+        check.copy_from_slice(data);
+        p.peer_check = Some(check);
+
+        self.try_check(t)
+    }
+
+    /// Exchanges the DHKey checks once the user confirmed and the DHKey is known
+    fn try_check(&mut self, t: &mut Transport) -> Result<(), PairingError> {
+        let handle = self.handle()?;
+        let p = match self.pairing.as_mut() {
+            Some(p) if p.phase == Phase::Check && p.confirmed => p,
+            _ => return Ok(()),
+        };
+
+        let dhkey = match p.dhkey {
+            Some(dhkey) => dhkey,
+            None => return Ok(()),
+        };
+
+        let (initiator_nonce, responder_nonce) = p.nonces();
+        let (mac_key, ltk) = crypto::f5(
+            &dhkey,
+            &initiator_nonce,
+            &responder_nonce,
+            &p.initiator_address,
+            &p.responder_address,
+        );
+
+        let r = p.passkey_value();
+        let mut initiator_io = [0u8; 3];
+        initiator_io.copy_from_slice(&p.preq[1..4]);
+        let mut responder_io = [0u8; 3];
+        responder_io.copy_from_slice(&p.pres[1..4]);
+
+        let initiator_check = crypto::f6(
+            &mac_key,
+            &initiator_nonce,
+            &responder_nonce,
+            &r,
+            &initiator_io,
+            &p.initiator_address,
+            &p.responder_address,
+        );
+        let responder_check = crypto::f6(
+            &mac_key,
+            &responder_nonce,
+            &initiator_nonce,
+            &r,
+            &responder_io,
+            &p.responder_address,
+            &p.initiator_address,
+        );
+
+        let (local_check, expected) = if p.initiator {
+            (initiator_check, responder_check)
+        } else {
+            (responder_check, initiator_check)
+        };
+
+        // the initiator goes first
+        if p.initiator && !p.check_sent {
+            send_value(t, handle, PAIRING_DHKEY_CHECK, &local_check)?;
+            p.check_sent = true;
+        }
+
+        let peer_check = match p.peer_check {
+            Some(peer_check) => peer_check,
+            None => return Ok(()),
+        };
+
+        if peer_check != expected {
+            return Err(PairingError::Failed(REASON_DHKEY_CHECK_FAILED));
+        }
+
+        p.key = mask_key(ltk, p.key_size);
+        p.phase = Phase::WaitEncryption;
+
+        if p.initiator {
+            let ltk = LtkInfo {
+                ltk: p.key,
+                ..Default::default()
+            };
+            enable_encryption(t, handle, &ltk)?;
+        } else {
+            send_value(t, handle, PAIRING_DHKEY_CHECK, &local_check)?;
+        }
+
+        Ok(())
+    }
+
+    fn long_term_key_request(
+        &mut self,
+        t: &mut Transport,
+        handle: u16,
+        rand: &[u8; 8],
+        ediv: u16,
+    ) -> Result<(), HostError> {
+        let link = match self.link {
+            Some(link) if link.handle == handle && link.role == Role::Peripheral => link,
+            _ => return negative_ltk_reply(t, handle),
+        };
+
+        let key = match &self.pairing {
+            // keys from pairing are identified by zero EDIV and Rand
+            Some(p) if p.phase == Phase::WaitEncryption => Some(p.key),
+            Some(_) => None,
+            None => self
+                .find_bond(&link.peer)
+                .and_then(|bond| bond.local_ltk)
+                .filter(|ltk| ltk.ediv == ediv && ltk.rand == *rand)
+                .map(|ltk| ltk.ltk),
+        };
+
+        match key {
+            Some(key) => {
+                let mut params = [0u8; 18];
+                params[0..2].copy_from_slice(&handle.to_le_bytes());
+                params[2..].copy_from_slice(&key);
+                t.send_command(command::LE_LONG_TERM_KEY_REQUEST_REPLY, &params)
+            }
+            None => {
+                warn!("no LTK for {:?}", link.peer);
+                negative_ltk_reply(t, handle)
+            }
+        }
+    }
+
+    fn encryption_changed(
+        &mut self,
+        t: &mut Transport,
+        handle: u16,
+        enabled: bool,
+    ) -> Result<(), PairingError> {
+        let link = match self.link.as_mut() {
+            Some(link) if link.handle == handle => link,
+            _ => return Ok(()),
+        };
+
+        let pairing = matches!(&self.pairing, Some(p) if p.phase == Phase::WaitEncryption);
+        link.encrypted = enabled;
+        if !enabled && !pairing {
+            link.bond_failed = true;
+        }
+
+        self.emit(SecurityEvent::EncryptionChanged { enabled });
+
+        if !pairing {
+            return Ok(());
+        }
+
+        if !enabled {
+            return Err(PairingError::Failed(REASON_UNSPECIFIED));
+        }
+
+        let p = expect(&mut self.pairing, Phase::WaitEncryption)?;
+        p.phase = Phase::KeyDistribution;
+
+        // the responder distributes its keys first
+        if !p.initiator {
+            self.send_keys(t)?;
+        }
+        self.try_complete(t)
+    }
+
+    fn command_failed(&mut self, opcode: u16) -> Result<(), PairingError> {
+        match opcode {
+            command::LE_GENERATE_DHKEY | command::LE_ENABLE_ENCRYPTION
+                if self.pairing.is_some() =>
+            {
+                Err(PairingError::Failed(REASON_UNSPECIFIED))
+            }
+            command::LE_ENABLE_ENCRYPTION => {
+                if let Some(link) = self.link.as_mut() {
+                    link.bond_failed = true;
+                }
+                self.emit(SecurityEvent::EncryptionChanged { enabled: false });
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn send_keys(&mut self, t: &mut Transport) -> Result<(), PairingError> {
+        let handle = self.handle()?;
+        let p = expect(&mut self.pairing, Phase::KeyDistribution)?;
+
+        if p.local_keys & KEY_ENC != 0 {
+            let mut ltk = LtkInfo::default();
+            random_bytes(&mut ltk.ltk);
+            ltk.ltk = mask_key(ltk.ltk, p.key_size);
+            ltk.ediv = random_u32() as u16;
+            random_bytes(&mut ltk.rand);
+
+            send_value(t, handle, ENCRYPTION_INFORMATION, &ltk.ltk)?;
+
+            let mut identification = [0u8; 11];
+            identification[0] = CENTRAL_IDENTIFICATION;
+            identification[1..3].copy_from_slice(&ltk.ediv.to_le_bytes());
+            identification[3..].copy_from_slice(&ltk.rand);
+            send(t, handle, &identification)?;
+
+            p.bond.local_ltk = Some(ltk);
+        }
+
+        p.local_keys = 0;
+        Ok(())
+    }
+
+    fn on_key(&mut self, t: &mut Transport, code: u8, data: &[u8]) -> Result<(), PairingError> {
+        let p = expect(&mut self.pairing, Phase::KeyDistribution)?;
+
+        let (key, len) = match code {
+            ENCRYPTION_INFORMATION => (KEY_ENC, 16),
+            CENTRAL_IDENTIFICATION => (KEY_ENC, 10),
+            IDENTITY_INFORMATION => (KEY_ID, 16),
+            IDENTITY_ADDRESS_INFORMATION => (KEY_ID, 7),
+            _ => (KEY_SIGN, 16),
+        };
+
+        if p.peer_keys & key == 0 || data.len() != len {
+            return Err(PairingError::Failed(REASON_INVALID_PARAMETERS));
+        }
+
+        match code {
+            ENCRYPTION_INFORMATION => {
+                let mut ltk = LtkInfo::default();
+                ltk.ltk.copy_from_slice(data);
+                p.bond.peer_ltk = Some(ltk);
+            }
+            CENTRAL_IDENTIFICATION => {
+                let ltk = p
+                    .bond
+                    .peer_ltk
+                    .as_mut()
+                    .ok_or(PairingError::Failed(REASON_UNSPECIFIED))?;
+                ltk.ediv = read_u16(data, 0);
+                ltk.rand.copy_from_slice(&data[2..]);
+                p.peer_keys &= !KEY_ENC;
+            }
+            IDENTITY_INFORMATION => {
+                let mut irk = [0u8; 16];
+                irk.copy_from_slice(data);
+                p.bond.irk = Some(irk);
+            }
+            IDENTITY_ADDRESS_INFORMATION => {
+                let mut addr = [0u8; 6];
+                addr.copy_from_slice(&data[1..]);
+                p.bond.peer = Address {
+                    kind: AddressKind::from_u8(data[0]),
+                    addr,
+                };
+                p.peer_keys &= !KEY_ID;
+            }
+            _ => p.peer_keys &= !KEY_SIGN,
+        }
+
+        self.try_complete(t)
+    }
+
+    /// Completes pairing once all keys are exchanged
+    fn try_complete(&mut self, t: &mut Transport) -> Result<(), PairingError> {
+        let p = expect(&mut self.pairing, Phase::KeyDistribution)?;
+        if p.peer_keys != 0 {
+            return Ok(());
+        }
+
+        if p.local_keys != 0 {
+            self.send_keys(t)?;
+        }
+
+        let p = match self.pairing.take() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        let mut bond = p.bond;
+        if p.secure_connections {
+            let ltk = Some(LtkInfo {
+                ltk: p.key,
+                ..Default::default()
+            });
+            bond.local_ltk = ltk;
+            bond.peer_ltk = ltk;
+        }
+        bond.authenticated = p.method != Method::JustWorks;
+        bond.secure_connections = p.secure_connections;
+        bond.key_size = p.key_size;
+
+        let has_keys = bond.local_ltk.is_some() || bond.peer_ltk.is_some();
+        let bonded = match self.bonds.as_mut() {
+            Some(bonds) if p.bonding && has_keys => {
+                bonds.store(bond);
+                true
+            }
+            _ => false,
+        };
+
+        self.emit(SecurityEvent::PairingComplete {
+            bonded,
+            authenticated: bond.authenticated,
+        });
+
+        Ok(())
+    }
+
+    fn handle(&self) -> Result<u16, PairingError> {
+        self.link
+            .map(|link| link.handle)
+            .ok_or(PairingError::Host(HostError::Disconnected))
+    }
+}
+
+/// Returns the pairing if it is in the given phase, a PDU arriving in any other phase fails
+/// pairing
+fn expect(pairing: &mut Option<Pairing>, phase: Phase) -> Result<&mut Pairing, PairingError> {
+    match pairing.as_mut() {
+        Some(pairing) if pairing.phase == phase => Ok(pairing),
+        _ => Err(PairingError::Failed(REASON_UNSPECIFIED)),
+    }
+}
+
+fn send(t: &mut Transport, handle: u16, pdu: &[u8]) -> Result<(), HostError> {
+    t.send_l2cap(handle, CID_SMP, pdu)
+}
+
+fn send_value(t: &mut Transport, handle: u16, code: u8, value: &[u8; 16]) -> Result<(), HostError> {
+    let mut pdu = [0u8; 17];
+    pdu[0] = code;
+    pdu[1..].copy_from_slice(value);
+    send(t, handle, &pdu)
+}
+
+fn send_public_key(t: &mut Transport, handle: u16, key: &[u8; 64]) -> Result<(), HostError> {
+    let mut pdu = [0u8; 65];
+    pdu[0] = PAIRING_PUBLIC_KEY;
+    pdu[1..].copy_from_slice(key);
+    send(t, handle, &pdu)
+}
+
+fn enable_encryption(t: &mut Transport, handle: u16, ltk: &LtkInfo) -> Result<(), HostError> {
+    let mut params = [0u8; 28];
+    params[0..2].copy_from_slice(&handle.to_le_bytes());
+    params[2..10].copy_from_slice(&ltk.rand);
+    params[10..12].copy_from_slice(&ltk.ediv.to_le_bytes());
+    params[12..].copy_from_slice(&ltk.ltk);
+    t.send_command(command::LE_ENABLE_ENCRYPTION, &params)
+}
+
+pub(crate) fn negative_ltk_reply(t: &mut Transport, handle: u16) -> Result<(), HostError> {
+    t.send_command(
+        command::LE_LONG_TERM_KEY_REQUEST_NEGATIVE_REPLY,
+        &handle.to_le_bytes(),
+    )
+}
+
+fn encode_address(address: &Address) -> [u8; 7] {
+    let mut encoded = [0u8; 7];
+    encoded[0] = address.kind.to_u8();
+    encoded[1..].copy_from_slice(&address.addr);
+    encoded
+}
+
+fn x_coordinate(key: &[u8; 64]) -> &[u8; 32] {
+    key[..32].try_into().unwrap()
+}
+
+/// Keys shorter than 16 bytes have their most significant bytes cleared
+fn mask_key(mut key: [u8; 16], key_size: u8) -> [u8; 16] {
+    for byte in key[key_size as usize..].iter_mut() {
+        *byte = 0;
+    }
+    key
+}
+
+fn random_u32() -> u32 {
+    unsafe { crate::wifi::os_adapter::random() }
+}
+
+fn random_bytes(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(4) {
+        chunk.copy_from_slice(&random_u32().to_le_bytes()[..chunk.len()]);
+    }
+}
@@ -2,30 +2,36 @@ use core::mem::MaybeUninit;
 use log::trace;
 use Option;
 
-use crate::compat::common::StrBuf;
+use crate::compat::common::{create_queue, delete_queue, receive_queued, send_queued, StrBuf};
 use crate::compat::queue::SimpleQueue;
 
 use crate::binary::include::*;
-use crate::compat::work_queue::queue_work;
-use esp_alloc::memory_fence;
+use crate::compat::task::{create_task, delete_task};
+use crate::InitializationError;
 
 #[cfg_attr(feature = "esp32c3", path = "os_adapter_esp32c3.rs")]
 #[cfg_attr(feature = "esp32", path = "os_adapter_esp32.rs")]
 pub(crate) mod ble_os_adapter_chip_specific;
 
+pub mod beacon;
+
 pub mod controller;
 
+pub mod hci;
+
+pub mod host;
+
+use hci::{HciError, HciOutCollector, HciPacket, HCI_MAX_PACKET_LEN};
+
 static mut BLE_INITIALIZED: bool = false;
 
 static mut BT_RECEIVE_QUEUE: Option<SimpleQueue<ReceivedPacket, 10>> = None;
 
 pub struct ReceivedPacket {
-    pub len: u8,
-    pub data: [u8; 256],
+    pub len: u16,
+    pub data: [u8; HCI_MAX_PACKET_LEN],
 }
 
-static mut BT_INTERNAL_QUEUE: Option<SimpleQueue<[u8; 8], 5>> = None;
-
 #[repr(C)]
 struct vhci_host_callback_s {
     notify_host_send_available: extern "C" fn(), /* callback used to notify that the host can send packet to controller */
@@ -46,6 +52,8 @@ extern "C" {
     ) -> i32;
 
     fn btdm_controller_enable(mode: esp_bt_mode_t);
+    fn btdm_controller_disable();
+    fn btdm_controller_deinit();
 
     fn API_vhci_host_check_send_available() -> bool;
     fn API_vhci_host_send_packet(data: *const u8, len: u16);
@@ -59,26 +67,37 @@ static VHCI_HOST_CALLBACK: vhci_host_callback_s = vhci_host_callback_s {
 
 extern "C" fn notify_host_send_available() {
     trace!("notify_host_send_available");
+
+    #[cfg(feature = "async")]
+    controller::asynch::hci_send_space_available();
 }
 
 extern "C" fn notify_host_recv(data: *mut u8, len: u16) -> i32 {
     trace!("notify_host_recv {:p} {}", data, len);
 
+    if len as usize > HCI_MAX_PACKET_LEN {
+        log::warn!("dropping HCI packet of {} bytes", len);
+        return 0;
+    }
+
     unsafe {
-        let mut buf = [0u8; 256];
+        let mut buf = [0u8; HCI_MAX_PACKET_LEN];
         for i in 0..len {
             let b = data.offset(i as isize).read();
             buf[i as usize] = b;
         }
 
-        let packet = ReceivedPacket {
-            len: len as u8,
-            data: buf,
-        };
+        let packet = ReceivedPacket { len, data: buf };
 
-        BT_RECEIVE_QUEUE.as_mut().unwrap().enqueue(packet);
+        match BT_RECEIVE_QUEUE.as_mut() {
+            Some(queue) => queue.enqueue(packet),
+            None => log::warn!("dropping HCI packet, Bluetooth LE is not initialized"),
+        }
     }
 
+    #[cfg(feature = "async")]
+    controller::asynch::hci_read_data_available();
+
     0
 }
 
@@ -354,11 +373,11 @@ unsafe extern "C" fn interrupt_disable() {
 }
 
 unsafe extern "C" fn task_yield() {
-    todo!();
+    crate::timer::yield_task();
 }
 
 unsafe extern "C" fn task_yield_from_isr() {
-    todo!();
+    crate::timer::yield_task();
 }
 
 unsafe extern "C" fn semphr_create(max: u32, init: u32) -> *const () {
@@ -380,7 +399,10 @@ unsafe extern "C" fn semphr_give_from_isr(sem: *const (), _hptw: *const ()) -> i
 }
 
 unsafe extern "C" fn semphr_take(sem: *const (), block_time_ms: u32) -> i32 {
-    crate::wifi::semphr_take(sem as *mut crate::binary::c_types::c_void, block_time_ms)
+    crate::wifi::semphr_take(
+        sem as *mut crate::binary::c_types::c_void,
+        ms_to_ticks(block_time_ms),
+    )
 }
 
 unsafe extern "C" fn semphr_give(sem: *const ()) -> i32 {
@@ -404,34 +426,28 @@ unsafe extern "C" fn mutex_unlock(_mutex: *const ()) -> i32 {
 }
 
 unsafe extern "C" fn queue_create(len: u32, item_size: u32) -> *const () {
-    if len != 5 && item_size != 8 {
-        panic!("Unexpected queue spec {} {}", len, item_size);
-    }
-    &BT_INTERNAL_QUEUE as *const _ as *const ()
+    create_queue(len, item_size) as *const ()
 }
 
 unsafe extern "C" fn queue_delete(queue: *const ()) {
-    trace!("Unimplemented queue_delete {:p}", queue);
+    delete_queue(queue as *mut crate::binary::c_types::c_void);
 }
 
-unsafe extern "C" fn queue_send(queue: *const (), item: *const (), _block_time_ms: u32) -> i32 {
-    if queue == &BT_INTERNAL_QUEUE as *const _ as *const () {
-        critical_section::with(|_| {
-            // assume the size is 8 - shouldn't rely on that
-            let message = item as *const u8;
-            let mut data = [0u8; 8];
-            for i in 0..8 as usize {
-                data[i] = *(message.offset(i as isize));
-            }
-            trace!("queue posting {:x?}", data);
-
-            BT_INTERNAL_QUEUE.as_mut().unwrap().enqueue(data);
-            memory_fence();
-        });
+/// The controller passes timeouts in milliseconds, the compat queue expects ticks
+fn ms_to_ticks(block_time_ms: u32) -> u32 {
+    if block_time_ms == OSI_FUNCS_TIME_BLOCKING {
+        OSI_FUNCS_TIME_BLOCKING
     } else {
-        panic!("Unknown queue");
+        (block_time_ms as u64 * crate::timer::TICKS_PER_SECOND / 1000) as u32
     }
-    1
+}
+
+unsafe extern "C" fn queue_send(queue: *const (), item: *const (), block_time_ms: u32) -> i32 {
+    send_queued(
+        queue as *mut crate::binary::c_types::c_void,
+        item as *mut crate::binary::c_types::c_void,
+        ms_to_ticks(block_time_ms),
+    )
 }
 
 unsafe extern "C" fn queue_send_from_isr(
@@ -446,57 +462,22 @@ unsafe extern "C" fn queue_send_from_isr(
 }
 
 unsafe extern "C" fn queue_recv(queue: *const (), item: *const (), block_time_ms: u32) -> i32 {
-    trace!(
-        "queue_recv {:p} item {:p} block_time_tick {}",
-        queue,
-        item,
-        block_time_ms
-    );
-
-    let end_time = crate::timer::get_systimer_count() + block_time_ms as u64;
-
-    // handle the BT_QUEUE
-    if queue == &mut BT_INTERNAL_QUEUE as *const _ as *const () {
-        loop {
-            let res = critical_section::with(|_| {
-                memory_fence();
-                let message = BT_INTERNAL_QUEUE.as_mut().unwrap().dequeue();
-                if message.is_some() {
-                    let message = message.unwrap();
-                    let item = item as *mut u8;
-                    for i in 0..8 {
-                        item.offset(i).write_volatile(message[i as usize]);
-                    }
-                    trace!("received {:x?}", message);
-                    1
-                } else {
-                    0
-                }
-            });
-
-            if res == 1 {
-                trace!("queue_recv returns");
-                return res;
-            }
-
-            if block_time_ms != OSI_FUNCS_TIME_BLOCKING
-                && crate::timer::get_systimer_count() > end_time
-            {
-                trace!("queue_recv returns with timeout");
-                return -1;
-            }
-        }
-    } else {
-        panic!("Unknown queue to handle in queue_recv");
-    }
+    receive_queued(
+        queue as *mut crate::binary::c_types::c_void,
+        item as *mut crate::binary::c_types::c_void,
+        ms_to_ticks(block_time_ms),
+    )
 }
 
 unsafe extern "C" fn queue_recv_from_isr(
-    _queue: *const (),
-    _item: *const (),
-    _hptw: *const (),
+    queue: *const (),
+    item: *const (),
+    hptw: *const (),
 ) -> i32 {
-    todo!();
+    log::trace!("queue_recv_from_isr {:p} {:p} {:p}", queue, item, hptw);
+    // Force to set the value to be false
+    *(hptw as *mut bool) = false;
+    queue_recv(queue, item, 0)
 }
 
 unsafe extern "C" fn task_create(
@@ -510,7 +491,7 @@ unsafe extern "C" fn task_create(
 ) -> i32 {
     let n = StrBuf::from(name);
     trace!(
-        "recheck implementation: task_create {:p} {:p} {} {} {:p} {} {:p} {}",
+        "task_create {:p} {:p} {} {} {:p} {} {:p} {}",
         func,
         name,
         n.as_str_ref(),
@@ -521,14 +502,14 @@ unsafe extern "C" fn task_create(
         core_id
     );
 
-    *(handle as *mut usize) = 0; // we will run it in task 0
-
-    queue_work(func, name, stack_depth, param, prio, handle, core_id);
-    1
+    // there is only one core
+    create_task(func, stack_depth, param, prio, handle)
 }
 
-unsafe extern "C" fn task_delete(_task: *const ()) {
-    todo!();
+unsafe extern "C" fn task_delete(task: *const ()) {
+    trace!("task_delete {:p}", task);
+
+    delete_task(task as *const crate::binary::c_types::c_void);
 }
 
 unsafe extern "C" fn is_in_isr() -> i32 {
@@ -610,21 +591,31 @@ unsafe extern "C" fn btdm_sleep_exit_phase3() {
     todo!();
 }
 
-unsafe extern "C" fn coex_schm_status_bit_set(_typ: i32, _status: i32) {
-    trace!("!!! unimplemented coex_schm_status_bit_set");
+unsafe extern "C" fn coex_schm_status_bit_set(typ: i32, status: i32) {
+    trace!("coex_schm_status_bit_set {} {}", typ, status);
+
+    #[cfg(feature = "coex")]
+    crate::coex::coex_schm_status_bit_set(typ as u32, status as u32);
 }
 
-unsafe extern "C" fn coex_schm_status_bit_clear(_typ: i32, _status: i32) {
-    trace!("!!! unimplemented coex_schm_status_bit_clear");
+unsafe extern "C" fn coex_schm_status_bit_clear(typ: i32, status: i32) {
+    trace!("coex_schm_status_bit_clear {} {}", typ, status);
+
+    #[cfg(feature = "coex")]
+    crate::coex::coex_schm_status_bit_clear(typ as u32, status as u32);
 }
 
 unsafe extern "C" fn read_efuse_mac(mac: *const ()) -> i32 {
     crate::wifi::read_mac(mac as *mut _, 2)
 }
 
-pub fn ble_init() {
+/// Initializes the controller, does nothing if that already happened.
+pub(crate) fn ble_init() -> Result<(), InitializationError> {
+    if critical_section::with(|_| unsafe { BLE_INITIALIZED }) {
+        return Ok(());
+    }
+
     unsafe {
-        BT_INTERNAL_QUEUE = Some(SimpleQueue::new());
         BT_RECEIVE_QUEUE = Some(SimpleQueue::new());
 
         *(HCI_OUT_COLLECTOR.as_mut_ptr()) = HciOutCollector::new();
@@ -646,7 +637,8 @@ pub fn ble_init() {
 
         let res = btdm_osi_funcs_register(&G_OSI_FUNCS as *const _ as *const ());
         if res != 0 {
-            panic!("btdm_osi_funcs_register returned {}", res);
+            log::warn!("btdm_osi_funcs_register returned {}", res);
+            return Err(InitializationError::General(res));
         }
 
         let version = btdm_controller_get_compile_version();
@@ -659,6 +651,15 @@ pub fn ble_init() {
 
         ble_os_adapter_chip_specific::disable_sleep_mode();
 
+        #[cfg(feature = "coex")]
+        {
+            let res = crate::coex::coex_init();
+            if res != 0 {
+                log::warn!("coex_init returned {}", res);
+                return Err(InitializationError::General(res));
+            }
+        }
+
         #[cfg(feature = "esp32c3")]
         let res = btdm_controller_init(&mut cfg as *mut esp_bt_controller_config_t);
 
@@ -669,7 +670,8 @@ pub fn ble_init() {
         ); // see btdm_config_mask_load for mask
 
         if res != 0 {
-            panic!("btdm_controller_init returned {}", res);
+            log::warn!("btdm_controller_init returned {}", res);
+            return Err(InitializationError::General(res));
         }
 
         log::debug!("The btdm_controller_init was initialized");
@@ -685,6 +687,9 @@ pub fn ble_init() {
 
         // esp32_bt_controller_enable
 
+        #[cfg(feature = "coex")]
+        crate::coex::coex_enable();
+
         // modifyreg32(SYSTEM_WIFI_CLK_EN_REG, 0, UINT32_MAX);
         // bt_phy_enable();
         crate::wifi::os_adapter::phy_enable();
@@ -707,50 +712,137 @@ pub fn ble_init() {
             BLE_INITIALIZED = true;
         });
     }
+
+    Ok(())
+}
+
+/// Disables and deinitializes the controller, [ble_init] can be called again afterwards.
+pub(crate) fn ble_deinit() {
+    if !critical_section::with(|_| unsafe { BLE_INITIALIZED }) {
+        return;
+    }
+
+    unsafe {
+        btdm_controller_disable();
+
+        #[cfg(feature = "coex")]
+        crate::coex::coex_disable();
+
+        crate::wifi::os_adapter::phy_disable();
+
+        btdm_controller_deinit();
+
+        critical_section::with(|_| {
+            BLE_INITIALIZED = false;
+        });
+    }
 }
 
-pub fn send_hci(data: &[u8]) {
+/// Writes H4 framed data to the controller.
+///
+/// The data can be split at arbitrary positions, a packet is passed to the controller
+/// once it's complete.
+pub(crate) fn send_hci(data: &[u8]) -> Result<(), HciError> {
     let hci_out = unsafe { &mut *HCI_OUT_COLLECTOR.as_mut_ptr() };
-    hci_out.push(data);
 
-    if hci_out.is_ready() {
-        let packet = hci_out.packet();
+    let mut data = data;
+    while !data.is_empty() {
+        let consumed = hci_out.push(data)?;
+        data = &data[consumed..];
 
-        unsafe {
-            loop {
-                let can_send = API_vhci_host_check_send_available();
+        if hci_out.is_ready() {
+            send_raw(hci_out.packet());
+            hci_out.reset();
+        }
+    }
 
-                if !can_send {
-                    log::trace!("can_send is false");
-                    continue;
-                }
+    Ok(())
+}
 
-                API_vhci_host_send_packet(packet.as_ptr() as *const u8, packet.len() as u16);
-                log::trace!("sent vhci host packet");
+/// Sends a complete packet to the controller.
+pub(crate) fn send_packet(packet: HciPacket) -> Result<(), HciError> {
+    let mut buf = [0u8; HCI_MAX_PACKET_LEN];
+    let len = packet.write_h4(&mut buf)?;
+    send_raw(&buf[..len]);
 
-                break;
+    Ok(())
+}
+
+fn send_raw(packet: &[u8]) {
+    unsafe {
+        loop {
+            let can_send = API_vhci_host_check_send_available();
+
+            if !can_send {
+                log::trace!("can_send is false");
+                continue;
             }
+
+            API_vhci_host_send_packet(packet.as_ptr() as *const u8, packet.len() as u16);
+            log::trace!("sent vhci host packet");
+
+            break;
         }
+    }
+}
+
+/// Receives a complete packet from the controller.
+///
+/// The packet is copied into `buf` which should be at least [HCI_MAX_PACKET_LEN] bytes.
+/// Returns `Ok(None)` if no packet is available. This shouldn't be mixed with [read_hci]
+/// since a packet partially read via [read_hci] is skipped.
+pub(crate) fn receive_packet(buf: &mut [u8]) -> Result<Option<HciPacket<'_>>, HciError> {
+    let dequeued = critical_section::with(|_| unsafe {
+        BLE_HCI_READ_DATA_LEN = 0;
+        BLE_HCI_READ_DATA_INDEX = 0;
+        BT_RECEIVE_QUEUE.as_mut().and_then(|queue| queue.dequeue())
+    });
+
+    match dequeued {
+        Some(packet) => {
+            let len = packet.len as usize;
+            if buf.len() < len {
+                return Err(HciError::BufferTooSmall);
+            }
 
-        hci_out.reset();
+            buf[..len].copy_from_slice(&packet.data[..len]);
+            HciPacket::from_h4(&buf[..len]).map(Some)
+        }
+        None => Ok(None),
     }
 }
 
-static mut BLE_HCI_READ_DATA: [u8; 256] = [0u8; 256];
+/// Returns true if there is data received from the controller which wasn't read yet
+pub(crate) fn have_hci_read_data() -> bool {
+    critical_section::with(|_| unsafe {
+        BLE_HCI_READ_DATA_LEN > 0
+            || BT_RECEIVE_QUEUE
+                .as_ref()
+                .map(|queue| !queue.is_empty())
+                .unwrap_or(false)
+    })
+}
+
+/// Returns true if the controller is able to accept a packet from the host
+pub(crate) fn hci_send_available() -> bool {
+    unsafe { API_vhci_host_check_send_available() }
+}
+
+static mut BLE_HCI_READ_DATA: [u8; HCI_MAX_PACKET_LEN] = [0u8; HCI_MAX_PACKET_LEN];
 static mut BLE_HCI_READ_DATA_INDEX: usize = 0;
 static mut BLE_HCI_READ_DATA_LEN: usize = 0;
 
-pub fn read_hci(data: &mut [u8]) -> usize {
+pub(crate) fn read_hci(data: &mut [u8]) -> usize {
     unsafe {
         if BLE_HCI_READ_DATA_LEN == 0 {
-            let dequeued = BT_RECEIVE_QUEUE.as_mut().unwrap().dequeue();
+            let dequeued = BT_RECEIVE_QUEUE.as_mut().and_then(|queue| queue.dequeue());
             match dequeued {
                 Some(packet) => {
-                    for i in 0..(packet.len as usize + 0/*1*/) {
+                    for i in 0..packet.len as usize {
                         BLE_HCI_READ_DATA[i] = packet.data[i];
                     }
 
-                    BLE_HCI_READ_DATA_LEN = packet.len as usize + 0 /*1*/;
+                    BLE_HCI_READ_DATA_LEN = packet.len as usize;
                     BLE_HCI_READ_DATA_INDEX = 0;
                 }
                 None => (),
@@ -870,67 +962,3 @@ impl HciPipe {
 }
 
 static mut HCI_OUT_COLLECTOR: MaybeUninit<HciOutCollector> = MaybeUninit::uninit();
-
-#[derive(PartialEq, Debug)]
-enum HciOutType {
-    Unknown,
-    Acl,
-    Command,
-}
-
-struct HciOutCollector {
-    data: [u8; 256],
-    index: usize,
-    ready: bool,
-    kind: HciOutType,
-}
-
-impl HciOutCollector {
-    fn new() -> HciOutCollector {
-        HciOutCollector {
-            data: [0u8; 256],
-            index: 0,
-            ready: false,
-            kind: HciOutType::Unknown,
-        }
-    }
-
-    fn is_ready(&self) -> bool {
-        self.ready
-    }
-
-    fn push(&mut self, data: &[u8]) {
-        self.data[self.index..(self.index + data.len())].copy_from_slice(data);
-        self.index += data.len();
-
-        if self.kind == HciOutType::Unknown {
-            self.kind = match self.data[0] {
-                1 => HciOutType::Command,
-                2 => HciOutType::Acl,
-                _ => HciOutType::Unknown,
-            };
-        }
-
-        if !self.ready {
-            if self.kind == HciOutType::Command && self.index >= 4 {
-                if self.index == self.data[3] as usize + 4 {
-                    self.ready = true;
-                }
-            } else if self.kind == HciOutType::Acl && self.index >= 5 {
-                if self.index == (self.data[3] as usize) + ((self.data[4] as usize) << 8) + 5 {
-                    self.ready = true;
-                }
-            }
-        }
-    }
-
-    fn reset(&mut self) {
-        self.index = 0;
-        self.ready = false;
-        self.kind = HciOutType::Unknown;
-    }
-
-    fn packet(&self) -> &[u8] {
-        &self.data[0..(self.index as usize)]
-    }
-}
@@ -195,68 +195,152 @@ pub(crate) fn disable_sleep_mode() {
 }
 
 pub(crate) unsafe extern "C" fn coex_bt_wakeup_request() -> bool {
-    todo!()
+    // the controller never sleeps (see `disable_sleep_mode`) so there is nothing to wake up
+    log::trace!("coex_bt_wakeup_request");
+    false
 }
 
 pub(crate) unsafe extern "C" fn coex_bt_wakeup_request_end() -> () {
-    todo!()
+    log::trace!("coex_bt_wakeup_request_end");
 }
 
-pub(crate) unsafe extern "C" fn coex_bt_request(_event: u32, _latency: u32, _duration: u32) -> i32 {
-    log::trace!("COEX not supported in coex_bt_request");
-    0
+pub(crate) unsafe extern "C" fn coex_bt_request(event: u32, latency: u32, duration: u32) -> i32 {
+    log::trace!("coex_bt_request {} {} {}", event, latency, duration);
+
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_bt_request(event, latency, duration)
+    }
+
+    #[cfg(not(feature = "coex"))]
+    {
+        0
+    }
 }
 
-pub(crate) unsafe extern "C" fn coex_bt_release(_event: u32) -> i32 {
-    log::trace!("COEX not supported in coex_bt_release");
-    0
+pub(crate) unsafe extern "C" fn coex_bt_release(event: u32) -> i32 {
+    log::trace!("coex_bt_release {}", event);
+
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_bt_release(event)
+    }
+
+    #[cfg(not(feature = "coex"))]
+    {
+        0
+    }
 }
 
-pub(crate) unsafe extern "C" fn coex_register_bt_cb(_callback: unsafe extern "C" fn()) -> i32 {
-    log::trace!("COEX not supported in coex_register_bt_cb");
-    0
+pub(crate) unsafe extern "C" fn coex_register_bt_cb(callback: unsafe extern "C" fn()) -> i32 {
+    log::trace!("coex_register_bt_cb {:p}", callback);
+
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_register_bt_cb(callback)
+    }
+
+    #[cfg(not(feature = "coex"))]
+    {
+        0
+    }
 }
 
 pub(crate) unsafe extern "C" fn coex_bb_reset_lock() -> u32 {
-    log::trace!("COEX not supported in coex_bb_reset_lock");
-    0
+    log::trace!("coex_bb_reset_lock");
+
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_bb_reset_lock()
+    }
+
+    #[cfg(not(feature = "coex"))]
+    {
+        0
+    }
 }
 
-pub(crate) unsafe extern "C" fn coex_bb_reset_unlock(_event: u32) {
-    log::trace!("COEX not supported in coex_bb_reset_unlock");
+pub(crate) unsafe extern "C" fn coex_bb_reset_unlock(event: u32) {
+    log::trace!("coex_bb_reset_unlock {}", event);
+
+    #[cfg(feature = "coex")]
+    crate::coex::coex_bb_reset_unlock(event);
 }
 
 pub(crate) unsafe extern "C" fn coex_schm_register_btdm_callback(
-    _callback: unsafe extern "C" fn(),
+    callback: unsafe extern "C" fn(),
 ) -> i32 {
-    log::trace!("COEX not supported in coex_schm_register_btdm_callback");
-    0
+    log::trace!("coex_schm_register_btdm_callback {:p}", callback);
+
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_schm_register_btdm_callback(callback)
+    }
+
+    #[cfg(not(feature = "coex"))]
+    {
+        0
+    }
 }
 
 pub(crate) unsafe extern "C" fn coex_schm_interval_get() -> u32 {
-    todo!()
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_schm_interval_get()
+    }
+
+    #[cfg(not(feature = "coex"))]
+    todo!();
 }
 
 pub(crate) unsafe extern "C" fn coex_schm_curr_period_get() -> u8 {
-    todo!()
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_schm_curr_period_get()
+    }
+
+    #[cfg(not(feature = "coex"))]
+    todo!();
 }
 
 pub(crate) unsafe extern "C" fn coex_schm_curr_phase_get() -> *const () {
-    todo!()
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_schm_curr_phase_get() as *const ()
+    }
+
+    #[cfg(not(feature = "coex"))]
+    todo!();
 }
 
-pub(crate) unsafe extern "C" fn coex_wifi_channel_get(
-    _primary: *mut u8,
-    _secondary: *mut u8,
-) -> i32 {
-    log::trace!("COEX not supported in coex_wifi_channel_get");
-    -1
+pub(crate) unsafe extern "C" fn coex_wifi_channel_get(primary: *mut u8, secondary: *mut u8) -> i32 {
+    log::trace!("coex_wifi_channel_get {:p} {:p}", primary, secondary);
+
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_wifi_channel_get(primary, secondary)
+    }
+
+    #[cfg(not(feature = "coex"))]
+    {
+        -1
+    }
 }
 
 pub(crate) unsafe extern "C" fn coex_register_wifi_channel_change_callback(
-    _callback: unsafe extern "C" fn(),
+    callback: unsafe extern "C" fn(),
 ) -> i32 {
-    todo!()
+    log::trace!("coex_register_wifi_channel_change_callback {:p}", callback);
+
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_register_wifi_channel_change_callback(callback)
+    }
+
+    #[cfg(not(feature = "coex"))]
+    {
+        0
+    }
 }
 
 pub(crate) unsafe extern "C" fn set_isr(n: i32, f: unsafe extern "C" fn(), arg: *const ()) -> i32 {
@@ -134,14 +134,25 @@ pub(crate) unsafe extern "C" fn interrupt_handler_set(
 }
 
 pub(crate) unsafe extern "C" fn coex_wifi_sleep_set(sleep: i32) {
-    trace!("!!!! unimplemented coex_wifi_sleep_set {}", sleep);
+    // the original hook does nothing either
+    trace!("coex_wifi_sleep_set {}", sleep);
 }
 
 pub(crate) unsafe extern "C" fn coex_core_ble_conn_dyn_prio_get(
-    _low: *mut i32,
-    _high: *mut i32,
+    low: *mut i32,
+    high: *mut i32,
 ) -> i32 {
-    todo!();
+    trace!("coex_core_ble_conn_dyn_prio_get {:p} {:p}", low, high);
+
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_core_ble_conn_dyn_prio_get(low, high)
+    }
+
+    #[cfg(not(feature = "coex"))]
+    {
+        0
+    }
 }
 
 pub(crate) unsafe extern "C" fn esp_hw_power_down() {
@@ -0,0 +1,198 @@
+//! Wi-Fi and Bluetooth coexistence.
+//!
+//! With the `coex` feature both radios share the RF, the coexistence scheduler in
+//! `libcoexist` decides which one gets to use it.
+
+use crate::binary::c_types::{c_int, c_void};
+use crate::wifi::os_adapter::{
+    free, is_from_isr, malloc, semphr_create, semphr_delete, semphr_give, semphr_take,
+    task_yield_from_isr,
+};
+#[cfg(feature = "esp32")]
+use crate::wifi::os_adapter::{
+    spin_lock_create, spin_lock_delete, timer_arm_us, timer_disarm, timer_done, timer_setfn,
+    wifi_int_disable, wifi_int_restore,
+};
+use crate::wifi::WifiError;
+
+const COEX_ADAPTER_VERSION: i32 = 0x00000002;
+const COEX_ADAPTER_MAGIC: u32 = 0xDEADBEAF;
+
+/// Which radio gets more opportunities to use the RF
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CoexPreference {
+    Wifi,
+    Bluetooth,
+    #[default]
+    Balance,
+}
+
+impl CoexPreference {
+    fn to_coex_prefer(&self) -> u32 {
+        match self {
+            CoexPreference::Wifi => 0,
+            CoexPreference::Bluetooth => 1,
+            CoexPreference::Balance => 2,
+        }
+    }
+}
+
+/// Sets the coexistence preference, it can be changed at any time.
+pub fn set_preference(preference: CoexPreference) -> Result<(), WifiError> {
+    let res = unsafe { coex_preference_set(preference.to_coex_prefer()) };
+    if res != 0 {
+        return Err(WifiError::General(res));
+    }
+
+    Ok(())
+}
+
+#[repr(C)]
+struct coex_adapter_funcs_t {
+    _version: i32,
+    #[cfg(feature = "esp32")]
+    _spin_lock_create: Option<unsafe extern "C" fn() -> *mut c_void>,
+    #[cfg(feature = "esp32")]
+    _spin_lock_delete: Option<unsafe extern "C" fn(*mut c_void)>,
+    #[cfg(feature = "esp32")]
+    _int_disable: Option<unsafe extern "C" fn(*mut c_void) -> u32>,
+    #[cfg(feature = "esp32")]
+    _int_enable: Option<unsafe extern "C" fn(*mut c_void, u32)>,
+    _task_yield_from_isr: Option<unsafe extern "C" fn()>,
+    _semphr_create: Option<unsafe extern "C" fn(u32, u32) -> *mut c_void>,
+    _semphr_delete: Option<unsafe extern "C" fn(*mut c_void)>,
+    _semphr_take_from_isr: Option<unsafe extern "C" fn(*mut c_void, *mut c_void) -> i32>,
+    _semphr_give_from_isr: Option<unsafe extern "C" fn(*mut c_void, *mut c_void) -> i32>,
+    _semphr_take: Option<unsafe extern "C" fn(*mut c_void, u32) -> i32>,
+    _semphr_give: Option<unsafe extern "C" fn(*mut c_void) -> i32>,
+    _is_in_isr: Option<unsafe extern "C" fn() -> c_int>,
+    _malloc_internal: Option<unsafe extern "C" fn(u32) -> *mut c_void>,
+    _free: Option<unsafe extern "C" fn(*mut c_void)>,
+    #[cfg(feature = "esp32")]
+    _timer_disarm: Option<unsafe extern "C" fn(*mut c_void)>,
+    #[cfg(feature = "esp32")]
+    _timer_done: Option<unsafe extern "C" fn(*mut c_void)>,
+    #[cfg(feature = "esp32")]
+    _timer_setfn: Option<unsafe extern "C" fn(*mut c_void, *mut c_void, *mut c_void)>,
+    #[cfg(feature = "esp32")]
+    _timer_arm_us: Option<unsafe extern "C" fn(*mut c_void, u32, bool)>,
+    _esp_timer_get_time: Option<unsafe extern "C" fn() -> i64>,
+    _magic: i32,
+}
+
+static mut G_COEX_ADAPTER_FUNCS: coex_adapter_funcs_t = coex_adapter_funcs_t {
+    _version: COEX_ADAPTER_VERSION,
+    #[cfg(feature = "esp32")]
+    _spin_lock_create: Some(spin_lock_create),
+    #[cfg(feature = "esp32")]
+    _spin_lock_delete: Some(spin_lock_delete),
+    #[cfg(feature = "esp32")]
+    _int_disable: Some(wifi_int_disable),
+    #[cfg(feature = "esp32")]
+    _int_enable: Some(wifi_int_restore),
+    _task_yield_from_isr: Some(task_yield_from_isr),
+    _semphr_create: Some(semphr_create),
+    _semphr_delete: Some(semphr_delete),
+    _semphr_take_from_isr: Some(semphr_take_from_isr),
+    _semphr_give_from_isr: Some(semphr_give_from_isr),
+    _semphr_take: Some(semphr_take),
+    _semphr_give: Some(semphr_give),
+    _is_in_isr: Some(is_in_isr),
+    _malloc_internal: Some(malloc),
+    _free: Some(free),
+    #[cfg(feature = "esp32")]
+    _timer_disarm: Some(timer_disarm),
+    #[cfg(feature = "esp32")]
+    _timer_done: Some(timer_done),
+    #[cfg(feature = "esp32")]
+    _timer_setfn: Some(timer_setfn),
+    #[cfg(feature = "esp32")]
+    _timer_arm_us: Some(timer_arm_us),
+    _esp_timer_get_time: Some(crate::wifi::os_adapter::esp_timer_get_time),
+    _magic: COEX_ADAPTER_MAGIC as i32,
+};
+
+unsafe extern "C" fn semphr_take_from_isr(semphr: *mut c_void, _hptw: *mut c_void) -> i32 {
+    semphr_take(semphr, 0)
+}
+
+unsafe extern "C" fn semphr_give_from_isr(semphr: *mut c_void, _hptw: *mut c_void) -> i32 {
+    semphr_give(semphr)
+}
+
+unsafe extern "C" fn is_in_isr() -> c_int {
+    is_from_isr() as c_int
+}
+
+/// Registers the adapter functions, has to happen before either radio is initialized
+pub(crate) fn coex_initialize() -> Result<(), WifiError> {
+    unsafe {
+        let res = esp_coex_adapter_register(&mut G_COEX_ADAPTER_FUNCS);
+        if res != 0 {
+            return Err(WifiError::General(res));
+        }
+
+        let res = coex_pre_init();
+        if res != 0 {
+            return Err(WifiError::General(res));
+        }
+    }
+
+    Ok(())
+}
+
+/// Undoes [coex_initialize], has to happen after both radios are deinitialized
+pub(crate) fn coex_deinitialize() {
+    unsafe {
+        coex_deinit();
+    }
+}
+
+extern "C" {
+    fn esp_coex_adapter_register(funcs: *mut coex_adapter_funcs_t) -> i32;
+    fn coex_pre_init() -> i32;
+    fn coex_preference_set(prefer: u32) -> i32;
+
+    pub(crate) fn coex_init() -> i32;
+    pub(crate) fn coex_deinit();
+    pub(crate) fn coex_enable() -> i32;
+    pub(crate) fn coex_disable();
+    pub(crate) fn coex_status_get() -> u32;
+    pub(crate) fn coex_condition_set(type_: u32, dissatisfy: bool);
+    pub(crate) fn coex_wifi_request(event: u32, latency: u32, duration: u32) -> i32;
+    pub(crate) fn coex_wifi_release(event: u32) -> i32;
+    pub(crate) fn coex_wifi_channel_set(primary: u8, secondary: u8) -> i32;
+    pub(crate) fn coex_event_duration_get(event: u32, duration: *mut u32) -> i32;
+    #[cfg(feature = "esp32c3")]
+    pub(crate) fn coex_pti_get(event: u32, pti: *mut u8) -> i32;
+    pub(crate) fn coex_schm_status_bit_clear(type_: u32, status: u32);
+    pub(crate) fn coex_schm_status_bit_set(type_: u32, status: u32);
+    pub(crate) fn coex_schm_interval_set(interval: u32) -> i32;
+    pub(crate) fn coex_schm_interval_get() -> u32;
+    pub(crate) fn coex_schm_curr_period_get() -> u8;
+    pub(crate) fn coex_schm_curr_phase_get() -> *mut c_void;
+    pub(crate) fn coex_schm_curr_phase_idx_set(idx: c_int) -> c_int;
+    pub(crate) fn coex_schm_curr_phase_idx_get() -> c_int;
+
+    #[cfg(feature = "esp32c3")]
+    pub(crate) fn coex_core_ble_conn_dyn_prio_get(low: *mut i32, high: *mut i32) -> i32;
+
+    #[cfg(feature = "esp32")]
+    pub(crate) fn coex_bt_request(event: u32, latency: u32, duration: u32) -> i32;
+    #[cfg(feature = "esp32")]
+    pub(crate) fn coex_bt_release(event: u32) -> i32;
+    #[cfg(feature = "esp32")]
+    pub(crate) fn coex_register_bt_cb(callback: unsafe extern "C" fn()) -> i32;
+    #[cfg(feature = "esp32")]
+    pub(crate) fn coex_bb_reset_lock() -> u32;
+    #[cfg(feature = "esp32")]
+    pub(crate) fn coex_bb_reset_unlock(restore: u32);
+    #[cfg(feature = "esp32")]
+    pub(crate) fn coex_schm_register_btdm_callback(callback: unsafe extern "C" fn()) -> i32;
+    #[cfg(feature = "esp32")]
+    pub(crate) fn coex_wifi_channel_get(primary: *mut u8, secondary: *mut u8) -> i32;
+    #[cfg(feature = "esp32")]
+    pub(crate) fn coex_register_wifi_channel_change_callback(
+        callback: unsafe extern "C" fn(),
+    ) -> i32;
+}
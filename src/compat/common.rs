@@ -3,12 +3,15 @@
 use core::{ffi::VaListImpl, fmt::Write};
 
 use esp_alloc::memory_fence;
-use log::{info, trace};
+use log::{info, trace, warn};
 
-use super::queue::SimpleQueue;
+use super::queue::RawQueue;
 use crate::{
     binary::{c_types::c_void, include::OSI_FUNCS_TIME_BLOCKING},
-    preempt::preempt::current_task,
+    preempt::{
+        preempt::{current_task, MAX_TASK},
+        prepare_wait, wait_for_wake_up, wake_tasks,
+    },
 };
 
 static mut CURR_SEM: [Option<u32>; 20] = [
@@ -16,24 +19,25 @@ static mut CURR_SEM: [Option<u32>; 20] = [
     None, None, None, None,
 ];
 
-static mut PER_THREAD_SEM: [Option<*mut crate::binary::c_types::c_void>; 3] = [None; 3];
+static mut PER_THREAD_SEM: [Option<*mut crate::binary::c_types::c_void>; MAX_TASK] =
+    [None; MAX_TASK];
 
 #[derive(Clone, Copy, Debug)]
 struct Mutex {
     locking_pid: usize,
     count: u32,
     recursive: bool,
+    allocated: bool,
 }
 
-static mut MUTEXES: [Mutex; 10] = [Mutex {
+const MUTEX_UNALLOCATED: Mutex = Mutex {
     locking_pid: 0xffff_ffff,
     count: 0,
     recursive: false,
-}; 10];
-static mut MUTEX_IDX_CURRENT: usize = 0;
+    allocated: false,
+};
 
-static mut FAKE_WIFI_QUEUE: &Option<SimpleQueue<[u8; 8], 200>> = unsafe { &REAL_WIFI_QUEUE };
-static mut REAL_WIFI_QUEUE: Option<SimpleQueue<[u8; 8], 200>> = None; // first there is a ptr to the real queue - driver checks it's not null
+static mut MUTEXES: [Mutex; 10] = [MUTEX_UNALLOCATED; 10];
 
 pub struct StrBuf {
     buffer: [u8; 512],
@@ -264,47 +268,49 @@ pub fn sem_delete(semphr: *mut crate::binary::c_types::c_void) {
 pub fn sem_take(semphr: *mut crate::binary::c_types::c_void, tick: u32) -> i32 {
     trace!(">>>> semphr_take {:p} block_time_tick {}", semphr, tick);
 
-    let forever = if tick == OSI_FUNCS_TIME_BLOCKING {
-        true
-    } else {
-        false
-    };
-    let tick = if tick == 0 { 1 } else { tick };
+    // with a timeout of 0 it's tried once, that's what happens in an ISR
+    let forever = tick == OSI_FUNCS_TIME_BLOCKING;
     let end_time = crate::timer::get_systimer_count() + tick as u64;
 
     loop {
-        loop {
-            let res = critical_section::with(|_| unsafe {
-                memory_fence();
-                if let Some(cnt) = CURR_SEM[semphr as usize - 1] {
-                    if cnt > 0 {
-                        CURR_SEM[semphr as usize - 1] = Some(cnt - 1);
-                        1
-                    } else {
-                        0
-                    }
-                } else {
-                    0
+        let res = critical_section::with(|_| unsafe {
+            memory_fence();
+            if let Some(cnt) = CURR_SEM[semphr as usize - 1] {
+                if cnt > 0 {
+                    CURR_SEM[semphr as usize - 1] = Some(cnt - 1);
+                    return 1;
                 }
-            });
-
-            if res == 1 {
-                trace!(">>>> return from semphr_take");
-                return 1;
             }
 
-            if !forever {
-                if crate::timer::get_systimer_count() > end_time {
-                    break;
-                }
-            }
+            prepare_wait(semphr as *const (), wait_until(forever, end_time));
+            0
+        });
+
+        if res == 1 {
+            trace!(">>>> return from semphr_take");
+            return 1;
+        }
+
+        if !forever && crate::timer::get_systimer_count() >= end_time {
+            break;
         }
+
+        wait_for_wake_up();
     }
 
     trace!(">>>> return from semphr_take with timeout");
     0
 }
 
+/// The deadline to hand to [prepare_wait]
+fn wait_until(forever: bool, end_time: u64) -> Option<u64> {
+    if forever {
+        None
+    } else {
+        Some(end_time)
+    }
+}
+
 pub fn sem_give(semphr: *mut crate::binary::c_types::c_void) -> i32 {
     trace!("semphr_give {:p}", semphr);
 
@@ -312,6 +318,7 @@ pub fn sem_give(semphr: *mut crate::binary::c_types::c_void) -> i32 {
         if let Some(cnt) = CURR_SEM[semphr as usize - 1] {
             CURR_SEM[semphr as usize - 1] = Some(cnt + 1);
             memory_fence();
+            wake_tasks(semphr as *const ());
             1
         } else {
             0
@@ -340,48 +347,64 @@ pub fn thread_sem_get() -> *mut crate::binary::c_types::c_void {
 
 pub fn create_recursive_mutex() -> *mut crate::binary::c_types::c_void {
     critical_section::with(|_| unsafe {
-        let ptr = &mut MUTEXES[MUTEX_IDX_CURRENT] as *mut _ as *mut Mutex;
-        (*ptr).recursive = true;
-        MUTEX_IDX_CURRENT += 1;
+        memory_fence();
+        let ptr = match MUTEXES.iter_mut().find(|mutex| !mutex.allocated) {
+            Some(mutex) => {
+                *mutex = Mutex {
+                    recursive: true,
+                    allocated: true,
+                    ..MUTEX_UNALLOCATED
+                };
+                mutex as *mut Mutex
+            }
+            None => core::ptr::null_mut(),
+        };
         memory_fence();
         trace!("recursive_mutex_create called {:p}", ptr);
         ptr as *mut crate::binary::c_types::c_void
     })
 }
 
+pub fn delete_mutex(mutex: *mut crate::binary::c_types::c_void) {
+    trace!("mutex_delete {:p}", mutex);
+
+    critical_section::with(|_| unsafe {
+        match MUTEXES
+            .iter_mut()
+            .find(|m| core::ptr::eq(&**m, mutex as *const Mutex))
+        {
+            Some(m) => *m = MUTEX_UNALLOCATED,
+            None => warn!("mutex_delete of unknown mutex {:p}", mutex),
+        }
+        memory_fence();
+    })
+}
+
 pub fn lock_mutex(mutex: *mut crate::binary::c_types::c_void) -> i32 {
     trace!("mutex_lock ptr = {:p}", mutex);
 
-    unsafe {
-        let ptr = mutex as *mut Mutex;
-        let current_task = current_task();
-        let success = loop {
-            let (should_break, success) = critical_section::with(|_| {
-                if (*ptr).count == 0 {
-                    (*ptr).locking_pid = current_task;
-                    (*ptr).count += 1;
-                    (true, true)
-                } else if (*ptr).count != 0 && (*ptr).locking_pid == current_task {
-                    (*ptr).count += 1;
-                    (true, true)
-                } else if (*ptr).count != 0 && (*ptr).locking_pid != current_task {
-                    (true, false)
-                } else {
-                    (false, false)
-                }
-            });
-            memory_fence();
+    let ptr = mutex as *mut Mutex;
+    let current_task = current_task();
 
-            if should_break {
-                break success;
+    loop {
+        let locked = critical_section::with(|_| unsafe {
+            memory_fence();
+            if (*ptr).count == 0 || (*ptr).locking_pid == current_task {
+                (*ptr).locking_pid = current_task;
+                (*ptr).count += 1;
+                memory_fence();
+                return true;
             }
-        };
 
-        if success {
-            1
-        } else {
-            0
+            prepare_wait(mutex as *const (), None);
+            false
+        });
+
+        if locked {
+            return 1;
         }
+
+        wait_for_wake_up();
     }
 }
 
@@ -393,6 +416,9 @@ pub fn unlock_mutex(mutex: *mut crate::binary::c_types::c_void) -> i32 {
         memory_fence();
         if (*ptr).count > 0 {
             (*ptr).count -= 1;
+            if (*ptr).count == 0 {
+                wake_tasks(mutex as *const ());
+            }
             1
         } else {
             0
@@ -400,66 +426,153 @@ pub fn unlock_mutex(mutex: *mut crate::binary::c_types::c_void) -> i32 {
     })
 }
 
+/// Frees all semaphores and mutexes - has to happen after the driver is deinitialized since
+/// it doesn't free everything itself.
+pub fn reset_sync_primitives() {
+    critical_section::with(|_| unsafe {
+        CURR_SEM = [None; 20];
+        PER_THREAD_SEM = [None; MAX_TASK];
+        MUTEXES = [MUTEX_UNALLOCATED; 10];
+        memory_fence();
+    })
+}
+
+pub fn create_queue(queue_len: u32, item_size: u32) -> *mut crate::binary::c_types::c_void {
+    trace!("create_queue len={} size={}", queue_len, item_size);
+
+    let mut queue = match RawQueue::new(queue_len as usize, item_size as usize) {
+        Some(queue) => queue,
+        None => {
+            warn!("no memory left for a queue of {} items", queue_len);
+            return core::ptr::null_mut();
+        }
+    };
+
+    unsafe {
+        let ptr = esp_alloc::malloc(core::mem::size_of::<RawQueue>() as u32) as *mut RawQueue;
+        if ptr.is_null() {
+            warn!("no memory left for a queue");
+            queue.release_storage();
+            return core::ptr::null_mut();
+        }
+        ptr.write(queue);
+
+        trace!("created queue @{:p}", ptr);
+        ptr as *mut crate::binary::c_types::c_void
+    }
+}
+
+pub fn delete_queue(queue: *mut crate::binary::c_types::c_void) {
+    trace!("delete_queue {:p}", queue);
+
+    critical_section::with(|_| unsafe {
+        let queue = queue as *mut RawQueue;
+        (*queue).release_storage();
+        esp_alloc::free(queue as *const u8);
+    });
+}
+
+/// The wifi driver's static queues are a pointer to the actual queue followed by its storage
 pub fn create_wifi_queue(
     queue_len: crate::binary::c_types::c_int,
     item_size: crate::binary::c_types::c_int,
 ) -> *mut crate::binary::c_types::c_void {
+    trace!("wifi_create_queue len={} size={}", queue_len, item_size);
+
     unsafe {
-        trace!(
-            "wifi_create_queue len={} size={} ptr={:p} real-queue {:p}  - not checked",
-            queue_len,
-            item_size,
-            &FAKE_WIFI_QUEUE,
-            &REAL_WIFI_QUEUE,
-        );
-    }
+        let wifi_queue = esp_alloc::calloc(2, core::mem::size_of::<*mut c_void>() as u32)
+            as *mut *mut crate::binary::c_types::c_void;
+        if wifi_queue.is_null() {
+            warn!("no memory left for a wifi queue");
+            return core::ptr::null_mut();
+        }
+
+        *wifi_queue = create_queue(queue_len as u32, item_size as u32);
+        if (*wifi_queue).is_null() {
+            esp_alloc::free(wifi_queue as *const u8);
+            return core::ptr::null_mut();
+        }
 
-    if item_size > 8 {
-        panic!("don't expecting the wifi queue to hold items larger than 8");
+        wifi_queue as *mut crate::binary::c_types::c_void
     }
+}
 
-    unsafe {
-        if REAL_WIFI_QUEUE.is_none() {
-            REAL_WIFI_QUEUE = Some(SimpleQueue::new());
-        } else {
-            panic!("don't expecting more than one wifi queue");
-        }
+pub fn delete_wifi_queue(queue: *mut crate::binary::c_types::c_void) {
+    trace!("wifi_delete_queue {:p}", queue);
 
-        &mut FAKE_WIFI_QUEUE as *mut _ as *mut crate::binary::c_types::c_void
+    unsafe {
+        let wifi_queue = queue as *mut *mut crate::binary::c_types::c_void;
+        delete_queue(*wifi_queue);
+        esp_alloc::free(wifi_queue as *const u8);
     }
 }
 
-pub fn send_queued(
+fn send_queued_at(
     queue: *mut crate::binary::c_types::c_void,
     item: *mut crate::binary::c_types::c_void,
     block_time_tick: u32,
+    front: bool,
 ) -> i32 {
     trace!(
-        "queue_send queue {:p} item {:p} block_time_tick {}",
+        "queue_send queue {:p} item {:p} block_time_tick {} front {}",
         queue,
         item,
-        block_time_tick
+        block_time_tick,
+        front
     );
 
-    // handle the WIFI_QUEUE
-    unsafe {
-        if queue == &mut REAL_WIFI_QUEUE as *mut _ as *mut crate::binary::c_types::c_void {
-            critical_section::with(|_| {
-                // assume the size is 8 - shouldn't rely on that
-                let message = item as *const u8;
-                let mut data = [0u8; 8];
-                for i in 0..8 as usize {
-                    data[i] = *(message.offset(i as isize));
-                }
-                trace!("queue posting {:x?}", data);
+    let forever = block_time_tick == OSI_FUNCS_TIME_BLOCKING;
+    let end_time = crate::timer::get_systimer_count() + block_time_tick as u64;
 
-                REAL_WIFI_QUEUE.as_mut().unwrap().enqueue(data);
-                memory_fence();
-            });
+    loop {
+        let res = critical_section::with(|_| unsafe {
+            memory_fence();
+            let raw_queue = &mut *(queue as *mut RawQueue);
+            // the driver passes a pointer to an item of the queue's item size
+            let item = core::slice::from_raw_parts(item as *const u8, raw_queue.item_size());
+            let sent = if front {
+                raw_queue.enqueue_front(item)
+            } else {
+                raw_queue.enqueue(item)
+            };
+            memory_fence();
+
+            if sent {
+                // receivers wait for the queue, too
+                wake_tasks(queue as *const ());
+            } else {
+                prepare_wait(queue as *const (), wait_until(forever, end_time));
+            }
+            sent
+        });
+
+        if res {
+            return 1;
         }
+
+        if !forever && crate::timer::get_systimer_count() >= end_time {
+            trace!("queue_send returns with timeout");
+            return 0;
+        }
+
+        wait_for_wake_up();
     }
+}
 
-    1
+pub fn send_queued(
+    queue: *mut crate::binary::c_types::c_void,
+    item: *mut crate::binary::c_types::c_void,
+    block_time_tick: u32,
+) -> i32 {
+    send_queued_at(queue, item, block_time_tick, false)
+}
+
+pub fn send_queued_to_front(
+    queue: *mut crate::binary::c_types::c_void,
+    item: *mut crate::binary::c_types::c_void,
+    block_time_tick: u32,
+) -> i32 {
+    send_queued_at(queue, item, block_time_tick, true)
 }
 
 pub fn receive_queued(
@@ -474,43 +587,43 @@ pub fn receive_queued(
         block_time_tick
     );
 
+    let forever = block_time_tick == OSI_FUNCS_TIME_BLOCKING;
     let end_time = crate::timer::get_systimer_count() + block_time_tick as u64;
 
-    // handle the WIFI_QUEUE
-    unsafe {
-        if queue == &mut REAL_WIFI_QUEUE as *mut _ as *mut crate::binary::c_types::c_void {
-            loop {
-                let res = critical_section::with(|_| {
-                    memory_fence();
-                    let message = REAL_WIFI_QUEUE.as_mut().unwrap().dequeue();
-                    if message.is_some() {
-                        let message = message.unwrap();
-                        let item = item as *mut u8;
-                        for i in 0..8 {
-                            item.offset(i).write_volatile(message[i as usize]);
-                        }
-                        trace!("received {:x?}", message);
-                        1
-                    } else {
-                        0
-                    }
-                });
-
-                if res == 1 {
-                    trace!("queue_recv returns");
-                    return res;
-                }
+    loop {
+        let res = critical_section::with(|_| unsafe {
+            memory_fence();
+            let raw_queue = &mut *(queue as *mut RawQueue);
+            let item = core::slice::from_raw_parts_mut(item as *mut u8, raw_queue.item_size());
+            let received = raw_queue.try_dequeue(item);
 
-                if block_time_tick != OSI_FUNCS_TIME_BLOCKING
-                    && crate::timer::get_systimer_count() > end_time
-                {
-                    trace!("queue_recv returns with timeout");
-                    return -1;
-                }
+            if received {
+                // senders wait for the queue, too
+                wake_tasks(queue as *const ());
+            } else {
+                prepare_wait(queue as *const (), wait_until(forever, end_time));
             }
-        } else {
-            panic!("Unknown queue to handle in queue_recv");
-            -1
+            received
+        });
+
+        if res {
+            trace!("queue_recv returns");
+            return 1;
         }
+
+        if !forever && crate::timer::get_systimer_count() >= end_time {
+            trace!("queue_recv returns with timeout");
+            return 0;
+        }
+
+        wait_for_wake_up();
     }
 }
+
+pub fn number_of_messages_in_queue(queue: *const crate::binary::c_types::c_void) -> u32 {
+    critical_section::with(|_| unsafe {
+        memory_fence();
+        let queue = &*(queue as *const RawQueue);
+        queue.count() as u32
+    })
+}
@@ -0,0 +1,257 @@
+use esp_alloc::memory_fence;
+use log::trace;
+
+use crate::binary::include::OSI_FUNCS_TIME_BLOCKING;
+use crate::preempt::{prepare_wait, wait_for_wake_up, wake_tasks};
+
+/// Like FreeRTOS the upper 8 bits are reserved
+const EVENT_BITS_MASK: u32 = 0x00ff_ffff;
+
+/// FreeRTOS compatible event group
+pub struct EventGroup {
+    bits: u32,
+}
+
+impl EventGroup {
+    pub const fn new() -> EventGroup {
+        EventGroup { bits: 0 }
+    }
+
+    /// Sets the bits, returns the bits after setting them
+    pub fn set_bits(&mut self, bits: u32) -> u32 {
+        self.bits |= bits & EVENT_BITS_MASK;
+        self.bits
+    }
+
+    /// Clears the bits, returns the bits before clearing them
+    pub fn clear_bits(&mut self, bits: u32) -> u32 {
+        let before = self.bits;
+        self.bits &= !(bits & EVENT_BITS_MASK);
+        before
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// Checks the wait condition, clears the waited for bits if requested and it's met.
+    /// Returns the bits before clearing and whether the condition is met. Waiting for no bits,
+    /// e.g. only reserved ones, is never met.
+    pub fn try_wait(
+        &mut self,
+        bits_to_wait_for: u32,
+        clear_on_exit: bool,
+        wait_for_all: bool,
+    ) -> (u32, bool) {
+        let bits = self.bits;
+        let wanted = bits_to_wait_for & EVENT_BITS_MASK;
+
+        let met = if wanted == 0 {
+            false
+        } else if wait_for_all {
+            bits & wanted == wanted
+        } else {
+            bits & wanted != 0
+        };
+
+        if met && clear_on_exit {
+            self.bits &= !wanted;
+        }
+
+        (bits, met)
+    }
+}
+
+impl Default for EventGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn create_event_group() -> *mut crate::binary::c_types::c_void {
+    unsafe {
+        let ptr = esp_alloc::malloc(core::mem::size_of::<EventGroup>() as u32) as *mut EventGroup;
+        ptr.write(EventGroup::new());
+
+        trace!("created event group @{:p}", ptr);
+        ptr as *mut crate::binary::c_types::c_void
+    }
+}
+
+pub fn delete_event_group(event: *mut crate::binary::c_types::c_void) {
+    trace!("delete event group {:p}", event);
+
+    unsafe {
+        esp_alloc::free(event as *const u8);
+    }
+}
+
+pub fn set_event_bits(event: *mut crate::binary::c_types::c_void, bits: u32) -> u32 {
+    trace!("set event bits {:p} {:x}", event, bits);
+
+    critical_section::with(|_| unsafe {
+        let res = (*(event as *mut EventGroup)).set_bits(bits);
+        memory_fence();
+        wake_tasks(event as *const ());
+        res
+    })
+}
+
+pub fn clear_event_bits(event: *mut crate::binary::c_types::c_void, bits: u32) -> u32 {
+    trace!("clear event bits {:p} {:x}", event, bits);
+
+    critical_section::with(|_| unsafe {
+        let res = (*(event as *mut EventGroup)).clear_bits(bits);
+        memory_fence();
+        res
+    })
+}
+
+/// Waits until any or all of the bits are set, returns the bits when the condition was met
+/// or the current bits on timeout.
+pub fn wait_event_bits(
+    event: *mut crate::binary::c_types::c_void,
+    bits_to_wait_for: u32,
+    clear_on_exit: bool,
+    wait_for_all: bool,
+    block_time_tick: u32,
+) -> u32 {
+    trace!(
+        "wait event bits {:p} {:x} clear_on_exit {} wait_for_all {} block_time_tick {}",
+        event,
+        bits_to_wait_for,
+        clear_on_exit,
+        wait_for_all,
+        block_time_tick
+    );
+
+    let forever = block_time_tick == OSI_FUNCS_TIME_BLOCKING;
+    let end_time = crate::timer::get_systimer_count() + block_time_tick as u64;
+
+    loop {
+        let (bits, met) = critical_section::with(|_| unsafe {
+            memory_fence();
+            let res = (*(event as *mut EventGroup)).try_wait(
+                bits_to_wait_for,
+                clear_on_exit,
+                wait_for_all,
+            );
+
+            if !res.1 {
+                prepare_wait(
+                    event as *const (),
+                    if forever { None } else { Some(end_time) },
+                );
+            }
+            res
+        });
+
+        if met {
+            return bits;
+        }
+
+        if !forever && crate::timer::get_systimer_count() >= end_time {
+            trace!("wait event bits returns with timeout");
+            return bits;
+        }
+
+        wait_for_wake_up();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_bits_returns_the_bits_after_setting() {
+        let mut group = EventGroup::new();
+        assert_eq!(group.set_bits(0b0001), 0b0001);
+        assert_eq!(group.set_bits(0b0100), 0b0101);
+        assert_eq!(group.set_bits(0b0001), 0b0101);
+        assert_eq!(group.bits(), 0b0101);
+    }
+
+    #[test]
+    fn clear_bits_returns_the_bits_before_clearing() {
+        let mut group = EventGroup::new();
+        group.set_bits(0b0111);
+        assert_eq!(group.clear_bits(0b0010), 0b0111);
+        assert_eq!(group.clear_bits(0b0010), 0b0101);
+        assert_eq!(group.bits(), 0b0101);
+    }
+
+    #[test]
+    fn reserved_bits_are_ignored() {
+        let mut group = EventGroup::default();
+        assert_eq!(group.set_bits(0xff00_0001), 0x0000_0001);
+        assert_eq!(group.bits(), 0x0000_0001);
+
+        group.set_bits(0x00ff_ffff);
+        assert_eq!(group.clear_bits(0xff00_0000), 0x00ff_ffff);
+        assert_eq!(group.bits(), 0x00ff_ffff);
+    }
+
+    #[test]
+    fn waiting_for_reserved_bits_is_never_met() {
+        let mut group = EventGroup::new();
+        group.set_bits(0x00ff_ffff);
+        assert_eq!(
+            group.try_wait(0xff00_0000, true, false),
+            (0x00ff_ffff, false)
+        );
+        assert_eq!(group.bits(), 0x00ff_ffff);
+    }
+
+    #[test]
+    fn waiting_for_all_of_no_bits_is_never_met() {
+        let mut group = EventGroup::new();
+        group.set_bits(0x00ff_ffff);
+        assert_eq!(
+            group.try_wait(0xff00_0000, true, true),
+            (0x00ff_ffff, false)
+        );
+        assert_eq!(group.try_wait(0, true, true), (0x00ff_ffff, false));
+        assert_eq!(group.bits(), 0x00ff_ffff);
+    }
+
+    #[test]
+    fn wait_any() {
+        let mut group = EventGroup::new();
+        assert_eq!(group.try_wait(0b0011, false, false), (0, false));
+
+        group.set_bits(0b0010);
+        assert_eq!(group.try_wait(0b0011, false, false), (0b0010, true));
+        assert_eq!(group.try_wait(0b0100, false, false), (0b0010, false));
+    }
+
+    #[test]
+    fn wait_all() {
+        let mut group = EventGroup::new();
+        group.set_bits(0b0010);
+        assert_eq!(group.try_wait(0b0011, false, true), (0b0010, false));
+
+        group.set_bits(0b0001);
+        assert_eq!(group.try_wait(0b0011, false, true), (0b0011, true));
+    }
+
+    #[test]
+    fn clear_on_exit_clears_only_the_waited_for_bits() {
+        let mut group = EventGroup::new();
+        group.set_bits(0b0111);
+        assert_eq!(group.try_wait(0b0011, true, true), (0b0111, true));
+        assert_eq!(group.bits(), 0b0100);
+
+        group.set_bits(0b0001);
+        assert_eq!(group.try_wait(0b0011, true, false), (0b0101, true));
+        assert_eq!(group.bits(), 0b0100);
+    }
+
+    #[test]
+    fn clear_on_exit_keeps_the_bits_when_not_met() {
+        let mut group = EventGroup::new();
+        group.set_bits(0b0001);
+        assert_eq!(group.try_wait(0b0011, true, true), (0b0001, false));
+        assert_eq!(group.bits(), 0b0001);
+    }
+}
@@ -1,4 +1,6 @@
 pub mod common;
+pub mod event_group;
 pub mod queue;
+pub mod task;
 pub mod timer_compat;
-pub mod work_queue;
+pub mod timer_queue;
@@ -1,7 +1,5 @@
 #![allow(unused)]
 
-use core::mem::MaybeUninit;
-
 pub struct SimpleQueue<T, const N: usize> {
     data: [Option<T>; N],
     read_index: usize,
@@ -10,19 +8,11 @@ pub struct SimpleQueue<T, const N: usize> {
 
 impl<T, const N: usize> SimpleQueue<T, N> {
     pub fn new() -> SimpleQueue<T, N> {
-        let mut queue = unsafe {
-            SimpleQueue {
-                data: MaybeUninit::uninit().assume_init(),
-                read_index: 0,
-                write_index: 0,
-            }
-        };
-
-        for i in 0..N {
-            queue.data[i] = None;
+        SimpleQueue {
+            data: core::array::from_fn(|_| None),
+            read_index: 0,
+            write_index: 0,
         }
-
-        queue
     }
 
     pub fn enqueue(&mut self, e: T) -> bool {
@@ -54,9 +44,258 @@ impl<T, const N: usize> SimpleQueue<T, N> {
     }
 
     pub fn is_full(&self) -> bool {
-        let mut next_write = self.read_index + 1;
+        let mut next_write = self.write_index + 1;
         next_write %= N;
 
         next_write == self.read_index
     }
 }
+
+#[cfg(not(test))]
+fn allocate_storage(size: usize) -> *mut u8 {
+    unsafe { esp_alloc::calloc(size as u32, 1) }
+}
+
+#[cfg(not(test))]
+fn free_storage(storage: *mut u8, _size: usize) {
+    unsafe { esp_alloc::free(storage) }
+}
+
+// the tests run on the host, there is no esp_alloc heap
+#[cfg(test)]
+fn allocate_storage(size: usize) -> *mut u8 {
+    extern crate std;
+    let layout = std::alloc::Layout::from_size_align(size.max(1), 1).unwrap();
+    unsafe { std::alloc::alloc_zeroed(layout) }
+}
+
+#[cfg(test)]
+fn free_storage(storage: *mut u8, size: usize) {
+    extern crate std;
+    let layout = std::alloc::Layout::from_size_align(size.max(1), 1).unwrap();
+    unsafe { std::alloc::dealloc(storage, layout) }
+}
+
+/// A queue of fixed size items living on the heap, the OS adapters hand out pointers to it.
+pub struct RawQueue {
+    capacity: usize,
+    item_size: usize,
+    current_read: usize,
+    count: usize,
+    storage: *mut u8,
+}
+
+impl RawQueue {
+    /// Returns `None` if the storage can't be allocated
+    pub fn new(capacity: usize, item_size: usize) -> Option<RawQueue> {
+        let storage = allocate_storage(capacity * item_size);
+        if storage.is_null() {
+            return None;
+        }
+
+        Some(RawQueue {
+            capacity,
+            item_size,
+            current_read: 0,
+            count: 0,
+            storage,
+        })
+    }
+
+    /// Frees the storage, the queue can't be used afterwards
+    pub fn release_storage(&mut self) {
+        if !self.storage.is_null() {
+            free_storage(self.storage, self.capacity * self.item_size);
+        }
+        self.storage = core::ptr::null_mut();
+        self.capacity = 0;
+        self.count = 0;
+    }
+
+    fn slot(&mut self, index: usize) -> &mut [u8] {
+        let offset = (index % self.capacity) * self.item_size;
+        // the storage holds `capacity` items and is only reachable through `&mut self`
+        unsafe { core::slice::from_raw_parts_mut(self.storage.add(offset), self.item_size) }
+    }
+
+    /// Size of a single item in bytes
+    pub fn item_size(&self) -> usize {
+        self.item_size
+    }
+
+    /// Copies `item` to the back, it has to be `item_size` bytes long
+    pub fn enqueue(&mut self, item: &[u8]) -> bool {
+        if self.count >= self.capacity {
+            return false;
+        }
+
+        self.slot(self.current_read + self.count)
+            .copy_from_slice(item);
+        self.count += 1;
+
+        true
+    }
+
+    /// Copies `item` to the front so it's dequeued next, it has to be `item_size` bytes long
+    pub fn enqueue_front(&mut self, item: &[u8]) -> bool {
+        if self.count >= self.capacity {
+            return false;
+        }
+
+        let front = (self.current_read + self.capacity - 1) % self.capacity;
+        self.slot(front).copy_from_slice(item);
+        self.current_read = front;
+        self.count += 1;
+
+        true
+    }
+
+    /// Copies the front item to `item`, it has to be `item_size` bytes long
+    pub fn try_dequeue(&mut self, item: &mut [u8]) -> bool {
+        if self.count == 0 {
+            return false;
+        }
+
+        let read = self.current_read;
+        item.copy_from_slice(self.slot(read));
+        self.current_read = (self.current_read + 1) % self.capacity;
+        self.count -= 1;
+
+        true
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count >= self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(capacity: usize) -> RawQueue {
+        RawQueue::new(capacity, core::mem::size_of::<u32>()).unwrap()
+    }
+
+    fn enqueue(queue: &mut RawQueue, item: u32) -> bool {
+        queue.enqueue(&item.to_ne_bytes())
+    }
+
+    fn enqueue_front(queue: &mut RawQueue, item: u32) -> bool {
+        queue.enqueue_front(&item.to_ne_bytes())
+    }
+
+    fn dequeue(queue: &mut RawQueue) -> Option<u32> {
+        let mut item = [0u8; 4];
+        if queue.try_dequeue(&mut item) {
+            Some(u32::from_ne_bytes(item))
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn back_is_fifo() {
+        let mut queue = queue(3);
+        assert!(enqueue(&mut queue, 1));
+        assert!(enqueue(&mut queue, 2));
+        assert!(enqueue(&mut queue, 3));
+
+        assert_eq!(dequeue(&mut queue), Some(1));
+        assert_eq!(dequeue(&mut queue), Some(2));
+        assert_eq!(dequeue(&mut queue), Some(3));
+        assert_eq!(dequeue(&mut queue), None);
+        queue.release_storage();
+    }
+
+    #[test]
+    fn front_is_dequeued_next() {
+        let mut queue = queue(4);
+        assert!(enqueue(&mut queue, 1));
+        assert!(enqueue(&mut queue, 2));
+        assert!(enqueue_front(&mut queue, 3));
+        assert!(enqueue_front(&mut queue, 4));
+
+        assert_eq!(dequeue(&mut queue), Some(4));
+        assert_eq!(dequeue(&mut queue), Some(3));
+        assert_eq!(dequeue(&mut queue), Some(1));
+        assert_eq!(dequeue(&mut queue), Some(2));
+        assert_eq!(dequeue(&mut queue), None);
+        queue.release_storage();
+    }
+
+    #[test]
+    fn front_of_an_empty_queue_wraps() {
+        let mut queue = queue(2);
+        assert!(enqueue_front(&mut queue, 1));
+        assert!(enqueue(&mut queue, 2));
+
+        assert_eq!(dequeue(&mut queue), Some(1));
+        assert_eq!(dequeue(&mut queue), Some(2));
+        queue.release_storage();
+    }
+
+    #[test]
+    fn wraparound() {
+        let mut queue = queue(3);
+        for round in 0..10 {
+            assert!(enqueue(&mut queue, round * 2));
+            assert!(enqueue(&mut queue, round * 2 + 1));
+            assert_eq!(dequeue(&mut queue), Some(round * 2));
+            assert_eq!(dequeue(&mut queue), Some(round * 2 + 1));
+        }
+
+        for round in 0..10 {
+            assert!(enqueue(&mut queue, round));
+            assert!(enqueue_front(&mut queue, 100 + round));
+            assert_eq!(dequeue(&mut queue), Some(100 + round));
+            assert_eq!(dequeue(&mut queue), Some(round));
+        }
+        queue.release_storage();
+    }
+
+    #[test]
+    fn full_and_empty() {
+        let mut queue = queue(2);
+        assert!(!queue.is_full());
+        assert_eq!(dequeue(&mut queue), None);
+
+        assert!(enqueue(&mut queue, 1));
+        assert!(!queue.is_full());
+        assert!(enqueue(&mut queue, 2));
+        assert!(queue.is_full());
+
+        assert!(!enqueue(&mut queue, 3));
+        assert!(!enqueue_front(&mut queue, 3));
+
+        assert_eq!(dequeue(&mut queue), Some(1));
+        assert!(!queue.is_full());
+        assert_eq!(dequeue(&mut queue), Some(2));
+        assert_eq!(dequeue(&mut queue), None);
+        queue.release_storage();
+    }
+
+    #[test]
+    fn message_count() {
+        let mut queue = queue(3);
+        assert_eq!(queue.count(), 0);
+
+        enqueue(&mut queue, 1);
+        enqueue_front(&mut queue, 2);
+        assert_eq!(queue.count(), 2);
+
+        enqueue(&mut queue, 3);
+        enqueue(&mut queue, 4);
+        assert_eq!(queue.count(), 3);
+
+        dequeue(&mut queue);
+        assert_eq!(queue.count(), 2);
+
+        queue.release_storage();
+        assert_eq!(queue.count(), 0);
+    }
+}
@@ -0,0 +1,122 @@
+use log::{trace, warn};
+
+use crate::preempt::preempt::{current_task, task_create, task_delete, MAX_TASK};
+use crate::preempt::task_generation;
+
+/// Tasks created by the drivers and their generation, they are deleted when the radios are
+/// deinitialized
+static mut DRIVER_TASKS: [Option<u32>; MAX_TASK] = [None; MAX_TASK];
+
+/// The low byte of a handle is the task id plus one, the rest is the generation of the task so
+/// a handle of a deleted task doesn't refer to a later task with the same id. A null handle
+/// means the current task like in FreeRTOS.
+const HANDLE_ID_BITS: u32 = 8;
+const HANDLE_ID_MASK: usize = (1 << HANDLE_ID_BITS) - 1;
+const HANDLE_GENERATION_MASK: usize = usize::MAX >> HANDLE_ID_BITS;
+
+fn task_handle(id: usize, generation: u32) -> *mut crate::binary::c_types::c_void {
+    let generation = generation as usize & HANDLE_GENERATION_MASK;
+    (generation << HANDLE_ID_BITS | (id + 1)) as *mut crate::binary::c_types::c_void
+}
+
+/// The id of the task the handle refers to, `None` if that task doesn't exist anymore
+fn task_id(handle: *const crate::binary::c_types::c_void) -> Option<usize> {
+    if handle.is_null() {
+        return Some(current_task());
+    }
+
+    let handle = handle as usize;
+    let id = (handle & HANDLE_ID_MASK).checked_sub(1)?;
+    if id >= MAX_TASK {
+        return None;
+    }
+
+    let generation = task_generation(id)? as usize & HANDLE_GENERATION_MASK;
+    if handle >> HANDLE_ID_BITS == generation {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+/// Creates a driver task, returns 1 on success and 0 on failure
+pub fn create_task(
+    task_func: *mut crate::binary::c_types::c_void,
+    stack_depth: u32,
+    param: *mut crate::binary::c_types::c_void,
+    prio: u32,
+    task_handle_out: *mut crate::binary::c_types::c_void,
+) -> i32 {
+    // allocating and filling the stack takes a while, it's done outside of a critical section
+    let created = unsafe {
+        task_create(
+            core::mem::transmute(task_func),
+            param,
+            prio,
+            stack_depth as usize,
+        )
+    };
+
+    match created {
+        Some((id, generation)) => {
+            trace!("created task {} for {:p}", id, task_func);
+
+            critical_section::with(|_| unsafe {
+                // a task with a higher priority may have run and ended already
+                if task_generation(id) == Some(generation) {
+                    DRIVER_TASKS[id] = Some(generation);
+                }
+            });
+
+            if !task_handle_out.is_null() {
+                unsafe {
+                    *(task_handle_out as *mut *mut crate::binary::c_types::c_void) =
+                        task_handle(id, generation);
+                }
+            }
+            1
+        }
+        None => {
+            warn!("no task slot or stack left for {:p}", task_func);
+            0
+        }
+    }
+}
+
+/// Deletes a driver task, doesn't return if it's the current one. A handle of a task which
+/// doesn't exist anymore is ignored.
+pub fn delete_task(handle: *const crate::binary::c_types::c_void) {
+    let id = match task_id(handle) {
+        Some(id) => id,
+        None => {
+            warn!("delete_task with stale handle {:p}", handle);
+            return;
+        }
+    };
+    trace!("delete task {}", id);
+
+    critical_section::with(|_| unsafe {
+        DRIVER_TASKS[id] = None;
+    });
+    task_delete(id);
+}
+
+pub fn current_task_handle() -> *mut crate::binary::c_types::c_void {
+    let id = current_task();
+    task_handle(id, task_generation(id).unwrap_or(0))
+}
+
+/// Deletes the driver tasks which are still around
+pub fn delete_driver_tasks() {
+    for id in 0..MAX_TASK {
+        let is_driver_task = critical_section::with(|_| unsafe {
+            let generation = DRIVER_TASKS[id].take();
+            generation.is_some() && task_generation(id) == generation
+        });
+
+        if is_driver_task {
+            trace!("delete leftover task {}", id);
+            task_delete(id);
+        }
+    }
+}
@@ -1,23 +1,26 @@
 use esp_alloc::memory_fence;
-use log::{debug, trace};
+use log::{debug, trace, warn};
 
-use crate::binary::include::{esp_timer_create_args_t, esp_timer_handle_t};
-
-static ESP_FAKE_TIMER: () = ();
+pub use super::timer_queue::Timer;
+use super::timer_queue::TimerQueue;
+use crate::binary::include::{
+    esp_timer_create_args_t, esp_timer_handle_t, ESP_ERR_INVALID_ARG, ESP_ERR_INVALID_STATE,
+    ESP_ERR_NO_MEM, ESP_OK,
+};
 
 const TIMER_INITIALIZED_VAL: u32 = 0x5aa5a55a;
 
-#[derive(Debug, Clone, Copy)]
-pub struct Timer {
-    pub ptimer: *mut crate::binary::c_types::c_void,
-    pub expire: u64,
-    pub period: u64,
-    pub active: bool,
-    pub timer_ptr: *mut crate::binary::c_types::c_void,
-    pub arg_ptr: *mut crate::binary::c_types::c_void,
-}
+pub(crate) static mut TIMERS: TimerQueue = TimerQueue::new();
 
-pub static mut TIMERS: [Option<Timer>; 20] = [None; 20];
+/// Id of a timer set up via [compat_timer_setfn], `priv_` is only a hint for finding it
+unsafe fn timer_id(ptimer: *mut crate::binary::c_types::c_void) -> Option<usize> {
+    let hint = (*(ptimer as *mut crate::binary::include::ets_timer)).priv_ as usize;
+
+    match TIMERS.get(hint) {
+        Some(timer) if timer.ptimer == ptimer => Some(hint),
+        _ => TIMERS.find(ptimer),
+    }
+}
 
 pub fn compat_timer_arm(ptimer: *mut crate::binary::c_types::c_void, tmout: u32, repeat: bool) {
     compat_timer_arm_us(ptimer, tmout * 1000, repeat);
@@ -34,21 +37,16 @@ pub fn compat_timer_arm_us(ptimer: *mut crate::binary::c_types::c_void, us: u32,
     critical_section::with(|_| unsafe {
         memory_fence();
 
-        for i in 0..TIMERS.len() {
-            if let Some(mut timer) = TIMERS[i] {
-                memory_fence();
+        if let Some(id) = timer_id(ptimer) {
+            trace!("found timer ...");
+            TIMERS.arm(
+                id,
+                crate::timer::get_systimer_count() + ticks,
+                if repeat { ticks } else { 0 },
+            );
 
-                if timer.ptimer == ptimer {
-                    trace!("found timer ...");
-                    timer.expire = ticks as u64 + crate::timer::get_systimer_count();
-                    timer.active = true;
-                    if repeat {
-                        timer.period = ticks as u64;
-                    }
-                    TIMERS[i] = Some(timer);
-                    break;
-                }
-            }
+            // the timer task might sleep past the new expiry
+            crate::tasks::wake_timer_task();
         }
     });
 }
@@ -58,16 +56,9 @@ pub fn compat_timer_disarm(ptimer: *mut crate::binary::c_types::c_void) {
     critical_section::with(|_| unsafe {
         memory_fence();
 
-        for i in 0..TIMERS.len() {
-            memory_fence();
-            if let Some(mut timer) = TIMERS[i] {
-                if timer.ptimer == ptimer {
-                    trace!("found timer ...");
-                    timer.active = false;
-                    TIMERS[i] = Some(timer);
-                    break;
-                }
-            }
+        if let Some(id) = timer_id(ptimer) {
+            trace!("found timer ...");
+            TIMERS.disarm(id);
         }
     });
 }
@@ -77,16 +68,9 @@ pub fn compat_timer_done(ptimer: *mut crate::binary::c_types::c_void) {
     critical_section::with(|_| unsafe {
         memory_fence();
 
-        for i in 0..TIMERS.len() {
-            memory_fence();
-
-            if let Some(timer) = TIMERS[i] {
-                if timer.ptimer == ptimer {
-                    trace!("found timer ...");
-                    TIMERS[i] = None;
-                    break;
-                }
-            }
+        if let Some(id) = timer_id(ptimer) {
+            trace!("found timer ...");
+            TIMERS.remove(id);
         }
     });
 }
@@ -100,42 +84,71 @@ pub fn compat_timer_setfn(
     debug!("timer_setfn {:p} {:p} {:p}", ptimer, pfunction, parg,);
 
     critical_section::with(|_| unsafe {
-        if (*ets_timer).expire != TIMER_INITIALIZED_VAL {
-            (*ets_timer).priv_ = core::ptr::null_mut();
-
-            let mut success = false;
-            memory_fence();
-
-            for i in 0..TIMERS.len() {
-                memory_fence();
-
-                if TIMERS[i].is_none() {
-                    TIMERS[i] = Some(Timer {
-                        ptimer: ptimer,
-                        expire: 0,
-                        period: 0,
-                        active: false,
-                        timer_ptr: pfunction,
-                        arg_ptr: parg,
-                    });
-                    (*ets_timer).priv_ = i as *mut crate::binary::c_types::c_void;
-                    (*ets_timer).expire = TIMER_INITIALIZED_VAL;
-                    success = true;
-                    break;
-                }
+        memory_fence();
+
+        // the expire field is only a hint, after a reset the timer needs to be added again
+        let registered = if (*ets_timer).expire == TIMER_INITIALIZED_VAL {
+            timer_id(ptimer)
+        } else {
+            None
+        };
+
+        match registered {
+            Some(id) => {
+                let timer = TIMERS.get_mut(id).unwrap();
+                timer.timer_ptr = pfunction;
+                timer.arg_ptr = parg;
             }
+            None => {
+                let id = match TIMERS.add(Timer {
+                    ptimer,
+                    expire: 0,
+                    period: 0,
+                    skip_missed: true,
+                    timer_ptr: pfunction,
+                    arg_ptr: parg,
+                }) {
+                    Some(id) => id,
+                    None => {
+                        // without the hint the timer isn't found and arming it does nothing
+                        warn!("no memory left for the timer {:p}", ptimer);
+                        return;
+                    }
+                };
 
-            if !success {
-                panic!("Ran out of timers!");
+                (*ets_timer).priv_ = id as *mut crate::binary::c_types::c_void;
+                (*ets_timer).expire = TIMER_INITIALIZED_VAL;
             }
         }
     });
 }
 
+/// Removes all timers, the ones still in use need to be set up again via [compat_timer_setfn].
+pub fn compat_timer_reset() {
+    critical_section::with(|_| unsafe {
+        TIMERS.release();
+        memory_fence();
+    });
+}
+
+/// Handles of the timers created via [compat_esp_timer_create] are their id plus one
+unsafe fn esp_timer_id(handle: esp_timer_handle_t) -> Option<usize> {
+    let id = (handle as usize).checked_sub(1)?;
+
+    match TIMERS.get(id) {
+        Some(timer) if timer.ptimer == handle as *mut crate::binary::c_types::c_void => Some(id),
+        _ => None,
+    }
+}
+
 pub fn compat_esp_timer_create(
     args: *const esp_timer_create_args_t,
-    mut out_handle: *mut esp_timer_handle_t,
+    out_handle: *mut esp_timer_handle_t,
 ) -> i32 {
+    if args.is_null() || out_handle.is_null() || unsafe { (*args).callback.is_none() } {
+        return ESP_ERR_INVALID_ARG as i32;
+    }
+
     unsafe {
         debug!(
             "esp_timer_create {:?} {:?} {:p}",
@@ -145,36 +158,108 @@ pub fn compat_esp_timer_create(
         );
     }
 
-    let args = args as *const esp_timer_create_args_t;
+    critical_section::with(|_| unsafe {
+        memory_fence();
+
+        let id = match TIMERS.add(Timer {
+            ptimer: core::ptr::null_mut(),
+            expire: 0,
+            period: 0,
+            skip_missed: (*args).skip_unhandled_events,
+            timer_ptr: core::mem::transmute((*args).callback.unwrap()),
+            arg_ptr: (*args).arg,
+        }) {
+            Some(id) => id,
+            None => return ESP_ERR_NO_MEM as i32,
+        };
+
+        let handle = (id + 1) as esp_timer_handle_t;
+        TIMERS.get_mut(id).unwrap().ptimer = handle as *mut crate::binary::c_types::c_void;
+        *out_handle = handle;
+
+        debug!("esp_timer_create {:p} {:p}", args, handle);
+        ESP_OK as i32
+    })
+}
+
+/// Starts a stopped timer, it's due in `timeout_us` and repeats if `periodic`
+pub fn compat_esp_timer_start(handle: esp_timer_handle_t, timeout_us: u64, periodic: bool) -> i32 {
+    debug!(
+        "esp_timer_start {:p} {} periodic {}",
+        handle, timeout_us, periodic
+    );
+
+    // a period of 0 would make it a one-shot timer
+    if periodic && timeout_us == 0 {
+        return ESP_ERR_INVALID_ARG as i32;
+    }
+
+    // far enough in the future to never expire
+    let ticks = timeout_us.saturating_mul(crate::timer::TICKS_PER_SECOND / 1_000_000);
 
     critical_section::with(|_| unsafe {
-        let mut success = false;
         memory_fence();
 
-        for i in 0..TIMERS.len() {
-            memory_fence();
+        let id = match esp_timer_id(handle) {
+            Some(id) => id,
+            None => return ESP_ERR_INVALID_ARG as i32,
+        };
 
-            debug!("esp_timer_create {}", i);
-            if TIMERS[i].is_none() {
-                TIMERS[i] = Some(Timer {
-                    ptimer: &ESP_FAKE_TIMER as *const _ as *mut crate::binary::c_types::c_void,
-                    expire: 0,
-                    period: 0,
-                    active: false,
-                    timer_ptr: core::mem::transmute((*args).callback.unwrap()),
-                    arg_ptr: (*args).arg,
-                });
-                out_handle = &ESP_FAKE_TIMER as *const _ as *mut esp_timer_handle_t;
-                success = true;
-                debug!("esp_timer_create {:p} {:p}", args, out_handle);
-
-                break;
+        if TIMERS.is_armed(id) {
+            return ESP_ERR_INVALID_STATE as i32;
+        }
+
+        TIMERS.arm(
+            id,
+            crate::timer::get_systimer_count().saturating_add(ticks),
+            if periodic { ticks } else { 0 },
+        );
+        crate::tasks::wake_timer_task();
+        ESP_OK as i32
+    })
+}
+
+pub fn compat_esp_timer_stop(handle: esp_timer_handle_t) -> i32 {
+    debug!("esp_timer_stop {:p}", handle);
+
+    critical_section::with(|_| unsafe {
+        memory_fence();
+
+        match esp_timer_id(handle) {
+            Some(id) if TIMERS.is_armed(id) => {
+                TIMERS.disarm(id);
+                ESP_OK as i32
             }
+            Some(_) => ESP_ERR_INVALID_STATE as i32,
+            None => ESP_ERR_INVALID_ARG as i32,
         }
-        if !success {
-            panic!("ran out of timers");
+    })
+}
+
+/// Deletes a stopped timer, the handle is invalid afterwards
+pub fn compat_esp_timer_delete(handle: esp_timer_handle_t) -> i32 {
+    debug!("esp_timer_delete {:p}", handle);
+
+    critical_section::with(|_| unsafe {
+        memory_fence();
+
+        match esp_timer_id(handle) {
+            Some(id) if TIMERS.is_armed(id) => ESP_ERR_INVALID_STATE as i32,
+            Some(id) => {
+                TIMERS.remove(id);
+                ESP_OK as i32
+            }
+            None => ESP_ERR_INVALID_ARG as i32,
         }
-    });
+    })
+}
 
-    0
+/// Time of the next timer event in microseconds, `i64::MAX` if no timer is armed
+pub fn compat_esp_timer_get_next_alarm() -> i64 {
+    critical_section::with(|_| unsafe {
+        match TIMERS.next_expiry() {
+            Some(expire) => (expire / (crate::timer::TICKS_PER_SECOND / 1_000_000)) as i64,
+            None => i64::MAX,
+        }
+    })
 }
@@ -0,0 +1,524 @@
+use core::mem::size_of;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Timer {
+    pub ptimer: *mut crate::binary::c_types::c_void,
+    /// Systimer count at which the timer is due, only meaningful while it's armed
+    pub expire: u64,
+    /// Period in systimer ticks, 0 for a one-shot timer
+    pub period: u64,
+    /// A periodic timer which fell behind skips the missed periods instead of catching up
+    pub skip_missed: bool,
+    pub timer_ptr: *mut crate::binary::c_types::c_void,
+    pub arg_ptr: *mut crate::binary::c_types::c_void,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    timer: Option<Timer>,
+    /// Position in the heap while the timer is armed
+    heap_index: Option<usize>,
+    /// The next free slot while this one is free
+    next_free: Option<usize>,
+}
+
+/// Registered timers, the armed ones are kept in a binary min-heap ordered by their expiry.
+///
+/// The storage is allocated on the heap and grows when needed, ids of registered timers stay the
+/// same until they are removed. The free slots are chained into a list.
+pub struct TimerQueue {
+    slots: *mut Slot,
+    /// Slot ids of the armed timers
+    heap: *mut usize,
+    capacity: usize,
+    armed: usize,
+    registered: usize,
+    /// First slot of the free list
+    free: Option<usize>,
+}
+
+impl TimerQueue {
+    pub const fn new() -> TimerQueue {
+        TimerQueue {
+            slots: core::ptr::null_mut(),
+            heap: core::ptr::null_mut(),
+            capacity: 0,
+            armed: 0,
+            registered: 0,
+            free: None,
+        }
+    }
+
+    /// Frees the storage, all timers are gone afterwards
+    pub fn release(&mut self) {
+        if self.capacity != 0 {
+            unsafe {
+                esp_alloc::free(self.slots as *const u8);
+                esp_alloc::free(self.heap as *const u8);
+            }
+        }
+
+        *self = TimerQueue::new();
+    }
+
+    fn grow(&mut self) -> bool {
+        let capacity = if self.capacity == 0 {
+            8
+        } else {
+            self.capacity * 2
+        };
+
+        unsafe {
+            let slots = esp_alloc::malloc((capacity * size_of::<Slot>()) as u32) as *mut Slot;
+            let heap = esp_alloc::malloc((capacity * size_of::<usize>()) as u32) as *mut usize;
+            if slots.is_null() || heap.is_null() {
+                if !slots.is_null() {
+                    esp_alloc::free(slots as *const u8);
+                }
+                if !heap.is_null() {
+                    esp_alloc::free(heap as *const u8);
+                }
+                return false;
+            }
+
+            // all slots are taken when growing, the new ones become the free list
+            for i in 0..capacity {
+                let slot = if i < self.capacity {
+                    *self.slots.add(i)
+                } else {
+                    Slot {
+                        timer: None,
+                        heap_index: None,
+                        next_free: Some(i + 1).filter(|next| *next < capacity),
+                    }
+                };
+                slots.add(i).write(slot);
+            }
+            core::ptr::copy_nonoverlapping(self.heap, heap, self.armed);
+
+            let old_capacity = self.capacity;
+            let armed = self.armed;
+            let registered = self.registered;
+            self.release();
+            self.slots = slots;
+            self.heap = heap;
+            self.capacity = capacity;
+            self.armed = armed;
+            self.registered = registered;
+            self.free = Some(old_capacity);
+        }
+
+        true
+    }
+
+    fn slot(&self, id: usize) -> &Slot {
+        unsafe { &*self.slots.add(id) }
+    }
+
+    fn slot_mut(&mut self, id: usize) -> &mut Slot {
+        unsafe { &mut *self.slots.add(id) }
+    }
+
+    /// Registers a disarmed timer, returns its id or `None` if there is no memory left
+    pub fn add(&mut self, timer: Timer) -> Option<usize> {
+        if self.free.is_none() && !self.grow() {
+            return None;
+        }
+
+        let id = self.free?;
+        self.free = self.slot(id).next_free;
+        *self.slot_mut(id) = Slot {
+            timer: Some(timer),
+            heap_index: None,
+            next_free: None,
+        };
+        self.registered += 1;
+        Some(id)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Timer> {
+        if id < self.capacity {
+            self.slot(id).timer.as_ref()
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut Timer> {
+        if id < self.capacity {
+            self.slot_mut(id).timer.as_mut()
+        } else {
+            None
+        }
+    }
+
+    /// Looks up a timer by the driver's handle. This goes through all slots, it's only the
+    /// fallback for when the id stored in the `ets_timer`'s `priv_` doesn't match.
+    pub fn find(&self, ptimer: *mut crate::binary::c_types::c_void) -> Option<usize> {
+        (0..self.capacity)
+            .find(|id| matches!(self.slot(*id).timer, Some(timer) if timer.ptimer == ptimer))
+    }
+
+    /// Disarms and unregisters the timer
+    pub fn remove(&mut self, id: usize) -> Option<Timer> {
+        self.disarm(id);
+        self.get(id)?;
+
+        let free = self.free;
+        let slot = self.slot_mut(id);
+        let timer = slot.timer.take();
+        slot.next_free = free;
+        self.free = Some(id);
+        self.registered -= 1;
+        timer
+    }
+
+    /// Number of registered timers
+    pub fn len(&self) -> usize {
+        self.registered
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.registered == 0
+    }
+
+    pub fn is_armed(&self, id: usize) -> bool {
+        id < self.capacity && self.slot(id).heap_index.is_some()
+    }
+
+    /// Arms the timer or moves its expiry if it's armed already
+    pub fn arm(&mut self, id: usize, expire: u64, period: u64) {
+        let timer = match self.get_mut(id) {
+            Some(timer) => timer,
+            None => return,
+        };
+        timer.expire = expire;
+        timer.period = period;
+
+        match self.slot(id).heap_index {
+            Some(index) => {
+                self.sift_up(index);
+                let index = self.slot(id).heap_index.unwrap();
+                self.sift_down(index);
+            }
+            None => {
+                let index = self.armed;
+                unsafe {
+                    self.heap.add(index).write(id);
+                }
+                self.slot_mut(id).heap_index = Some(index);
+                self.armed += 1;
+                self.sift_up(index);
+            }
+        }
+    }
+
+    pub fn disarm(&mut self, id: usize) {
+        if !self.is_armed(id) {
+            return;
+        }
+
+        let index = self.slot(id).heap_index.unwrap();
+        let last = self.armed - 1;
+        self.swap(index, last);
+        self.armed -= 1;
+        self.slot_mut(id).heap_index = None;
+
+        if index < self.armed {
+            self.sift_up(index);
+            let moved = self.heap_id(index);
+            let index = self.slot(moved).heap_index.unwrap();
+            self.sift_down(index);
+        }
+    }
+
+    /// Systimer count at which the next armed timer is due
+    pub fn next_expiry(&self) -> Option<u64> {
+        if self.armed == 0 {
+            None
+        } else {
+            Some(self.expire_at(0))
+        }
+    }
+
+    /// Takes the next timer due at `now`. A periodic timer is armed again for its next period,
+    /// after the missed ones if it skips them. A one-shot timer is disarmed.
+    pub fn pop_due(&mut self, now: u64) -> Option<Timer> {
+        if self.next_expiry()? > now {
+            return None;
+        }
+
+        let id = self.heap_id(0);
+        let timer = *self.get(id)?;
+
+        if timer.period != 0 {
+            // relative to the expiry instead of now so the timer doesn't drift
+            let periods = if timer.skip_missed {
+                (now - timer.expire) / timer.period + 1
+            } else {
+                1
+            };
+            self.arm(id, timer.expire + periods * timer.period, timer.period);
+        } else {
+            self.disarm(id);
+        }
+
+        Some(timer)
+    }
+
+    fn heap_id(&self, index: usize) -> usize {
+        unsafe { *self.heap.add(index) }
+    }
+
+    fn expire_at(&self, index: usize) -> u64 {
+        self.slot(self.heap_id(index)).timer.unwrap().expire
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        let id_a = self.heap_id(a);
+        let id_b = self.heap_id(b);
+
+        unsafe {
+            self.heap.add(a).write(id_b);
+            self.heap.add(b).write(id_a);
+        }
+        self.slot_mut(id_a).heap_index = Some(b);
+        self.slot_mut(id_b).heap_index = Some(a);
+    }
+
+    fn sift_up(&mut self, mut index: usize) {
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            if self.expire_at(parent) <= self.expire_at(index) {
+                break;
+            }
+
+            self.swap(parent, index);
+            index = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut index: usize) {
+        loop {
+            let left = index * 2 + 1;
+            let right = left + 1;
+            let mut smallest = index;
+
+            if left < self.armed && self.expire_at(left) < self.expire_at(smallest) {
+                smallest = left;
+            }
+            if right < self.armed && self.expire_at(right) < self.expire_at(smallest) {
+                smallest = right;
+            }
+            if smallest == index {
+                break;
+            }
+
+            self.swap(index, smallest);
+            index = smallest;
+        }
+    }
+}
+
+impl Default for TimerQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handle(n: usize) -> *mut crate::binary::c_types::c_void {
+        n as *mut crate::binary::c_types::c_void
+    }
+
+    fn timer(n: usize) -> Timer {
+        Timer {
+            ptimer: handle(n),
+            expire: 0,
+            period: 0,
+            skip_missed: true,
+            timer_ptr: core::ptr::null_mut(),
+            arg_ptr: core::ptr::null_mut(),
+        }
+    }
+
+    fn pop_handle(queue: &mut TimerQueue, now: u64) -> Option<usize> {
+        queue.pop_due(now).map(|timer| timer.ptimer as usize)
+    }
+
+    #[test]
+    fn add_and_find() {
+        let mut queue = TimerQueue::default();
+        assert!(queue.is_empty());
+
+        let a = queue.add(timer(1)).unwrap();
+        let b = queue.add(timer(2)).unwrap();
+        assert_eq!(queue.len(), 2);
+        assert!(!queue.is_empty());
+        assert_eq!(queue.find(handle(2)), Some(b));
+        assert_eq!(queue.find(handle(3)), None);
+        assert!(!queue.is_armed(a));
+        assert_eq!(queue.next_expiry(), None);
+
+        queue.release();
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn grows_and_keeps_ids() {
+        let mut queue = TimerQueue::new();
+        for n in 0..20 {
+            assert_eq!(queue.add(timer(n)), Some(n));
+            queue.arm(n, 100 - n as u64, 0);
+        }
+
+        for n in 0..20 {
+            assert_eq!(queue.find(handle(n)), Some(n));
+        }
+        assert_eq!(queue.next_expiry(), Some(81));
+        queue.release();
+    }
+
+    #[test]
+    fn due_in_expiry_order() {
+        let mut queue = TimerQueue::new();
+        for (n, expire) in [(1, 30), (2, 10), (3, 50), (4, 20), (5, 40)] {
+            let id = queue.add(timer(n)).unwrap();
+            queue.arm(id, expire, 0);
+        }
+
+        assert_eq!(queue.next_expiry(), Some(10));
+        assert_eq!(pop_handle(&mut queue, 5), None);
+        assert_eq!(pop_handle(&mut queue, 25), Some(2));
+        assert_eq!(pop_handle(&mut queue, 25), Some(4));
+        assert_eq!(pop_handle(&mut queue, 25), None);
+        assert_eq!(pop_handle(&mut queue, 100), Some(1));
+        assert_eq!(pop_handle(&mut queue, 100), Some(5));
+        assert_eq!(pop_handle(&mut queue, 100), Some(3));
+        assert_eq!(pop_handle(&mut queue, 100), None);
+
+        // one-shot timers are disarmed but stay registered
+        assert_eq!(queue.len(), 5);
+        queue.release();
+    }
+
+    #[test]
+    fn rearm_moves_the_expiry() {
+        let mut queue = TimerQueue::new();
+        let a = queue.add(timer(1)).unwrap();
+        let b = queue.add(timer(2)).unwrap();
+        queue.arm(a, 10, 0);
+        queue.arm(b, 20, 0);
+
+        queue.arm(a, 30, 0);
+        assert_eq!(queue.next_expiry(), Some(20));
+        queue.arm(a, 5, 0);
+        assert_eq!(queue.next_expiry(), Some(5));
+        queue.release();
+    }
+
+    #[test]
+    fn disarm() {
+        let mut queue = TimerQueue::new();
+        let ids: [usize; 4] = core::array::from_fn(|i| {
+            let id = queue.add(timer(i)).unwrap();
+            queue.arm(id, 10 * (i as u64 + 1), 0);
+            id
+        });
+
+        queue.disarm(ids[0]);
+        queue.disarm(ids[2]);
+        queue.disarm(ids[2]);
+        assert!(!queue.is_armed(ids[0]));
+        assert!(queue.is_armed(ids[1]));
+
+        assert_eq!(pop_handle(&mut queue, 100), Some(1));
+        assert_eq!(pop_handle(&mut queue, 100), Some(3));
+        assert_eq!(pop_handle(&mut queue, 100), None);
+        queue.release();
+    }
+
+    #[test]
+    fn done_removes_the_timer() {
+        let mut queue = TimerQueue::new();
+        let a = queue.add(timer(1)).unwrap();
+        let b = queue.add(timer(2)).unwrap();
+        queue.arm(a, 10, 0);
+        queue.arm(b, 20, 0);
+
+        assert_eq!(queue.remove(a).map(|timer| timer.ptimer as usize), Some(1));
+        assert!(queue.remove(a).is_none());
+        assert!(queue.get(a).is_none());
+        assert_eq!(queue.find(handle(1)), None);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.next_expiry(), Some(20));
+
+        // the slot is reused
+        assert_eq!(queue.add(timer(3)), Some(a));
+        queue.release();
+    }
+
+    #[test]
+    fn removed_slots_are_reused_before_growing() {
+        let mut queue = TimerQueue::new();
+        for n in 0..8 {
+            queue.add(timer(n)).unwrap();
+        }
+        queue.remove(2);
+        queue.remove(5);
+        assert_eq!(queue.len(), 6);
+
+        let mut reused = [queue.add(timer(8)).unwrap(), queue.add(timer(9)).unwrap()];
+        reused.sort();
+        assert_eq!(reused, [2, 5]);
+        assert_eq!(queue.len(), 8);
+
+        // all slots are taken, the next one is a new one
+        assert_eq!(queue.add(timer(10)), Some(8));
+        assert_eq!(queue.len(), 9);
+        queue.release();
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn periodic_rearm() {
+        let mut queue = TimerQueue::new();
+        let id = queue.add(timer(1)).unwrap();
+        queue.arm(id, 100, 50);
+
+        assert_eq!(pop_handle(&mut queue, 100), Some(1));
+        assert!(queue.is_armed(id));
+        assert_eq!(queue.next_expiry(), Some(150));
+
+        // late, but relative to the expiry
+        assert_eq!(pop_handle(&mut queue, 160), Some(1));
+        assert_eq!(queue.next_expiry(), Some(200));
+
+        queue.disarm(id);
+        assert_eq!(pop_handle(&mut queue, 1000), None);
+        queue.release();
+    }
+
+    #[test]
+    fn periodic_skips_missed_periods() {
+        let mut queue = TimerQueue::new();
+        let skipping = queue.add(timer(1)).unwrap();
+        let catching_up = queue
+            .add(Timer {
+                skip_missed: false,
+                ..timer(2)
+            })
+            .unwrap();
+        queue.arm(skipping, 100, 50);
+        queue.arm(catching_up, 100, 50);
+
+        assert!(queue.pop_due(275).is_some());
+        assert!(queue.pop_due(275).is_some());
+        assert_eq!(queue.get(skipping).unwrap().expire, 300);
+        assert_eq!(queue.get(catching_up).unwrap().expire, 150);
+        queue.release();
+    }
+}
@@ -0,0 +1,353 @@
+//! Initialization of the scheduler and the radios.
+//!
+//! The timer and the RNG are handed over once to [EspWifiInit] together with the frozen clocks
+//! to check the CPU clock against, [EspWifiInit::init] brings
+//! up the selected radios and returns an [EspWifiInitialization]. That handle is needed to
+//! create a [crate::wifi::WifiDevice] or a [crate::ble::controller::BleConnector], dropping it
+//! deinitializes the radios again.
+//!
+//! [EspWifiInitialization::deinit] turns the radios off and returns an [EspWifiDeinitialized]
+//! which can initialize them again, the scheduler keeps running in between.
+//!
+//! A failed initialization returns an [InitFailure] which gives back what's needed to try
+//! again.
+
+#[cfg(feature = "esp32")]
+use esp32_hal as hal;
+#[cfg(feature = "esp32c3")]
+use esp32c3_hal as hal;
+
+use hal::clock::Clocks;
+
+use crate::compat::{
+    common::reset_sync_primitives, task::delete_driver_tasks, timer_compat::compat_timer_reset,
+};
+use crate::tasks::init_tasks;
+use crate::timer::{setup_timer_isr, DEFAULT_TICK_RATE_HZ, TICKS_PER_SECOND};
+use crate::wifi::{
+    configure, init_buffer, init_clocks, init_rng, set_mac_derivation, wifi_deinit, wifi_init,
+    wifi_set_log_verbose, wifi_start, MacDerivation, WifiConfig, WifiError,
+};
+
+/// The radios need the CPU to run at this clock
+#[cfg(feature = "esp32c3")]
+const RADIO_CPU_CLOCK_MHZ: u32 = 160;
+
+/// The radios need the CPU to run at this clock
+#[cfg(feature = "esp32")]
+const RADIO_CPU_CLOCK_MHZ: u32 = 240;
+
+/// The timer driving the scheduler
+#[cfg(feature = "esp32c3")]
+pub type EspWifiTimer = hal::pac::SYSTIMER;
+
+/// The timer driving the scheduler
+#[cfg(feature = "esp32")]
+pub type EspWifiTimer = hal::pac::TIMG1;
+
+/// The radios to initialize
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EspWifiInitFor {
+    Wifi,
+    Ble,
+    #[cfg(feature = "coex")]
+    WifiBle,
+}
+
+impl EspWifiInitFor {
+    fn is_wifi(&self) -> bool {
+        match self {
+            EspWifiInitFor::Wifi => true,
+            EspWifiInitFor::Ble => false,
+            #[cfg(feature = "coex")]
+            EspWifiInitFor::WifiBle => true,
+        }
+    }
+
+    fn is_ble(&self) -> bool {
+        match self {
+            EspWifiInitFor::Wifi => false,
+            EspWifiInitFor::Ble => true,
+            #[cfg(feature = "coex")]
+            EspWifiInitFor::WifiBle => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum InitializationError {
+    /// The [WifiConfig] or the tick rate has values out of the range the driver accepts
+    InvalidConfiguration,
+    /// The CPU doesn't run at the clock the radios need, 160MHz on the ESP32-C3 and 240MHz on
+    /// the ESP32
+    WrongClockConfig,
+    General(i32),
+    WifiError(WifiError),
+}
+
+impl From<WifiError> for InitializationError {
+    fn from(err: WifiError) -> Self {
+        InitializationError::WifiError(err)
+    }
+}
+
+/// A failed initialization
+pub struct InitFailure {
+    pub error: InitializationError,
+    /// Starts over
+    pub retry: InitRetry,
+}
+
+/// What a failed initialization gives back
+pub enum InitRetry {
+    /// The configuration was rejected, nothing was started and the peripherals are still in
+    /// the builder
+    Init(EspWifiInit),
+    /// The scheduler is running but the radios failed to come up, they are turned off again
+    Radios(EspWifiDeinitialized),
+}
+
+impl core::fmt::Debug for InitFailure {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("InitFailure")
+            .field("error", &self.error)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Builder for the initialization, see [EspWifiInit::init]
+pub struct EspWifiInit {
+    init_for: EspWifiInitFor,
+    timer: EspWifiTimer,
+    rng: hal::pac::RNG,
+    cpu_clock_mhz: u32,
+    wifi_config: WifiConfig,
+    mac_derivation: MacDerivation,
+    tick_rate_hz: u32,
+}
+
+impl EspWifiInit {
+    /// Takes the peripherals needed by the scheduler, the radios to initialize are
+    /// selected by `init_for`. [EspWifiInit::init] fails if `clocks` don't run the CPU at the
+    /// clock the radios need.
+    pub fn new(
+        init_for: EspWifiInitFor,
+        timer: EspWifiTimer,
+        rng: hal::pac::RNG,
+        clocks: &Clocks,
+    ) -> EspWifiInit {
+        EspWifiInit {
+            init_for,
+            timer,
+            rng,
+            cpu_clock_mhz: clocks.cpu_clock.to_MHz(),
+            wifi_config: WifiConfig::default(),
+            mac_derivation: MacDerivation::default(),
+            tick_rate_hz: DEFAULT_TICK_RATE_HZ,
+        }
+    }
+
+    /// Configuration of the Wi-Fi driver, see [WifiConfig]
+    pub fn with_wifi_config(mut self, config: WifiConfig) -> EspWifiInit {
+        self.wifi_config = config;
+        self
+    }
+
+    /// Number of statically allocated Wi-Fi RX buffers (2 - 25)
+    pub fn with_static_rx_buf_num(mut self, num: u8) -> EspWifiInit {
+        self.wifi_config.static_rx_buf_num = num;
+        self
+    }
+
+    /// Maximum number of dynamically allocated Wi-Fi RX buffers (0 - 1024, 0 means unlimited)
+    pub fn with_dynamic_rx_buf_num(mut self, num: u16) -> EspWifiInit {
+        self.wifi_config.dynamic_rx_buf_num = num;
+        self
+    }
+
+    /// Maximum number of dynamically allocated Wi-Fi TX buffers (1 - 128)
+    pub fn with_dynamic_tx_buf_num(mut self, num: u16) -> EspWifiInit {
+        self.wifi_config.dynamic_tx_buf_num = num;
+        self
+    }
+
+    /// How the STA, AP and BT MAC addresses are derived from the base MAC, see [MacDerivation]
+    pub fn with_mac_derivation(mut self, derivation: MacDerivation) -> EspWifiInit {
+        self.mac_derivation = derivation;
+        self
+    }
+
+    /// Task switches per second, a task runs at most this long before others of the same
+    /// priority get their turn
+    pub fn with_tick_rate_hz(mut self, tick_rate_hz: u32) -> EspWifiInit {
+        self.tick_rate_hz = tick_rate_hz;
+        self
+    }
+
+    /// Starts the scheduler and initializes the selected radios.
+    pub fn init(self) -> Result<EspWifiInitialization, InitFailure> {
+        if !self.wifi_config.is_valid()
+            || self.tick_rate_hz == 0
+            || self.tick_rate_hz as u64 > TICKS_PER_SECOND
+        {
+            return Err(InitFailure {
+                error: InitializationError::InvalidConfiguration,
+                retry: InitRetry::Init(self),
+            });
+        }
+
+        if self.cpu_clock_mhz != RADIO_CPU_CLOCK_MHZ {
+            return Err(InitFailure {
+                error: InitializationError::WrongClockConfig,
+                retry: InitRetry::Init(self),
+            });
+        }
+
+        init_rng(self.rng);
+        set_mac_derivation(self.mac_derivation);
+        init_tasks();
+        setup_timer_isr(self.timer, self.tick_rate_hz);
+        wifi_set_log_verbose();
+
+        init_radios(self.init_for, self.wifi_config)
+    }
+}
+
+fn init_radios(
+    init_for: EspWifiInitFor,
+    wifi_config: WifiConfig,
+) -> Result<EspWifiInitialization, InitFailure> {
+    init_clocks();
+    init_buffer();
+
+    if init_for.is_ble() {
+        unsafe {
+            crate::wifi::BLE_ENABLED = true;
+        }
+    }
+
+    // from here on the handle cleans up whatever got initialized
+    let initialization = EspWifiInitialization {
+        init_for,
+        wifi_config,
+    };
+
+    match start_radios(init_for, &wifi_config) {
+        Ok(()) => Ok(initialization),
+        Err(error) => Err(InitFailure {
+            error,
+            retry: InitRetry::Radios(initialization.deinit()),
+        }),
+    }
+}
+
+fn start_radios(
+    init_for: EspWifiInitFor,
+    wifi_config: &WifiConfig,
+) -> Result<(), InitializationError> {
+    #[cfg(feature = "coex")]
+    crate::coex::coex_initialize()?;
+
+    if init_for.is_wifi() {
+        configure(wifi_config);
+
+        let res = wifi_init();
+        if res != 0 {
+            return Err(InitializationError::General(res));
+        }
+        let res = wifi_start();
+        if res != 0 {
+            return Err(InitializationError::General(res));
+        }
+    }
+
+    if init_for.is_ble() {
+        crate::ble::ble_init()?;
+    }
+
+    Ok(())
+}
+
+fn deinit_radios(init_for: EspWifiInitFor) {
+    if init_for.is_ble() {
+        crate::ble::ble_deinit();
+
+        unsafe {
+            crate::wifi::BLE_ENABLED = false;
+        }
+    }
+
+    if init_for.is_wifi() {
+        let res = wifi_deinit();
+        if res != 0 {
+            log::warn!("wifi_deinit returned {}", res);
+        }
+    }
+
+    #[cfg(feature = "coex")]
+    crate::coex::coex_deinitialize();
+
+    // the driver should have deleted its tasks, whatever they left behind can go, too
+    delete_driver_tasks();
+    compat_timer_reset();
+    reset_sync_primitives();
+}
+
+/// Proof that the radios are initialized, they are deinitialized when it's dropped.
+pub struct EspWifiInitialization {
+    init_for: EspWifiInitFor,
+    wifi_config: WifiConfig,
+}
+
+impl EspWifiInitialization {
+    /// The radios this was initialized for
+    pub fn init_for(&self) -> EspWifiInitFor {
+        self.init_for
+    }
+
+    pub fn is_wifi(&self) -> bool {
+        self.init_for.is_wifi()
+    }
+
+    pub fn is_ble(&self) -> bool {
+        self.init_for.is_ble()
+    }
+
+    /// Turns the radios off completely, they can be initialized again via the returned
+    /// [EspWifiDeinitialized].
+    pub fn deinit(self) -> EspWifiDeinitialized {
+        let deinitialized = EspWifiDeinitialized {
+            init_for: self.init_for,
+            wifi_config: self.wifi_config,
+        };
+
+        deinit_radios(self.init_for);
+        core::mem::forget(self);
+
+        deinitialized
+    }
+
+    /// Deinitializes and initializes the radios again, e.g. to recover from a driver fault.
+    pub fn restart(self) -> Result<EspWifiInitialization, InitFailure> {
+        self.deinit().init()
+    }
+}
+
+/// The radios are turned off, the scheduler is still running.
+pub struct EspWifiDeinitialized {
+    init_for: EspWifiInitFor,
+    wifi_config: WifiConfig,
+}
+
+impl EspWifiDeinitialized {
+    /// Initializes the radios again, with the same configuration as before.
+    pub fn init(self) -> Result<EspWifiInitialization, InitFailure> {
+        init_radios(self.init_for, self.wifi_config)
+    }
+}
+
+impl Drop for EspWifiInitialization {
+    fn drop(&mut self) {
+        deinit_radios(self.init_for);
+    }
+}
@@ -2,6 +2,7 @@
 #![feature(c_variadic)]
 #![cfg_attr(target_arch = "xtensa", feature(asm_experimental_arch))]
 #![feature(alloc_error_handler)]
+#![cfg_attr(feature = "async", feature(generic_associated_types))]
 
 #[doc(hidden)]
 pub mod binary;
@@ -17,10 +18,18 @@ pub mod preempt;
 #[cfg_attr(feature = "esp32", path = "timer_esp32.rs")]
 pub mod timer;
 
+mod init;
+pub use init::*;
+
 pub mod wifi;
 
 pub mod ble;
 
+#[cfg(feature = "coex")]
+pub mod coex;
+
+pub mod thread;
+
 #[doc(hidden)]
 pub mod tasks;
 
@@ -31,5 +40,5 @@ use timer::{get_systimer_count, TICKS_PER_SECOND};
 pub mod wifi_interface;
 
 pub fn current_millis() -> u64 {
-    get_systimer_count() / TICKS_PER_SECOND
+    get_systimer_count() * 1000 / TICKS_PER_SECOND
 }
@@ -1,3 +1,302 @@
 #[cfg_attr(target_arch = "riscv32", path = "preempt_riscv.rs")]
 #[cfg_attr(target_arch = "xtensa", path = "preempt_xtensa.rs")]
 pub mod preempt;
+
+mod stack;
+
+use esp_alloc::memory_fence;
+
+use preempt::{current_task, MAX_TASK};
+pub(crate) use stack::Stack;
+pub use stack::{DEFAULT_STACK_SIZE, MIN_STACK_SIZE};
+
+#[derive(Debug, Clone, Copy)]
+struct Wait {
+    /// What the task waits for, usually the address of a semaphore, mutex or queue
+    object: usize,
+    /// Systimer count at which the wait times out
+    until: Option<u64>,
+}
+
+static mut WAITING: [Option<Wait>; MAX_TASK] = [None; MAX_TASK];
+
+/// Priority of the main task, the driver tasks run above it. Higher is more important.
+pub const MAIN_TASK_PRIORITY: u32 = 1;
+
+/// Priority of the idle task, it runs when no other task is runnable
+pub const IDLE_TASK_PRIORITY: u32 = 0;
+
+/// Interrupts taken while idle run on this stack, too
+const IDLE_STACK_SIZE: usize = 8192;
+
+static mut IDLE_TASK: Option<usize> = None;
+
+#[derive(Debug, Clone, Copy)]
+struct Task {
+    priority: u32,
+    /// The main task runs on the stack it was started with
+    stack: Option<Stack>,
+}
+
+static mut TASKS: [Option<Task>; MAX_TASK] = [None; MAX_TASK];
+
+/// Counts the tasks which used a slot, tells a task apart from an earlier one with the same id
+static mut GENERATIONS: [u32; MAX_TASK] = [0; MAX_TASK];
+
+/// Takes a free task slot for a task running on `stack`, the stack is released again if all
+/// slots are taken. Allocate the stack before entering a critical section, filling it takes a
+/// while.
+///
+/// Returns the id and the generation of the task.
+pub(crate) fn allocate_task(priority: u32, stack: Option<Stack>) -> Option<(usize, u32)> {
+    critical_section::with(|_| unsafe {
+        let id = match TASKS.iter().position(|task| task.is_none()) {
+            Some(id) => id,
+            None => {
+                if let Some(stack) = stack {
+                    stack.release();
+                }
+                return None;
+            }
+        };
+
+        TASKS[id] = Some(Task { priority, stack });
+        WAITING[id] = None;
+        GENERATIONS[id] = GENERATIONS[id].wrapping_add(1);
+        memory_fence();
+        Some((id, GENERATIONS[id]))
+    })
+}
+
+/// Returns the slot and the stack of a deleted task
+pub(crate) fn free_task(id: usize) {
+    critical_section::with(|_| unsafe {
+        if let Some(Task {
+            stack: Some(stack), ..
+        }) = TASKS[id].take()
+        {
+            stack.release();
+        }
+        WAITING[id] = None;
+        memory_fence();
+    });
+}
+
+/// The end of the task's stack, the context of a new task is set up from here
+pub(crate) fn stack_top(id: usize) -> usize {
+    unsafe {
+        TASKS[id]
+            .and_then(|task| task.stack)
+            .expect("task has no stack")
+            .top()
+    }
+}
+
+/// Used by the scheduler after saving the context of a task
+pub(crate) fn check_stack(id: usize) {
+    unsafe {
+        if let Some(Task {
+            stack: Some(stack), ..
+        }) = TASKS[id]
+        {
+            if stack.is_overflowed() {
+                panic!("stack overflow in task {}", id);
+            }
+        }
+    }
+}
+
+/// Size of the task's stack in bytes, `None` for the main task or an unused id
+pub fn stack_size(id: usize) -> Option<usize> {
+    task_stack(id).map(|stack| stack.size())
+}
+
+/// Bytes of the task's stack which were never used so far, `None` for the main task or an
+/// unused id
+pub fn stack_high_water_mark(id: usize) -> Option<usize> {
+    task_stack(id).map(|stack| stack.high_water_mark())
+}
+
+fn task_stack(id: usize) -> Option<Stack> {
+    critical_section::with(|_| unsafe { TASKS.get(id).copied().flatten()?.stack })
+}
+
+pub fn set_task_priority(id: usize, priority: u32) {
+    critical_section::with(|_| unsafe {
+        if let Some(Some(task)) = TASKS.get_mut(id) {
+            task.priority = priority;
+        }
+    });
+}
+
+/// The generation of the task currently using the slot, `None` for an unused id
+pub fn task_generation(id: usize) -> Option<u32> {
+    critical_section::with(|_| unsafe {
+        TASKS.get(id)?.as_ref()?;
+        Some(GENERATIONS[id])
+    })
+}
+
+pub fn task_priority(id: usize) -> Option<u32> {
+    critical_section::with(|_| unsafe {
+        TASKS.get(id).copied().flatten().map(|task| task.priority)
+    })
+}
+
+/// Picks the runnable task with the highest priority, tasks of the same priority take turns.
+///
+/// With nothing runnable it stays at the current task which waits in [wait_for_wake_up].
+pub(crate) fn select_next_task(current: usize, now: u64) -> usize {
+    unsafe {
+        let mut next: Option<(usize, u32)> = None;
+
+        // starting after the current task makes it the last candidate of its priority
+        for offset in 1..=MAX_TASK {
+            let id = (current + offset) % MAX_TASK;
+            let task = match TASKS[id] {
+                Some(task) => task,
+                None => continue,
+            };
+
+            if !is_runnable(id, now) {
+                continue;
+            }
+
+            match next {
+                Some((_, priority)) if priority >= task.priority => (),
+                _ => next = Some((id, task.priority)),
+            }
+        }
+
+        match next {
+            Some((id, _)) => id,
+            None if TASKS[current].is_some() => current,
+            // the current task is gone, wait in any other one
+            None => TASKS
+                .iter()
+                .position(|task| task.is_some())
+                .unwrap_or(current),
+        }
+    }
+}
+
+/// Marks the current task as waiting for `object` until [wake_tasks] is called for it or the
+/// systimer count reaches `until`, [wait_for_wake_up] parks it then.
+///
+/// Call it in the critical section which found `object` unavailable, otherwise a wake up in
+/// between gets lost.
+pub fn prepare_wait(object: *const (), until: Option<u64>) {
+    critical_section::with(|_| unsafe {
+        WAITING[current_task()] = Some(Wait {
+            object: object as usize,
+            until,
+        });
+        memory_fence();
+    });
+}
+
+/// Parks the current task after [prepare_wait] until it's woken or the wait timed out. The
+/// caller has to check again what it waited for, waking is only a hint.
+pub fn wait_for_wake_up() {
+    let id = current_task();
+
+    crate::timer::yield_task();
+
+    // the scheduler doesn't pick a parked task unless there is nothing else to run
+    while critical_section::with(|_| unsafe { WAITING[id].is_some() }) {}
+}
+
+/// Makes all tasks waiting for `object` runnable again, switches to one of them right away if
+/// it's more important than the current task
+pub fn wake_tasks(object: *const ()) {
+    let preempt = critical_section::with(|_| unsafe {
+        let current_priority = TASKS[current_task()].map_or(0, |task| task.priority);
+        let mut preempt = false;
+
+        for (id, wait) in WAITING.iter_mut().enumerate() {
+            if let Some(Wait {
+                object: waiting_for,
+                ..
+            }) = wait
+            {
+                if *waiting_for == object as usize {
+                    *wait = None;
+                    preempt |= TASKS[id].map_or(false, |task| task.priority > current_priority);
+                }
+            }
+        }
+        memory_fence();
+        preempt
+    });
+
+    if preempt {
+        crate::timer::yield_task();
+    }
+}
+
+/// Drops the wait of a task, e.g. when it's restarted
+pub(crate) fn clear_wait(id: usize) {
+    critical_section::with(|_| unsafe {
+        WAITING[id] = None;
+    });
+}
+
+/// Used by the scheduler, wakes the task if its wait timed out
+pub(crate) fn is_runnable(id: usize, now: u64) -> bool {
+    unsafe {
+        match WAITING[id] {
+            None => true,
+            Some(Wait {
+                until: Some(until), ..
+            }) if now >= until => {
+                WAITING[id] = None;
+                true
+            }
+            Some(_) => false,
+        }
+    }
+}
+
+/// Creates the task running when nothing else is runnable
+pub(crate) fn create_idle_task() {
+    let (id, _) = preempt::task_create(
+        preempt::idle_task,
+        core::ptr::null_mut(),
+        IDLE_TASK_PRIORITY,
+        IDLE_STACK_SIZE,
+    )
+    .expect("no task slot left for the idle task");
+
+    unsafe {
+        IDLE_TASK = Some(id);
+    }
+}
+
+/// With only the idle task running the timer interrupt isn't needed before the next task wakes
+/// up. Returns that systimer count, `None` while there are time slices to run.
+pub(crate) fn idle_deadline() -> Option<u64> {
+    unsafe {
+        if IDLE_TASK != Some(current_task()) {
+            return None;
+        }
+
+        Some(
+            WAITING
+                .iter()
+                .filter_map(|wait| wait.and_then(|wait| wait.until))
+                .min()
+                .unwrap_or(u64::MAX),
+        )
+    }
+}
+
+/// Parks the current task for the given number of systimer ticks
+pub fn task_sleep(ticks: u64) {
+    let until = crate::timer::get_systimer_count() + ticks;
+
+    while crate::timer::get_systimer_count() < until {
+        // nobody wakes the sleeping task, only the timeout does
+        prepare_wait(core::ptr::null(), Some(until));
+        wait_for_wake_up();
+    }
+}
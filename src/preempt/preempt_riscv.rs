@@ -8,16 +8,19 @@ pub struct Context {
     _running: bool,
 }
 
-const STACK_SIZE: usize = 8192 * 2; // TODO how much is enough? would be better to have this per task
-const MAX_TASK: usize = 3;
+pub(crate) const MAX_TASK: usize = 8;
 
-static mut TASK_STACK: [u8; STACK_SIZE * MAX_TASK] = [0u8; STACK_SIZE * MAX_TASK];
+pub(crate) static mut FIRST_SWITCH: AtomicBool = AtomicBool::new(true);
 
-pub static mut FIRST_SWITCH: AtomicBool = AtomicBool::new(true);
+static mut CTX_NOW: usize = 0;
 
-static mut TASK_TOP: usize = 0;
+static mut TASK_ENTRY: [usize; MAX_TASK] = [0; MAX_TASK];
 
-static mut CTX_NOW: usize = 0;
+static mut TASK_PARAM: [usize; MAX_TASK] = [0; MAX_TASK];
+
+static mut DISCARD_CURRENT_CONTEXT: bool = false;
+
+static mut DELETE_CURRENT_TASK: bool = false;
 
 static mut CTX_TASKS: [Context; MAX_TASK] = [Context {
     trap_frame: TrapFrame {
@@ -57,29 +60,89 @@ static mut CTX_TASKS: [Context; MAX_TASK] = [Context {
     _running: false,
 }; MAX_TASK];
 
-pub fn task_create(task: extern "C" fn()) -> usize {
+/// Creates a task with the given priority, higher is more important, and a stack of
+/// `stack_size` bytes. The task gets `param` passed and must not return.
+///
+/// Returns the id and the generation of the task or `None` if all slots are taken or there is
+/// no memory left for the stack.
+pub fn task_create(
+    task: extern "C" fn(*mut crate::binary::c_types::c_void),
+    param: *mut crate::binary::c_types::c_void,
+    priority: u32,
+    stack_size: usize,
+) -> Option<(usize, u32)> {
+    let stack = super::Stack::allocate(stack_size)?;
+
+    critical_section::with(|_| unsafe {
+        let (id, generation) = super::allocate_task(priority, Some(stack))?;
+        TASK_ENTRY[id] = task as usize;
+        TASK_PARAM[id] = param as usize;
+        init_task_context(id);
+        Some((id, generation))
+    })
+}
+
+/// Removes a task from the scheduler, its slot can be used by a new task afterwards.
+///
+/// Deleting the current task doesn't return.
+pub fn task_delete(id: usize) {
+    let delete_current = critical_section::with(|_| unsafe {
+        if id == CTX_NOW {
+            // the context is still in use, it's dropped on the next task switch
+            DELETE_CURRENT_TASK = true;
+        } else {
+            TASK_ENTRY[id] = 0;
+            super::free_task(id);
+        }
+        DELETE_CURRENT_TASK
+    });
+
+    if delete_current {
+        crate::timer::yield_task();
+        loop {}
+    }
+}
+
+fn init_task_context(id: usize) {
     unsafe {
-        let i = TASK_TOP;
-        TASK_TOP += 1;
-        CTX_TASKS[i].pc = task as usize;
+        CTX_TASKS[id].pc = TASK_ENTRY[id];
 
         // stack must be aligned by 16
-        let task_stack_ptr = &TASK_STACK as *const _ as usize
-            + (STACK_SIZE as usize * i as usize)
-            + STACK_SIZE as usize
-            - 4;
+        let task_stack_ptr = super::stack_top(id) - 4;
         let stack_ptr = task_stack_ptr - (task_stack_ptr % 0x10);
-        CTX_TASKS[i].trap_frame.sp = stack_ptr;
+        CTX_TASKS[id].trap_frame.sp = stack_ptr;
+        CTX_TASKS[id].trap_frame.a0 = TASK_PARAM[id];
+    }
+}
 
-        CTX_NOW = i;
-        i
+/// Starts a task over from its entry point.
+///
+/// Restarting the current task doesn't return, its context is discarded on the next task switch.
+pub fn task_restart(id: usize) {
+    let restart_current = critical_section::with(|_| unsafe {
+        if TASK_ENTRY[id] == 0 {
+            panic!("task {} can't be restarted", id);
+        }
+        super::clear_wait(id);
+
+        init_task_context(id);
+
+        if id == CTX_NOW {
+            DISCARD_CURRENT_CONTEXT = true;
+        }
+        DISCARD_CURRENT_CONTEXT
+    });
+
+    if restart_current {
+        crate::timer::yield_task();
+        loop {}
     }
 }
 
 fn task_create_from_mepc(mepc: usize) -> usize {
     unsafe {
-        let i = TASK_TOP;
-        TASK_TOP += 1;
+        let (i, _) = super::allocate_task(super::MAIN_TASK_PRIORITY, None)
+            .expect("no task slot left for the main task");
         CTX_TASKS[i].pc = mepc;
         CTX_NOW = i;
         i
@@ -164,7 +227,8 @@ pub fn trap_frame_to_task(id: usize, pc: usize, trap_frame: &TrapFrame) {
 
 pub fn next_task() {
     unsafe {
-        CTX_NOW = (CTX_NOW + 1) % TASK_TOP;
+        let now = crate::timer::get_systimer_count();
+        CTX_NOW = super::select_next_task(CTX_NOW, now);
     }
 }
 
@@ -178,7 +242,16 @@ pub fn task_switch(trap_frame: &mut TrapFrame) {
             CTX_NOW = main_task;
         }
 
-        trap_frame_to_task(CTX_NOW, old_mepc, trap_frame);
+        if DELETE_CURRENT_TASK {
+            DELETE_CURRENT_TASK = false;
+            TASK_ENTRY[CTX_NOW] = 0;
+            super::free_task(CTX_NOW);
+        } else if DISCARD_CURRENT_CONTEXT {
+            DISCARD_CURRENT_CONTEXT = false;
+        } else {
+            trap_frame_to_task(CTX_NOW, old_mepc, trap_frame);
+            super::check_stack(CTX_NOW);
+        }
 
         next_task();
 
@@ -199,6 +272,15 @@ pub fn task_switch(trap_frame: &mut TrapFrame) {
     }
 }
 
+/// Sleeps until the next interrupt, runs when no other task is runnable
+pub(crate) extern "C" fn idle_task(_param: *mut crate::binary::c_types::c_void) {
+    loop {
+        unsafe {
+            riscv::asm::wfi();
+        }
+    }
+}
+
 pub fn current_task() -> usize {
     unsafe { CTX_NOW }
 }
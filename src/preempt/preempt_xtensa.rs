@@ -6,16 +6,19 @@ pub struct TaskContext {
     trap_frame: Context,
 }
 
-const STACK_SIZE: usize = 8192 * 2;
-const MAX_TASK: usize = 3;
+pub(crate) const MAX_TASK: usize = 8;
 
-static mut TASK_STACK: [u8; STACK_SIZE * MAX_TASK] = [0x0u8; STACK_SIZE * MAX_TASK];
+pub(crate) static mut FIRST_SWITCH: AtomicBool = AtomicBool::new(true);
 
-pub static mut FIRST_SWITCH: AtomicBool = AtomicBool::new(true);
+static mut CTX_NOW: usize = 0;
 
-static mut TASK_TOP: usize = 0;
+static mut TASK_ENTRY: [u32; MAX_TASK] = [0; MAX_TASK];
 
-static mut CTX_NOW: usize = 0;
+static mut TASK_PARAM: [u32; MAX_TASK] = [0; MAX_TASK];
+
+static mut DISCARD_CURRENT_CONTEXT: bool = false;
+
+static mut DELETE_CURRENT_TASK: bool = false;
 
 static mut CTX_TASKS: [TaskContext; MAX_TASK] = [TaskContext {
     trap_frame: Context {
@@ -76,32 +79,92 @@ static mut CTX_TASKS: [TaskContext; MAX_TASK] = [TaskContext {
     },
 }; MAX_TASK];
 
-pub fn task_create(task: extern "C" fn()) -> usize {
-    unsafe {
-        let i = TASK_TOP;
-        TASK_TOP += 1;
+/// Creates a task with the given priority, higher is more important, and a stack of
+/// `stack_size` bytes. The task gets `param` passed and must not return.
+///
+/// Returns the id and the generation of the task or `None` if all slots are taken or there is
+/// no memory left for the stack.
+pub fn task_create(
+    task: extern "C" fn(*mut crate::binary::c_types::c_void),
+    param: *mut crate::binary::c_types::c_void,
+    priority: u32,
+    stack_size: usize,
+) -> Option<(usize, u32)> {
+    let stack = super::Stack::allocate(stack_size)?;
+
+    critical_section::with(|_| unsafe {
+        let (id, generation) = super::allocate_task(priority, Some(stack))?;
+        TASK_ENTRY[id] = task as u32;
+        TASK_PARAM[id] = param as u32;
+        init_task_context(id);
+        Some((id, generation))
+    })
+}
+
+/// Removes a task from the scheduler, its slot can be used by a new task afterwards.
+///
+/// Deleting the current task doesn't return.
+pub fn task_delete(id: usize) {
+    let delete_current = critical_section::with(|_| unsafe {
+        if id == CTX_NOW {
+            // the context is still in use, it's dropped on the next task switch
+            DELETE_CURRENT_TASK = true;
+        } else {
+            TASK_ENTRY[id] = 0;
+            super::free_task(id);
+        }
+        DELETE_CURRENT_TASK
+    });
+
+    if delete_current {
+        crate::timer::yield_task();
+        loop {}
+    }
+}
 
-        CTX_TASKS[i].trap_frame.PC = task as u32;
+fn init_task_context(id: usize) {
+    unsafe {
+        CTX_TASKS[id].trap_frame.PC = TASK_ENTRY[id];
 
         // stack must be aligned by 16
-        let task_stack_ptr = (&TASK_STACK as *const _ as usize
-            + (STACK_SIZE as usize * i as usize)
-            + STACK_SIZE as usize
-            - 4) as u32;
+        let task_stack_ptr = (super::stack_top(id) - 4) as u32;
         let stack_ptr = task_stack_ptr - (task_stack_ptr % 0x10);
-        CTX_TASKS[i].trap_frame.A1 = stack_ptr;
+        CTX_TASKS[id].trap_frame.A1 = stack_ptr;
 
-        CTX_TASKS[i].trap_frame.PS = 0x00040000 | (1 & 3) << 16; // For windowed ABI set WOE and CALLINC (pretend task was 'call4'd).
+        CTX_TASKS[id].trap_frame.PS = 0x00040000 | (1 & 3) << 16; // For windowed ABI set WOE and CALLINC (pretend task was 'call4'd).
 
-        CTX_TASKS[i].trap_frame.A0 = 0;
+        CTX_TASKS[id].trap_frame.A0 = 0;
+        // with CALLINC = 1 the caller's a6 is the first argument
+        CTX_TASKS[id].trap_frame.A6 = TASK_PARAM[id];
 
         *((task_stack_ptr - 4) as *mut u32) = 0;
         *((task_stack_ptr - 8) as *mut u32) = 0;
         *((task_stack_ptr - 12) as *mut u32) = stack_ptr;
         *((task_stack_ptr - 16) as *mut u32) = 0;
+    }
+}
+
+/// Starts a task over from its entry point.
+///
+/// Restarting the current task doesn't return, its context is discarded on the next task switch.
+pub fn task_restart(id: usize) {
+    let restart_current = critical_section::with(|_| unsafe {
+        if TASK_ENTRY[id] == 0 {
+            panic!("task {} can't be restarted", id);
+        }
+        super::clear_wait(id);
+
+        if id == CTX_NOW {
+            DISCARD_CURRENT_CONTEXT = true;
+        } else {
+            init_task_context(id);
+        }
+        DISCARD_CURRENT_CONTEXT
+    });
 
-        CTX_NOW = i;
-        i
+    if restart_current {
+        crate::timer::yield_task();
+        loop {}
     }
 }
 
@@ -225,7 +288,8 @@ pub fn trap_frame_to_task(id: usize, trap_frame: &Context) {
 
 pub fn next_task() {
     unsafe {
-        CTX_NOW = (CTX_NOW + 1) % TASK_TOP;
+        let now = crate::timer::get_systimer_count();
+        CTX_NOW = super::select_next_task(CTX_NOW, now);
     }
 }
 
@@ -233,11 +297,23 @@ pub fn task_switch(trap_frame: &mut Context) {
     unsafe {
         if FIRST_SWITCH.load(Ordering::Relaxed) {
             FIRST_SWITCH.store(false, Ordering::Relaxed);
-            TASK_TOP += 1;
-            CTX_NOW = TASK_TOP - 1;
+            CTX_NOW = super::allocate_task(super::MAIN_TASK_PRIORITY, None)
+                .expect("no task slot left for the main task")
+                .0;
         }
 
-        trap_frame_to_task(CTX_NOW, trap_frame);
+        if DELETE_CURRENT_TASK {
+            DELETE_CURRENT_TASK = false;
+            TASK_ENTRY[CTX_NOW] = 0;
+            super::free_task(CTX_NOW);
+        } else if DISCARD_CURRENT_CONTEXT {
+            // the stack is still in use until the switch, reset it now
+            DISCARD_CURRENT_CONTEXT = false;
+            init_task_context(CTX_NOW);
+        } else {
+            trap_frame_to_task(CTX_NOW, trap_frame);
+            super::check_stack(CTX_NOW);
+        }
         next_task();
         task_to_trap_frame(CTX_NOW, trap_frame);
 
@@ -254,6 +330,15 @@ pub fn task_switch(trap_frame: &mut Context) {
     };
 }
 
+/// Sleeps until the next interrupt, runs when no other task is runnable
+pub(crate) extern "C" fn idle_task(_param: *mut crate::binary::c_types::c_void) {
+    loop {
+        unsafe {
+            core::arch::asm!("waiti 0");
+        }
+    }
+}
+
 pub fn current_task() -> usize {
     unsafe { CTX_NOW }
 }
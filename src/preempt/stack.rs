@@ -0,0 +1,112 @@
+// STACK_POOL_NUM and STACK_POOL_SIZE generated by build.rs, set via the ESP_WIFI_* environment
+// variables at build time
+include!(concat!(env!("OUT_DIR"), "/scheduler_config.rs"));
+
+/// Stack size of the internal tasks
+pub const DEFAULT_STACK_SIZE: usize = 8192 * 2;
+
+/// The compat functions called by the driver tasks need more stack than on ESP-IDF, smaller
+/// requests are rounded up to this
+pub const MIN_STACK_SIZE: usize = 4096;
+
+/// New stacks are filled with this, what's left of it shows how much stack was never used
+const STACK_FILL_BYTE: u8 = 0xa5;
+
+/// Like FreeRTOS a stack counts as overflowed when the lowest bytes were written
+const STACK_CANARY_SIZE: usize = 16;
+
+#[repr(align(16))]
+struct PoolSlot([u8; STACK_POOL_SIZE]);
+
+const EMPTY_POOL_SLOT: PoolSlot = PoolSlot([0u8; STACK_POOL_SIZE]);
+
+static mut STACK_POOL: [PoolSlot; STACK_POOL_NUM] = [EMPTY_POOL_SLOT; STACK_POOL_NUM];
+
+static mut STACK_POOL_USED: [bool; STACK_POOL_NUM] = [false; STACK_POOL_NUM];
+
+/// The stack of a task, taken from the static pool if a slot is free and big enough, otherwise
+/// from the heap
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Stack {
+    bottom: usize,
+    size: usize,
+    pool_slot: Option<usize>,
+}
+
+impl Stack {
+    pub fn allocate(size: usize) -> Option<Stack> {
+        let size = (size.max(MIN_STACK_SIZE) + 15) & !15;
+
+        let stack = critical_section::with(|_| unsafe {
+            match STACK_POOL_USED.iter().position(|used| !used) {
+                Some(slot) if size <= STACK_POOL_SIZE => {
+                    STACK_POOL_USED[slot] = true;
+                    Some(Stack {
+                        bottom: STACK_POOL[slot].0.as_ptr() as usize,
+                        size: STACK_POOL_SIZE,
+                        pool_slot: Some(slot),
+                    })
+                }
+                _ => None,
+            }
+        });
+
+        let stack = match stack {
+            Some(stack) => stack,
+            None => {
+                let bottom = unsafe { esp_alloc::malloc(size as u32) } as usize;
+                if bottom == 0 {
+                    return None;
+                }
+
+                Stack {
+                    bottom,
+                    size,
+                    pool_slot: None,
+                }
+            }
+        };
+
+        unsafe {
+            core::ptr::write_bytes(stack.bottom as *mut u8, STACK_FILL_BYTE, stack.size);
+        }
+
+        Some(stack)
+    }
+
+    pub fn release(self) {
+        match self.pool_slot {
+            Some(slot) => critical_section::with(|_| unsafe {
+                STACK_POOL_USED[slot] = false;
+            }),
+            None => unsafe { esp_alloc::free(self.bottom as *const u8) },
+        }
+    }
+
+    /// The end of the stack, it grows downwards from here
+    pub fn top(&self) -> usize {
+        self.bottom + self.size
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_overflowed(&self) -> bool {
+        unsafe {
+            core::slice::from_raw_parts(self.bottom as *const u8, STACK_CANARY_SIZE)
+                .iter()
+                .any(|b| *b != STACK_FILL_BYTE)
+        }
+    }
+
+    /// Bytes of the stack which were never used
+    pub fn high_water_mark(&self) -> usize {
+        unsafe {
+            core::slice::from_raw_parts(self.bottom as *const u8, self.size)
+                .iter()
+                .position(|b| *b != STACK_FILL_BYTE)
+                .unwrap_or(self.size)
+        }
+    }
+}
@@ -2,79 +2,70 @@ use esp_alloc::memory_fence;
 use log::{debug, trace};
 
 use crate::{
-    compat::{
-        self,
-        queue::SimpleQueue,
-        timer_compat::{Timer, TIMERS},
+    compat::timer_compat::TIMERS,
+    preempt::{
+        create_idle_task, preempt::task_create, prepare_wait, wait_for_wake_up, wake_tasks,
+        DEFAULT_STACK_SIZE, MAIN_TASK_PRIORITY,
     },
-    preempt::preempt::task_create,
     timer::get_systimer_count,
-    wifi::send_data_if_needed,
+    wifi::{send_data_if_needed, tx_pending},
 };
 
+/// The timer task runs above the main task, it's parked while no timer is due
+const TIMER_TASK_PRIORITY: u32 = MAIN_TASK_PRIORITY + 1;
+
+/// The timer task waits for this while there is nothing to do
+static TIMER_TASK_EVENT: u8 = 0;
+
 pub fn init_tasks() {
-    task_create(worker_task1);
-    task_create(worker_task2);
+    task_create(
+        timer_task,
+        core::ptr::null_mut(),
+        TIMER_TASK_PRIORITY,
+        DEFAULT_STACK_SIZE,
+    )
+    .unwrap();
+    create_idle_task();
 }
 
-pub extern "C" fn worker_task1() {
-    loop {
-        compat::work_queue::do_work();
-    }
+/// Makes the timer task check the timers and the data to send again
+pub fn wake_timer_task() {
+    wake_tasks(&TIMER_TASK_EVENT as *const _ as *const ());
 }
 
-pub extern "C" fn worker_task2() {
+pub extern "C" fn timer_task(_param: *mut crate::binary::c_types::c_void) {
     loop {
-        let mut to_run: SimpleQueue<
-            (
-                fn(*mut crate::binary::c_types::c_void),
-                *mut crate::binary::c_types::c_void,
-            ),
-            10,
-        > = SimpleQueue::new();
-
-        critical_section::with(|_| unsafe {
-            for i in 0..TIMERS.len() {
-                memory_fence();
-                TIMERS[i] = match &TIMERS[i] {
-                    Some(old) => {
-                        if old.active && get_systimer_count() >= old.expire {
-                            debug!("timer is due.... {:p}", old.ptimer);
-                            let fnctn: fn(*mut crate::binary::c_types::c_void) =
-                                core::mem::transmute(old.timer_ptr);
-                            to_run.enqueue((fnctn, old.arg_ptr));
-
-                            Some(Timer {
-                                expire: if old.period != 0 {
-                                    get_systimer_count() + old.period
-                                } else {
-                                    0
-                                },
-                                active: if old.period != 0 { true } else { false },
-                                ..*old
-                            })
-                        } else {
-                            Some(*old)
-                        }
-                    }
-                    None => None,
-                };
-            }
-        });
+        let now = get_systimer_count();
 
         // run the due timer callbacks NOT in an interrupt free context
-        loop {
-            let run_now = to_run.dequeue();
-            if run_now.is_none() {
-                break;
-            }
+        while let Some(timer) = critical_section::with(|_| unsafe {
+            memory_fence();
+            TIMERS.pop_due(now)
+        }) {
+            debug!("timer is due.... {:p}", timer.ptimer);
+            let fnc: fn(*mut crate::binary::c_types::c_void) =
+                unsafe { core::mem::transmute(timer.timer_ptr) };
 
-            let (fnc, arg) = run_now.unwrap();
             trace!("trigger timer....");
-            fnc(arg);
+            fnc(timer.arg_ptr);
             trace!("timer callback called");
         }
 
         send_data_if_needed();
+
+        // sleep until the next timer is due, arming a timer or queuing data wakes the task
+        let idle = critical_section::with(|_| {
+            let idle = !tx_pending();
+            if idle {
+                prepare_wait(&TIMER_TASK_EVENT as *const _ as *const (), unsafe {
+                    TIMERS.next_expiry()
+                });
+            }
+            idle
+        });
+
+        if idle {
+            wait_for_wake_up();
+        }
     }
 }
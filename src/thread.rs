@@ -0,0 +1,402 @@
+//! Tasks for the application, running on the same scheduler as the driver tasks.
+//!
+//! Threads need the scheduler which is started by [crate::EspWifiInit::init]. There is no join,
+//! a spawned thread runs until its closure returns and dropping the [Thread] handle doesn't
+//! affect it.
+//!
+//! A thread with a higher priority runs until it blocks in [sleep_ms], [Mutex::lock] or
+//! [Channel::receive]/[Channel::send], threads of the same priority take turns. The main task
+//! runs at [MAIN_TASK_PRIORITY], the driver tasks above it.
+
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    ops::{Deref, DerefMut},
+};
+
+use crate::preempt::{
+    preempt::{current_task, task_create, task_delete},
+    prepare_wait, set_task_priority, stack_high_water_mark, stack_size, task_generation,
+    task_priority, task_sleep, wait_for_wake_up, wake_tasks, DEFAULT_STACK_SIZE,
+};
+use crate::timer::TICKS_PER_SECOND;
+
+pub use crate::preempt::MAIN_TASK_PRIORITY;
+
+/// There is no task slot or no memory for the stack left
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnError;
+
+/// Handle of a thread, it stays valid after the thread ended. The id of an ended thread is
+/// reused by later threads, the handle doesn't refer to them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Thread {
+    id: usize,
+    generation: u32,
+}
+
+impl Thread {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Whether the thread didn't end yet
+    pub fn is_running(&self) -> bool {
+        task_generation(self.id) == Some(self.generation)
+    }
+
+    /// `None` if the thread ended
+    pub fn priority(&self) -> Option<u32> {
+        self.if_running(|| task_priority(self.id))
+    }
+
+    /// Does nothing if the thread ended
+    pub fn set_priority(&self, priority: u32) {
+        self.if_running(|| {
+            set_task_priority(self.id, priority);
+            Some(())
+        });
+    }
+
+    /// Size of the thread's stack in bytes, `None` for the main task or if the thread ended
+    pub fn stack_size(&self) -> Option<usize> {
+        self.if_running(|| stack_size(self.id))
+    }
+
+    /// Bytes of the thread's stack which were never used so far, `None` for the main task or
+    /// if the thread ended
+    pub fn stack_high_water_mark(&self) -> Option<usize> {
+        self.if_running(|| stack_high_water_mark(self.id))
+    }
+
+    /// Runs `f` unless the thread ended, the slot can't be taken by another thread meanwhile
+    fn if_running<R>(&self, f: impl FnOnce() -> Option<R>) -> Option<R> {
+        critical_section::with(|_| if self.is_running() { f() } else { None })
+    }
+
+    /// Drops the handle, the thread keeps running
+    pub fn detach(self) {}
+}
+
+/// Configuration of a new thread, see [Builder::spawn]
+pub struct Builder {
+    stack_size: usize,
+    priority: u32,
+}
+
+impl Builder {
+    /// A thread with a stack of [DEFAULT_STACK_SIZE] bytes running at [MAIN_TASK_PRIORITY]
+    pub fn new() -> Builder {
+        Builder {
+            stack_size: DEFAULT_STACK_SIZE,
+            priority: MAIN_TASK_PRIORITY,
+        }
+    }
+
+    /// Stack size in bytes, small stacks are rounded up to [crate::preempt::MIN_STACK_SIZE]
+    pub fn stack_size(mut self, stack_size: usize) -> Builder {
+        self.stack_size = stack_size;
+        self
+    }
+
+    /// Higher is more important
+    pub fn priority(mut self, priority: u32) -> Builder {
+        self.priority = priority;
+        self
+    }
+
+    pub fn spawn<F>(self, f: F) -> Result<Thread, SpawnError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // the closure is moved to the heap, the new thread takes it from there
+        let size = core::mem::size_of::<F>() + core::mem::align_of::<F>();
+        let ptr = unsafe { esp_alloc::malloc(size as u32) };
+        if ptr.is_null() {
+            return Err(SpawnError);
+        }
+
+        unsafe {
+            (aligned::<F>(ptr as usize) as *mut F).write(f);
+        }
+
+        match task_create(
+            thread_entry::<F>,
+            ptr as *mut crate::binary::c_types::c_void,
+            self.priority,
+            self.stack_size,
+        ) {
+            Some((id, generation)) => Ok(Thread { id, generation }),
+            None => {
+                unsafe {
+                    drop((aligned::<F>(ptr as usize) as *mut F).read());
+                    esp_alloc::free(ptr);
+                }
+                Err(SpawnError)
+            }
+        }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+fn aligned<F>(ptr: usize) -> usize {
+    let align = core::mem::align_of::<F>();
+    (ptr + align - 1) & !(align - 1)
+}
+
+extern "C" fn thread_entry<F>(param: *mut crate::binary::c_types::c_void)
+where
+    F: FnOnce() + Send + 'static,
+{
+    let f = unsafe {
+        let f = (aligned::<F>(param as usize) as *const F).read();
+        esp_alloc::free(param as *const u8);
+        f
+    };
+
+    f();
+
+    task_delete(current_task());
+}
+
+/// Spawns a thread with the defaults of [Builder::new]
+pub fn spawn<F>(f: F) -> Result<Thread, SpawnError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    Builder::new().spawn(f)
+}
+
+/// The thread calling this
+pub fn current() -> Thread {
+    critical_section::with(|_| {
+        let id = current_task();
+        Thread {
+            id,
+            generation: task_generation(id).unwrap_or_default(),
+        }
+    })
+}
+
+/// Parks the current thread, other threads run in the meantime
+pub fn sleep_ms(ms: u32) {
+    task_sleep(ms as u64 * TICKS_PER_SECOND / 1000);
+}
+
+/// Lets the other threads of the same priority run
+pub fn yield_now() {
+    crate::timer::yield_task();
+}
+
+/// Mutex which parks the threads waiting for it instead of disabling interrupts like a
+/// [critical_section::Mutex], its bookkeeping is done in a critical section so it can be
+/// used next to that.
+///
+/// It can't be locked from an interrupt handler.
+pub struct Mutex<T> {
+    locked: UnsafeCell<bool>,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Mutex<T> {
+        Mutex {
+            locked: UnsafeCell::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Locks the mutex, waits for other threads holding it
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        loop {
+            let locked = critical_section::with(|_| unsafe {
+                let locked = *self.locked.get();
+                if locked {
+                    prepare_wait(self as *const _ as *const (), None);
+                } else {
+                    *self.locked.get() = true;
+                }
+                locked
+            });
+
+            if !locked {
+                return MutexGuard { mutex: self };
+            }
+
+            wait_for_wake_up();
+        }
+    }
+
+    /// Locks the mutex if no other thread holds it
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        critical_section::with(|_| unsafe {
+            if *self.locked.get() {
+                None
+            } else {
+                *self.locked.get() = true;
+                Some(MutexGuard { mutex: self })
+            }
+        })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+/// Unlocks the [Mutex] when dropped
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        critical_section::with(|_| unsafe {
+            *self.mutex.locked.get() = false;
+            wake_tasks(self.mutex as *const _ as *const ());
+        });
+    }
+}
+
+/// Bounded channel holding up to `N` items, usually put in a `static` and shared by the
+/// threads.
+///
+/// [Channel::try_send] and [Channel::try_receive] don't block and can be used from an
+/// interrupt handler.
+pub struct Channel<T, const N: usize> {
+    buffer: UnsafeCell<MaybeUninit<[T; N]>>,
+    read: UnsafeCell<usize>,
+    count: UnsafeCell<usize>,
+}
+
+unsafe impl<T: Send, const N: usize> Sync for Channel<T, N> {}
+
+impl<T, const N: usize> Channel<T, N> {
+    pub const fn new() -> Channel<T, N> {
+        Channel {
+            buffer: UnsafeCell::new(MaybeUninit::uninit()),
+            read: UnsafeCell::new(0),
+            count: UnsafeCell::new(0),
+        }
+    }
+
+    fn slot(&self, index: usize) -> *mut T {
+        unsafe { (self.buffer.get() as *mut T).add(index % N) }
+    }
+
+    /// Queues the item, returns it if the channel is full
+    pub fn try_send(&self, item: T) -> Result<(), T> {
+        critical_section::with(|_| unsafe {
+            let count = *self.count.get();
+            if count == N {
+                return Err(item);
+            }
+
+            self.slot(*self.read.get() + count).write(item);
+            *self.count.get() = count + 1;
+            wake_tasks(self as *const _ as *const ());
+            Ok(())
+        })
+    }
+
+    /// Queues the item, waits for space if the channel is full
+    pub fn send(&self, item: T) {
+        let mut item = item;
+
+        loop {
+            item = match self.try_send_or_wait(item) {
+                Ok(()) => return,
+                Err(item) => item,
+            };
+
+            wait_for_wake_up();
+        }
+    }
+
+    fn try_send_or_wait(&self, item: T) -> Result<(), T> {
+        critical_section::with(|_| {
+            let res = self.try_send(item);
+            if res.is_err() {
+                prepare_wait(self as *const _ as *const (), None);
+            }
+            res
+        })
+    }
+
+    /// Takes the oldest item, `None` if the channel is empty
+    pub fn try_receive(&self) -> Option<T> {
+        critical_section::with(|_| unsafe {
+            let count = *self.count.get();
+            if count == 0 {
+                return None;
+            }
+
+            let read = *self.read.get();
+            let item = self.slot(read).read();
+            *self.read.get() = (read + 1) % N;
+            *self.count.get() = count - 1;
+            wake_tasks(self as *const _ as *const ());
+            Some(item)
+        })
+    }
+
+    /// Takes the oldest item, waits for one if the channel is empty
+    pub fn receive(&self) -> T {
+        loop {
+            let item = critical_section::with(|_| {
+                let item = self.try_receive();
+                if item.is_none() {
+                    prepare_wait(self as *const _ as *const (), None);
+                }
+                item
+            });
+
+            if let Some(item) = item {
+                return item;
+            }
+
+            wait_for_wake_up();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        critical_section::with(|_| unsafe { *self.count.get() })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T, const N: usize> Default for Channel<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for Channel<T, N> {
+    fn drop(&mut self) {
+        while self.try_receive().is_some() {}
+    }
+}
@@ -15,10 +15,17 @@ use crate::preempt::preempt::task_switch;
 
 pub const TICKS_PER_SECOND: u64 = 40_000_000;
 
+/// Task switches per second if not configured otherwise
 #[cfg(debug_assertions)]
-const TIMER_DELAY: u64 = 20_000u64;
+pub const DEFAULT_TICK_RATE_HZ: u32 = 2_000;
 #[cfg(not(debug_assertions))]
-const TIMER_DELAY: u64 = 500u64;
+pub const DEFAULT_TICK_RATE_HZ: u32 = 80_000;
+
+/// Length of a time slice in timer ticks
+static mut TIMER_DELAY: u64 = TICKS_PER_SECOND / DEFAULT_TICK_RATE_HZ as u64;
+
+/// Longest time without a timer interrupt while idle
+const MAX_IDLE_DELAY: u64 = TICKS_PER_SECOND;
 
 static mut TIMER1: SpinLockMutex<RefCell<Option<Timer<TIMG1>>>> =
     SpinLockMutex::new(RefCell::new(None));
@@ -38,16 +45,20 @@ fn read_timer_value() -> u64 {
     value
 }
 
-pub fn setup_timer_isr(timg1: TIMG1) {
+pub fn setup_timer_isr(timg1: TIMG1, tick_rate_hz: u32) {
     let mut timer1 = Timer::new(timg1);
 
+    unsafe {
+        TIMER_DELAY = TICKS_PER_SECOND / tick_rate_hz as u64;
+    }
+
     interrupt::enable(
         Cpu::ProCpu,
         pac::Interrupt::TG1_T0_LEVEL,
         interrupt::CpuInterrupt::Interrupt20LevelPriority2,
     );
     timer1.listen();
-    timer1.start(TIMER_DELAY);
+    timer1.start(unsafe { TIMER_DELAY });
 
     unsafe {
         (&TIMER1).lock(|data| (*data).replace(Some(timer1)));
@@ -134,23 +145,47 @@ pub fn level2_interrupt(context: &mut Context) {
         interrupt::CpuInterrupt::Interrupt20LevelPriority2,
     );
 
-    unsafe {
-        TIME.store(
-            TIME.load(Ordering::Relaxed) + TIMER_DELAY,
-            Ordering::Relaxed,
-        );
-    }
-
     task_switch(context);
 
-    unsafe {
+    restart_timer(next_delay());
+}
+
+/// Starts a new time slice, the time passed in the current one is added to `TIME` since the
+/// counter starts over.
+fn restart_timer(delay: u64) {
+    // a task yielding mustn't be interrupted by the timer while holding the lock
+    critical_section::with(|_| unsafe {
         (&TIMER1).lock(|data| {
             esp_alloc::memory_fence();
 
             let mut timer1 = data.borrow_mut();
             let timer1 = timer1.as_mut().unwrap();
+
+            TIME.store(
+                TIME.load(Ordering::Relaxed) + read_timer_value(),
+                Ordering::Relaxed,
+            );
+
             timer1.clear_interrupt();
-            timer1.start(TIMER_DELAY);
+            timer1.start(delay);
         });
+    });
+}
+
+/// Time until the next task switch, while idle that's when the next task wakes up
+fn next_delay() -> u64 {
+    let slice = unsafe { TIMER_DELAY };
+
+    match crate::preempt::idle_deadline() {
+        Some(deadline) => deadline
+            .saturating_sub(get_systimer_count())
+            .clamp(slice, MAX_IDLE_DELAY.max(slice)),
+        None => slice,
     }
 }
+
+/// Switches to the next task without waiting for the time slice to end
+pub fn yield_task() {
+    // ends the current time slice right away
+    restart_timer(1);
+}
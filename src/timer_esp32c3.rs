@@ -8,33 +8,40 @@ use log::trace;
 
 pub const TICKS_PER_SECOND: u64 = 16_000_000;
 
+/// Task switches per second if not configured otherwise
 #[cfg(debug_assertions)]
-const TIMER_DELAY: u32 = 8_000u32;
+pub const DEFAULT_TICK_RATE_HZ: u32 = 2_000;
 #[cfg(not(debug_assertions))]
-const TIMER_DELAY: u32 = 500u32;
+pub const DEFAULT_TICK_RATE_HZ: u32 = 32_000;
+
+/// Length of a time slice in systimer ticks
+static mut TIMER_DELAY: u64 = TICKS_PER_SECOND / DEFAULT_TICK_RATE_HZ as u64;
+
+/// Longest time without a timer interrupt while idle
+const MAX_IDLE_DELAY: u64 = TICKS_PER_SECOND;
+
+/// An alarm closer than this might be missed while it's set
+const MIN_ALARM_DELAY: u64 = 100;
+
+pub fn setup_timer_isr(systimer: SYSTIMER, tick_rate_hz: u32) {
+    unsafe {
+        TIMER_DELAY = TICKS_PER_SECOND / tick_rate_hz as u64;
+    }
 
-pub fn setup_timer_isr(systimer: &mut SYSTIMER, interrupt_core0: &mut INTERRUPT_CORE0) {
     // set systimer to 0
     systimer.unit0_load_lo.write(|w| unsafe { w.bits(0) });
     systimer.unit0_load_hi.write(|w| unsafe { w.bits(0) });
     systimer.unit0_load.write(|w| unsafe { w.bits(1) });
 
-    // PERIOD_MODE + PERIOD
-    systimer
-        .target0_conf
-        .write(|w| unsafe { w.bits((1 << 30) | TIMER_DELAY) });
-    // LOAD CONF VALUE
-    systimer.comp0_load.write(|w| unsafe { w.bits(1) });
-    // set SYSTIMER_TARGET0_WORK_EN + UNIT0_WORK_EN
-    systimer
-        .conf
-        .write(|w| unsafe { w.bits(1 << 24 | 1 << 30) });
+    // the alarm is set again on every task switch, see `set_alarm`
+    set_alarm(unsafe { TIMER_DELAY });
 
     systimer.int_clr.write(|w| unsafe { w.bits(1 << 0) });
 
     // TARGET0 INT ENA
     systimer.int_ena.write(|w| unsafe { w.bits(1 << 0) });
 
+    let interrupt_core0 = unsafe { &*INTERRUPT_CORE0::ptr() };
     interrupt_core0
         .systimer_target0_int_map
         .write(|w| unsafe { w.bits(10) });
@@ -45,6 +52,11 @@ pub fn setup_timer_isr(systimer: &mut SYSTIMER, interrupt_core0: &mut INTERRUPT_
         .cpu_int_enable
         .write(|w| unsafe { w.bits(1 << 10) }); // ENABLE INT 10
 
+    // FROM_CPU_INTR0 triggers the task switch early, see `yield_task`
+    interrupt_core0
+        .cpu_intr_from_cpu_0_map
+        .write(|w| unsafe { w.bits(10) });
+
     unsafe {
         riscv::interrupt::enable();
     }
@@ -119,7 +131,58 @@ pub fn interrupt10(trap_frame: &mut TrapFrame) {
             .int_clr
             .write(|w| w.bits(1 << 0));
 
+        // clear a yield request
+        (*hal::pac::SYSTEM::ptr())
+            .cpu_intr_from_cpu_0
+            .write(|w| w.bits(0));
+
         task_switch(trap_frame);
+
+        set_alarm(next_delay());
+    }
+}
+
+/// Time until the next task switch, while idle that's when the next task wakes up
+fn next_delay() -> u64 {
+    let slice = unsafe { TIMER_DELAY };
+
+    match crate::preempt::idle_deadline() {
+        Some(deadline) => deadline
+            .saturating_sub(get_systimer_count())
+            .clamp(slice, MAX_IDLE_DELAY.max(slice)),
+        None => slice,
+    }
+}
+
+/// Sets TARGET0 to fire once `delay` ticks from now
+fn set_alarm(delay: u64) {
+    unsafe {
+        let systimer = &(*hal::pac::SYSTIMER::ptr());
+        let target = get_systimer_count() + delay.max(MIN_ALARM_DELAY);
+
+        // UNIT0_WORK_EN, the comparator is off while the target changes
+        systimer.conf.write(|w| w.bits(1 << 30));
+
+        // target mode, compared to UNIT0
+        systimer.target0_conf.write(|w| w.bits(0));
+        systimer
+            .target0_hi
+            .write(|w| w.bits((target >> 32) as u32 & 0xfffff));
+        systimer.target0_lo.write(|w| w.bits(target as u32));
+        // LOAD CONF VALUE
+        systimer.comp0_load.write(|w| w.bits(1));
+
+        // set SYSTIMER_TARGET0_WORK_EN + UNIT0_WORK_EN
+        systimer.conf.write(|w| w.bits(1 << 24 | 1 << 30));
+    }
+}
+
+/// Switches to the next task without waiting for the time slice to end
+pub fn yield_task() {
+    unsafe {
+        (*hal::pac::SYSTEM::ptr())
+            .cpu_intr_from_cpu_0
+            .write(|w| w.bits(1));
     }
 }
 
@@ -0,0 +1,64 @@
+// STATIC_RX_BUF_NUM, DYNAMIC_RX_BUF_NUM, DYNAMIC_TX_BUF_NUM, RX_BA_WIN and MGMT_SBUF_NUM
+// generated by build.rs, set via the ESP_WIFI_* environment variables at build time
+include!(concat!(env!("OUT_DIR"), "/wifi_config.rs"));
+
+/// Configuration of the Wi-Fi driver, applied when it's initialized
+///
+/// The defaults for the buffer numbers can be set at build time via the environment
+/// variables `ESP_WIFI_STATIC_RX_BUF_NUM`, `ESP_WIFI_DYNAMIC_RX_BUF_NUM`,
+/// `ESP_WIFI_DYNAMIC_TX_BUF_NUM`, `ESP_WIFI_RX_BA_WIN` and `ESP_WIFI_MGMT_SBUF_NUM`.
+/// AMPDU is enabled by default with the `ampdu` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WifiConfig {
+    /// Number of statically allocated RX buffers (2 - 25)
+    pub static_rx_buf_num: u8,
+    /// Maximum number of dynamically allocated RX buffers (0 - 1024, 0 means unlimited)
+    pub dynamic_rx_buf_num: u16,
+    /// Maximum number of dynamically allocated TX buffers (1 - 128)
+    pub dynamic_tx_buf_num: u16,
+    /// Number of TX buffers cached for AMSDU (0 or 16 - 128)
+    pub cache_tx_buf_num: u8,
+    /// Accept aggregated MPDUs
+    pub ampdu_rx_enable: bool,
+    /// Send aggregated MPDUs
+    pub ampdu_tx_enable: bool,
+    /// Send aggregated MSDUs, needs `cache_tx_buf_num` to be at least 16
+    pub amsdu_tx_enable: bool,
+    /// Block ack RX window size (2 - 32), only used with `ampdu_rx_enable`
+    pub rx_ba_win: u8,
+    /// Number of management short buffers (6 - 32)
+    pub mgmt_sbuf_num: u8,
+    /// Maximum length of a beacon in soft-AP mode (752 - 1024)
+    pub beacon_max_len: u16,
+}
+
+impl Default for WifiConfig {
+    fn default() -> Self {
+        WifiConfig {
+            static_rx_buf_num: STATIC_RX_BUF_NUM,
+            dynamic_rx_buf_num: DYNAMIC_RX_BUF_NUM,
+            dynamic_tx_buf_num: DYNAMIC_TX_BUF_NUM,
+            cache_tx_buf_num: 0,
+            ampdu_rx_enable: cfg!(feature = "ampdu"),
+            ampdu_tx_enable: cfg!(feature = "ampdu"),
+            amsdu_tx_enable: false,
+            rx_ba_win: RX_BA_WIN,
+            mgmt_sbuf_num: MGMT_SBUF_NUM,
+            beacon_max_len: 752,
+        }
+    }
+}
+
+impl WifiConfig {
+    /// Checks the values are in the ranges the driver accepts
+    pub fn is_valid(&self) -> bool {
+        (2..=25).contains(&self.static_rx_buf_num)
+            && self.dynamic_rx_buf_num <= 1024
+            && (1..=128).contains(&self.dynamic_tx_buf_num)
+            && (self.cache_tx_buf_num == 0 || (16..=128).contains(&self.cache_tx_buf_num))
+            && (!self.amsdu_tx_enable || self.cache_tx_buf_num >= 16)
+            && (2..=32).contains(&self.rx_ba_win)
+            && (6..=32).contains(&self.mgmt_sbuf_num)
+            && (752..=1024).contains(&self.beacon_max_len)
+    }
+}
@@ -0,0 +1,139 @@
+use embedded_hal::prelude::_embedded_hal_blocking_rng_Read;
+
+use crate::binary::include::{esp_wifi_get_mac, esp_wifi_set_mac};
+
+use super::{WifiError, WifiInterface, RANDOM_GENERATOR};
+
+/// What a MAC address is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MacType {
+    Sta,
+    Ap,
+    Bt,
+}
+
+impl MacType {
+    /// The `type` argument of the driver's `read_mac`
+    pub(crate) fn from_raw(type_: u32) -> MacType {
+        match type_ {
+            1 => MacType::Ap,
+            2 => MacType::Bt,
+            _ => MacType::Sta,
+        }
+    }
+}
+
+/// How the per-interface MAC addresses are derived from the base MAC in eFuse
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MacDerivation {
+    /// AP and BT get a locally administered variant of the base MAC, BT has the last
+    /// octet incremented
+    #[default]
+    Local,
+    /// Like ESP-IDF with four universal addresses: STA is the base MAC, AP adds one and
+    /// BT adds two to the last octet
+    Universal,
+}
+
+static mut MAC_DERIVATION: MacDerivation = MacDerivation::Local;
+
+static mut MAC_PRIVACY: bool = false;
+
+/// Selects the derivation, needs to happen before the driver is initialized
+pub(crate) fn set_mac_derivation(derivation: MacDerivation) {
+    critical_section::with(|_| unsafe {
+        MAC_DERIVATION = derivation;
+    });
+}
+
+/// The MAC address burned into eFuse
+pub fn base_mac() -> [u8; 6] {
+    unsafe { crate::wifi::os_adapter::os_adapter_chip_specific::read_base_mac() }
+}
+
+/// Derives the MAC address of `typ` from `base`
+pub fn derive_mac(base: [u8; 6], typ: MacType, derivation: MacDerivation) -> [u8; 6] {
+    let mut mac = base;
+
+    match derivation {
+        MacDerivation::Local => {
+            if typ != MacType::Sta {
+                // flip bits until the locally administered address differs from the base
+                for i in 0..64u8 {
+                    mac[0] = (base[0] | 0x02) ^ (i << 2);
+                    if mac[0] != base[0] {
+                        break;
+                    }
+                }
+            }
+
+            if typ == MacType::Bt {
+                mac[5] = mac[5].wrapping_add(1);
+            }
+        }
+        MacDerivation::Universal => {
+            let offset = match typ {
+                MacType::Sta => 0,
+                MacType::Ap => 1,
+                MacType::Bt => 2,
+            };
+            mac[5] = mac[5].wrapping_add(offset);
+        }
+    }
+
+    mac
+}
+
+/// The MAC address of `typ` with the derivation selected via
+/// [crate::EspWifiInit::with_mac_derivation]
+pub fn default_mac(typ: MacType) -> [u8; 6] {
+    derive_mac(base_mac(), typ, unsafe { MAC_DERIVATION })
+}
+
+/// A random unicast, locally administered MAC address, the RNG is available once the
+/// driver is initialized
+pub fn random_mac() -> Result<[u8; 6], WifiError> {
+    let mut mac = [0u8; 6];
+
+    match unsafe { &mut RANDOM_GENERATOR } {
+        Some(rng) => rng.read(&mut mac).unwrap(),
+        None => return Err(WifiError::NoRandomGenerator),
+    }
+
+    mac[0] = (mac[0] & !0x01) | 0x02;
+    Ok(mac)
+}
+
+/// Sets the MAC address of an interface, the interface has to be enabled by the current
+/// mode and the station mustn't be connected.
+pub fn set_mac(interface: WifiInterface, mac: [u8; 6]) -> Result<(), WifiError> {
+    let res = unsafe { esp_wifi_set_mac(interface.to_wifi_interface_t(), mac.as_ptr()) };
+    if res != 0 {
+        return Err(WifiError::General(res));
+    }
+
+    Ok(())
+}
+
+/// Returns the MAC address an interface currently uses
+pub fn get_mac(interface: WifiInterface) -> Result<[u8; 6], WifiError> {
+    let mut mac = [0u8; 6];
+    let res = unsafe { esp_wifi_get_mac(interface.to_wifi_interface_t(), mac.as_mut_ptr()) };
+    if res != 0 {
+        return Err(WifiError::General(res));
+    }
+
+    Ok(mac)
+}
+
+/// In privacy mode the station uses a new random MAC for every connection, see
+/// [random_mac]. The network interface's hardware address needs to follow it.
+pub fn set_mac_privacy(enabled: bool) {
+    critical_section::with(|_| unsafe {
+        MAC_PRIVACY = enabled;
+    });
+}
+
+pub fn is_mac_privacy() -> bool {
+    critical_section::with(|_| unsafe { MAC_PRIVACY })
+}
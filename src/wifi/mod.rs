@@ -9,6 +9,7 @@ use esp32c3_hal as hal;
 #[cfg(feature = "esp32c3")]
 use esp32c3_hal::Rng;
 
+use core::marker::PhantomData;
 #[doc(hidden)]
 pub use os_adapter::*;
 use smoltcp::phy::{Device, DeviceCapabilities, RxToken, TxToken};
@@ -23,25 +24,38 @@ mod additional_esp32;
 #[cfg(feature = "utils")]
 pub mod utils;
 
+mod config;
+pub use config::WifiConfig;
+
+mod mac;
+pub use mac::*;
+
 use crate::{
     binary::include::{
-        __BindgenBitfieldUnit, esp_err_t, esp_interface_t_ESP_IF_WIFI_STA, esp_supplicant_init,
-        esp_wifi_connect, esp_wifi_init_internal, esp_wifi_internal_free_rx_buffer,
-        esp_wifi_internal_reg_rxcb, esp_wifi_internal_tx, esp_wifi_scan_start, esp_wifi_set_config,
-        esp_wifi_set_country, esp_wifi_set_mode, esp_wifi_set_ps, esp_wifi_set_tx_done_cb,
+        __BindgenBitfieldUnit, esp_err_t, esp_interface_t_ESP_IF_WIFI_STA, esp_supplicant_deinit,
+        esp_supplicant_init, esp_wifi_connect, esp_wifi_deinit_internal, esp_wifi_get_bandwidth,
+        esp_wifi_get_max_tx_power, esp_wifi_get_protocol, esp_wifi_init_internal,
+        esp_wifi_internal_free_rx_buffer, esp_wifi_internal_get_negotiated_bandwidth,
+        esp_wifi_internal_reg_rxcb, esp_wifi_internal_tx, esp_wifi_scan_start,
+        esp_wifi_set_bandwidth, esp_wifi_set_config, esp_wifi_set_country,
+        esp_wifi_set_max_tx_power, esp_wifi_set_mode, esp_wifi_set_protocol, esp_wifi_set_ps,
+        esp_wifi_set_rssi_threshold, esp_wifi_set_tx_done_cb, esp_wifi_sta_get_ap_info,
         esp_wifi_start, esp_wifi_stop, g_wifi_default_wpa_crypto_funcs, wifi_active_scan_time_t,
-        wifi_auth_mode_t_WIFI_AUTH_OPEN, wifi_config_t,
+        wifi_ap_record_t, wifi_auth_mode_t_WIFI_AUTH_OPEN, wifi_bandwidth_t,
+        wifi_bandwidth_t_WIFI_BW_HT20, wifi_bandwidth_t_WIFI_BW_HT40, wifi_config_t,
         wifi_country_policy_t_WIFI_COUNTRY_POLICY_MANUAL, wifi_country_t, wifi_init_config_t,
-        wifi_interface_t_WIFI_IF_STA, wifi_mode_t_WIFI_MODE_STA, wifi_osi_funcs_t,
-        wifi_pmf_config_t, wifi_ps_type_t_WIFI_PS_NONE, wifi_scan_config_t,
-        wifi_scan_method_t_WIFI_FAST_SCAN, wifi_scan_threshold_t, wifi_scan_time_t,
-        wifi_scan_type_t_WIFI_SCAN_TYPE_ACTIVE, wifi_sort_method_t_WIFI_CONNECT_AP_BY_SIGNAL,
-        wifi_sta_config_t, wpa_crypto_funcs_t, ESP_WIFI_OS_ADAPTER_MAGIC,
-        ESP_WIFI_OS_ADAPTER_VERSION, WIFI_INIT_CONFIG_MAGIC,
+        wifi_interface_t, wifi_interface_t_WIFI_IF_AP, wifi_interface_t_WIFI_IF_STA,
+        wifi_mode_t_WIFI_MODE_STA, wifi_osi_funcs_t, wifi_pmf_config_t,
+        wifi_ps_type_t_WIFI_PS_NONE, wifi_scan_config_t, wifi_scan_method_t_WIFI_FAST_SCAN,
+        wifi_scan_threshold_t, wifi_scan_time_t, wifi_scan_type_t_WIFI_SCAN_TYPE_ACTIVE,
+        wifi_sort_method_t_WIFI_CONNECT_AP_BY_SIGNAL, wifi_sta_config_t, wpa_crypto_funcs_t,
+        ESP_ERR_WIFI_NOT_INIT, ESP_WIFI_OS_ADAPTER_MAGIC, ESP_WIFI_OS_ADAPTER_VERSION,
+        WIFI_INIT_CONFIG_MAGIC, WIFI_PROTOCOL_11B, WIFI_PROTOCOL_11G, WIFI_PROTOCOL_11N,
+        WIFI_PROTOCOL_LR,
     },
     compat::queue::SimpleQueue,
+    EspWifiInitialization,
 };
-use crate::{tasks::init_tasks, timer::setup_timer_isr};
 use log::{debug, info};
 
 #[cfg(feature = "dump_packets")]
@@ -56,125 +70,56 @@ struct DataFrame {
 
 static mut DATA_QUEUE_RX: Option<SimpleQueue<DataFrame, 3>> = None;
 
-pub static mut TX_BUFFER: [u8; 2500] = [0u8; 2500]; // should be a queue
-pub static mut TX_QUEUED: bool = false;
-pub static mut TX_QUEUED_DATA_LEN: u16 = 0;
+static mut TX_BUFFER: [u8; 2500] = [0u8; 2500]; // should be a queue
+static mut TX_QUEUED: bool = false;
+static mut TX_QUEUED_DATA_LEN: u16 = 0;
 
 static mut RANDOM_GENERATOR: Option<Rng> = None;
 
 #[derive(Debug, Clone, Copy)]
 pub enum WifiError {
     General(i32),
+    /// A random MAC was requested before the driver got the RNG
+    NoRandomGenerator,
 }
 
-#[allow(unused)]
-static mut BLE_ENABLED: bool = false;
-
-#[cfg(feature = "esp32c3")]
-/// Initialize for using WiFi
-/// This will initialize internals and also initialize WiFi
-pub fn initialize(
-    systimer: &mut esp32c3_hal::pac::SYSTIMER,
-    interrupt_core0: &mut esp32c3_hal::pac::INTERRUPT_CORE0,
-    rng: hal::pac::RNG,
-) -> Result<(), WifiError> {
-    init_rng(rng);
-    init_tasks();
-    setup_timer_isr(systimer, interrupt_core0);
-    wifi_set_log_verbose();
-    init_clocks();
-    init_buffer();
-    let res = wifi_init();
-    if res != 0 {
-        return Err(WifiError::General(res));
-    }
-    let res = wifi_start();
-    if res != 0 {
-        return Err(WifiError::General(res));
-    }
-
-    Ok(())
-}
-
-#[cfg(feature = "esp32c3")]
-/// Initialize for using Bluetooth LE
-/// This will just initialize internals.
-pub fn initialize_ble(
-    systimer: &mut esp32c3_hal::pac::SYSTIMER,
-    interrupt_core0: &mut esp32c3_hal::pac::INTERRUPT_CORE0,
-    rng: hal::pac::RNG,
-) -> Result<(), WifiError> {
-    init_rng(rng);
-    init_tasks();
-    setup_timer_isr(systimer, interrupt_core0);
-    wifi_set_log_verbose();
-    init_clocks();
-    init_buffer();
+/// Set when Bluetooth LE is initialized, the PHY is configured for both radios then
+pub(crate) static mut BLE_ENABLED: bool = false;
 
+pub(crate) fn init_buffer() {
     unsafe {
-        BLE_ENABLED = true;
-    }
-
-    Ok(())
-}
-
-#[cfg(feature = "esp32")]
-/// Initialize for using WiFi
-/// This will initialize internals and also initialize WiFi
-pub fn initialize(timg1: esp32_hal::pac::TIMG1, rng: hal::pac::RNG) -> Result<(), WifiError> {
-    init_rng(rng);
-    init_tasks();
-    setup_timer_isr(timg1);
-    wifi_set_log_verbose();
-    init_clocks();
-    init_buffer();
-    let res = wifi_init();
-    if res != 0 {
-        return Err(WifiError::General(res));
-    }
-    let res = wifi_start();
-    if res != 0 {
-        return Err(WifiError::General(res));
-    }
-
-    Ok(())
-}
-
-#[cfg(feature = "esp32")]
-/// Initialize for using Bluetooth LE
-/// This will just initialize internals.
-pub fn initialize_ble(timg1: esp32_hal::pac::TIMG1, rng: hal::pac::RNG) -> Result<(), WifiError> {
-    init_rng(rng);
-    init_tasks();
-    setup_timer_isr(timg1);
-    wifi_set_log_verbose();
-    init_clocks();
-    init_buffer();
-
-    unsafe {
-        BLE_ENABLED = true;
+        DATA_QUEUE_RX = Some(SimpleQueue::new());
+        TX_QUEUED = false;
     }
-
-    Ok(())
 }
 
-pub fn init_buffer() {
+/// Applies the configuration, has to happen before [wifi_init]
+pub(crate) fn configure(config: &WifiConfig) {
     unsafe {
-        DATA_QUEUE_RX = Some(SimpleQueue::new());
+        G_CONFIG.static_rx_buf_num = config.static_rx_buf_num as i32;
+        G_CONFIG.dynamic_rx_buf_num = config.dynamic_rx_buf_num as i32;
+        G_CONFIG.dynamic_tx_buf_num = config.dynamic_tx_buf_num as i32;
+        G_CONFIG.cache_tx_buf_num = config.cache_tx_buf_num as i32;
+        G_CONFIG.ampdu_rx_enable = config.ampdu_rx_enable as i32;
+        G_CONFIG.ampdu_tx_enable = config.ampdu_tx_enable as i32;
+        G_CONFIG.amsdu_tx_enable = config.amsdu_tx_enable as i32;
+        G_CONFIG.rx_ba_win = config.rx_ba_win as i32;
+        G_CONFIG.mgmt_sbuf_num = config.mgmt_sbuf_num as i32;
+        G_CONFIG.beacon_max_len = config.beacon_max_len as i32;
     }
 }
 
-pub fn init_rng(rng: hal::pac::RNG) {
+pub(crate) fn init_rng(rng: hal::pac::RNG) {
     unsafe {
         RANDOM_GENERATOR = Some(Rng::new(rng));
     }
 }
 
-pub fn init_clocks() {
+pub(crate) fn init_clocks() {
     crate::wifi::os_adapter::os_adapter_chip_specific::init_clocks();
 }
 
-pub fn wifi_set_log_verbose() {
+pub(crate) fn wifi_set_log_verbose() {
     #[cfg(feature = "wifi_logs")]
     unsafe {
         use crate::binary::include::{esp_wifi_internal_set_log_level, wifi_log_level_t};
@@ -375,13 +320,12 @@ static mut G_CONFIG: wifi_init_config_t = wifi_init_config_t {
     magic: WIFI_INIT_CONFIG_MAGIC as i32,
 };
 
+/// The station's MAC address derived from eFuse, use [get_mac] for the one in use
 pub fn get_sta_mac(mac: &mut [u8; 6]) {
-    unsafe {
-        read_mac(mac as *mut u8, 0);
-    }
+    *mac = default_mac(MacType::Sta);
 }
 
-pub fn wifi_init() -> i32 {
+pub(crate) fn wifi_init() -> i32 {
     unsafe {
         G_CONFIG.wpa_crypto_funcs = g_wifi_default_wpa_crypto_funcs;
         G_CONFIG.feature_caps = g_wifi_feature_caps;
@@ -499,7 +443,7 @@ unsafe extern "C" fn esp_wifi_tx_done_cb(
     debug!("esp_wifi_tx_done_cb");
 }
 
-pub fn wifi_start() -> i32 {
+pub(crate) fn wifi_start() -> i32 {
     unsafe {
         let res = esp_wifi_start();
         if res != 0 {
@@ -533,6 +477,8 @@ pub fn wifi_start_scan() -> i32 {
     unsafe { esp_wifi_scan_start(&scan_config, true) }
 }
 
+/// Connects the station, in privacy mode with a new random MAC. An interface created by
+/// [utils::create_network_interface] needs [utils::update_hardware_address] then.
 pub fn wifi_connect(ssid: &str, password: &str) -> i32 {
     unsafe {
         let mut cfg = wifi_config_t {
@@ -566,6 +512,17 @@ pub fn wifi_connect(ssid: &str, password: &str) -> i32 {
             return res;
         }
 
+        if is_mac_privacy() {
+            let res = match random_mac().and_then(|mac| set_mac(WifiInterface::Sta, mac)) {
+                Ok(()) => 0,
+                Err(WifiError::General(res)) => res,
+                Err(WifiError::NoRandomGenerator) => ESP_ERR_WIFI_NOT_INIT as i32,
+            };
+            if res != 0 {
+                return res;
+            }
+        }
+
         esp_wifi_connect()
     }
 }
@@ -574,17 +531,278 @@ pub fn wifi_stop() -> i32 {
     unsafe { esp_wifi_stop() }
 }
 
+/// A Wi-Fi interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WifiInterface {
+    Sta,
+    Ap,
+}
+
+impl WifiInterface {
+    fn to_wifi_interface_t(&self) -> wifi_interface_t {
+        match self {
+            WifiInterface::Sta => wifi_interface_t_WIFI_IF_STA,
+            WifiInterface::Ap => wifi_interface_t_WIFI_IF_AP,
+        }
+    }
+}
+
+/// The 802.11 protocols an interface uses, the default is 11b/g/n
+///
+/// `long_range` is Espressif's LR mode which only works between Espressif devices. It
+/// trades data rate for range, with only `long_range` set the interface uses LR exclusively.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Protocols {
+    pub ieee80211b: bool,
+    pub ieee80211g: bool,
+    pub ieee80211n: bool,
+    pub long_range: bool,
+}
+
+impl Default for Protocols {
+    fn default() -> Self {
+        Protocols {
+            ieee80211b: true,
+            ieee80211g: true,
+            ieee80211n: true,
+            long_range: false,
+        }
+    }
+}
+
+impl Protocols {
+    fn to_bitmap(&self) -> u8 {
+        let mut bitmap = 0;
+        if self.ieee80211b {
+            bitmap |= WIFI_PROTOCOL_11B;
+        }
+        if self.ieee80211g {
+            bitmap |= WIFI_PROTOCOL_11G;
+        }
+        if self.ieee80211n {
+            bitmap |= WIFI_PROTOCOL_11N;
+        }
+        if self.long_range {
+            bitmap |= WIFI_PROTOCOL_LR;
+        }
+        bitmap as u8
+    }
+
+    fn from_bitmap(bitmap: u8) -> Protocols {
+        let bitmap = bitmap as u32;
+        Protocols {
+            ieee80211b: bitmap & WIFI_PROTOCOL_11B != 0,
+            ieee80211g: bitmap & WIFI_PROTOCOL_11G != 0,
+            ieee80211n: bitmap & WIFI_PROTOCOL_11N != 0,
+            long_range: bitmap & WIFI_PROTOCOL_LR != 0,
+        }
+    }
+}
+
+/// Channel bandwidth, HT40 needs 802.11n
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bandwidth {
+    Ht20,
+    Ht40,
+}
+
+impl Bandwidth {
+    fn to_wifi_bandwidth_t(&self) -> wifi_bandwidth_t {
+        match self {
+            Bandwidth::Ht20 => wifi_bandwidth_t_WIFI_BW_HT20,
+            Bandwidth::Ht40 => wifi_bandwidth_t_WIFI_BW_HT40,
+        }
+    }
+
+    fn from_wifi_bandwidth_t(bw: wifi_bandwidth_t) -> Bandwidth {
+        if bw == wifi_bandwidth_t_WIFI_BW_HT40 {
+            Bandwidth::Ht40
+        } else {
+            Bandwidth::Ht20
+        }
+    }
+}
+
+/// Selects the protocols of an interface, the interface needs to be enabled by the
+/// current mode.
+pub fn set_protocols(interface: WifiInterface, protocols: Protocols) -> Result<(), WifiError> {
+    let res =
+        unsafe { esp_wifi_set_protocol(interface.to_wifi_interface_t(), protocols.to_bitmap()) };
+    if res != 0 {
+        return Err(WifiError::General(res));
+    }
+
+    Ok(())
+}
+
+/// Returns the protocols of an interface
+pub fn get_protocols(interface: WifiInterface) -> Result<Protocols, WifiError> {
+    let mut bitmap = 0u8;
+    let res = unsafe { esp_wifi_get_protocol(interface.to_wifi_interface_t(), &mut bitmap) };
+    if res != 0 {
+        return Err(WifiError::General(res));
+    }
+
+    Ok(Protocols::from_bitmap(bitmap))
+}
+
+/// Sets the bandwidth of an interface
+pub fn set_bandwidth(interface: WifiInterface, bandwidth: Bandwidth) -> Result<(), WifiError> {
+    let res = unsafe {
+        esp_wifi_set_bandwidth(
+            interface.to_wifi_interface_t(),
+            bandwidth.to_wifi_bandwidth_t(),
+        )
+    };
+    if res != 0 {
+        return Err(WifiError::General(res));
+    }
+
+    Ok(())
+}
+
+/// Returns the configured bandwidth of an interface
+pub fn get_bandwidth(interface: WifiInterface) -> Result<Bandwidth, WifiError> {
+    let mut bw: wifi_bandwidth_t = 0;
+    let res = unsafe { esp_wifi_get_bandwidth(interface.to_wifi_interface_t(), &mut bw) };
+    if res != 0 {
+        return Err(WifiError::General(res));
+    }
+
+    Ok(Bandwidth::from_wifi_bandwidth_t(bw))
+}
+
+/// Returns the bandwidth negotiated with a peer once the connection is established
+///
+/// `aid` is the association id of the peer, it's only relevant for the AP interface.
+pub fn get_negotiated_bandwidth(interface: WifiInterface, aid: u8) -> Result<Bandwidth, WifiError> {
+    let mut bw = 0u8;
+    let res = unsafe {
+        esp_wifi_internal_get_negotiated_bandwidth(interface.to_wifi_interface_t(), aid, &mut bw)
+    };
+    if res != 0 {
+        return Err(WifiError::General(res));
+    }
+
+    Ok(Bandwidth::from_wifi_bandwidth_t(bw as wifi_bandwidth_t))
+}
+
+/// Limits the TX power, the driver accepts 2 - 20 dBm in 0.25 dBm steps.
+///
+/// Needs Wi-Fi to be started.
+pub fn set_max_tx_power(dbm: f32) -> Result<(), WifiError> {
+    // the driver counts in 0.25 dBm
+    let res = unsafe { esp_wifi_set_max_tx_power((dbm * 4.0) as i8) };
+    if res != 0 {
+        return Err(WifiError::General(res));
+    }
+
+    Ok(())
+}
+
+/// Returns the maximum TX power in dBm
+pub fn get_max_tx_power() -> Result<f32, WifiError> {
+    let mut power = 0i8;
+    let res = unsafe { esp_wifi_get_max_tx_power(&mut power) };
+    if res != 0 {
+        return Err(WifiError::General(res));
+    }
+
+    Ok(power as f32 / 4.0)
+}
+
+/// Arms the RSSI-low event, it fires once when the RSSI of the connected AP drops below
+/// `rssi` (in dBm). Poll it via [take_rssi_low_event] and call this again to re-arm it.
+pub fn set_rssi_threshold(rssi: i32) -> Result<(), WifiError> {
+    let res = unsafe { esp_wifi_set_rssi_threshold(rssi) };
+    if res != 0 {
+        return Err(WifiError::General(res));
+    }
+
+    Ok(())
+}
+
+/// Information about the AP the station is connected to
+#[derive(Debug, Clone, Copy)]
+pub struct ApInfo {
+    pub bssid: [u8; 6],
+    ssid: [u8; 33],
+    pub channel: u8,
+    /// Signal strength in dBm
+    pub rssi: i8,
+}
+
+impl ApInfo {
+    pub fn ssid(&self) -> &str {
+        let len = self
+            .ssid
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(self.ssid.len());
+        core::str::from_utf8(&self.ssid[..len]).unwrap_or("")
+    }
+}
+
+/// Returns the info of the AP the station is connected to, including the current RSSI
+pub fn sta_get_ap_info() -> Result<ApInfo, WifiError> {
+    let mut record: wifi_ap_record_t = unsafe { core::mem::zeroed() };
+    let res = unsafe { esp_wifi_sta_get_ap_info(&mut record) };
+    if res != 0 {
+        return Err(WifiError::General(res));
+    }
+
+    Ok(ApInfo {
+        bssid: record.bssid,
+        ssid: record.ssid,
+        channel: record.primary,
+        rssi: record.rssi,
+    })
+}
+
+/// Stops Wi-Fi and deinitializes the driver, [wifi_init] can be called again afterwards
+pub(crate) fn wifi_deinit() -> i32 {
+    unsafe {
+        // fails if Wi-Fi isn't started, the driver needs to be deinitialized anyway
+        let res = esp_wifi_stop();
+        if res != 0 {
+            debug!("esp_wifi_stop returned {}", res);
+        }
+
+        let res = esp_supplicant_deinit();
+        if res != 0 {
+            return res;
+        }
+
+        let res = esp_wifi_deinit_internal();
+        if res != 0 {
+            return res;
+        }
+
+        WIFI_STATE = -1;
+        RSSI_LOW = None;
+    }
+
+    0
+}
+
 /// A wifi device implementing smoltcp's Device trait.
-pub struct WifiDevice {}
+///
+/// It borrows the initialization, the radio can't be deinitialized while it's in use.
+pub struct WifiDevice<'d> {
+    _init: PhantomData<&'d EspWifiInitialization>,
+}
+
+impl<'d> WifiDevice<'d> {
+    /// Creates the device, Wi-Fi has to be enabled in the initialization.
+    pub fn new(init: &'d EspWifiInitialization) -> WifiDevice<'d> {
+        assert!(init.is_wifi(), "Wi-Fi is not initialized");
 
-impl WifiDevice {
-    pub fn new() -> WifiDevice {
-        WifiDevice {}
+        WifiDevice { _init: PhantomData }
     }
 }
 
 // see https://docs.rs/smoltcp/0.7.1/smoltcp/phy/index.html
-impl<'a> Device<'a> for WifiDevice {
+impl<'a, 'd> Device<'a> for WifiDevice<'d> {
     type RxToken = WifiRxToken;
 
     type TxToken = WifiTxToken;
@@ -673,6 +891,7 @@ impl TxToken for WifiTxToken {
                 if !TX_QUEUED {
                     TX_QUEUED_DATA_LEN = len as u16;
                     TX_QUEUED = true;
+                    crate::tasks::wake_timer_task();
                     res
                 } else {
                     Err(smoltcp::Error::Exhausted)
@@ -683,6 +902,11 @@ impl TxToken for WifiTxToken {
     }
 }
 
+/// Data is waiting for [send_data_if_needed]
+pub(crate) fn tx_pending() -> bool {
+    critical_section::with(|_| unsafe { TX_QUEUED })
+}
+
 pub fn send_data_if_needed() {
     let to_send = critical_section::with(|_| unsafe {
         if TX_QUEUED {
@@ -10,20 +10,26 @@ use crate::{
     binary::include::*,
     compat::{
         common::{
-            create_recursive_mutex, create_wifi_queue, lock_mutex, receive_queued, sem_create,
-            sem_delete, sem_give, sem_take, send_queued, syslog, thread_sem_get, unlock_mutex,
-            StrBuf,
+            create_queue, create_recursive_mutex, create_wifi_queue, delete_mutex, delete_queue,
+            delete_wifi_queue, lock_mutex, number_of_messages_in_queue, receive_queued, sem_create,
+            sem_delete, sem_give, sem_take, send_queued, send_queued_to_front, syslog,
+            thread_sem_get, unlock_mutex, StrBuf,
         },
+        event_group::{
+            clear_event_bits, create_event_group, delete_event_group, set_event_bits,
+            wait_event_bits,
+        },
+        task::{create_task, current_task_handle, delete_task},
         timer_compat::{
-            compat_esp_timer_create, compat_timer_arm, compat_timer_arm_us, compat_timer_disarm,
-            compat_timer_done, compat_timer_setfn,
+            compat_esp_timer_create, compat_esp_timer_delete, compat_esp_timer_get_next_alarm,
+            compat_esp_timer_start, compat_esp_timer_stop, compat_timer_arm, compat_timer_arm_us,
+            compat_timer_disarm, compat_timer_done, compat_timer_setfn,
         },
-        work_queue::queue_work,
     },
     wifi::RANDOM_GENERATOR,
 };
 
-pub static mut WIFI_STATE: i32 = -1;
+pub(crate) static mut WIFI_STATE: i32 = -1;
 
 pub fn is_connected() -> bool {
     unsafe { WIFI_STATE == wifi_event_t_WIFI_EVENT_STA_CONNECTED as i32 }
@@ -51,6 +57,14 @@ pub fn get_wifi_state() -> WifiState {
     }
 }
 
+pub(crate) static mut RSSI_LOW: Option<i32> = None;
+
+/// Returns the RSSI reported by the last RSSI-low event since this was called, see
+/// [crate::wifi::set_rssi_threshold].
+pub fn take_rssi_low_event() -> Option<i32> {
+    critical_section::with(|_| unsafe { RSSI_LOW.take() })
+}
+
 /****************************************************************************
  * Name: esp_event_send_internal
  *
@@ -99,6 +113,11 @@ pub unsafe extern "C" fn esp_event_send_internal(
         WIFI_STATE = event_id;
     }
 
+    if event_id as u32 == wifi_event_t_WIFI_EVENT_STA_BSS_RSSI_LOW && !event_data.is_null() {
+        let event = event_data as *const wifi_event_bss_rssi_low_t;
+        RSSI_LOW = Some((*event).rssi);
+    }
+
     memory_fence();
 
     0
@@ -163,7 +182,7 @@ pub unsafe extern "C" fn clear_intr(_intr_source: u32, _intr_num: u32) {
     debug!("clear_intr called {} {}", _intr_source, _intr_num);
 }
 
-pub static mut ISR_INTERRUPT_1: (
+pub(crate) static mut ISR_INTERRUPT_1: (
     *mut crate::binary::c_types::c_void,
     *mut crate::binary::c_types::c_void,
 ) = (core::ptr::null_mut(), core::ptr::null_mut());
@@ -339,7 +358,7 @@ pub unsafe extern "C" fn wifi_int_restore(
  * Name: esp_task_yield_from_isr
  *
  * Description:
- *   Do nothing in NuttX
+ *   Switch to the next task once the ISR returned
  *
  * Input Parameters:
  *   None
@@ -349,8 +368,8 @@ pub unsafe extern "C" fn wifi_int_restore(
  *
  ****************************************************************************/
 pub unsafe extern "C" fn task_yield_from_isr() {
-    // original: /* Do nothing */
-    trace!("task_yield_from_isr")
+    trace!("task_yield_from_isr");
+    crate::timer::yield_task();
 }
 
 /****************************************************************************
@@ -492,8 +511,8 @@ pub unsafe extern "C" fn recursive_mutex_create() -> *mut crate::binary::c_types
  *   None
  *
  ****************************************************************************/
-pub unsafe extern "C" fn mutex_delete(_mutex: *mut crate::binary::c_types::c_void) {
-    todo!("mutex_delete")
+pub unsafe extern "C" fn mutex_delete(mutex: *mut crate::binary::c_types::c_void) {
+    delete_mutex(mutex);
 }
 
 /****************************************************************************
@@ -545,10 +564,10 @@ pub unsafe extern "C" fn mutex_unlock(mutex: *mut crate::binary::c_types::c_void
  *
  ****************************************************************************/
 pub unsafe extern "C" fn queue_create(
-    _queue_len: u32,
-    _item_size: u32,
+    queue_len: u32,
+    item_size: u32,
 ) -> *mut crate::binary::c_types::c_void {
-    todo!("queue_create")
+    create_queue(queue_len, item_size)
 }
 
 /****************************************************************************
@@ -564,8 +583,8 @@ pub unsafe extern "C" fn queue_create(
  *   None
  *
  ****************************************************************************/
-pub unsafe extern "C" fn queue_delete(_queue: *mut crate::binary::c_types::c_void) {
-    todo!("queue_delete")
+pub unsafe extern "C" fn queue_delete(queue: *mut crate::binary::c_types::c_void) {
+    delete_queue(queue);
 }
 
 /****************************************************************************
@@ -613,7 +632,8 @@ pub unsafe extern "C" fn queue_send_from_isr(
     _hptw: *mut crate::binary::c_types::c_void,
 ) -> i32 {
     trace!("queue_send_from_isr");
-    queue_send(queue, item, 1000)
+    // an ISR mustn't wait for space in the queue
+    queue_send(queue, item, 0)
 }
 
 /****************************************************************************
@@ -632,11 +652,11 @@ pub unsafe extern "C" fn queue_send_from_isr(
  *
  ****************************************************************************/
 pub unsafe extern "C" fn queue_send_to_back(
-    _queue: *mut crate::binary::c_types::c_void,
-    _item: *mut crate::binary::c_types::c_void,
-    _block_time_tick: u32,
+    queue: *mut crate::binary::c_types::c_void,
+    item: *mut crate::binary::c_types::c_void,
+    block_time_tick: u32,
 ) -> i32 {
-    todo!("queue_send_to_back")
+    send_queued(queue, item, block_time_tick)
 }
 
 /****************************************************************************
@@ -655,11 +675,11 @@ pub unsafe extern "C" fn queue_send_to_back(
  *
  ****************************************************************************/
 pub unsafe extern "C" fn queue_send_to_front(
-    _queue: *mut crate::binary::c_types::c_void,
-    _item: *mut crate::binary::c_types::c_void,
-    _block_time_tick: u32,
+    queue: *mut crate::binary::c_types::c_void,
+    item: *mut crate::binary::c_types::c_void,
+    block_time_tick: u32,
 ) -> i32 {
-    todo!("queue_send_to_front")
+    send_queued_to_front(queue, item, block_time_tick)
 }
 
 /****************************************************************************
@@ -698,75 +718,114 @@ pub unsafe extern "C" fn queue_recv(
  *   Message number
  *
  ****************************************************************************/
-pub unsafe extern "C" fn queue_msg_waiting(_queue: *mut crate::binary::c_types::c_void) -> u32 {
-    todo!("queue_msg_waiting")
+pub unsafe extern "C" fn queue_msg_waiting(queue: *mut crate::binary::c_types::c_void) -> u32 {
+    number_of_messages_in_queue(queue)
 }
 
 /****************************************************************************
  * Name: esp_event_group_create
  *
  * Description:
- *   Don't support
+ *   Create event group
+ *
+ * Returned Value:
+ *   Event group data pointer
  *
  ****************************************************************************/
 pub unsafe extern "C" fn event_group_create() -> *mut crate::binary::c_types::c_void {
-    todo!("event_group_create")
+    create_event_group()
 }
 
 /****************************************************************************
  * Name: esp_event_group_delete
  *
  * Description:
- *   Don't support
+ *   Delete event group
+ *
+ * Input Parameters:
+ *   event - Event group data pointer
+ *
+ * Returned Value:
+ *   None
  *
  ****************************************************************************/
-pub unsafe extern "C" fn event_group_delete(_event: *mut crate::binary::c_types::c_void) {
-    todo!("event_group_delete")
+pub unsafe extern "C" fn event_group_delete(event: *mut crate::binary::c_types::c_void) {
+    delete_event_group(event);
 }
 
 /****************************************************************************
  * Name: esp_event_group_set_bits
  *
  * Description:
- *   Don't support
+ *   Set event group bits
+ *
+ * Input Parameters:
+ *   event - Event group data pointer
+ *   bits  - Bits to set
+ *
+ * Returned Value:
+ *   Event group bits after setting
  *
  ****************************************************************************/
 pub unsafe extern "C" fn event_group_set_bits(
-    _event: *mut crate::binary::c_types::c_void,
-    _bits: u32,
+    event: *mut crate::binary::c_types::c_void,
+    bits: u32,
 ) -> u32 {
-    todo!("event_group_set_bits")
+    set_event_bits(event, bits)
 }
 
 /****************************************************************************
  * Name: esp_event_group_clear_bits
  *
  * Description:
- *   Don't support
+ *   Clear event group bits
+ *
+ * Input Parameters:
+ *   event - Event group data pointer
+ *   bits  - Bits to clear
+ *
+ * Returned Value:
+ *   Event group bits before clearing
  *
  ****************************************************************************/
 pub unsafe extern "C" fn event_group_clear_bits(
-    _event: *mut crate::binary::c_types::c_void,
-    _bits: u32,
+    event: *mut crate::binary::c_types::c_void,
+    bits: u32,
 ) -> u32 {
-    todo!("event_group_clear_bits")
+    clear_event_bits(event, bits)
 }
 
 /****************************************************************************
  * Name: esp_event_group_wait_bits
  *
  * Description:
- *   Don't support
+ *   Wait for any or all of the event group bits within a certain period of time
+ *
+ * Input Parameters:
+ *   event             - Event group data pointer
+ *   bits_to_wait_for  - Bits to wait for
+ *   clear_on_exit     - Clear the bits waited for when the wait succeeds
+ *   wait_for_all_bits - Wait for all bits instead of any of them
+ *   block_time_tick   - Wait ticks
+ *
+ * Returned Value:
+ *   Event group bits when the wait succeeded or timed out
  *
  ****************************************************************************/
 pub unsafe extern "C" fn event_group_wait_bits(
-    _event: *mut crate::binary::c_types::c_void,
-    _bits_to_wait_for: u32,
-    _clear_on_exit: crate::binary::c_types::c_int,
-    _wait_for_all_bits: crate::binary::c_types::c_int,
-    _block_time_tick: u32,
+    event: *mut crate::binary::c_types::c_void,
+    bits_to_wait_for: u32,
+    clear_on_exit: crate::binary::c_types::c_int,
+    wait_for_all_bits: crate::binary::c_types::c_int,
+    block_time_tick: u32,
 ) -> u32 {
-    todo!("event_group_wait_bits")
+    wait_event_bits(
+        event,
+        bits_to_wait_for,
+        clear_on_exit != 0,
+        wait_for_all_bits != 0,
+        block_time_tick,
+    )
 }
 
 /****************************************************************************
@@ -809,18 +868,8 @@ pub unsafe extern "C" fn task_create_pinned_to_core(
         core_id
     );
 
-    *(task_handle as *mut usize) = 0; // we will run it in task 0
-
-    queue_work(
-        task_func,
-        name,
-        stack_depth,
-        param,
-        prio,
-        task_handle,
-        core_id,
-    );
-    1
+    // there is only one core
+    create_task(task_func, stack_depth, param, prio, task_handle)
 }
 
 /****************************************************************************
@@ -843,14 +892,24 @@ pub unsafe extern "C" fn task_create_pinned_to_core(
  *
  ****************************************************************************/
 pub unsafe extern "C" fn task_create(
-    _task_func: *mut crate::binary::c_types::c_void,
-    _name: *const crate::binary::c_types::c_char,
-    _stack_depth: u32,
-    _param: *mut crate::binary::c_types::c_void,
-    _prio: u32,
-    _task_handle: *mut crate::binary::c_types::c_void,
+    task_func: *mut crate::binary::c_types::c_void,
+    name: *const crate::binary::c_types::c_char,
+    stack_depth: u32,
+    param: *mut crate::binary::c_types::c_void,
+    prio: u32,
+    task_handle: *mut crate::binary::c_types::c_void,
 ) -> i32 {
-    todo!("task_create");
+    trace!(
+        "task_create task_func {:p} name {} stack_depth {} param {:p} prio {}, task_handle {:p}",
+        task_func,
+        StrBuf::from(name).as_str_ref(),
+        stack_depth,
+        param,
+        prio,
+        task_handle
+    );
+
+    create_task(task_func, stack_depth, param, prio, task_handle)
 }
 
 /****************************************************************************
@@ -867,8 +926,10 @@ pub unsafe extern "C" fn task_create(
  *   None
  *
  ****************************************************************************/
-pub unsafe extern "C" fn task_delete(_task_handle: *mut crate::binary::c_types::c_void) {
-    todo!("task_delete")
+pub unsafe extern "C" fn task_delete(task_handle: *mut crate::binary::c_types::c_void) {
+    trace!("task_delete {:p}", task_handle);
+
+    delete_task(task_handle);
 }
 
 /****************************************************************************
@@ -886,14 +947,7 @@ pub unsafe extern "C" fn task_delete(_task_handle: *mut crate::binary::c_types::
  ****************************************************************************/
 pub unsafe extern "C" fn task_delay(tick: u32) {
     trace!("task_delay tick {}", tick);
-    let mut now = crate::timer::get_systimer_count();
-    let timeout = now + tick as u64;
-    loop {
-        if now > timeout {
-            break;
-        }
-        now = crate::timer::get_systimer_count();
-    }
+    crate::preempt::task_sleep(tick as u64);
 }
 
 /****************************************************************************
@@ -928,7 +982,7 @@ pub unsafe extern "C" fn task_ms_to_tick(ms: u32) -> i32 {
  *
  ****************************************************************************/
 pub unsafe extern "C" fn task_get_current_task() -> *mut crate::binary::c_types::c_void {
-    let res = crate::preempt::preempt::current_task() as *mut crate::binary::c_types::c_void;
+    let res = current_task_handle();
     trace!("task get current task - return {:p}", res);
 
     res
@@ -1093,6 +1147,8 @@ pub unsafe extern "C" fn wifi_apb80m_release() {
     trace!("wifi_apb80m_release - no-op")
 }
 
+static mut PHY_ACCESS_REF: u32 = 0;
+
 /****************************************************************************
  * Name: esp32c3_phy_disable
  *
@@ -1107,7 +1163,11 @@ pub unsafe extern "C" fn wifi_apb80m_release() {
  *
  ****************************************************************************/
 pub unsafe extern "C" fn phy_disable() {
-    trace!("phy_disable")
+    trace!("phy_disable");
+
+    critical_section::with(|_| {
+        PHY_ACCESS_REF = PHY_ACCESS_REF.saturating_sub(1);
+    });
 }
 
 /****************************************************************************
@@ -1127,7 +1187,15 @@ pub unsafe extern "C" fn phy_enable() {
     // quite some code needed here
     trace!("phy_enable - not fully implemented");
 
-    crate::wifi::os_adapter::os_adapter_chip_specific::phy_enable();
+    // Wi-Fi and BLE both enable the PHY, only the first one initializes it
+    let first = critical_section::with(|_| {
+        PHY_ACCESS_REF += 1;
+        PHY_ACCESS_REF == 1
+    });
+
+    if first {
+        crate::wifi::os_adapter::os_adapter_chip_specific::phy_enable();
+    }
 }
 
 /****************************************************************************
@@ -1162,7 +1230,10 @@ pub unsafe extern "C" fn phy_update_country_info(
 pub unsafe extern "C" fn read_mac(mac: *mut u8, type_: u32) -> crate::binary::c_types::c_int {
     trace!("read_mac {:p} {}", mac, type_);
 
-    crate::wifi::os_adapter::os_adapter_chip_specific::read_mac(mac, type_)
+    let derived = crate::wifi::default_mac(crate::wifi::MacType::from_raw(type_));
+    core::ptr::copy_nonoverlapping(derived.as_ptr(), mac, derived.len());
+
+    0
 }
 
 /****************************************************************************
@@ -1361,7 +1432,8 @@ pub unsafe extern "C" fn wifi_rtc_disable_iso() {
 #[no_mangle]
 pub unsafe extern "C" fn esp_timer_get_time() -> i64 {
     trace!("esp_timer_get_time");
-    (crate::timer::get_systimer_count() / crate::timer::TICKS_PER_SECOND / 1_000) as i64
+    // microseconds, the coexistence scheduler relies on it
+    (crate::timer::get_systimer_count() / (crate::timer::TICKS_PER_SECOND / 1_000_000)) as i64
 }
 
 /****************************************************************************
@@ -1951,32 +2023,41 @@ pub unsafe extern "C" fn wifi_create_queue(
  *
  ****************************************************************************/
 pub unsafe extern "C" fn wifi_delete_queue(queue: *mut crate::binary::c_types::c_void) {
-    trace!(
-        "wifi_delete_queue {:p} - not implemented - doing nothing",
-        queue
-    );
+    delete_wifi_queue(queue);
 }
 
 /****************************************************************************
  * Name: wifi_coex_init
  *
  * Description:
- *   Don't support
+ *   Init software coexist
  *
  ****************************************************************************/
 pub unsafe extern "C" fn coex_init() -> crate::binary::c_types::c_int {
-    trace!("coex_init - not implemented");
-    0
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_init()
+    }
+
+    #[cfg(not(feature = "coex"))]
+    {
+        trace!("coex_init - not implemented");
+        0
+    }
 }
 
 /****************************************************************************
  * Name: wifi_coex_deinit
  *
  * Description:
- *   Don't support
+ *   De-init software coexist
  *
  ****************************************************************************/
 pub unsafe extern "C" fn coex_deinit() {
+    #[cfg(feature = "coex")]
+    crate::coex::coex_deinit();
+
+    #[cfg(not(feature = "coex"))]
     trace!("coex_deinit - not implemented");
 }
 
@@ -1984,202 +2065,315 @@ pub unsafe extern "C" fn coex_deinit() {
  * Name: wifi_coex_enable
  *
  * Description:
- *   Don't support
+ *   Enable software coexist
  *
  ****************************************************************************/
 pub unsafe extern "C" fn coex_enable() -> crate::binary::c_types::c_int {
-    trace!("coex_enable - not implemented");
-    0
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_enable()
+    }
+
+    #[cfg(not(feature = "coex"))]
+    {
+        trace!("coex_enable - not implemented");
+        0
+    }
 }
 
 /****************************************************************************
  * Name: wifi_coex_disable
  *
  * Description:
- *   Don't support
+ *   Disable software coexist
  *
  ****************************************************************************/
 pub unsafe extern "C" fn coex_disable() {
-    todo!("coex_disable")
+    #[cfg(feature = "coex")]
+    crate::coex::coex_disable();
+
+    #[cfg(not(feature = "coex"))]
+    todo!("coex_disable");
 }
 
 /****************************************************************************
  * Name: esp_coex_status_get
  *
  * Description:
- *   Don't support
+ *   Get software coexist status
  *
  ****************************************************************************/
 pub unsafe extern "C" fn coex_status_get() -> u32 {
-    trace!("coex_status_get");
-    0
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_status_get()
+    }
+
+    #[cfg(not(feature = "coex"))]
+    {
+        trace!("coex_status_get");
+        0
+    }
 }
 
 /****************************************************************************
  * Name: esp_coex_condition_set
  *
  * Description:
- *   Don't support
+ *   Set software coexist condition
  *
  ****************************************************************************/
-pub unsafe extern "C" fn coex_condition_set(_type_: u32, _dissatisfy: bool) {
-    trace!("coex_condition_set - do nothing")
+pub unsafe extern "C" fn coex_condition_set(type_: u32, dissatisfy: bool) {
+    #[cfg(feature = "coex")]
+    crate::coex::coex_condition_set(type_, dissatisfy);
+
+    #[cfg(not(feature = "coex"))]
+    trace!("coex_condition_set {} {} - do nothing", type_, dissatisfy);
 }
 
 /****************************************************************************
  * Name: esp_coex_wifi_request
  *
  * Description:
- *   Don't support
+ *   Request Wi-Fi coexistence
  *
  ****************************************************************************/
 pub unsafe extern "C" fn coex_wifi_request(
-    _event: u32,
-    _latency: u32,
-    _duration: u32,
+    event: u32,
+    latency: u32,
+    duration: u32,
 ) -> crate::binary::c_types::c_int {
-    trace!("coex_wifi_request - not implemented");
-    0
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_wifi_request(event, latency, duration)
+    }
+
+    #[cfg(not(feature = "coex"))]
+    {
+        trace!(
+            "coex_wifi_request {} {} {} - not implemented",
+            event,
+            latency,
+            duration
+        );
+        0
+    }
 }
 
 /****************************************************************************
  * Name: esp_coex_wifi_release
  *
  * Description:
- *   Don't support
+ *   Release Wi-Fi coexistence
  *
  ****************************************************************************/
-pub unsafe extern "C" fn coex_wifi_release(_event: u32) -> crate::binary::c_types::c_int {
-    trace!("coex_wifi_release - not implemented");
-    0
+pub unsafe extern "C" fn coex_wifi_release(event: u32) -> crate::binary::c_types::c_int {
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_wifi_release(event)
+    }
+
+    #[cfg(not(feature = "coex"))]
+    {
+        trace!("coex_wifi_release {} - not implemented", event);
+        0
+    }
 }
 
 /****************************************************************************
  * Name: wifi_coex_wifi_set_channel
  *
  * Description:
- *   Don't support
+ *   Set Wi-Fi channel to coexistence module
  *
  ****************************************************************************/
 pub unsafe extern "C" fn coex_wifi_channel_set(
-    _primary: u8,
-    _secondary: u8,
+    primary: u8,
+    secondary: u8,
 ) -> crate::binary::c_types::c_int {
-    trace!("coex_wifi_channel_set");
-    0
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_wifi_channel_set(primary, secondary)
+    }
+
+    #[cfg(not(feature = "coex"))]
+    {
+        trace!("coex_wifi_channel_set {} {}", primary, secondary);
+        0
+    }
 }
 
 /****************************************************************************
  * Name: wifi_coex_get_event_duration
  *
  * Description:
- *   Don't support
+ *   Get coexistence event duration
  *
  ****************************************************************************/
 pub unsafe extern "C" fn coex_event_duration_get(
-    _event: u32,
-    _duration: *mut u32,
+    event: u32,
+    duration: *mut u32,
 ) -> crate::binary::c_types::c_int {
-    trace!("coex_event_duration_get");
-    // does nothing in original code
-    0
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_event_duration_get(event, duration)
+    }
+
+    #[cfg(not(feature = "coex"))]
+    {
+        trace!("coex_event_duration_get {} {:p}", event, duration);
+        // does nothing in original code
+        0
+    }
 }
 
 /****************************************************************************
  * Name: wifi_coex_get_pti
  *
  * Description:
- *   Don't support
+ *   Get coexistence event priority, the hardware PTI is only used by the ESP32-C3
  *
  ****************************************************************************/
-pub unsafe extern "C" fn coex_pti_get(_event: u32, _pti: *mut u8) -> crate::binary::c_types::c_int {
-    trace!("coex_pti_get");
-    0
+pub unsafe extern "C" fn coex_pti_get(event: u32, pti: *mut u8) -> crate::binary::c_types::c_int {
+    #[cfg(all(feature = "coex", feature = "esp32c3"))]
+    {
+        crate::coex::coex_pti_get(event, pti)
+    }
+
+    #[cfg(not(all(feature = "coex", feature = "esp32c3")))]
+    {
+        trace!("coex_pti_get {} {:p}", event, pti);
+        0
+    }
 }
 
 /****************************************************************************
  * Name: wifi_coex_clear_schm_status_bit
  *
  * Description:
- *   Don't support
+ *   Clear coexistence status
  *
  ****************************************************************************/
-pub unsafe extern "C" fn coex_schm_status_bit_clear(_type_: u32, _status: u32) {
-    trace!("coex_schm_status_bit_clear")
+pub unsafe extern "C" fn coex_schm_status_bit_clear(type_: u32, status: u32) {
+    #[cfg(feature = "coex")]
+    crate::coex::coex_schm_status_bit_clear(type_, status);
+
     // original implementation does nothing here
+    #[cfg(not(feature = "coex"))]
+    trace!("coex_schm_status_bit_clear {} {}", type_, status);
 }
 
 /****************************************************************************
  * Name: wifi_coex_set_schm_status_bit
  *
  * Description:
- *   Don't support
+ *   Set coexistence status
  *
  ****************************************************************************/
-pub unsafe extern "C" fn coex_schm_status_bit_set(_type_: u32, _status: u32) {
-    trace!("coex_schm_status_bit_set")
+pub unsafe extern "C" fn coex_schm_status_bit_set(type_: u32, status: u32) {
+    #[cfg(feature = "coex")]
+    crate::coex::coex_schm_status_bit_set(type_, status);
+
     // original implementation does nothing here
+    #[cfg(not(feature = "coex"))]
+    trace!("coex_schm_status_bit_set {} {}", type_, status);
 }
 
 /****************************************************************************
  * Name: wifi_coex_set_schm_interval
  *
  * Description:
- *   Don't support
+ *   Set coexistence scheme interval
  *
  ****************************************************************************/
-pub unsafe extern "C" fn coex_schm_interval_set(_interval: u32) -> crate::binary::c_types::c_int {
-    todo!("coex_schm_interval_set")
+pub unsafe extern "C" fn coex_schm_interval_set(interval: u32) -> crate::binary::c_types::c_int {
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_schm_interval_set(interval)
+    }
+
+    #[cfg(not(feature = "coex"))]
+    todo!("coex_schm_interval_set {}", interval);
 }
 
 /****************************************************************************
  * Name: wifi_coex_get_schm_interval
  *
  * Description:
- *   Don't support
+ *   Get coexistence scheme interval
  *
  ****************************************************************************/
 pub unsafe extern "C" fn coex_schm_interval_get() -> u32 {
-    todo!("coex_schm_interval_get")
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_schm_interval_get()
+    }
+
+    #[cfg(not(feature = "coex"))]
+    todo!("coex_schm_interval_get");
 }
 
 /****************************************************************************
  * Name: wifi_coex_get_schm_curr_period
  *
  * Description:
- *   Don't support
+ *   Get coexistence scheme period
  *
  ****************************************************************************/
 pub unsafe extern "C" fn coex_schm_curr_period_get() -> u8 {
-    todo!("coex_schm_curr_period_get")
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_schm_curr_period_get()
+    }
+
+    #[cfg(not(feature = "coex"))]
+    todo!("coex_schm_curr_period_get");
 }
 
 /****************************************************************************
  * Name: wifi_coex_get_schm_curr_phase
  *
  * Description:
- *   Don't support
+ *   Get coexistence scheme phase
  *
  ****************************************************************************/
 pub unsafe extern "C" fn coex_schm_curr_phase_get() -> *mut crate::binary::c_types::c_void {
-    todo!("coex_schm_curr_phase_get")
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_schm_curr_phase_get()
+    }
+
+    #[cfg(not(feature = "coex"))]
+    todo!("coex_schm_curr_phase_get");
 }
 
 pub unsafe extern "C" fn coex_schm_curr_phase_idx_set(
-    _idx: crate::binary::c_types::c_int,
+    idx: crate::binary::c_types::c_int,
 ) -> crate::binary::c_types::c_int {
-    todo!("coex_schm_curr_phase_idx_set")
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_schm_curr_phase_idx_set(idx)
+    }
+
+    #[cfg(not(feature = "coex"))]
+    todo!("coex_schm_curr_phase_idx_set {}", idx);
 }
 
 /****************************************************************************
  * Name: wifi_coex_set_schm_curr_phase_idx
  *
  * Description:
- *   Don't support
+ *   Get coexistence scheme phase index
  *
  ****************************************************************************/
 pub unsafe extern "C" fn coex_schm_curr_phase_idx_get() -> crate::binary::c_types::c_int {
-    todo!("coex_schm_curr_phase_idx_get")
+    #[cfg(feature = "coex")]
+    {
+        crate::coex::coex_schm_curr_phase_idx_get()
+    }
+
+    #[cfg(not(feature = "coex"))]
+    todo!("coex_schm_curr_phase_idx_get");
 }
 
 /****************************************************************************
@@ -2300,18 +2494,23 @@ pub unsafe extern "C" fn esp_fill_random(dst: *mut u8, len: u32) {
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn esp_timer_stop(_handle: *mut ()) {
-    todo!("esp_timer_stop");
+pub unsafe extern "C" fn esp_timer_stop(handle: esp_timer_handle_t) -> i32 {
+    compat_esp_timer_stop(handle)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn esp_timer_delete(handle: esp_timer_handle_t) -> i32 {
+    compat_esp_timer_delete(handle)
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn esp_timer_delete(_handle: *mut ()) {
-    todo!("esp_timer_delete");
+pub unsafe extern "C" fn esp_timer_start_once(handle: esp_timer_handle_t, timeout_us: u64) -> i32 {
+    compat_esp_timer_start(handle, timeout_us, false)
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn esp_timer_start_once(_handle: *mut (), _timeout_us: u64) -> i32 {
-    todo!("esp_timer_start_once");
+pub unsafe extern "C" fn esp_timer_start_periodic(handle: esp_timer_handle_t, period: u64) -> i32 {
+    compat_esp_timer_start(handle, period, true)
 }
 
 #[no_mangle]
@@ -2322,6 +2521,11 @@ pub unsafe extern "C" fn esp_timer_create(
     compat_esp_timer_create(args, out_handle)
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn esp_timer_get_next_alarm() -> i64 {
+    compat_esp_timer_get_next_alarm()
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn strrchr(_s: *const (), _c: u32) -> *const u8 {
     todo!("strrchr");
@@ -169,54 +169,19 @@ pub(crate) unsafe fn phy_enable_clock() {
     trace!("phy_enable_clock done!");
 }
 
-pub(crate) unsafe extern "C" fn read_mac(
-    mac: *mut u8,
-    type_: u32,
-) -> crate::binary::c_types::c_int {
-    trace!("read_mac {:p} {}", mac, type_);
-
+/// Reads the base MAC address from eFuse
+pub(crate) unsafe fn read_base_mac() -> [u8; 6] {
     let mut regval = [0u32; 2];
     let data = &regval as *const _ as *const u8;
     regval[0] = ((0x60008800 + 0x44) as *const u32).read_volatile();
     regval[1] = ((0x60008800 + 0x48) as *const u32).read_volatile();
 
-    for i in 0..6 {
-        mac.offset(i)
-            .write_volatile(data.offset(5 - i).read_volatile());
-    }
-
-    /* ESP_MAC_WIFI_SOFTAP */
-    if type_ == 1 {
-        let tmp = mac.offset(0).read_volatile();
-        for i in 0..64 {
-            mac.offset(0).write_volatile(tmp | 0x02);
-            mac.offset(0)
-                .write_volatile(mac.offset(0).read_volatile() ^ (i << 2));
-
-            if mac.offset(0).read_volatile() != tmp {
-                break;
-            }
-        }
-    }
-
-    // ESP_MAC_BT
-    if type_ == 2 {
-        let tmp = mac.offset(0).read_volatile();
-        for i in 0..64 {
-            mac.offset(0).write_volatile(tmp | 0x02);
-            mac.offset(0)
-                .write_volatile(mac.offset(0).read_volatile() ^ (i << 2));
-
-            if mac.offset(0).read_volatile() != tmp {
-                break;
-            }
-        }
-
-        mac.offset(5)
-            .write_volatile(mac.offset(5).read_volatile() + 1);
+    let mut mac = [0u8; 6];
+    for (i, byte) in mac.iter_mut().enumerate() {
+        *byte = data.offset(5 - i as isize).read_volatile();
     }
 
-    0
+    mac
 }
 
 pub(crate) unsafe extern "C" fn wifi_clock_enable() {
@@ -4,7 +4,8 @@ use smoltcp::{
     wire::{EthernetAddress, IpAddress, IpCidr},
 };
 
-use crate::wifi::get_sta_mac;
+use crate::wifi::{get_mac, get_sta_mac, WifiInterface};
+use crate::EspWifiInitialization;
 
 use super::WifiDevice;
 
@@ -40,23 +41,22 @@ macro_rules! network_stack_storage {
 /// Convenient way to create an `smoltcp` ethernet interface
 /// You can use the provided macros to create and pass a suitable backing storage.
 pub fn create_network_interface<'a>(
+    init: &'a EspWifiInitialization,
     storage: (
         &'a mut [SocketStorage<'a>],
         &'a mut [Option<(IpAddress, Neighbor)>],
         &'a mut [Option<(IpCidr, Route)>],
         &'a mut [IpCidr; 1],
     ),
-) -> Interface<WifiDevice> {
+) -> Interface<'a, WifiDevice<'a>> {
     let socket_set_entries = storage.0;
     let neighbor_cache_storage = storage.1;
     let routes_storage = storage.2;
     let ip_addrs = storage.3;
 
-    let mut mac = [0u8; 6];
-    get_sta_mac(&mut mac);
-    let hw_address = EthernetAddress::from_bytes(&mac);
+    let hw_address = sta_hardware_address();
 
-    let device = WifiDevice::new();
+    let device = WifiDevice::new(init);
 
     let neighbor_cache = NeighborCache::new(&mut neighbor_cache_storage[..]);
     let routes = Routes::new(&mut routes_storage[..]);
@@ -87,3 +87,21 @@ pub fn create_network_interface<'a>(
 
     ethernet
 }
+
+/// Updates the hardware address of an interface created by [create_network_interface]
+/// after the station MAC changed, e.g. by connecting in privacy mode
+pub fn update_hardware_address(interface: &mut Interface<'_, WifiDevice<'_>>) {
+    interface.set_hardware_addr(smoltcp::wire::HardwareAddress::Ethernet(
+        sta_hardware_address(),
+    ));
+}
+
+fn sta_hardware_address() -> EthernetAddress {
+    // the driver knows about a MAC set via `set_mac`
+    let mac = get_mac(WifiInterface::Sta).unwrap_or_else(|_| {
+        let mut mac = [0u8; 6];
+        get_sta_mac(&mut mac);
+        mac
+    });
+    EthernetAddress::from_bytes(&mac)
+}
@@ -23,7 +23,7 @@ const MAX_SCAN_RESULT: u16 = 10;
 
 /// An implementation of `embedded-svc`'s wifi trait.
 pub struct Wifi<'a> {
-    network_interface: Interface<'a, WifiDevice>,
+    network_interface: Interface<'a, WifiDevice<'a>>,
     current_config: embedded_svc::wifi::Configuration,
     network_config: Option<smoltcp::socket::Dhcpv4Config>,
     dhcp_socket_handle: Option<SocketHandle>,
@@ -31,7 +31,7 @@ pub struct Wifi<'a> {
 
 impl<'a> Wifi<'a> {
     /// Create a new instance from a `NetworkStack`
-    pub fn new(mut network_interface: Interface<'a, WifiDevice>) -> Wifi<'a> {
+    pub fn new(mut network_interface: Interface<'a, WifiDevice<'a>>) -> Wifi<'a> {
         let mut dhcp_socket_handle: Option<SocketHandle> = None;
 
         for (handle, socket) in network_interface.sockets_mut() {
@@ -50,7 +50,7 @@ impl<'a> Wifi<'a> {
     }
 
     /// Get a mutable reference to the `NetworkStack`
-    pub fn network_interface(&mut self) -> &mut Interface<'a, WifiDevice> {
+    pub fn network_interface(&mut self) -> &mut Interface<'a, WifiDevice<'a>> {
         &mut self.network_interface
     }
 
@@ -306,10 +306,15 @@ impl<'a> embedded_svc::wifi::Wifi for Wifi<'a> {
         };
 
         if res != 0 {
-            Err(WifiError::Unknown(res))
-        } else {
-            Ok(())
+            return Err(WifiError::Unknown(res));
         }
+
+        // in privacy mode connecting changed the MAC
+        if crate::wifi::is_mac_privacy() {
+            crate::wifi::utils::update_hardware_address(&mut self.network_interface);
+        }
+
+        Ok(())
     }
 }
 